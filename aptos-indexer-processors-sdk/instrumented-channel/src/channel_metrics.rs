@@ -51,6 +51,12 @@ pub fn init_channel_metrics_registry(registry: &mut Registry) {
         "Size of the channel",
         CHANNEL_SIZE.clone(),
     );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "channel_capacity"),
+        "Capacity of the channel",
+        CHANNEL_CAPACITY.clone(),
+    );
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
@@ -79,6 +85,21 @@ pub static FAILED_RECEIVES_COUNT: Lazy<Family<ChannelMetricLabels, Counter>> =
 pub static CHANNEL_SIZE: Lazy<Family<ChannelMetricLabels, Gauge>> =
     Lazy::new(Family::<ChannelMetricLabels, Gauge>::default);
 
+pub static CHANNEL_CAPACITY: Lazy<Family<ChannelMetricLabels, Gauge>> =
+    Lazy::new(Family::<ChannelMetricLabels, Gauge>::default);
+
+/// Returns the last observed size and the capacity of the channel that carries the output of
+/// `output_of`. The size is updated on every send and receive.
+pub fn get_channel_size_and_capacity(output_of: &str) -> (u64, u64) {
+    let labels = ChannelMetricLabels {
+        output_of: output_of.to_string(),
+    };
+    (
+        CHANNEL_SIZE.get_or_create(&labels).get().max(0) as u64,
+        CHANNEL_CAPACITY.get_or_create(&labels).get().max(0) as u64,
+    )
+}
+
 #[derive(Builder, Clone)]
 pub struct ChannelMetrics {
     pub labels: ChannelMetricLabels,
@@ -127,4 +148,11 @@ impl ChannelMetrics {
         CHANNEL_SIZE.get_or_create(&self.labels).set(size as i64);
         self
     }
+
+    pub fn log_channel_capacity(&self, capacity: u64) -> &Self {
+        CHANNEL_CAPACITY
+            .get_or_create(&self.labels)
+            .set(capacity as i64);
+        self
+    }
 }
@@ -38,7 +38,7 @@ impl<T> InstrumentedAsyncSender<T> {
             pub fn len(&self) -> usize;
             pub fn is_empty(&self) -> bool;
             pub fn is_full(&self) -> bool;
-            pub fn capacity(&self);
+            pub fn capacity(&self) -> usize;
             pub fn receiver_count(&self) -> u32;
             pub fn sender_count(&self) -> u32;
             pub fn close(&self) -> bool;
@@ -99,7 +99,7 @@ impl<T> InstrumentedAsyncReceiver<T> {
             pub fn len(&self) -> usize;
             pub fn is_empty(&self) -> bool;
             pub fn is_full(&self) -> bool;
-            pub fn capacity(&self);
+            pub fn capacity(&self) -> usize;
             pub fn receiver_count(&self) -> u32;
             pub fn sender_count(&self) -> u32;
             pub fn close(&self) -> bool;
@@ -150,10 +150,9 @@ pub fn instrumented_bounded_channel<T>(
     size: usize,
 ) -> (InstrumentedAsyncSender<T>, InstrumentedAsyncReceiver<T>) {
     let (sender, receiver) = kanal::bounded_async(size);
-    (
-        InstrumentedAsyncSender::new(sender, output_of),
-        InstrumentedAsyncReceiver::new(receiver, output_of),
-    )
+    let sender = InstrumentedAsyncSender::new(sender, output_of);
+    sender.channel_metrics.log_channel_capacity(size as u64);
+    (sender, InstrumentedAsyncReceiver::new(receiver, output_of))
 }
 
 pub fn instrumented_unbounded_channel<T>(
@@ -186,6 +185,12 @@ mod tests {
         sender.send(999).await.unwrap();
         sender.send(3).await.unwrap();
         assert_eq!(receiver.recv().await.unwrap(), 42);
+        assert_eq!(sender.len(), 2);
+        assert_eq!(sender.capacity(), 10);
+        assert_eq!(
+            channel_metrics::get_channel_size_and_capacity("my_channel"),
+            (2, 10)
+        );
         // TODO: check prometheus metrics
         let metrics = gather_metrics_to_string();
         println!("{}", metrics);
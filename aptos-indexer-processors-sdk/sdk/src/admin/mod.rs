@@ -0,0 +1,5 @@
+pub mod server;
+pub mod stream_control;
+
+pub use server::{register_admin_routes, run_admin_server, AdminConfig};
+pub use stream_control::{stream_control, StreamControl};
@@ -0,0 +1,228 @@
+use crate::admin::stream_control::stream_control;
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Address the admin API listens on. Defaults to localhost only.
+    #[serde(default = "AdminConfig::default_bind_address")]
+    pub bind_address: String,
+    /// If set, every request must send `Authorization: Bearer <auth_token>`. Required unless
+    /// `bind_address` is a loopback address.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn default_bind_address() -> String {
+        "127.0.0.1:8086".to_string()
+    }
+
+    /// Checks that the admin API can't be reached from outside the host without a token.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.auth_token.is_none() && !is_loopback_address(&self.bind_address) {
+            anyhow::bail!(
+                "admin_config.auth_token is required to bind the admin API to {}, which is not a loopback address",
+                self.bind_address
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether `bind_address` only accepts connections from the host itself.
+pub(crate) fn is_loopback_address(bind_address: &str) -> bool {
+    match bind_address.parse::<SocketAddr>() {
+        Ok(address) => address.ip().is_loopback(),
+        Err(_) => bind_address
+            .rsplit_once(':')
+            .is_some_and(|(host, _)| host == "localhost"),
+    }
+}
+
+/// Routes added by processors, served next to the SDK admin routes.
+static ADMIN_ROUTES: Lazy<Mutex<Vec<Router>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Registers processor specific routes on the admin API, behind the same auth. Routes have to
+/// be registered before the admin server starts, e.g. at the top of `main`.
+pub fn register_admin_routes(router: Router) {
+    ADMIN_ROUTES.lock().unwrap().push(router);
+}
+
+#[derive(Debug, Serialize)]
+struct StreamState {
+    paused: bool,
+    pending_rewind: Option<u64>,
+    skipped_versions: Vec<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionRequest {
+    version: u64,
+}
+
+fn stream_state() -> Json<StreamState> {
+    let control = stream_control();
+    Json(StreamState {
+        paused: control.is_paused(),
+        pending_rewind: control.pending_rewind(),
+        skipped_versions: control.skipped_versions(),
+    })
+}
+
+async fn get_stream_handler() -> impl IntoResponse {
+    stream_state()
+}
+
+async fn pause_handler() -> impl IntoResponse {
+    info!("[Admin] Pausing transaction stream");
+    stream_control().pause();
+    stream_state()
+}
+
+async fn resume_handler() -> impl IntoResponse {
+    info!("[Admin] Resuming transaction stream");
+    stream_control().resume();
+    stream_state()
+}
+
+async fn rewind_handler(Json(request): Json<VersionRequest>) -> impl IntoResponse {
+    info!(
+        version = request.version,
+        "[Admin] Rewinding transaction stream"
+    );
+    stream_control().request_rewind(request.version);
+    stream_state()
+}
+
+async fn skip_version_handler(Json(request): Json<VersionRequest>) -> impl IntoResponse {
+    info!(version = request.version, "[Admin] Skipping version");
+    stream_control().skip_version(request.version);
+    stream_state()
+}
+
+async fn unskip_version_handler(Path(version): Path<u64>) -> impl IntoResponse {
+    info!(version = version, "[Admin] No longer skipping version");
+    if stream_control().unskip_version(version) {
+        stream_state().into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Version {} is not skipped", version),
+        )
+            .into_response()
+    }
+}
+
+fn is_authorized(headers: &HeaderMap, auth_token: Option<&str>) -> bool {
+    let Some(auth_token) = auth_token else {
+        return true;
+    };
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), auth_token.as_bytes()))
+}
+
+/// Compares without returning early on the first differing byte, so that the time taken
+/// doesn't reveal how much of a guessed token is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub(crate) async fn auth_middleware(
+    State(auth_token): State<Arc<Option<String>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !is_authorized(request.headers(), auth_token.as_deref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+fn admin_router(config: &AdminConfig) -> Router {
+    let mut router = Router::new()
+        .route("/admin/stream", get(get_stream_handler))
+        .route("/admin/stream/pause", post(pause_handler))
+        .route("/admin/stream/resume", post(resume_handler))
+        .route("/admin/stream/rewind", post(rewind_handler))
+        .route("/admin/stream/skip", post(skip_version_handler))
+        .route(
+            "/admin/stream/skip/:version",
+            delete(unskip_version_handler),
+        );
+    for processor_routes in ADMIN_ROUTES.lock().unwrap().drain(..) {
+        router = router.merge(processor_routes);
+    }
+    router.layer(middleware::from_fn_with_state(
+        Arc::new(config.auth_token.clone()),
+        auth_middleware,
+    ))
+}
+
+/// Serves the admin API: pausing, resuming and rewinding the transaction stream, the version
+/// skip-list, and routes registered by the processor.
+pub async fn run_admin_server(config: AdminConfig) {
+    let router = admin_router(&config);
+    if config.auth_token.is_none() {
+        info!(
+            bind_address = config.bind_address,
+            "Admin API has no auth token, make sure it is not exposed"
+        );
+    }
+    let listener = tokio::net::TcpListener::bind(&config.bind_address)
+        .await
+        .expect("Failed to bind admin TCP listener");
+    axum::serve(listener, router).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_admin_auth() {
+        let mut headers = HeaderMap::new();
+        assert!(is_authorized(&headers, None));
+        assert!(!is_authorized(&headers, Some("secret")));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer wrong"));
+        assert!(!is_authorized(&headers, Some("secret")));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secre"));
+        assert!(!is_authorized(&headers, Some("secret")));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert!(is_authorized(&headers, Some("secret")));
+    }
+
+    #[test]
+    fn test_admin_config_requires_token_off_loopback() {
+        let config = |bind_address: &str, auth_token: Option<&str>| AdminConfig {
+            bind_address: bind_address.to_string(),
+            auth_token: auth_token.map(str::to_string),
+        };
+        assert!(config("127.0.0.1:8086", None).validate().is_ok());
+        assert!(config("[::1]:8086", None).validate().is_ok());
+        assert!(config("localhost:8086", None).validate().is_ok());
+        assert!(config("0.0.0.0:8086", None).validate().is_err());
+        assert!(config("0.0.0.0:8086", Some("secret")).validate().is_ok());
+    }
+}
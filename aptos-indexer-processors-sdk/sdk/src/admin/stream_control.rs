@@ -0,0 +1,154 @@
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+
+static STREAM_CONTROL: Lazy<StreamControl> = Lazy::new(StreamControl::default);
+
+/// Returns the control shared by the admin API, `TransactionStreamStep` and
+/// `VersionTrackerStep`.
+pub fn stream_control() -> &'static StreamControl {
+    &STREAM_CONTROL
+}
+
+/// Runtime controls for the transaction stream: pausing, rewinding and skipping versions.
+///
+/// A rewind is applied in two places. `TransactionStreamStep` restarts the stream at the
+/// requested version, and `VersionTrackerStep` accepts the resulting gap once the first batch
+/// from the restarted stream arrives, resetting the saved processor status.
+#[derive(Default)]
+pub struct StreamControl {
+    paused: AtomicBool,
+    resumed: Notify,
+    last_resumed_at_secs: Mutex<Option<f64>>,
+    pending_stream_rewind: Mutex<Option<u64>>,
+    pending_tracker_rewind: Mutex<Option<u64>>,
+    skipped_versions: RwLock<BTreeSet<u64>>,
+}
+
+impl StreamControl {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            *self.last_resumed_at_secs.lock().unwrap() = Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            );
+        }
+        self.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Unix timestamp of the last time the stream was resumed, if it ever was paused.
+    pub fn last_resumed_at_secs(&self) -> Option<f64> {
+        *self.last_resumed_at_secs.lock().unwrap()
+    }
+
+    /// Waits until the stream is resumed, or at most `timeout`.
+    pub async fn wait_until_resumed(&self, timeout: Duration) {
+        let resumed = self.resumed.notified();
+        if !self.is_paused() {
+            return;
+        }
+        let _ = tokio::time::timeout(timeout, resumed).await;
+    }
+
+    /// Requests the stream to restart at `version`.
+    pub fn request_rewind(&self, version: u64) {
+        *self.pending_stream_rewind.lock().unwrap() = Some(version);
+        *self.pending_tracker_rewind.lock().unwrap() = Some(version);
+    }
+
+    pub fn pending_rewind(&self) -> Option<u64> {
+        *self.pending_stream_rewind.lock().unwrap()
+    }
+
+    /// Takes the rewind the stream has to apply, if any.
+    pub fn take_stream_rewind(&self) -> Option<u64> {
+        self.pending_stream_rewind.lock().unwrap().take()
+    }
+
+    /// Takes the pending rewind if a batch starting at `start_version` is the first batch of
+    /// the rewound stream.
+    pub fn take_tracker_rewind(&self, start_version: u64) -> bool {
+        let mut pending_tracker_rewind = self.pending_tracker_rewind.lock().unwrap();
+        if *pending_tracker_rewind == Some(start_version) {
+            *pending_tracker_rewind = None;
+            return true;
+        }
+        false
+    }
+
+    /// Adds a version to the skip-list. Returns false if it was already skipped.
+    pub fn skip_version(&self, version: u64) -> bool {
+        self.skipped_versions.write().unwrap().insert(version)
+    }
+
+    /// Removes a version from the skip-list. Returns false if it was not skipped.
+    pub fn unskip_version(&self, version: u64) -> bool {
+        self.skipped_versions.write().unwrap().remove(&version)
+    }
+
+    pub fn skipped_versions(&self) -> Vec<u64> {
+        self.skipped_versions
+            .read()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    pub fn has_skipped_versions(&self) -> bool {
+        !self.skipped_versions.read().unwrap().is_empty()
+    }
+
+    pub fn is_skipped(&self, version: u64) -> bool {
+        self.skipped_versions.read().unwrap().contains(&version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_stream_control() {
+        let control = StreamControl::default();
+
+        control.pause();
+        assert!(control.is_paused());
+        control.wait_until_resumed(Duration::from_millis(10)).await;
+        control.resume();
+        assert!(!control.is_paused());
+        assert!(control.last_resumed_at_secs().is_some());
+
+        control.request_rewind(100);
+        assert_eq!(control.take_stream_rewind(), Some(100));
+        assert_eq!(control.take_stream_rewind(), None);
+        assert!(!control.take_tracker_rewind(50));
+        assert!(control.take_tracker_rewind(100));
+        assert!(!control.take_tracker_rewind(100));
+
+        assert!(control.skip_version(7));
+        assert!(!control.skip_version(7));
+        assert!(control.is_skipped(7));
+        assert_eq!(control.skipped_versions(), vec![7]);
+        assert!(control.unskip_version(7));
+        assert!(!control.has_skipped_versions());
+    }
+}
@@ -1,4 +1,12 @@
 mod dag;
+mod pipeline;
 mod processor_builder;
+mod step_registry;
+mod topology;
 
-pub use processor_builder::ProcessorBuilder;
+pub use pipeline::{Pipeline, PipelineConfig, PipelineStepConfig};
+pub use processor_builder::{GraphBuilder, ProcessorBuilder};
+pub use step_registry::{ErasedReceiver, StepRegistry};
+pub use topology::{
+    live_graphs, ChannelPressure, StepStatus, Topology, TopologyEdge, TopologyNode,
+};
@@ -0,0 +1,621 @@
+use crate::{
+    builder::{
+        processor_builder::GraphBuilder,
+        step_registry::{ErasedReceiver, StepRegistry},
+    },
+    types::transaction_context::TransactionContext,
+};
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use instrumented_channel::InstrumentedAsyncReceiver;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet, VecDeque},
+};
+
+/// The `pipeline` section of the config. Describes the steps of a processor and how they are
+/// connected, so that the DAG can be re-tuned without a rebuild.
+///
+/// ```yaml
+/// pipeline:
+///   steps:
+///     - name: stream
+///       kind: transaction_stream
+///       config:
+///         indexer_grpc_data_service_address: "https://grpc.mainnet.aptoslabs.com:443"
+///         ...
+///     - name: extractor
+///       kind: events_extractor
+///       inputs: [stream]
+///       channel_size: 50
+///     - name: storer
+///       kind: events_storer
+///       inputs: [extractor]
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub steps: Vec<PipelineStepConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepConfig {
+    /// Unique name of the step within the pipeline. Other steps refer to it in `inputs`.
+    pub name: String,
+    /// The kind of step, as registered in the `StepRegistry`.
+    pub kind: String,
+    /// Names of the steps whose output this step consumes. A step without inputs is a first
+    /// step; a step with several inputs fans them in. A step consumed by several steps fans
+    /// its output out to all of them.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Size of the output channel of this step.
+    #[serde(default = "PipelineStepConfig::default_channel_size")]
+    pub channel_size: usize,
+    /// Config passed to the step factory.
+    #[serde(default)]
+    pub config: serde_yaml::Value,
+}
+
+impl PipelineStepConfig {
+    pub const fn default_channel_size() -> usize {
+        10
+    }
+}
+
+impl PipelineConfig {
+    /// Checks that step names are unique, inputs exist, the steps form a DAG, every kind is
+    /// registered, steps without inputs take `()` and the output type of every input matches
+    /// the input type of its consumer.
+    /// Returns the step indices in topological order.
+    pub fn validate(&self, registry: &StepRegistry) -> Result<Vec<usize>> {
+        if self.steps.is_empty() {
+            anyhow::bail!("Pipeline has no steps");
+        }
+
+        let mut index_by_name = HashMap::new();
+        for (idx, step) in self.steps.iter().enumerate() {
+            if index_by_name.insert(step.name.as_str(), idx).is_some() {
+                anyhow::bail!("Pipeline step `{}` is defined more than once", step.name);
+            }
+        }
+
+        for step in &self.steps {
+            let factory = registry.get(&step.kind).with_context(|| {
+                format!(
+                    "Pipeline step `{}` has unknown kind `{}`. Registered kinds: {:?}",
+                    step.name,
+                    step.kind,
+                    registry.kinds()
+                )
+            })?;
+            // A step without inputs is fed by a dummy channel that never receives anything
+            if step.inputs.is_empty() && factory.input_type != TypeId::of::<()>() {
+                anyhow::bail!(
+                    "Pipeline step `{}` ({}) has no inputs, but expects input `{}`. Only steps with input `()` can be first steps",
+                    step.name,
+                    step.kind,
+                    factory.input_type_name,
+                );
+            }
+            let mut seen_inputs = HashSet::new();
+            for input in &step.inputs {
+                if !seen_inputs.insert(input) {
+                    anyhow::bail!(
+                        "Pipeline step `{}` lists input `{}` more than once",
+                        step.name,
+                        input
+                    );
+                }
+                let input_idx = *index_by_name.get(input.as_str()).with_context(|| {
+                    format!(
+                        "Pipeline step `{}` has unknown input `{}`",
+                        step.name, input
+                    )
+                })?;
+                let input_step = &self.steps[input_idx];
+                let input_factory = registry
+                    .get(&input_step.kind)
+                    .expect("Kinds are checked before inputs are resolved");
+                if input_factory.output_type != factory.input_type {
+                    anyhow::bail!(
+                        "Type mismatch: pipeline step `{}` ({}) expects input `{}`, but its input `{}` ({}) outputs `{}`",
+                        step.name,
+                        step.kind,
+                        factory.input_type_name,
+                        input_step.name,
+                        input_step.kind,
+                        input_factory.output_type_name,
+                    );
+                }
+            }
+        }
+
+        // Kahn's algorithm, which also detects cycles
+        let mut num_pending_inputs = self
+            .steps
+            .iter()
+            .map(|step| step.inputs.len())
+            .collect::<Vec<_>>();
+        let mut ready = num_pending_inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n == 0)
+            .map(|(idx, _)| idx)
+            .collect::<VecDeque<_>>();
+        let mut order = Vec::with_capacity(self.steps.len());
+        while let Some(idx) = ready.pop_front() {
+            order.push(idx);
+            for (consumer_idx, consumer) in self.steps.iter().enumerate() {
+                if consumer.inputs.contains(&self.steps[idx].name) {
+                    num_pending_inputs[consumer_idx] -= 1;
+                    if num_pending_inputs[consumer_idx] == 0 {
+                        ready.push_back(consumer_idx);
+                    }
+                }
+            }
+        }
+        if order.len() != self.steps.len() {
+            let cyclic_steps = self
+                .steps
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !order.contains(idx))
+                .map(|(_, step)| step.name.as_str())
+                .collect::<Vec<_>>();
+            anyhow::bail!("Pipeline steps form a cycle: {:?}", cyclic_steps);
+        }
+        Ok(order)
+    }
+
+    fn consumers_of(&self, step_name: &str) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| step.inputs.iter().any(|input| input == step_name))
+            .count()
+    }
+
+    /// Validates the pipeline and returns its graph without building any step, e.g. to print
+    /// the DAG of a processor that is not running.
+    pub fn graph(&self, registry: &StepRegistry) -> Result<GraphBuilder> {
+        let order = self.validate(registry)?;
+
+        let mut graph = GraphBuilder::new();
+        let mut node_indices: HashMap<&str, NodeIndex> = HashMap::new();
+        for idx in order {
+            let step_config = &self.steps[idx];
+            let factory = registry
+                .get(&step_config.kind)
+                .expect("Kinds are checked during validation");
+            let node_index = graph.add_node(
+                step_config.name.clone(),
+                step_config.kind.clone(),
+                factory.input_type_name.to_string(),
+                factory.output_type_name.to_string(),
+            );
+            for input in &step_config.inputs {
+                graph.add_edge_from_to(node_indices[input.as_str()], node_index);
+            }
+            if self.consumers_of(&step_config.name) == 0 {
+                graph.set_end_step();
+            }
+            node_indices.insert(step_config.name.as_str(), node_index);
+        }
+        Ok(graph)
+    }
+
+    /// Validates the pipeline, then builds and spawns all of its steps.
+    pub async fn build(&self, registry: &StepRegistry) -> Result<Pipeline> {
+        let order = self.validate(registry)?;
+
+        let mut graph = GraphBuilder::new();
+        let mut node_indices: HashMap<&str, NodeIndex> = HashMap::new();
+        // Outputs of spawned steps that have not been handed to their consumers yet
+        let mut pending_outputs: HashMap<&str, Vec<ErasedReceiver>> = HashMap::new();
+        let mut outputs = HashMap::new();
+
+        for idx in order {
+            let step_config = &self.steps[idx];
+            let factory = registry
+                .get(&step_config.kind)
+                .expect("Kinds are checked during validation");
+            let step = factory
+                .build(step_config.config.clone())
+                .await
+                .with_context(|| format!("Failed to build pipeline step `{}`", step_config.name))?;
+
+            let node_index = graph.add_node(
+                step.name(),
+                step.type_name(),
+                factory.input_type_name.to_string(),
+                factory.output_type_name.to_string(),
+            );
+            let mut inputs = Vec::with_capacity(step_config.inputs.len());
+            for input in &step_config.inputs {
+                graph.add_edge_from_to(node_indices[input.as_str()], node_index);
+                inputs.push(
+                    pending_outputs
+                        .get_mut(input.as_str())
+                        .and_then(|receivers| receivers.pop())
+                        .expect("Every consumer gets one receiver of its input"),
+                );
+            }
+
+            let num_consumers = self.consumers_of(&step_config.name);
+            let (receivers, join_handle) =
+                step.spawn(inputs, step_config.channel_size, num_consumers)?;
+            graph.set_join_handle(node_index.index(), join_handle);
+            node_indices.insert(step_config.name.as_str(), node_index);
+
+            if num_consumers == 0 {
+                graph.set_end_step();
+                let receiver = receivers
+                    .into_iter()
+                    .next()
+                    .expect("A spawned step has an output");
+                outputs.insert(
+                    step_config.name.clone(),
+                    PipelineOutput {
+                        receiver,
+                        drain: factory.drain_output,
+                    },
+                );
+            } else {
+                pending_outputs.insert(step_config.name.as_str(), receivers);
+            }
+        }
+
+        Ok(Pipeline { graph, outputs })
+    }
+}
+
+struct PipelineOutput {
+    receiver: ErasedReceiver,
+    drain: fn(ErasedReceiver) -> Result<BoxFuture<'static, ()>>,
+}
+
+/// A running pipeline built from a `PipelineConfig`.
+pub struct Pipeline {
+    pub graph: GraphBuilder,
+    // Output receivers of the steps that no other step consumes, by step name
+    outputs: HashMap<String, PipelineOutput>,
+}
+
+impl Pipeline {
+    /// Names of the steps whose output is not consumed by another step.
+    pub fn output_step_names(&self) -> Vec<String> {
+        let mut names = self.outputs.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Takes the output receiver of an end step, e.g. to parse the results.
+    pub fn take_output<T: Send + 'static>(
+        &mut self,
+        step_name: &str,
+    ) -> Result<InstrumentedAsyncReceiver<TransactionContext<T>>> {
+        let output = self
+            .outputs
+            .remove(step_name)
+            .with_context(|| format!("Pipeline has no end step named `{}`", step_name))?;
+        output
+            .receiver
+            .downcast::<InstrumentedAsyncReceiver<TransactionContext<T>>>()
+            .map(|receiver| *receiver)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "End step `{}` does not output `{}`",
+                    step_name,
+                    std::any::type_name::<T>()
+                )
+            })
+    }
+
+    /// Consumes the remaining outputs until all channels are closed, i.e. until the pipeline
+    /// has finished.
+    pub async fn wait(self) -> Result<()> {
+        let drains = self
+            .outputs
+            .into_values()
+            .map(|output| (output.drain)(output.receiver))
+            .collect::<Result<Vec<_>>>()?;
+        futures::future::join_all(drains).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::StepStatus,
+        test::steps::pass_through_step::PassThroughStep,
+        traits::{
+            AsyncRunType, AsyncStep, IntoRunnableStep, NamedStep, PollableAsyncRunType,
+            PollableAsyncStep, Processable,
+        },
+        types::transaction_context::TransactionMetadata,
+        utils::errors::ProcessorError,
+    };
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, Deserialize)]
+    struct SourceConfig {
+        num_batches: u64,
+    }
+
+    /// Emits `num_batches` single-version batches, then stops polling.
+    struct SourceStep {
+        num_batches: u64,
+        next_version: u64,
+    }
+
+    #[async_trait]
+    impl Processable for SourceStep {
+        type Input = ();
+        type Output = Vec<u64>;
+        type RunType = PollableAsyncRunType;
+
+        async fn process(
+            &mut self,
+            _item: TransactionContext<()>,
+        ) -> Result<Option<TransactionContext<Vec<u64>>>, ProcessorError> {
+            Ok(None)
+        }
+    }
+
+    #[async_trait]
+    impl PollableAsyncStep for SourceStep {
+        fn poll_interval(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+
+        async fn poll(
+            &mut self,
+        ) -> Result<Option<Vec<TransactionContext<Vec<u64>>>>, ProcessorError> {
+            let version = self.next_version;
+            self.next_version += 1;
+            Ok(Some(vec![TransactionContext {
+                data: vec![version],
+                metadata: TransactionMetadata {
+                    start_version: version,
+                    end_version: version,
+                    ..Default::default()
+                },
+            }]))
+        }
+
+        async fn should_continue_polling(&mut self) -> bool {
+            self.next_version < self.num_batches
+        }
+    }
+
+    impl NamedStep for SourceStep {
+        fn name(&self) -> String {
+            "SourceStep".to_string()
+        }
+    }
+
+    struct ToStringStep;
+
+    impl AsyncStep for ToStringStep {}
+
+    impl NamedStep for ToStringStep {
+        fn name(&self) -> String {
+            "ToStringStep".to_string()
+        }
+    }
+
+    #[async_trait]
+    impl Processable for ToStringStep {
+        type Input = Vec<u64>;
+        type Output = Vec<String>;
+        type RunType = AsyncRunType;
+
+        async fn process(
+            &mut self,
+            item: TransactionContext<Vec<u64>>,
+        ) -> Result<Option<TransactionContext<Vec<String>>>, ProcessorError> {
+            Ok(Some(TransactionContext {
+                data: item.data.iter().map(|v| v.to_string()).collect(),
+                metadata: item.metadata,
+            }))
+        }
+    }
+
+    fn make_registry() -> StepRegistry {
+        let mut registry = StepRegistry::new();
+        registry
+            .register("source", |config: SourceConfig| async move {
+                Ok(SourceStep {
+                    num_batches: config.num_batches,
+                    next_version: 0,
+                }
+                .into_runnable_step())
+            })
+            .unwrap();
+        registry
+            .register("pass_through", |_: ()| async move {
+                Ok(PassThroughStep::<Vec<u64>>::default().into_runnable_step())
+            })
+            .unwrap();
+        registry
+            .register("to_string", |_: ()| async move {
+                Ok(ToStringStep.into_runnable_step())
+            })
+            .unwrap();
+        registry
+    }
+
+    fn parse_pipeline(yaml: &str) -> PipelineConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[allow(clippy::needless_return)]
+    async fn test_pipeline_fanout_and_fanin() {
+        let pipeline_config = parse_pipeline(
+            r#"
+            steps:
+              - name: source
+                kind: source
+                config:
+                  num_batches: 3
+              - name: left
+                kind: pass_through
+                inputs: [source]
+              - name: right
+                kind: pass_through
+                inputs: [source]
+              - name: joined
+                kind: to_string
+                inputs: [left, right]
+                channel_size: 20
+            "#,
+        );
+        let registry = make_registry();
+        let mut pipeline = pipeline_config.build(&registry).await.unwrap();
+        assert_eq!(pipeline.output_step_names(), vec!["joined".to_string()]);
+
+        let output_receiver = pipeline.take_output::<Vec<String>>("joined").unwrap();
+        let mut outputs = vec![];
+        for _ in 0..6 {
+            let result = tokio::time::timeout(Duration::from_secs(1), output_receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            outputs.extend(result.data);
+        }
+        outputs.sort();
+        assert_eq!(outputs, vec!["0", "0", "1", "1", "2", "2"]);
+
+        // The graph can also be described without building the steps
+        let graph = pipeline_config.graph(&registry).unwrap();
+        let topology = graph.topology();
+        assert_eq!(topology.nodes.len(), 4);
+        assert_eq!(topology.edges.len(), 4);
+        assert!(topology
+            .nodes
+            .iter()
+            .all(|node| node.status == StepStatus::Pending));
+        assert!(graph.dot().contains("joined"));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_pipeline_validation_errors() {
+        let registry = make_registry();
+
+        let mismatch = parse_pipeline(
+            r#"
+            steps:
+              - name: source
+                kind: source
+                config:
+                  num_batches: 1
+              - name: strings
+                kind: to_string
+                inputs: [source]
+              - name: numbers
+                kind: pass_through
+                inputs: [strings]
+            "#,
+        );
+        let err = mismatch.validate(&registry).unwrap_err().to_string();
+        assert!(err.contains("Type mismatch"), "{}", err);
+        assert!(err.contains("`numbers`"), "{}", err);
+
+        let unknown_kind = parse_pipeline(
+            r#"
+            steps:
+              - name: source
+                kind: does_not_exist
+            "#,
+        );
+        let err = unknown_kind.validate(&registry).unwrap_err().to_string();
+        assert!(err.contains("unknown kind"), "{}", err);
+
+        let cycle = parse_pipeline(
+            r#"
+            steps:
+              - name: source
+                kind: source
+                config:
+                  num_batches: 1
+              - name: a
+                kind: pass_through
+                inputs: [source, b]
+              - name: b
+                kind: pass_through
+                inputs: [a]
+            "#,
+        );
+        let err = cycle.validate(&registry).unwrap_err().to_string();
+        assert!(err.contains("cycle"), "{}", err);
+
+        let missing_input = parse_pipeline(
+            r#"
+            steps:
+              - name: source
+                kind: source
+                config:
+                  num_batches: 1
+              - name: strings
+                kind: to_string
+            "#,
+        );
+        let err = missing_input.validate(&registry).unwrap_err().to_string();
+        assert!(err.contains("has no inputs"), "{}", err);
+        assert!(err.contains("`strings`"), "{}", err);
+    }
+
+    #[test]
+    fn test_pipeline_with_common_steps() {
+        let registry = StepRegistry::with_common_steps();
+        let pipeline_config = parse_pipeline(
+            r#"
+            steps:
+              - name: stream
+                kind: transaction_stream
+              - name: batch
+                kind: batch_transactions
+                inputs: [stream]
+                config:
+                  max_rows: 1000
+              - name: split
+                kind: split_transactions
+                inputs: [batch]
+                config:
+                  max_rows: 100
+            "#,
+        );
+        let graph = pipeline_config.graph(&registry).unwrap();
+        let topology = graph.topology();
+        assert_eq!(topology.nodes.len(), 3);
+        assert_eq!(topology.edges.len(), 2);
+
+        // The transactions are not strings
+        let mismatch = parse_pipeline(
+            r#"
+            steps:
+              - name: stream
+                kind: transaction_stream
+              - name: strings
+                kind: to_string
+                inputs: [stream]
+            "#,
+        );
+        let mut registry = StepRegistry::with_common_steps();
+        registry
+            .register("to_string", |_: ()| async move {
+                Ok(ToStringStep.into_runnable_step())
+            })
+            .unwrap();
+        let err = mismatch.validate(&registry).unwrap_err().to_string();
+        assert!(err.contains("Type mismatch"), "{}", err);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    builder::dag::connect_two_steps,
+    builder::{dag::connect_two_steps, topology::register_live_graph},
     traits::{RunnableStep, RunnableStepWithInputReceiver},
     types::transaction_context::TransactionContext,
 };
@@ -29,12 +29,15 @@ pub struct GraphBuilder {
 
 impl GraphBuilder {
     pub fn new() -> Self {
-        Self {
+        let graph_builder = Self {
             graph: Arc::new(Mutex::new(DiGraph::new())),
             node_map: Arc::new(Mutex::new(HashMap::new())),
             node_counter: Arc::new(Mutex::new(0)),
             current_node_index: None,
-        }
+        };
+        // Make the graph visible to the topology endpoints of the health server
+        register_live_graph(&graph_builder);
+        graph_builder
     }
 
     pub fn add_step<Input, Output, Step>(
@@ -95,6 +98,35 @@ impl GraphBuilder {
         self.current_node_index = Some(new_node_index);
     }
 
+    /// Adds a node described by its name and types rather than by a step, e.g. for
+    /// pipelines built from config, and makes it the current node.
+    pub fn add_node(
+        &mut self,
+        name: String,
+        step_type: String,
+        input_type: String,
+        output_type: String,
+    ) -> NodeIndex {
+        let current_node_counter = *self.node_counter.lock().unwrap();
+        let new_node_index = self.graph.lock().unwrap().add_node(current_node_counter);
+        self.node_map
+            .lock()
+            .unwrap()
+            .insert(current_node_counter, GraphNode {
+                id: current_node_counter,
+                name,
+                step_type,
+                input_type,
+                output_type,
+                join_handle: None,
+                end_step: false,
+            });
+
+        *self.node_counter.lock().unwrap() += 1;
+        self.current_node_index = Some(new_node_index);
+        new_node_index
+    }
+
     pub fn set_end_step(&mut self) {
         let current_node_counter = self.current_node_index.as_ref().unwrap().index();
         self.node_map
@@ -0,0 +1,327 @@
+use crate::{
+    aptos_indexer_transaction_stream::TransactionStreamConfig,
+    common_steps::{BatchingConfig, BatchingStep, SplitStep, TransactionStreamStep},
+    traits::{IntoRunnableStep, RunnableStep},
+    types::transaction_context::TransactionContext,
+};
+use anyhow::{Context, Result};
+use aptos_protos::transaction::v1::Transaction;
+use futures::future::BoxFuture;
+use instrumented_channel::{instrumented_bounded_channel, InstrumentedAsyncReceiver};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    marker::PhantomData,
+};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// A type erased `InstrumentedAsyncReceiver<TransactionContext<T>>`.
+pub type ErasedReceiver = Box<dyn Any + Send>;
+
+type StepBuilderFn =
+    dyn Fn(serde_yaml::Value) -> BoxFuture<'static, Result<Box<dyn ErasedStep>>> + Send + Sync;
+
+/// Registry of named step factories, used to build pipelines from config.
+///
+/// Each factory is registered under a `kind` and takes a typed config, which is deserialized
+/// from the `config` field of the step in the `pipeline` section. The input and output types
+/// of the produced step are recorded so that pipelines can be validated before any step is
+/// spawned.
+///
+/// ```ignore
+/// let mut registry = StepRegistry::new();
+/// registry.register("transaction_stream", |config: TransactionStreamConfig| async move {
+///     Ok(TransactionStreamStep::new(config).await?.into_runnable_step())
+/// })?;
+/// ```
+#[derive(Default)]
+pub struct StepRegistry {
+    factories: HashMap<String, StepFactory>,
+}
+
+pub(crate) struct StepFactory {
+    pub input_type: TypeId,
+    pub input_type_name: &'static str,
+    pub output_type: TypeId,
+    pub output_type_name: &'static str,
+    /// Waits until an output receiver of the step is closed, discarding its items.
+    pub drain_output: fn(ErasedReceiver) -> Result<BoxFuture<'static, ()>>,
+    build: Box<StepBuilderFn>,
+}
+
+impl StepFactory {
+    pub async fn build(&self, config: serde_yaml::Value) -> Result<Box<dyn ErasedStep>> {
+        (self.build)(config).await
+    }
+}
+
+impl StepRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with the SDK's common steps over transactions, which only need their config:
+    /// - `transaction_stream`: a `TransactionStreamStep`, configured by a
+    ///   `TransactionStreamConfig`.
+    /// - `batch_transactions`: a `BatchingStep` of transactions, configured by a
+    ///   `BatchingConfig`.
+    /// - `split_transactions`: a `SplitStep` of transactions, configured by its `max_rows`.
+    pub fn with_common_steps() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register(
+                "transaction_stream",
+                |config: TransactionStreamConfig| async move {
+                    Ok(TransactionStreamStep::new(config)
+                        .await?
+                        .into_runnable_step())
+                },
+            )
+            .expect("Common step kinds are unique");
+        registry
+            .register("batch_transactions", |config: BatchingConfig| async move {
+                Ok(BatchingStep::<Transaction>::new(config).into_runnable_step())
+            })
+            .expect("Common step kinds are unique");
+        registry
+            .register("split_transactions", |config: SplitConfig| async move {
+                Ok(SplitStep::<Transaction>::new(config.max_rows).into_runnable_step())
+            })
+            .expect("Common step kinds are unique");
+        registry
+    }
+
+    /// Registers a step factory under `kind`. Returns an error if the kind is already taken.
+    pub fn register<C, Input, Output, Step, F, Fut>(&mut self, kind: &str, factory: F) -> Result<()>
+    where
+        C: DeserializeOwned + Send + 'static,
+        Input: Send + 'static,
+        Output: Clone + Send + Sync + 'static,
+        Step: RunnableStep<Input, Output>,
+        F: Fn(C) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Step>> + Send + 'static,
+    {
+        if self.factories.contains_key(kind) {
+            anyhow::bail!("Step kind `{}` is already registered", kind);
+        }
+        let kind_name = kind.to_string();
+        let build =
+            move |config: serde_yaml::Value| -> BoxFuture<'static, Result<Box<dyn ErasedStep>>> {
+                let config = deserialize_step_config::<C>(&kind_name, config);
+                let step = config.map(&factory);
+                Box::pin(async move {
+                    let step = step?.await?;
+                    Ok(Box::new(TypedStep::<Input, Output, Step>::new(step))
+                        as Box<dyn ErasedStep>)
+                })
+            };
+        self.factories.insert(
+            kind.to_string(),
+            StepFactory {
+                input_type: TypeId::of::<Input>(),
+                input_type_name: std::any::type_name::<Input>(),
+                output_type: TypeId::of::<Output>(),
+                output_type_name: std::any::type_name::<Output>(),
+                drain_output: drain_receiver::<Output>,
+                build: Box::new(build),
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns the registered step kinds, sorted.
+    pub fn kinds(&self) -> Vec<String> {
+        let mut kinds = self.factories.keys().cloned().collect::<Vec<_>>();
+        kinds.sort();
+        kinds
+    }
+
+    pub(crate) fn get(&self, kind: &str) -> Option<&StepFactory> {
+        self.factories.get(kind)
+    }
+}
+
+/// Config of the `split_transactions` step.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SplitConfig {
+    max_rows: usize,
+}
+
+/// Deserializes the config of a step. A missing config is treated as an empty mapping, so
+/// that configs with only default fields can be omitted.
+fn deserialize_step_config<C: DeserializeOwned>(
+    kind: &str,
+    config: serde_yaml::Value,
+) -> Result<C> {
+    if config.is_null() {
+        if let Ok(config) = serde_yaml::from_value::<C>(serde_yaml::Value::Null) {
+            return Ok(config);
+        }
+        return serde_yaml::from_value::<C>(serde_yaml::Value::Mapping(Default::default()))
+            .with_context(|| format!("Missing or invalid config for step kind `{}`", kind));
+    }
+    serde_yaml::from_value::<C>(config)
+        .with_context(|| format!("Invalid config for step kind `{}`", kind))
+}
+
+/// A step with its input and output types erased, so that steps of different types can be
+/// wired together from config.
+pub(crate) trait ErasedStep: Send {
+    fn name(&self) -> String;
+
+    fn type_name(&self) -> String;
+
+    /// Spawns the step. With no inputs, the step gets a dummy input channel; with several
+    /// inputs, they are merged into one. The output is broadcast to `num_outputs` receivers,
+    /// or returned as a single receiver if `num_outputs` is 0 or 1.
+    fn spawn(
+        self: Box<Self>,
+        inputs: Vec<ErasedReceiver>,
+        channel_size: usize,
+        num_outputs: usize,
+    ) -> Result<(Vec<ErasedReceiver>, JoinHandle<()>)>;
+}
+
+struct TypedStep<Input, Output, Step>
+where
+    Input: Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+    Step: RunnableStep<Input, Output>,
+{
+    step: Step,
+    _marker: PhantomData<fn(Input) -> Output>,
+}
+
+impl<Input, Output, Step> TypedStep<Input, Output, Step>
+where
+    Input: Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+    Step: RunnableStep<Input, Output>,
+{
+    fn new(step: Step) -> Self {
+        Self {
+            step,
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn downcast_receiver<T: Send + 'static>(
+    receiver: ErasedReceiver,
+    step_name: &str,
+) -> Result<InstrumentedAsyncReceiver<TransactionContext<T>>> {
+    receiver
+        .downcast::<InstrumentedAsyncReceiver<TransactionContext<T>>>()
+        .map(|receiver| *receiver)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Step `{}` received a channel that does not carry `{}`",
+                step_name,
+                std::any::type_name::<T>()
+            )
+        })
+}
+
+fn drain_receiver<T: Send + 'static>(receiver: ErasedReceiver) -> Result<BoxFuture<'static, ()>> {
+    let receiver = downcast_receiver::<T>(receiver, "pipeline output")?;
+    Ok(Box::pin(
+        async move { while receiver.recv().await.is_ok() {} },
+    ))
+}
+
+impl<Input, Output, Step> ErasedStep for TypedStep<Input, Output, Step>
+where
+    Input: Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+    Step: RunnableStep<Input, Output>,
+{
+    fn name(&self) -> String {
+        self.step.name()
+    }
+
+    fn type_name(&self) -> String {
+        <Step as RunnableStep<Input, Output>>::type_name(&self.step)
+    }
+
+    fn spawn(
+        self: Box<Self>,
+        inputs: Vec<ErasedReceiver>,
+        channel_size: usize,
+        num_outputs: usize,
+    ) -> Result<(Vec<ErasedReceiver>, JoinHandle<()>)> {
+        let step = self.step;
+        let step_name = step.name();
+        let mut inputs = inputs
+            .into_iter()
+            .map(|input| downcast_receiver::<Input>(input, &step_name))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (output_receiver, join_handle) = match inputs.len() {
+            0 => {
+                // Inputless first step. Keep the sender of the dummy channel alive so the step does not panic.
+                let (input_sender, input_receiver) = instrumented_bounded_channel("input", 1);
+                step.spawn(Some(input_receiver), channel_size, Some(input_sender))
+            },
+            1 => step.spawn(inputs.pop(), channel_size, None),
+            _ => {
+                // Fan in: forward all inputs into one channel that feeds the step
+                let (connector_sender, connector_receiver) = instrumented_bounded_channel(
+                    &format!("{}::FaninConnector", step_name),
+                    channel_size,
+                );
+                for input in inputs {
+                    let sender = connector_sender.clone();
+                    let step_name = step_name.clone();
+                    tokio::spawn(async move {
+                        while let Ok(item) = input.recv().await {
+                            if sender.send(item).await.is_err() {
+                                warn!(step_name = step_name, "Fan in connector channel closed");
+                                break;
+                            }
+                        }
+                    });
+                }
+                step.spawn(Some(connector_receiver), channel_size, None)
+            },
+        };
+
+        if num_outputs <= 1 {
+            return Ok((
+                vec![Box::new(output_receiver) as ErasedReceiver],
+                join_handle,
+            ));
+        }
+
+        // Fan out: broadcast every output to all consumers
+        let mut senders = Vec::with_capacity(num_outputs);
+        let mut receivers = Vec::with_capacity(num_outputs);
+        for idx in 0..num_outputs {
+            let (sender, receiver) = instrumented_bounded_channel::<TransactionContext<Output>>(
+                &format!("{}::Fanout::{}", step_name, idx),
+                channel_size,
+            );
+            senders.push(sender);
+            receivers.push(Box::new(receiver) as ErasedReceiver);
+        }
+        tokio::spawn(async move {
+            while let Ok(item) = output_receiver.recv().await {
+                let (last, rest) = senders.split_last().expect("Fan out has senders");
+                for sender in rest {
+                    if sender.send(item.clone()).await.is_err() {
+                        warn!(step_name = step_name, "Fan out channel closed");
+                        return;
+                    }
+                }
+                if last.send(item).await.is_err() {
+                    warn!(step_name = step_name, "Fan out channel closed");
+                    return;
+                }
+            }
+        });
+        Ok((receivers, join_handle))
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    builder::processor_builder::{GraphBuilder, GraphNode},
+    utils::step_metrics::{StepMetricLabels, LATEST_POLLED_VERSION, LATEST_PROCESSED_VERSION},
+};
+use instrumented_channel::channel_metrics::get_channel_size_and_capacity;
+use once_cell::sync::Lazy;
+use petgraph::graph::DiGraph;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Graphs of the pipelines that are currently alive, so that the health server can render
+/// them. Processors usually drop their `ProcessorBuilder` once the steps are connected, so the
+/// graphs are held here until they are no longer needed, see `LiveGraph::is_alive`.
+static LIVE_GRAPHS: Lazy<Mutex<Vec<LiveGraph>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct LiveGraph {
+    graph: Arc<Mutex<DiGraph<usize, usize>>>,
+    node_map: Arc<Mutex<HashMap<usize, GraphNode>>>,
+    node_counter: Arc<Mutex<usize>>,
+}
+
+impl LiveGraph {
+    /// A graph is alive while something besides the registry holds it, e.g. a
+    /// `ProcessorBuilder` or a `Pipeline`, or while any of its spawned steps is running.
+    /// Graphs that only describe a pipeline, e.g. for `print-dag`, disappear once dropped.
+    fn is_alive(&self) -> bool {
+        Arc::strong_count(&self.node_map) > 1
+            || self.node_map.lock().unwrap().values().any(
+                |node| matches!(&node.join_handle, Some(join_handle) if !join_handle.is_finished()),
+            )
+    }
+}
+
+pub(crate) fn register_live_graph(graph: &GraphBuilder) {
+    let mut live_graphs = LIVE_GRAPHS.lock().unwrap();
+    live_graphs.retain(LiveGraph::is_alive);
+    live_graphs.push(LiveGraph {
+        graph: graph.graph.clone(),
+        node_map: graph.node_map.clone(),
+        node_counter: graph.node_counter.clone(),
+    });
+}
+
+/// Returns the graphs of all pipelines that are currently alive.
+pub fn live_graphs() -> Vec<GraphBuilder> {
+    let mut live_graphs = LIVE_GRAPHS.lock().unwrap();
+    live_graphs.retain(LiveGraph::is_alive);
+    live_graphs
+        .iter()
+        .map(|live_graph| GraphBuilder {
+            graph: live_graph.graph.clone(),
+            node_map: live_graph.node_map.clone(),
+            node_counter: live_graph.node_counter.clone(),
+            current_node_index: None,
+        })
+        // Skip graphs that were never built on
+        .filter(|graph| graph.graph.lock().unwrap().node_count() > 0)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    /// The step has not been spawned yet.
+    Pending,
+    Running,
+    /// The task of the step has exited, either because its input closed or because it failed.
+    Finished,
+}
+
+/// Fill level of a channel, as last observed on a send or receive.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ChannelPressure {
+    pub len: u64,
+    pub capacity: u64,
+}
+
+impl ChannelPressure {
+    fn of(output_of: &str) -> Self {
+        let (len, capacity) = get_channel_size_and_capacity(output_of);
+        Self { len, capacity }
+    }
+
+    fn is_full(&self) -> bool {
+        self.capacity > 0 && self.len >= self.capacity
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TopologyNode {
+    pub id: usize,
+    pub name: String,
+    pub step_type: String,
+    pub input_type: String,
+    pub output_type: String,
+    pub end_step: bool,
+    pub status: StepStatus,
+    pub last_processed_version: Option<u64>,
+    pub output_channel: ChannelPressure,
+}
+
+/// An edge carries the output channel of its source step.
+#[derive(Clone, Debug, Serialize)]
+pub struct TopologyEdge {
+    pub from: usize,
+    pub to: usize,
+    pub channel: ChannelPressure,
+}
+
+/// A snapshot of a pipeline, annotated with the current state of its steps and channels.
+#[derive(Clone, Debug, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+}
+
+fn last_processed_version(step_name: &str) -> Option<u64> {
+    let labels = StepMetricLabels {
+        step_name: step_name.to_string(),
+    };
+    let version = LATEST_PROCESSED_VERSION
+        .get_or_create(&labels)
+        .get()
+        .max(LATEST_POLLED_VERSION.get_or_create(&labels).get());
+    // The gauges are 0 until the step has output anything
+    (version > 0).then_some(version as u64)
+}
+
+impl GraphBuilder {
+    /// Takes a snapshot of the graph with step statuses, last processed versions and
+    /// channel fill levels.
+    pub fn topology(&self) -> Topology {
+        let graph = self.graph.lock().unwrap().clone();
+        let node_map = self.node_map.lock().unwrap();
+
+        let nodes = graph
+            .node_weights()
+            .filter_map(|node_id| node_map.get(node_id))
+            .map(|node| TopologyNode {
+                id: node.id,
+                name: node.name.clone(),
+                step_type: node.step_type.clone(),
+                input_type: node.input_type.clone(),
+                output_type: node.output_type.clone(),
+                end_step: node.end_step,
+                status: match &node.join_handle {
+                    None => StepStatus::Pending,
+                    Some(join_handle) if join_handle.is_finished() => StepStatus::Finished,
+                    Some(_) => StepStatus::Running,
+                },
+                last_processed_version: last_processed_version(&node.name),
+                output_channel: ChannelPressure::of(&node.name),
+            })
+            .collect();
+
+        let edges = graph
+            .raw_edges()
+            .iter()
+            .map(|edge| {
+                let from = graph[edge.source()];
+                let to = graph[edge.target()];
+                let channel = node_map
+                    .get(&from)
+                    .map(|node| ChannelPressure::of(&node.name))
+                    .unwrap_or(ChannelPressure {
+                        len: 0,
+                        capacity: 0,
+                    });
+                TopologyEdge { from, to, channel }
+            })
+            .collect();
+
+        Topology { nodes, edges }
+    }
+
+    /// Renders the graph as a Mermaid flowchart. Full channels are drawn as thick edges, so
+    /// the bottleneck step is the one right after the last thick edge.
+    pub fn mermaid(&self) -> String {
+        let topology = self.topology();
+        let mut mermaid = String::from("flowchart TD\n");
+        for node in &topology.nodes {
+            let version = node
+                .last_processed_version
+                .map(|version| format!("<br/>version {}", version))
+                .unwrap_or_default();
+            mermaid.push_str(&format!(
+                "    step{}[\"{}<br/>{:?}{}\"]\n",
+                node.id,
+                node.name.replace('"', "'"),
+                node.status,
+                version,
+            ));
+        }
+        for edge in &topology.edges {
+            let arrow = if edge.channel.is_full() { "==>" } else { "-->" };
+            mermaid.push_str(&format!(
+                "    step{} {}|{}/{}| step{}\n",
+                edge.from, arrow, edge.channel.len, edge.channel.capacity, edge.to,
+            ));
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::ProcessorBuilder, test::steps::pass_through_step::PassThroughStep,
+        traits::IntoRunnableStep, types::transaction_context::TransactionContext,
+    };
+    use instrumented_channel::instrumented_bounded_channel;
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_topology_annotates_nodes_and_edges() {
+        let (input_sender, input_receiver) = instrumented_bounded_channel("input", 1);
+        let first_step = PassThroughStep::<usize>::new_named("TopologyFirstStep".to_string());
+        let second_step = PassThroughStep::<usize>::new_named("TopologySecondStep".to_string());
+
+        let (pb, output_receiver) = ProcessorBuilder::new_with_runnable_input_receiver_first_step(
+            first_step
+                .into_runnable_step()
+                .add_input_receiver(input_receiver),
+        )
+        .connect_to(second_step.into_runnable_step(), 5)
+        .end_and_return_output_receiver(5);
+
+        input_sender
+            .send(TransactionContext {
+                data: 1,
+                metadata: Default::default(),
+            })
+            .await
+            .unwrap();
+        output_receiver.recv().await.unwrap();
+
+        let topology = pb.graph.topology();
+        assert_eq!(topology.nodes.len(), 2);
+        assert_eq!(topology.nodes[0].name, "TopologyFirstStep");
+        assert_eq!(topology.nodes[0].status, StepStatus::Running);
+        assert_eq!(topology.nodes[0].output_channel.capacity, 5);
+        assert!(topology.nodes[1].end_step);
+        assert_eq!(topology.edges.len(), 1);
+        assert_eq!(topology.edges[0].from, 0);
+        assert_eq!(topology.edges[0].to, 1);
+
+        assert!(live_graphs()
+            .iter()
+            .any(|graph| Arc::ptr_eq(&graph.graph, &pb.graph.graph)));
+
+        let mermaid = pb.graph.mermaid();
+        assert!(mermaid.starts_with("flowchart TD"));
+        assert!(mermaid.contains("step0 -->|0/5| step1"));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_live_graphs_outlive_dropped_builder() {
+        let (input_sender, input_receiver) = instrumented_bounded_channel("input", 1);
+        let step = PassThroughStep::<usize>::new_named("DroppedBuilderStep".to_string());
+
+        // The builder is dropped right away, like in most processors
+        let (_, output_receiver) = ProcessorBuilder::new_with_runnable_input_receiver_first_step(
+            step.into_runnable_step().add_input_receiver(input_receiver),
+        )
+        .end_and_return_output_receiver(5);
+        let is_live = || {
+            live_graphs().iter().any(|graph| {
+                graph
+                    .topology()
+                    .nodes
+                    .iter()
+                    .any(|node| node.name == "DroppedBuilderStep")
+            })
+        };
+        assert!(is_live());
+
+        // Once its steps have finished, the graph is released
+        drop(input_sender);
+        assert!(output_receiver.recv().await.is_err());
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while is_live() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
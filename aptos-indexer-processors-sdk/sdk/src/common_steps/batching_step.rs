@@ -0,0 +1,321 @@
+use crate::{
+    traits::{
+        pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
+    },
+    types::transaction_context::{TransactionContext, TransactionMetadata},
+    utils::errors::ProcessorError,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Config for BatchingStep. A buffered batch is released as soon as any one of the
+/// limits is reached.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchingConfig {
+    /// Maximum number of rows in a released batch.
+    #[serde(default = "BatchingConfig::default_max_rows")]
+    pub max_rows: usize,
+    /// Maximum size of a released batch, as reported by `TransactionMetadata::total_size_in_bytes`.
+    #[serde(default = "BatchingConfig::default_max_bytes")]
+    pub max_bytes: u64,
+    /// Maximum time the oldest buffered item may wait before the batch is released.
+    #[serde(default = "BatchingConfig::default_max_latency_ms")]
+    pub max_latency_ms: u64,
+}
+
+impl BatchingConfig {
+    /// Defaults to 10,000 rows.
+    pub const fn default_max_rows() -> usize {
+        10_000
+    }
+
+    /// Defaults to 10 MB.
+    pub const fn default_max_bytes() -> u64 {
+        10_000_000
+    }
+
+    /// Defaults to 500 milliseconds.
+    pub const fn default_max_latency_ms() -> u64 {
+        500
+    }
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: Self::default_max_rows(),
+            max_bytes: Self::default_max_bytes(),
+            max_latency_ms: Self::default_max_latency_ms(),
+        }
+    }
+}
+
+#[allow(clippy::too_long_first_doc_paragraph)]
+/// BatchingStep merges consecutive `TransactionContext<Vec<T>>` items into larger batches,
+/// so that many small gRPC batches turn into fewer, DB-sized writes.
+///
+/// Items are concatenated until the buffered batch reaches `max_rows` or `max_bytes`, or
+/// until the oldest buffered item has waited `max_latency_ms`. Only batches with contiguous
+/// version ranges are merged; if an item does not start right after the buffered batch, the
+/// buffer is released first. The merged metadata spans the first item's start version and
+/// timestamp to the last item's end version and timestamp, and sums the sizes. An item that
+/// reaches a limit on its own is released as its own batch, right after the buffered batch.
+///
+/// Important: this step assumes ordered transactions. Please use the `OrderByVersionStep`
+/// before this step if the transactions are not ordered.
+pub struct BatchingStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: Send + 'static,
+{
+    config: BatchingConfig,
+    buffer: Option<TransactionContext<Vec<T>>>,
+    // When the oldest item currently in the buffer was received.
+    buffer_started_at: Option<Instant>,
+    // Batches released in order. `process` returns one batch at a time, so a second batch,
+    // e.g. an oversized item released with the buffer, is sent at the next poll.
+    released: VecDeque<TransactionContext<Vec<T>>>,
+}
+
+impl<T> BatchingStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: Send + 'static,
+{
+    pub fn new(config: BatchingConfig) -> Self {
+        Self {
+            config,
+            buffer: None,
+            buffer_started_at: None,
+            released: VecDeque::new(),
+        }
+    }
+
+    fn is_full(&self, batch: &TransactionContext<Vec<T>>) -> bool {
+        batch.data.len() >= self.config.max_rows
+            || batch.metadata.total_size_in_bytes >= self.config.max_bytes
+    }
+
+    fn would_overflow(
+        &self,
+        buffer: &TransactionContext<Vec<T>>,
+        item: &TransactionContext<Vec<T>>,
+    ) -> bool {
+        buffer.data.len() + item.data.len() > self.config.max_rows
+            || buffer.metadata.total_size_in_bytes + item.metadata.total_size_in_bytes
+                > self.config.max_bytes
+    }
+
+    fn take_buffer(&mut self) -> Option<TransactionContext<Vec<T>>> {
+        self.buffer_started_at = None;
+        self.buffer.take()
+    }
+
+    fn start_buffer(&mut self, item: TransactionContext<Vec<T>>) {
+        self.buffer = Some(item);
+        self.buffer_started_at = Some(Instant::now());
+    }
+}
+
+/// Appends `next` to `batch`, extending the version range and summing the sizes.
+fn merge_batches<T>(batch: &mut TransactionContext<Vec<T>>, next: TransactionContext<Vec<T>>) {
+    let TransactionContext { data, metadata } = next;
+    batch.data.extend(data);
+    batch.metadata = TransactionMetadata {
+        start_version: batch.metadata.start_version,
+        end_version: metadata.end_version,
+        start_transaction_timestamp: batch.metadata.start_transaction_timestamp.take(),
+        end_transaction_timestamp: metadata.end_transaction_timestamp,
+        total_size_in_bytes: batch.metadata.total_size_in_bytes + metadata.total_size_in_bytes,
+    };
+}
+
+#[async_trait]
+impl<T> Processable for BatchingStep<T>
+where
+    T: Send + Sync + 'static,
+{
+    type Input = Vec<T>;
+    type Output = Vec<T>;
+    type RunType = PollableAsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<Vec<T>>>, ProcessorError> {
+        match self.buffer.as_ref() {
+            None => self.start_buffer(item),
+            Some(buffer) => {
+                let is_contiguous = buffer.metadata.end_version + 1 == item.metadata.start_version;
+                if !is_contiguous || self.would_overflow(buffer, &item) {
+                    // Release what we have and start a new batch with the current item
+                    let released = self.take_buffer();
+                    self.released.extend(released);
+                    self.start_buffer(item);
+                } else {
+                    merge_batches(self.buffer.as_mut().unwrap(), item);
+                }
+            },
+        }
+
+        // If the buffer is already at a limit, e.g. with an oversized item, release it now
+        if self.buffer.as_ref().is_some_and(|b| self.is_full(b)) {
+            let full = self.take_buffer();
+            self.released.extend(full);
+        }
+        Ok(self.released.pop_front())
+    }
+
+    // Once polling ends, release the remaining items in buffer
+    async fn cleanup(
+        &mut self,
+    ) -> Result<Option<Vec<TransactionContext<Self::Output>>>, ProcessorError> {
+        let buffer = self.take_buffer();
+        self.released.extend(buffer);
+        if self.released.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.released.drain(..).collect()))
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> PollableAsyncStep for BatchingStep<T> {
+    fn poll_interval(&self) -> Duration {
+        // Poll twice per latency window so a batch never waits much longer than the limit.
+        Duration::from_millis((self.config.max_latency_ms / 2).max(1))
+    }
+
+    async fn poll(&mut self) -> Result<Option<Vec<TransactionContext<Vec<T>>>>, ProcessorError> {
+        let max_latency = Duration::from_millis(self.config.max_latency_ms);
+        let is_due = self
+            .buffer_started_at
+            .is_some_and(|started_at| started_at.elapsed() >= max_latency);
+        if is_due {
+            let buffer = self.take_buffer();
+            self.released.extend(buffer);
+        }
+        if self.released.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.released.drain(..).collect()))
+    }
+}
+
+impl<T: Send + 'static> NamedStep for BatchingStep<T> {
+    fn name(&self) -> String {
+        format!("BatchingStep: {}", std::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_batch(start_version: u64, num_rows: usize) -> TransactionContext<Vec<u64>> {
+        TransactionContext {
+            data: (start_version..start_version + num_rows as u64).collect(),
+            metadata: TransactionMetadata {
+                start_version,
+                end_version: start_version + num_rows as u64 - 1,
+                start_transaction_timestamp: None,
+                end_transaction_timestamp: None,
+                total_size_in_bytes: num_rows as u64 * 10,
+            },
+        }
+    }
+
+    fn make_step(max_rows: usize, max_bytes: u64, max_latency_ms: u64) -> BatchingStep<u64> {
+        BatchingStep::new(BatchingConfig {
+            max_rows,
+            max_bytes,
+            max_latency_ms,
+        })
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_batching_step_merges_until_row_limit() {
+        let mut step = make_step(10, u64::MAX, 60_000);
+
+        assert!(step.process(make_batch(0, 4)).await.unwrap().is_none());
+        assert!(step.process(make_batch(4, 4)).await.unwrap().is_none());
+        // The third batch would overflow the row limit, so the first two are released
+        let released = step.process(make_batch(8, 4)).await.unwrap().unwrap();
+
+        assert_eq!(released.data, (0..8).collect::<Vec<u64>>());
+        assert_eq!(released.metadata.start_version, 0);
+        assert_eq!(released.metadata.end_version, 7);
+        assert_eq!(released.metadata.total_size_in_bytes, 80);
+
+        let remaining = step.cleanup().await.unwrap().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].metadata.start_version, 8);
+        assert_eq!(remaining[0].metadata.end_version, 11);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_batching_step_releases_on_byte_limit() {
+        let mut step = make_step(usize::MAX, 50, 60_000);
+
+        assert!(step.process(make_batch(0, 2)).await.unwrap().is_none());
+        // 20 + 30 bytes reaches the limit exactly, so the merged batch is released
+        let released = step.process(make_batch(2, 3)).await.unwrap().unwrap();
+        assert_eq!(released.data.len(), 5);
+        assert_eq!(released.metadata.total_size_in_bytes, 50);
+        assert!(step.cleanup().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_batching_step_releases_oversized_item_on_its_own() {
+        let mut step = make_step(10, u64::MAX, 60_000);
+
+        assert!(step.process(make_batch(0, 4)).await.unwrap().is_none());
+        // The oversized item releases the buffer, and is released on its own right after
+        let released = step.process(make_batch(4, 12)).await.unwrap().unwrap();
+        assert_eq!(released.data, (0..4).collect::<Vec<u64>>());
+        let oversized = step.poll().await.unwrap().unwrap();
+        assert_eq!(oversized.len(), 1);
+        assert_eq!(oversized[0].data, (4..16).collect::<Vec<u64>>());
+
+        // Without a buffer, an oversized item is released by `process` directly
+        let oversized = step.process(make_batch(16, 12)).await.unwrap().unwrap();
+        assert_eq!(oversized.metadata.start_version, 16);
+        assert!(step.poll().await.unwrap().is_none());
+        assert!(step.cleanup().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_batching_step_does_not_merge_across_gaps() {
+        let mut step = make_step(100, u64::MAX, 60_000);
+
+        assert!(step.process(make_batch(0, 2)).await.unwrap().is_none());
+        let released = step.process(make_batch(10, 2)).await.unwrap().unwrap();
+        assert_eq!(released.metadata.start_version, 0);
+        assert_eq!(released.metadata.end_version, 1);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_batching_step_releases_on_latency() {
+        let mut step = make_step(100, u64::MAX, 50);
+
+        assert!(step.process(make_batch(0, 2)).await.unwrap().is_none());
+        assert!(step.poll().await.unwrap().is_none());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let released = step.poll().await.unwrap().unwrap();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].data, vec![0, 1]);
+        assert!(step.poll().await.unwrap().is_none());
+    }
+}
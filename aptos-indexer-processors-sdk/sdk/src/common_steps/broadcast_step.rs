@@ -0,0 +1,111 @@
+use crate::{
+    traits::{async_step::AsyncRunType, AsyncStep, NamedStep, Processable},
+    types::transaction_context::TransactionContext,
+    utils::{
+        errors::ProcessorError,
+        stream_hub::{enable_stream_hub, StreamConfig, StreamEvent, StreamHub},
+    },
+};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{marker::PhantomData, sync::Arc};
+
+/// A row pushed to the clients of the stream endpoints.
+pub trait StreamRecord: Serialize + Send + Sync + 'static {
+    /// Clients can filter on it, e.g. `ticket_purchase`.
+    fn event_type(&self) -> &str;
+
+    /// Clients can filter on it, e.g. the address of the module that emitted the event.
+    fn module_address(&self) -> Option<&str> {
+        None
+    }
+
+    /// The version of the transaction and the index of the event the row comes from, which
+    /// clients resume after.
+    fn idempotency_key(&self) -> (i64, i64);
+}
+
+/// Pushes the rows of each batch to the clients of `/stream/ws` and `/stream/sse` on the health
+/// server, and passes the batch on. Place it right after the step that writes the rows, so that
+/// clients only see committed rows. Creating the step turns the endpoints on.
+///
+/// The last `max_buffered_events` rows are kept, so that clients can resume from a version
+/// after a disconnect. Older rows must be read from the database.
+pub struct BroadcastStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: StreamRecord,
+{
+    hub: Arc<StreamHub>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> BroadcastStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: StreamRecord,
+{
+    pub fn new(config: &StreamConfig) -> Self {
+        Self {
+            hub: enable_stream_hub(config),
+            phantom: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Processable for BroadcastStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: StreamRecord,
+{
+    type Input = Vec<T>;
+    type Output = Vec<T>;
+    type RunType = AsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<Vec<T>>>, ProcessorError> {
+        let events = item
+            .data
+            .iter()
+            .map(|record| {
+                let (transaction_version, event_index) = record.idempotency_key();
+                let data =
+                    serde_json::to_value(record).map_err(|e| ProcessorError::ProcessError {
+                        message: format!(
+                            "Could not serialize the row of version {} and event {}: {:#}",
+                            transaction_version, event_index, e
+                        ),
+                    })?;
+                Ok(StreamEvent {
+                    transaction_version,
+                    event_index,
+                    event_type: record.event_type().to_string(),
+                    module_address: record.module_address().map(str::to_string),
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, ProcessorError>>()?;
+        self.hub.publish(item.metadata.start_version as i64, events);
+        Ok(Some(item))
+    }
+}
+
+impl<T> AsyncStep for BroadcastStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: StreamRecord,
+{
+}
+
+impl<T> NamedStep for BroadcastStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: StreamRecord,
+{
+    fn name(&self) -> String {
+        format!("BroadcastStep: {}", std::any::type_name::<T>())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    common_steps::queue_sink_step::{QueueMessage, QueueSink},
+    utils::errors::ProcessorError,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use rdkafka::{
+    message::{Header, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+    ClientConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Header of the `idempotency_key` of a message.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency_key";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// Comma-separated `host:port` of the brokers.
+    pub bootstrap_servers: String,
+    pub topic: String,
+    /// How long to wait for the broker to acknowledge a message before failing.
+    #[serde(default = "KafkaConfig::default_delivery_timeout_ms")]
+    pub delivery_timeout_ms: u64,
+    /// Other librdkafka producer properties, e.g. `security.protocol` or `compression.type`.
+    #[serde(default)]
+    pub producer_properties: HashMap<String, String>,
+}
+
+impl KafkaConfig {
+    pub const fn default_delivery_timeout_ms() -> u64 {
+        30_000
+    }
+}
+
+/// A `QueueSink` for a Kafka topic. The producer is idempotent and waits for all in-sync
+/// replicas, so retries within a run neither duplicate nor reorder messages of a partition.
+pub struct KafkaSink {
+    topic: String,
+    producer: FutureProducer,
+}
+
+impl KafkaSink {
+    pub fn new(config: &KafkaConfig) -> Result<Self> {
+        let mut client_config = ClientConfig::new();
+        for (key, value) in &config.producer_properties {
+            client_config.set(key, value);
+        }
+        let producer = client_config
+            .set("bootstrap.servers", &config.bootstrap_servers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .set("message.timeout.ms", config.delivery_timeout_ms.to_string())
+            .create()
+            .context("Failed to create the Kafka producer")?;
+        Ok(Self {
+            topic: config.topic.clone(),
+            producer,
+        })
+    }
+}
+
+#[async_trait]
+impl QueueSink for KafkaSink {
+    async fn send(&self, messages: Vec<QueueMessage>) -> Result<(), ProcessorError> {
+        let deliveries = messages.iter().map(|message| {
+            let mut record = FutureRecord::<str, Vec<u8>>::to(&self.topic)
+                .payload(&message.payload)
+                .headers(OwnedHeaders::new().insert(Header {
+                    key: IDEMPOTENCY_KEY_HEADER,
+                    value: Some(&message.idempotency_key),
+                }));
+            if let Some(key) = &message.key {
+                record = record.key(key.as_str());
+            }
+            self.producer.send(record, Timeout::Never)
+        });
+        // The producer queues the messages in order, and they are acknowledged as they come
+        let deliveries = futures::future::join_all(deliveries).await;
+        for (message, delivery) in messages.iter().zip(deliveries) {
+            delivery.map_err(|(e, _)| ProcessorError::ProcessError {
+                message: format!(
+                    "Kafka did not acknowledge the message {}: {:#}",
+                    message.idempotency_key, e
+                ),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing_framework"))]
+mod tests {
+    use super::*;
+    use crate::{
+        common_steps::{QueueKeying, QueueRecord, QueueSinkStep},
+        testing_framework::kafka::KafkaTestBroker,
+        traits::Processable,
+        types::transaction_context::{TransactionContext, TransactionMetadata},
+    };
+    use rdkafka::{
+        consumer::{Consumer, StreamConsumer},
+        message::Headers,
+        Message,
+    };
+    use std::time::Duration;
+
+    #[derive(Serialize)]
+    struct TestEvent {
+        transaction_version: i64,
+        event_index: i64,
+        account_address: String,
+    }
+
+    impl QueueRecord for TestEvent {
+        fn idempotency_key(&self) -> (i64, i64) {
+            (self.transaction_version, self.event_index)
+        }
+    }
+
+    // Needs Docker to run the broker
+    #[ignore]
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_kafka_sink() {
+        let broker = KafkaTestBroker::start().await.unwrap();
+        let config = KafkaConfig {
+            bootstrap_servers: broker.bootstrap_servers(),
+            topic: "events".to_string(),
+            delivery_timeout_ms: KafkaConfig::default_delivery_timeout_ms(),
+            producer_properties: HashMap::new(),
+        };
+        let mut step = QueueSinkStep::new(
+            KafkaSink::new(&config).unwrap(),
+            QueueKeying::Field {
+                name: "account_address".to_string(),
+            },
+        );
+        let batch = TransactionContext {
+            data: (0..3)
+                .map(|event_index| TestEvent {
+                    transaction_version: 42,
+                    event_index,
+                    account_address: "0x1".to_string(),
+                })
+                .collect(),
+            metadata: TransactionMetadata {
+                start_version: 40,
+                end_version: 42,
+                ..Default::default()
+            },
+        };
+        step.process(batch).await.unwrap();
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", broker.bootstrap_servers())
+            .set("group.id", "test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&["events"]).unwrap();
+        let mut idempotency_keys = vec![];
+        for _ in 0..3 {
+            let message = tokio::time::timeout(Duration::from_secs(30), consumer.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(message.key(), Some("0x1".as_bytes()));
+            let header = message.headers().unwrap().get(0);
+            assert_eq!(header.key, IDEMPOTENCY_KEY_HEADER);
+            idempotency_keys.push(String::from_utf8(header.value.unwrap().to_vec()).unwrap());
+        }
+        assert_eq!(idempotency_keys, vec!["42:0", "42:1", "42:2"]);
+    }
+}
@@ -1,16 +1,44 @@
 pub mod arcify_step;
+pub mod batching_step;
+#[cfg(feature = "stream")]
+pub mod broadcast_step;
+#[cfg(feature = "kafka")]
+pub mod kafka_sink;
 pub mod order_by_version_step;
+#[cfg(feature = "parquet")]
+pub mod parquet_sink_step;
+pub mod queue_sink_step;
+pub mod split_step;
+pub mod spool_step;
 pub mod timed_buffer_step;
 pub mod transaction_stream_step;
 pub mod version_tracker_step;
+#[cfg(feature = "webhook")]
+pub mod webhook_sink_step;
 pub mod write_rate_limit_step;
 
 // Re-export the steps
 pub use arcify_step::ArcifyStep;
+pub use batching_step::{BatchingConfig, BatchingStep};
+#[cfg(feature = "stream")]
+pub use broadcast_step::{BroadcastStep, StreamRecord};
+#[cfg(feature = "kafka")]
+pub use kafka_sink::{KafkaConfig, KafkaSink};
 pub use order_by_version_step::OrderByVersionStep;
+#[cfg(feature = "parquet")]
+pub use parquet_sink_step::{
+    ParquetCompression, ParquetPartitioning, ParquetRow, ParquetSinkConfig, ParquetSinkStep,
+};
+pub use queue_sink_step::{QueueKeying, QueueMessage, QueueRecord, QueueSink, QueueSinkStep};
+pub use split_step::SplitStep;
+pub use spool_step::{SpoolConfig, SpoolSink, SpoolStep};
 pub use timed_buffer_step::TimedBufferStep;
 pub use transaction_stream_step::TransactionStreamStep;
 pub use version_tracker_step::{
     ProcessorStatusSaver, VersionTrackerStep, DEFAULT_UPDATE_PROCESSOR_STATUS_SECS,
 };
+#[cfg(feature = "webhook")]
+pub use webhook_sink_step::{
+    WebhookConfig, WebhookEndpointConfig, WebhookOutbox, WebhookPayload, WebhookSinkStep,
+};
 pub use write_rate_limit_step::{Sizeable, WriteRateLimitConfig, WriteRateLimitStep};
@@ -0,0 +1,521 @@
+use crate::{
+    aptos_indexer_transaction_stream::utils::time::parse_timestamp,
+    common_steps::ProcessorStatusSaver,
+    traits::{
+        pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
+    },
+    types::transaction_context::{TransactionContext, TransactionMetadata},
+    utils::{
+        checkpoint_store::{CheckpointStore, FileCheckpointStore},
+        errors::ProcessorError,
+    },
+};
+use anyhow::{Context, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::{ArrowError, SchemaRef};
+use async_trait::async_trait;
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+const IN_PROGRESS_EXTENSION: &str = "inprogress";
+const CHECKPOINT_FILE_NAME: &str = "_checkpoint.json";
+
+/// A row that can be written to Parquet.
+pub trait ParquetRow: Sized + Send + Sync + 'static {
+    /// Name of the table, the directory of its files under `ParquetSinkConfig::directory`.
+    const TABLE_NAME: &'static str;
+
+    fn schema() -> SchemaRef;
+
+    /// Converts `rows` to columns of `schema()`.
+    fn to_record_batch(rows: &[Self]) -> Result<RecordBatch, ArrowError>;
+}
+
+/// How the files of a table are split into directories.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ParquetPartitioning {
+    /// One directory per `versions_per_partition` versions, e.g. `versions=0-999999`
+    VersionRange { versions_per_partition: u64 },
+    /// One directory per UTC day of the transactions, e.g. `date=2024-06-01`
+    Date,
+}
+
+impl Default for ParquetPartitioning {
+    fn default() -> Self {
+        Self::VersionRange {
+            versions_per_partition: 1_000_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    #[default]
+    Zstd,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetSinkConfig {
+    /// Root directory of the tables. Must not be shared with another processor.
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub partitioning: ParquetPartitioning,
+    /// Maximum number of rows of a row group.
+    #[serde(default = "ParquetSinkConfig::default_row_group_size")]
+    pub row_group_size: usize,
+    #[serde(default)]
+    pub compression: ParquetCompression,
+    /// A file is finalized once it has been open this long, even if its partition isn't over,
+    /// so that slow tables still get exported.
+    #[serde(default = "ParquetSinkConfig::default_max_file_age_secs")]
+    pub max_file_age_secs: u64,
+}
+
+impl ParquetSinkConfig {
+    pub const fn default_row_group_size() -> usize {
+        128 * 1024
+    }
+
+    pub const fn default_max_file_age_secs() -> u64 {
+        600
+    }
+}
+
+/// The file being written, named after the versions of its batches once finalized.
+struct OpenFile {
+    partition: String,
+    in_progress_path: PathBuf,
+    writer: ArrowWriter<std::fs::File>,
+    metadata: TransactionMetadata,
+    opened_at: Instant,
+}
+
+fn to_processor_error(e: impl std::fmt::Display) -> ProcessorError {
+    ProcessorError::ProcessError {
+        message: format!("Parquet sink error: {:#}", e),
+    }
+}
+
+/// Lists the files of a table, including those of its partitions.
+fn list_files(table_directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(table_directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            for entry in std::fs::read_dir(&path)? {
+                files.push(entry?.path());
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the last version of a finalized file, named `<start_version>-<end_version>.parquet`.
+fn finalized_end_version(path: &Path) -> Option<u64> {
+    if path.extension()? != "parquet" {
+        return None;
+    }
+    let (_, end_version) = path.file_stem()?.to_str()?.split_once('-')?;
+    end_version.parse().ok()
+}
+
+/// Writes batches of rows to Parquet files under `<directory>/<TABLE_NAME>/<partition>/`. A
+/// file is written to a temporary path, then synced and renamed to
+/// `<start_version>-<end_version>.parquet` when its partition is over, it gets too old, or the
+/// processor stops, so readers never see partial files.
+///
+/// The step keeps its own checkpoint in `_checkpoint.json` next to the files, advanced after
+/// every finalized file. On start, unfinalized files are removed, and the transaction stream
+/// should start from `starting_version`, so that a restart neither duplicates nor drops rows.
+/// Batches must arrive in order, e.g. after an `OrderByVersionStep`, and a batch goes into the
+/// partition of its first version. The step outputs the version range of every finalized file,
+/// e.g. for a `VersionTrackerStep`.
+pub struct ParquetSinkStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: ParquetRow,
+{
+    config: ParquetSinkConfig,
+    table_directory: PathBuf,
+    checkpoint_store: FileCheckpointStore,
+    last_checkpointed_version: Option<u64>,
+    open_file: Option<OpenFile>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> ParquetSinkStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: ParquetRow,
+{
+    pub async fn new(config: ParquetSinkConfig) -> Result<Self> {
+        let table_directory = config.directory.join(T::TABLE_NAME);
+        std::fs::create_dir_all(&table_directory)
+            .with_context(|| format!("Could not create {:?}", table_directory))?;
+        let checkpoint_store =
+            FileCheckpointStore::new(table_directory.join(CHECKPOINT_FILE_NAME))?;
+
+        let mut last_finalized_version = None;
+        for path in list_files(&table_directory)? {
+            if path
+                .extension()
+                .is_some_and(|ext| ext == IN_PROGRESS_EXTENSION)
+            {
+                warn!(path = ?path, "Removing unfinalized Parquet file");
+                std::fs::remove_file(path)?;
+            } else if let Some(end_version) = finalized_end_version(&path) {
+                last_finalized_version = last_finalized_version.max(Some(end_version));
+            }
+        }
+        // A file may have been finalized right before a crash, without its checkpoint
+        let last_checkpointed_version = checkpoint_store.load_checkpoint().await?;
+        if last_finalized_version > last_checkpointed_version {
+            if let Some(version) = last_finalized_version {
+                checkpoint_store.reset_processor_status(version).await?;
+            }
+        }
+
+        Ok(Self {
+            config,
+            table_directory,
+            checkpoint_store,
+            last_checkpointed_version: last_checkpointed_version.max(last_finalized_version),
+            open_file: None,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The version to start the transaction stream from, right after the last finalized file,
+    /// or `None` if nothing was written yet.
+    pub fn starting_version(&self) -> Option<u64> {
+        self.last_checkpointed_version.map(|version| version + 1)
+    }
+
+    fn partition_of(&self, metadata: &TransactionMetadata) -> Result<String, ProcessorError> {
+        match self.config.partitioning {
+            ParquetPartitioning::VersionRange {
+                versions_per_partition,
+            } => {
+                let versions_per_partition = versions_per_partition.max(1);
+                let start =
+                    metadata.start_version / versions_per_partition * versions_per_partition;
+                Ok(format!(
+                    "versions={}-{}",
+                    start,
+                    start + versions_per_partition - 1
+                ))
+            },
+            ParquetPartitioning::Date => {
+                let timestamp = metadata
+                    .start_transaction_timestamp
+                    .as_ref()
+                    .ok_or_else(|| {
+                        to_processor_error(format!(
+                            "batch starting at version {} has no timestamp to partition by",
+                            metadata.start_version
+                        ))
+                    })?;
+                let date = parse_timestamp(timestamp, metadata.start_version as i64).date_naive();
+                Ok(format!("date={}", date.format("%Y-%m-%d")))
+            },
+        }
+    }
+
+    fn open(
+        &self,
+        partition: String,
+        metadata: TransactionMetadata,
+    ) -> Result<OpenFile, ProcessorError> {
+        let partition_directory = self.table_directory.join(&partition);
+        std::fs::create_dir_all(&partition_directory).map_err(to_processor_error)?;
+        let in_progress_path = partition_directory.join(format!(
+            "{:020}.parquet.{}",
+            metadata.start_version, IN_PROGRESS_EXTENSION
+        ));
+        let file = std::fs::File::create(&in_progress_path).map_err(to_processor_error)?;
+        let compression = match self.config.compression {
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(self.config.row_group_size)
+            .set_compression(compression)
+            .build();
+        let writer = ArrowWriter::try_new(file, T::schema(), Some(properties))
+            .map_err(to_processor_error)?;
+        Ok(OpenFile {
+            partition,
+            in_progress_path,
+            writer,
+            metadata: TransactionMetadata {
+                total_size_in_bytes: 0,
+                ..metadata
+            },
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Closes the open file, moves it to its final name and advances the checkpoint.
+    async fn finalize(&mut self) -> Result<Option<TransactionContext<()>>, ProcessorError> {
+        let Some(open_file) = self.open_file.take() else {
+            return Ok(None);
+        };
+        let file = open_file.writer.into_inner().map_err(to_processor_error)?;
+        file.sync_all().map_err(to_processor_error)?;
+        let partition_directory = self.table_directory.join(&open_file.partition);
+        let path = partition_directory.join(format!(
+            "{:020}-{:020}.parquet",
+            open_file.metadata.start_version, open_file.metadata.end_version
+        ));
+        std::fs::rename(&open_file.in_progress_path, &path).map_err(to_processor_error)?;
+        // Sync the directory too, so that the rename survives a crash
+        #[cfg(unix)]
+        std::fs::File::open(&partition_directory)
+            .and_then(|directory| directory.sync_all())
+            .map_err(to_processor_error)?;
+
+        let finalized = TransactionContext {
+            data: (),
+            metadata: open_file.metadata,
+        };
+        self.checkpoint_store
+            .save_processor_status(&finalized)
+            .await?;
+        self.last_checkpointed_version = Some(finalized.metadata.end_version);
+        info!(
+            step_name = self.name(),
+            path = ?path,
+            start_version = finalized.metadata.start_version,
+            end_version = finalized.metadata.end_version,
+            "Finalized Parquet file"
+        );
+        Ok(Some(finalized))
+    }
+}
+
+#[async_trait]
+impl<T> Processable for ParquetSinkStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: ParquetRow,
+{
+    type Input = Vec<T>;
+    type Output = ();
+    type RunType = PollableAsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<()>>, ProcessorError> {
+        if let Some(checkpointed_version) = self.last_checkpointed_version {
+            // Already in a finalized file, e.g. when the stream didn't start from
+            // `starting_version`
+            if item.metadata.end_version <= checkpointed_version {
+                return Ok(None);
+            }
+            if item.metadata.start_version <= checkpointed_version {
+                return Err(to_processor_error(format!(
+                    "batch {}-{} overlaps the finalized files up to version {}, start the stream from `starting_version`",
+                    item.metadata.start_version, item.metadata.end_version, checkpointed_version
+                )));
+            }
+        }
+
+        let partition = self.partition_of(&item.metadata)?;
+        let finalized = match &self.open_file {
+            Some(open_file) if open_file.partition != partition => self.finalize().await?,
+            _ => None,
+        };
+        let open_file = match self.open_file.take() {
+            Some(open_file) => open_file,
+            None => self.open(partition, item.metadata.clone())?,
+        };
+        let open_file = self.open_file.insert(open_file);
+        if !item.data.is_empty() {
+            let batch = T::to_record_batch(&item.data).map_err(to_processor_error)?;
+            open_file.writer.write(&batch).map_err(to_processor_error)?;
+        }
+        open_file.metadata.end_version = item.metadata.end_version;
+        open_file.metadata.end_transaction_timestamp = item.metadata.end_transaction_timestamp;
+        open_file.metadata.total_size_in_bytes += item.metadata.total_size_in_bytes;
+        Ok(finalized)
+    }
+
+    async fn cleanup(
+        &mut self,
+    ) -> Result<Option<Vec<TransactionContext<Self::Output>>>, ProcessorError> {
+        Ok(Some(self.finalize().await?.into_iter().collect()))
+    }
+}
+
+#[async_trait]
+impl<T> PollableAsyncStep for ParquetSinkStep<T>
+where
+    Self: Sized + Send + Sync + 'static,
+    T: ParquetRow,
+{
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    async fn poll(&mut self) -> Result<Option<Vec<TransactionContext<()>>>, ProcessorError> {
+        let max_file_age = Duration::from_secs(self.config.max_file_age_secs);
+        let is_too_old = self
+            .open_file
+            .as_ref()
+            .is_some_and(|open_file| open_file.opened_at.elapsed() >= max_file_age);
+        if !is_too_old {
+            return Ok(None);
+        }
+        Ok(Some(self.finalize().await?.into_iter().collect()))
+    }
+}
+
+impl<T> NamedStep for ParquetSinkStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: ParquetRow,
+{
+    fn name(&self) -> String {
+        format!("ParquetSinkStep: {}", T::TABLE_NAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Int64Array, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::sync::Arc;
+
+    struct TestRow {
+        transaction_version: i64,
+        name: String,
+    }
+
+    impl ParquetRow for TestRow {
+        const TABLE_NAME: &'static str = "test_events";
+
+        fn schema() -> SchemaRef {
+            Arc::new(Schema::new(vec![
+                Field::new("transaction_version", DataType::Int64, false),
+                Field::new("name", DataType::Utf8, false),
+            ]))
+        }
+
+        fn to_record_batch(rows: &[Self]) -> Result<RecordBatch, ArrowError> {
+            RecordBatch::try_new(
+                Self::schema(),
+                vec![
+                    Arc::new(Int64Array::from_iter_values(
+                        rows.iter().map(|row| row.transaction_version),
+                    )),
+                    Arc::new(StringArray::from_iter_values(
+                        rows.iter().map(|row| row.name.as_str()),
+                    )),
+                ],
+            )
+        }
+    }
+
+    fn batch(start_version: u64, end_version: u64) -> TransactionContext<Vec<TestRow>> {
+        TransactionContext {
+            data: (start_version..=end_version)
+                .map(|version| TestRow {
+                    transaction_version: version as i64,
+                    name: format!("event {}", version),
+                })
+                .collect(),
+            metadata: TransactionMetadata {
+                start_version,
+                end_version,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn num_rows(path: &Path) -> usize {
+        ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_parquet_sink_step() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = ParquetSinkConfig {
+            directory: directory.path().to_path_buf(),
+            partitioning: ParquetPartitioning::VersionRange {
+                versions_per_partition: 10,
+            },
+            row_group_size: 4,
+            compression: ParquetCompression::default(),
+            max_file_age_secs: ParquetSinkConfig::default_max_file_age_secs(),
+        };
+        let mut step = ParquetSinkStep::<TestRow>::new(config.clone())
+            .await
+            .unwrap();
+        assert_eq!(step.starting_version(), None);
+
+        assert!(step.process(batch(0, 4)).await.unwrap().is_none());
+        assert!(step.process(batch(5, 9)).await.unwrap().is_none());
+        // The next partition finalizes the file of the previous one
+        let finalized = step.process(batch(10, 12)).await.unwrap().unwrap();
+        assert_eq!(
+            (
+                finalized.metadata.start_version,
+                finalized.metadata.end_version
+            ),
+            (0, 9)
+        );
+        let table_directory = directory.path().join("test_events");
+        let path = table_directory
+            .join("versions=0-9")
+            .join("00000000000000000000-00000000000000000009.parquet");
+        assert_eq!(num_rows(&path), 10);
+
+        // Without cleanup, e.g. after a crash, the open file is dropped and replayed
+        drop(step);
+        let mut step = ParquetSinkStep::<TestRow>::new(config.clone())
+            .await
+            .unwrap();
+        assert_eq!(step.starting_version(), Some(10));
+        assert_eq!(list_files(&table_directory).unwrap().len(), 2);
+        assert!(step.process(batch(0, 9)).await.unwrap().is_none());
+        assert!(step.process(batch(8, 12)).await.is_err());
+        assert!(step.process(batch(10, 12)).await.unwrap().is_none());
+        let finalized = step.cleanup().await.unwrap().unwrap();
+        assert_eq!(finalized[0].metadata.end_version, 12);
+        assert_eq!(
+            num_rows(
+                &table_directory
+                    .join("versions=10-19")
+                    .join("00000000000000000010-00000000000000000012.parquet")
+            ),
+            3
+        );
+    }
+}
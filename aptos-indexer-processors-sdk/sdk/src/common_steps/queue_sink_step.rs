@@ -0,0 +1,249 @@
+use crate::{
+    traits::{async_step::AsyncRunType, AsyncStep, NamedStep, Processable},
+    types::transaction_context::TransactionContext,
+    utils::errors::ProcessorError,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// A record published to a message queue.
+pub trait QueueRecord: Serialize + Send + Sync + 'static {
+    /// The version of the transaction and the index of the event the record comes from, which
+    /// identify the record across restarts.
+    fn idempotency_key(&self) -> (i64, i64);
+}
+
+/// What a message is keyed by, which decides its partition, and so the order of delivery.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum QueueKeying {
+    /// No key, the messages are spread over the partitions
+    #[default]
+    None,
+    TransactionVersion,
+    /// A top-level field of the serialized record, e.g. `account_address`
+    Field {
+        name: String,
+    },
+}
+
+/// A message as sent to the queue: the record serialized as JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueueMessage {
+    pub key: Option<String>,
+    /// `<transaction_version>:<event_index>`, for consumers to drop redelivered messages
+    pub idempotency_key: String,
+    pub payload: Vec<u8>,
+}
+
+/// Where `QueueSinkStep` publishes messages, e.g. a Kafka topic.
+#[async_trait]
+pub trait QueueSink: Send + Sync {
+    /// Sends the messages in order, and returns once the broker acknowledged all of them.
+    async fn send(&self, messages: Vec<QueueMessage>) -> Result<(), ProcessorError>;
+}
+
+/// Publishes each record of a batch as a message to a `QueueSink`. A batch is passed on only
+/// once the broker acknowledged all of its messages, so a `VersionTrackerStep` after this step
+/// never checkpoints unsent records.
+///
+/// After a restart, the records since the last checkpoint are sent again. Every message carries
+/// the `idempotency_key` of its record, so that consumers can drop the duplicates.
+pub struct QueueSinkStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: QueueRecord,
+    S: QueueSink + 'static,
+{
+    sink: S,
+    keying: QueueKeying,
+    phantom: PhantomData<T>,
+}
+
+impl<T, S> QueueSinkStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: QueueRecord,
+    S: QueueSink + 'static,
+{
+    pub fn new(sink: S, keying: QueueKeying) -> Self {
+        Self {
+            sink,
+            keying,
+            phantom: PhantomData,
+        }
+    }
+
+    fn to_message(&self, record: &T) -> Result<QueueMessage, ProcessorError> {
+        let (transaction_version, event_index) = record.idempotency_key();
+        let to_processor_error = |e: serde_json::Error| ProcessorError::ProcessError {
+            message: format!(
+                "Could not serialize the record of version {} and event {}: {:#}",
+                transaction_version, event_index, e
+            ),
+        };
+        let key = match &self.keying {
+            QueueKeying::None => None,
+            QueueKeying::TransactionVersion => Some(transaction_version.to_string()),
+            QueueKeying::Field { name } => {
+                match serde_json::to_value(record)
+                    .map_err(to_processor_error)?
+                    .get(name)
+                {
+                    Some(serde_json::Value::String(value)) => Some(value.clone()),
+                    Some(value) => Some(value.to_string()),
+                    None => {
+                        return Err(ProcessorError::ProcessError {
+                            message: format!("Records have no `{}` field to key by", name),
+                        })
+                    },
+                }
+            },
+        };
+        Ok(QueueMessage {
+            key,
+            idempotency_key: format!("{}:{}", transaction_version, event_index),
+            payload: serde_json::to_vec(record).map_err(to_processor_error)?,
+        })
+    }
+}
+
+#[async_trait]
+impl<T, S> Processable for QueueSinkStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: QueueRecord,
+    S: QueueSink + 'static,
+{
+    type Input = Vec<T>;
+    type Output = ();
+    type RunType = AsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<()>>, ProcessorError> {
+        let messages = item
+            .data
+            .iter()
+            .map(|record| self.to_message(record))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !messages.is_empty() {
+            self.sink.send(messages).await?;
+        }
+        Ok(Some(TransactionContext {
+            data: (),
+            metadata: item.metadata,
+        }))
+    }
+}
+
+impl<T, S> AsyncStep for QueueSinkStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: QueueRecord,
+    S: QueueSink + 'static,
+{
+}
+
+impl<T, S> NamedStep for QueueSinkStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: QueueRecord,
+    S: QueueSink + 'static,
+{
+    fn name(&self) -> String {
+        format!("QueueSinkStep: {}", std::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transaction_context::TransactionMetadata;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Serialize)]
+    struct TestEvent {
+        transaction_version: i64,
+        event_index: i64,
+        account_address: String,
+    }
+
+    impl QueueRecord for TestEvent {
+        fn idempotency_key(&self) -> (i64, i64) {
+            (self.transaction_version, self.event_index)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestSink {
+        sent: Arc<Mutex<Vec<QueueMessage>>>,
+    }
+
+    #[async_trait]
+    impl QueueSink for TestSink {
+        async fn send(&self, messages: Vec<QueueMessage>) -> Result<(), ProcessorError> {
+            self.sent.lock().unwrap().extend(messages);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_queue_sink_step() {
+        let sink = TestSink::default();
+        let mut step = QueueSinkStep::new(
+            sink.clone(),
+            QueueKeying::Field {
+                name: "account_address".to_string(),
+            },
+        );
+        let batch = TransactionContext {
+            data: vec![
+                TestEvent {
+                    transaction_version: 7,
+                    event_index: 0,
+                    account_address: "0x1".to_string(),
+                },
+                TestEvent {
+                    transaction_version: 7,
+                    event_index: 1,
+                    account_address: "0x2".to_string(),
+                },
+            ],
+            metadata: TransactionMetadata {
+                start_version: 5,
+                end_version: 7,
+                ..Default::default()
+            },
+        };
+        let output = step.process(batch).await.unwrap().unwrap();
+        assert_eq!(output.metadata.end_version, 7);
+
+        let sent = sink.sent.lock().unwrap().clone();
+        assert_eq!(
+            sent.iter()
+                .map(|message| (message.key.as_deref(), message.idempotency_key.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(Some("0x1"), "7:0"), (Some("0x2"), "7:1")]
+        );
+
+        let mut step = QueueSinkStep::<TestEvent, _>::new(
+            sink,
+            QueueKeying::Field {
+                name: "missing".to_string(),
+            },
+        );
+        let batch = TransactionContext {
+            data: vec![TestEvent {
+                transaction_version: 8,
+                event_index: 0,
+                account_address: "0x1".to_string(),
+            }],
+            metadata: TransactionMetadata::default(),
+        };
+        assert!(step.process(batch).await.is_err());
+    }
+}
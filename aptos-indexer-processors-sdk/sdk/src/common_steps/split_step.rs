@@ -0,0 +1,195 @@
+use crate::{
+    traits::{
+        pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
+    },
+    types::transaction_context::{TransactionContext, TransactionMetadata},
+    utils::errors::ProcessorError,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{collections::VecDeque, time::Duration};
+
+pub const DEFAULT_SPLIT_STEP_POLL_INTERVAL_MS: u64 = 10;
+
+#[allow(clippy::too_long_first_doc_paragraph)]
+/// SplitStep breaks oversized `TransactionContext<Vec<T>>` items into chunks of at most
+/// `max_rows` rows, e.g. to keep each DB write below the Diesel parameter limit.
+///
+/// Items that already fit are passed through unchanged. Oversized items are split into
+/// chunks that are released in order at every poll interval. Every chunk carries the version
+/// range and timestamps of the item it came from, and the item's size is divided evenly
+/// between the chunks, the remainder going to the last chunk so that the sizes add up.
+/// Because of that, steps that track versions (e.g. `VersionTrackerStep`) will see the same
+/// range once per chunk and should not be placed directly after this step.
+pub struct SplitStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: Send + 'static,
+{
+    max_rows: usize,
+    poll_interval: Duration,
+    pending_chunks: VecDeque<TransactionContext<Vec<T>>>,
+}
+
+impl<T> SplitStep<T>
+where
+    Self: Sized + Send + 'static,
+    T: Send + 'static,
+{
+    pub fn new(max_rows: usize) -> Self {
+        Self::new_with_poll_interval(
+            max_rows,
+            Duration::from_millis(DEFAULT_SPLIT_STEP_POLL_INTERVAL_MS),
+        )
+    }
+
+    pub fn new_with_poll_interval(max_rows: usize, poll_interval: Duration) -> Self {
+        assert!(max_rows > 0, "SplitStep max_rows must be greater than 0");
+        Self {
+            max_rows,
+            poll_interval,
+            pending_chunks: VecDeque::new(),
+        }
+    }
+
+    fn split(&self, item: TransactionContext<Vec<T>>) -> Vec<TransactionContext<Vec<T>>> {
+        let TransactionContext { data, metadata } = item;
+        let num_chunks = data.len().div_ceil(self.max_rows) as u64;
+        let chunk_size_in_bytes = metadata.total_size_in_bytes / num_chunks;
+        let remainder_in_bytes = metadata.total_size_in_bytes % num_chunks;
+
+        let mut chunks = Vec::with_capacity(num_chunks as usize);
+        let mut rows = data.into_iter().peekable();
+        while rows.peek().is_some() {
+            chunks.push(TransactionContext {
+                data: rows.by_ref().take(self.max_rows).collect(),
+                metadata: TransactionMetadata {
+                    total_size_in_bytes: chunk_size_in_bytes,
+                    ..metadata.clone()
+                },
+            });
+        }
+        if let Some(last_chunk) = chunks.last_mut() {
+            last_chunk.metadata.total_size_in_bytes += remainder_in_bytes;
+        }
+        chunks
+    }
+}
+
+#[async_trait]
+impl<T> Processable for SplitStep<T>
+where
+    T: Send + Sync + 'static,
+{
+    type Input = Vec<T>;
+    type Output = Vec<T>;
+    type RunType = PollableAsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<Vec<T>>>, ProcessorError> {
+        // Pass small items straight through, unless that would overtake pending chunks
+        if item.data.len() <= self.max_rows && self.pending_chunks.is_empty() {
+            return Ok(Some(item));
+        }
+        if item.data.len() <= self.max_rows {
+            self.pending_chunks.push_back(item);
+        } else {
+            let chunks = self.split(item);
+            self.pending_chunks.extend(chunks);
+        }
+        Ok(None)
+    }
+
+    // Once polling ends, release the remaining chunks
+    async fn cleanup(
+        &mut self,
+    ) -> Result<Option<Vec<TransactionContext<Self::Output>>>, ProcessorError> {
+        Ok(Some(self.pending_chunks.drain(..).collect()))
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> PollableAsyncStep for SplitStep<T> {
+    fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    async fn poll(&mut self) -> Result<Option<Vec<TransactionContext<Vec<T>>>>, ProcessorError> {
+        Ok(Some(self.pending_chunks.drain(..).collect()))
+    }
+}
+
+impl<T: Send + 'static> NamedStep for SplitStep<T> {
+    fn name(&self) -> String {
+        format!("SplitStep: {}", std::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_batch(num_rows: usize) -> TransactionContext<Vec<usize>> {
+        TransactionContext {
+            data: (0..num_rows).collect(),
+            metadata: TransactionMetadata {
+                start_version: 100,
+                end_version: 199,
+                start_transaction_timestamp: None,
+                end_transaction_timestamp: None,
+                total_size_in_bytes: 300,
+            },
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_split_step_passes_small_batches_through() {
+        let mut step = SplitStep::<usize>::new(10);
+
+        let result = step.process(make_batch(10)).await.unwrap().unwrap();
+        assert_eq!(result.data.len(), 10);
+        assert!(step.poll().await.unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_split_step_splits_oversized_batches_in_order() {
+        let mut step = SplitStep::<usize>::new(4);
+
+        assert!(step.process(make_batch(10)).await.unwrap().is_none());
+        // A small batch after an oversized one has to wait for the pending chunks
+        assert!(step.process(make_batch(2)).await.unwrap().is_none());
+
+        let chunks = step.poll().await.unwrap().unwrap();
+        let chunk_lens = chunks.iter().map(|c| c.data.len()).collect::<Vec<_>>();
+        assert_eq!(chunk_lens, vec![4, 4, 2, 2]);
+        assert_eq!(chunks[0].data, vec![0, 1, 2, 3]);
+        assert_eq!(chunks[2].data, vec![8, 9]);
+        for chunk in &chunks[..3] {
+            assert_eq!(chunk.metadata.start_version, 100);
+            assert_eq!(chunk.metadata.end_version, 199);
+            assert_eq!(chunk.metadata.total_size_in_bytes, 100);
+        }
+        assert_eq!(chunks[3].metadata.total_size_in_bytes, 300);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_split_step_keeps_size_remainder() {
+        let mut step = SplitStep::<usize>::new(4);
+
+        // 300 bytes don't divide evenly into 7 chunks
+        assert!(step.process(make_batch(25)).await.unwrap().is_none());
+        let chunks = step.poll().await.unwrap().unwrap();
+        assert_eq!(chunks.len(), 7);
+        let sizes = chunks
+            .iter()
+            .map(|c| c.metadata.total_size_in_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![42, 42, 42, 42, 42, 42, 48]);
+        assert_eq!(sizes.iter().sum::<u64>(), 300);
+    }
+}
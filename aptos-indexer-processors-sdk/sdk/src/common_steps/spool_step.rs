@@ -0,0 +1,422 @@
+use crate::{
+    traits::{
+        pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
+    },
+    types::transaction_context::{TransactionContext, TransactionMetadata},
+    utils::{
+        errors::ProcessorError,
+        step_metrics::{StepMetricLabels, SPOOL_STEP_SPOOLED_BATCHES, SPOOL_STEP_SPOOLED_BYTES},
+    },
+};
+use anyhow::Result;
+use aptos_protos::util::timestamp::Timestamp;
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::VecDeque, marker::PhantomData, path::PathBuf, time::Duration};
+use tracing::{info, warn};
+
+const SPOOLED_BATCH_EXTENSION: &str = "batch";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolConfig {
+    /// Directory of the spooled batches. Must not be shared with another processor.
+    pub directory: PathBuf,
+    /// Once the spool holds this many bytes, the step stops taking batches until it drains.
+    #[serde(default = "SpoolConfig::default_max_bytes")]
+    pub max_bytes: u64,
+    /// How often to try writing the spooled batches to the sink.
+    #[serde(default = "SpoolConfig::default_drain_interval_ms")]
+    pub drain_interval_ms: u64,
+}
+
+impl SpoolConfig {
+    pub const fn default_max_bytes() -> u64 {
+        1024 * 1024 * 1024
+    }
+
+    pub const fn default_drain_interval_ms() -> u64 {
+        1000
+    }
+}
+
+/// Where `SpoolStep` writes batches, e.g. the database.
+#[async_trait]
+pub trait SpoolSink<T>: Send + Sync {
+    async fn write(&self, batch: &TransactionContext<T>) -> Result<(), ProcessorError>;
+
+    /// Whether `error` means that the sink is unreachable, so that the batch is spooled rather
+    /// than failing the processor.
+    fn is_unavailable(&self, error: &ProcessorError) -> bool;
+}
+
+/// A batch as stored on disk. `TransactionMetadata` isn't serializable because of the
+/// protobuf timestamps.
+#[derive(Deserialize, Serialize)]
+struct SpooledBatch<T> {
+    data: T,
+    start_version: u64,
+    end_version: u64,
+    start_transaction_timestamp: Option<(i64, i32)>,
+    end_transaction_timestamp: Option<(i64, i32)>,
+    total_size_in_bytes: u64,
+}
+
+impl<T> From<SpooledBatch<T>> for TransactionContext<T> {
+    fn from(batch: SpooledBatch<T>) -> Self {
+        let to_timestamp = |(seconds, nanos)| Timestamp { seconds, nanos };
+        TransactionContext {
+            data: batch.data,
+            metadata: TransactionMetadata {
+                start_version: batch.start_version,
+                end_version: batch.end_version,
+                start_transaction_timestamp: batch.start_transaction_timestamp.map(to_timestamp),
+                end_transaction_timestamp: batch.end_transaction_timestamp.map(to_timestamp),
+                total_size_in_bytes: batch.total_size_in_bytes,
+            },
+        }
+    }
+}
+
+struct SpoolEntry {
+    path: PathBuf,
+    size_in_bytes: u64,
+}
+
+/// Writes batches to a `SpoolSink`, and keeps them on local disk while the sink is unreachable,
+/// e.g. while Postgres restarts. Spooled batches are written in order once the sink is back,
+/// and only then passed on, so a `VersionTrackerStep` after this step only advances the
+/// checkpoint past committed data.
+///
+/// Batches left on disk by a previous run are removed on start: the checkpoint was not
+/// advanced past them, so the transaction stream replays them.
+pub struct SpoolStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: SpoolSink<T> + 'static,
+{
+    config: SpoolConfig,
+    sink: S,
+    spool: VecDeque<SpoolEntry>,
+    spooled_bytes: u64,
+    next_sequence_number: u64,
+    // Batches written from the spool but not passed on yet
+    drained: Vec<TransactionContext<T>>,
+    phantom: PhantomData<T>,
+}
+
+impl<T, S> SpoolStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: SpoolSink<T> + 'static,
+{
+    pub fn new(config: SpoolConfig, sink: S) -> Result<Self> {
+        std::fs::create_dir_all(&config.directory)?;
+        for entry in std::fs::read_dir(&config.directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == SPOOLED_BATCH_EXTENSION || ext == "tmp")
+            {
+                warn!(path = ?path, "Removing batch spooled by a previous run");
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(Self {
+            config,
+            sink,
+            spool: VecDeque::new(),
+            spooled_bytes: 0,
+            next_sequence_number: 0,
+            drained: vec![],
+            phantom: PhantomData,
+        })
+    }
+
+    fn update_metrics(&self) {
+        let labels = StepMetricLabels {
+            step_name: self.name(),
+        };
+        SPOOL_STEP_SPOOLED_BATCHES
+            .get_or_create(&labels)
+            .set(self.spool.len() as i64);
+        SPOOL_STEP_SPOOLED_BYTES
+            .get_or_create(&labels)
+            .set(self.spooled_bytes as i64);
+    }
+
+    /// Appends the batch to the spool. The file is synced before being renamed into place, so a
+    /// crash never leaves a partial batch.
+    async fn spool(&mut self, item: TransactionContext<T>) -> Result<(), ProcessorError> {
+        let to_processor_error = |e: std::io::Error| ProcessorError::ProcessError {
+            message: format!("Failed to spool batch: {:#}", e),
+        };
+        let to_timestamp = |timestamp: &Timestamp| (timestamp.seconds, timestamp.nanos);
+        let metadata = &item.metadata;
+        let batch = SpooledBatch {
+            start_version: metadata.start_version,
+            end_version: metadata.end_version,
+            start_transaction_timestamp: metadata
+                .start_transaction_timestamp
+                .as_ref()
+                .map(to_timestamp),
+            end_transaction_timestamp: metadata
+                .end_transaction_timestamp
+                .as_ref()
+                .map(to_timestamp),
+            total_size_in_bytes: metadata.total_size_in_bytes,
+            data: item.data,
+        };
+        let bytes = serde_json::to_vec(&batch).map_err(|e| ProcessorError::ProcessError {
+            message: format!("Failed to serialize batch: {:#}", e),
+        })?;
+
+        let path = self.config.directory.join(format!(
+            "{:020}.{}",
+            self.next_sequence_number, SPOOLED_BATCH_EXTENSION
+        ));
+        let tmp_path = path.with_extension("tmp");
+        let file = tokio::fs::File::create(&tmp_path)
+            .await
+            .map_err(to_processor_error)?;
+        let mut file = tokio::io::BufWriter::new(file);
+        tokio::io::AsyncWriteExt::write_all(&mut file, &bytes)
+            .await
+            .map_err(to_processor_error)?;
+        tokio::io::AsyncWriteExt::flush(&mut file)
+            .await
+            .map_err(to_processor_error)?;
+        file.get_ref()
+            .sync_all()
+            .await
+            .map_err(to_processor_error)?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(to_processor_error)?;
+
+        self.next_sequence_number += 1;
+        self.spooled_bytes += bytes.len() as u64;
+        self.spool.push_back(SpoolEntry {
+            path,
+            size_in_bytes: bytes.len() as u64,
+        });
+        self.update_metrics();
+        Ok(())
+    }
+
+    /// Writes spooled batches to the sink in order, until the spool is empty or the sink is
+    /// unreachable again.
+    async fn drain(&mut self) -> Result<(), ProcessorError> {
+        while let Some(entry) = self.spool.front() {
+            let bytes =
+                tokio::fs::read(&entry.path)
+                    .await
+                    .map_err(|e| ProcessorError::ProcessError {
+                        message: format!("Failed to read spooled batch {:?}: {:#}", entry.path, e),
+                    })?;
+            let batch: TransactionContext<T> = serde_json::from_slice::<SpooledBatch<T>>(&bytes)
+                .map_err(|e| ProcessorError::ProcessError {
+                    message: format!("Failed to parse spooled batch {:?}: {:#}", entry.path, e),
+                })?
+                .into();
+            match self.sink.write(&batch).await {
+                Ok(()) => {},
+                Err(e) if self.sink.is_unavailable(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            if let Err(e) = tokio::fs::remove_file(&entry.path).await {
+                warn!(path = ?entry.path, error = ?e, "Failed to remove drained batch");
+            }
+            self.spooled_bytes -= entry.size_in_bytes;
+            self.spool.pop_front();
+            self.drained.push(batch);
+            self.update_metrics();
+            if self.spool.is_empty() {
+                info!(
+                    step_name = self.name(),
+                    "Drained the spool, the sink is back"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T, S> Processable for SpoolStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: SpoolSink<T> + 'static,
+{
+    type Input = T;
+    type Output = T;
+    type RunType = PollableAsyncRunType;
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<T>,
+    ) -> Result<Option<TransactionContext<T>>, ProcessorError> {
+        if self.spool.is_empty() {
+            match self.sink.write(&item).await {
+                Ok(()) => return Ok(Some(item)),
+                Err(e) if self.sink.is_unavailable(&e) => {
+                    warn!(
+                        step_name = self.name(),
+                        start_version = item.metadata.start_version,
+                        error = ?e,
+                        "Sink is unavailable, spooling batches to disk"
+                    );
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Apply backpressure once the spool is full
+        while self.spooled_bytes >= self.config.max_bytes {
+            tokio::time::sleep(self.poll_interval()).await;
+            self.drain().await?;
+        }
+        // Later batches go to the spool too while it isn't empty, to keep them in order
+        self.spool(item).await?;
+        Ok(None)
+    }
+
+    async fn cleanup(
+        &mut self,
+    ) -> Result<Option<Vec<TransactionContext<Self::Output>>>, ProcessorError> {
+        self.drain().await?;
+        if !self.spool.is_empty() {
+            warn!(
+                step_name = self.name(),
+                num_batches = self.spool.len(),
+                "Stopping with spooled batches, they will be replayed from the checkpoint"
+            );
+        }
+        Ok(Some(std::mem::take(&mut self.drained)))
+    }
+}
+
+#[async_trait]
+impl<T, S> PollableAsyncStep for SpoolStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: SpoolSink<T> + 'static,
+{
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.config.drain_interval_ms)
+    }
+
+    async fn poll(&mut self) -> Result<Option<Vec<TransactionContext<T>>>, ProcessorError> {
+        self.drain().await?;
+        Ok(Some(std::mem::take(&mut self.drained)))
+    }
+}
+
+impl<T, S> NamedStep for SpoolStep<T, S>
+where
+    Self: Sized + Send + 'static,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: SpoolSink<T> + 'static,
+{
+    fn name(&self) -> String {
+        format!("SpoolStep: {}", std::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    #[derive(Default)]
+    struct TestSink {
+        is_available: Arc<AtomicBool>,
+        written: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl SpoolSink<Vec<u64>> for TestSink {
+        async fn write(&self, batch: &TransactionContext<Vec<u64>>) -> Result<(), ProcessorError> {
+            if !self.is_available.load(Ordering::SeqCst) {
+                return Err(ProcessorError::DBStoreError {
+                    message: "connection refused".to_string(),
+                    query: None,
+                });
+            }
+            self.written
+                .lock()
+                .unwrap()
+                .push(batch.metadata.start_version);
+            Ok(())
+        }
+
+        fn is_unavailable(&self, error: &ProcessorError) -> bool {
+            matches!(error, ProcessorError::DBStoreError { .. })
+        }
+    }
+
+    fn batch(version: u64) -> TransactionContext<Vec<u64>> {
+        TransactionContext {
+            data: vec![version],
+            metadata: TransactionMetadata {
+                start_version: version,
+                end_version: version,
+                start_transaction_timestamp: Some(Timestamp {
+                    seconds: 1,
+                    nanos: 2,
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_spools_while_sink_is_unavailable() {
+        let directory = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        let is_available = sink.is_available.clone();
+        let written = sink.written.clone();
+        let mut step = SpoolStep::new(
+            SpoolConfig {
+                directory: directory.path().to_path_buf(),
+                max_bytes: SpoolConfig::default_max_bytes(),
+                drain_interval_ms: 10,
+            },
+            sink,
+        )
+        .unwrap();
+
+        assert!(step.process(batch(0)).await.unwrap().is_none());
+        // Stays in order behind the spooled batch even once the sink is back
+        is_available.store(true, Ordering::SeqCst);
+        assert!(step.process(batch(1)).await.unwrap().is_none());
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 2);
+
+        let drained = step.poll().await.unwrap().unwrap();
+        let versions = drained
+            .iter()
+            .map(|batch| batch.metadata.start_version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![0, 1]);
+        assert_eq!(*written.lock().unwrap(), vec![0, 1]);
+        assert_eq!(
+            drained[0].metadata.start_transaction_timestamp,
+            Some(Timestamp {
+                seconds: 1,
+                nanos: 2
+            })
+        );
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+
+        // With an empty spool, batches are written and passed on right away
+        assert!(step.process(batch(2)).await.unwrap().is_some());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{
+    admin::stream_control,
+    health::set_transaction_stream_connected,
     traits::{NamedStep, PollableAsyncRunType, PollableAsyncStep, Processable},
     types::transaction_context::{TransactionContext, TransactionMetadata},
     utils::errors::ProcessorError,
@@ -14,6 +16,9 @@ use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 
+// How long a paused stream waits for a resume before polling again
+const PAUSED_POLL_INTERVAL_MS: u64 = 500;
+
 // TransactionStreamStep is establishes a gRPC connection with Transaction Stream
 // fetches transactions, and outputs them for processing. It also handles reconnections with retries.
 // This is usually the initial step in a processor.
@@ -44,6 +49,28 @@ where
             }),
         }
     }
+
+    /// Restarts the stream at `version`.
+    async fn rewind(&mut self, version: u64) -> Result<(), ProcessorError> {
+        info!(
+            stream_address = self
+                .transaction_stream_config
+                .indexer_grpc_data_service_address
+                .to_string(),
+            version = version,
+            "Rewinding TransactionStream."
+        );
+        let transaction_stream = TransactionStreamInternal::new(TransactionStreamConfig {
+            starting_version: Some(version),
+            ..self.transaction_stream_config.clone()
+        })
+        .await
+        .map_err(|e| ProcessorError::PollError {
+            message: format!("Error rewinding TransactionStream: {:?}", e),
+        })?;
+        *self.transaction_stream.lock().await = transaction_stream;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -76,6 +103,17 @@ where
     async fn poll(
         &mut self,
     ) -> Result<Option<Vec<TransactionContext<Vec<Transaction>>>>, ProcessorError> {
+        let control = stream_control();
+        if control.is_paused() {
+            control
+                .wait_until_resumed(Duration::from_millis(PAUSED_POLL_INTERVAL_MS))
+                .await;
+            return Ok(None);
+        }
+        if let Some(version) = control.take_stream_rewind() {
+            self.rewind(version).await?;
+        }
+
         let txn_pb_response_res = self
             .transaction_stream
             .lock()
@@ -84,8 +122,23 @@ where
             .await;
         match txn_pb_response_res {
             Ok(txn_pb_response) => {
+                set_transaction_stream_connected(true);
+                let mut transactions = txn_pb_response.transactions;
+                if control.has_skipped_versions() {
+                    transactions.retain(|txn| {
+                        let is_skipped = control.is_skipped(txn.version);
+                        if is_skipped {
+                            warn!(
+                                version = txn.version,
+                                "Skipping transaction on the skip-list"
+                            );
+                        }
+                        !is_skipped
+                    });
+                }
+                // The version range is kept as is, so that skipped versions are checkpointed
                 let transactions_with_context = TransactionContext {
-                    data: txn_pb_response.transactions,
+                    data: transactions,
                     metadata: TransactionMetadata {
                         start_version: txn_pb_response.start_version,
                         end_version: txn_pb_response.end_version,
@@ -97,6 +150,7 @@ where
                 Ok(Some(vec![transactions_with_context]))
             },
             Err(e) => {
+                set_transaction_stream_connected(false);
                 warn!(
                     stream_address = self.transaction_stream_config.indexer_grpc_data_service_address.to_string(),
                     error = ?e,
@@ -1,4 +1,6 @@
 use crate::{
+    admin::stream_control,
+    health::set_latest_tracked_transaction_timestamp,
     traits::{
         pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
     },
@@ -20,6 +22,37 @@ pub trait ProcessorStatusSaver {
         &self,
         last_success_batch: &TransactionContext<()>,
     ) -> Result<(), ProcessorError>;
+
+    /// Overwrites the saved status with `last_success_version`, even if it goes backwards.
+    /// Used when the stream is rewound from the admin API.
+    async fn reset_processor_status(
+        &self,
+        _last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        Err(ProcessorError::ProcessError {
+            message: "This processor does not support resetting its status".to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl<S> ProcessorStatusSaver for std::sync::Arc<S>
+where
+    S: ProcessorStatusSaver + Send + Sync + ?Sized,
+{
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        (**self).save_processor_status(last_success_batch).await
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        (**self).reset_processor_status(last_success_version).await
+    }
 }
 
 /// Tracks the versioned processing of sequential transactions, ensuring no gaps
@@ -66,6 +99,13 @@ where
     }
 }
 
+/// Feeds the lag reported by `/status` and the readiness check.
+fn track_transaction_timestamp<T>(batch: &TransactionContext<T>) {
+    if let Some(timestamp) = batch.get_end_transaction_timestamp_unix() {
+        set_latest_tracked_transaction_timestamp(timestamp);
+    }
+}
+
 #[async_trait]
 impl<T, S> Processable for VersionTrackerStep<T, S>
 where
@@ -84,6 +124,20 @@ where
         // If there's a gap in version, return an error
         if let Some(last_success_batch) = self.last_success_batch.as_ref() {
             if last_success_batch.metadata.end_version + 1 != current_batch.metadata.start_version {
+                // The stream was rewound from the admin API, so the gap is expected
+                if stream_control().take_tracker_rewind(current_batch.metadata.start_version) {
+                    self.processor_status_saver
+                        .reset_processor_status(
+                            current_batch.metadata.start_version.saturating_sub(1),
+                        )
+                        .await?;
+                    self.last_success_batch = Some(TransactionContext {
+                        data: (),
+                        metadata: current_batch.metadata.clone(),
+                    });
+                    track_transaction_timestamp(&current_batch);
+                    return Ok(Some(current_batch));
+                }
                 return Err(ProcessorError::ProcessError {
                     message: format!(
                         "Gap detected starting from version: {}",
@@ -98,6 +152,7 @@ where
             data: (),
             metadata: current_batch.metadata.clone(),
         });
+        track_transaction_timestamp(&current_batch);
 
         // Pass through
         Ok(Some(current_batch))
@@ -0,0 +1,767 @@
+use crate::{
+    traits::{
+        pollable_async_step::PollableAsyncRunType, NamedStep, PollableAsyncStep, Processable,
+    },
+    types::transaction_context::TransactionContext,
+    utils::{
+        errors::ProcessorError,
+        step_metrics::{
+            WebhookMetricLabels, WEBHOOK_ABANDONED_DELIVERY_COUNT, WEBHOOK_DELIVERED_COUNT,
+            WEBHOOK_DELIVERY_LAG_SECS, WEBHOOK_FAILED_ATTEMPT_COUNT,
+        },
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+pub const WEBHOOK_ID_HEADER: &str = "X-Webhook-Id";
+pub const WEBHOOK_EVENT_HEADER: &str = "X-Webhook-Event";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// A record delivered to webhooks as JSON.
+pub trait WebhookPayload: Serialize + Send + Sync + 'static {
+    /// Endpoints only get the event types they list, e.g. `raffle_winner`.
+    fn event_type(&self) -> &str;
+
+    /// The version of the transaction and the index of the event the record comes from, which
+    /// identify the record across restarts.
+    fn idempotency_key(&self) -> (i64, i64);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpointConfig {
+    /// Identifies the endpoint in the outbox and the metrics, so it must not change.
+    pub name: String,
+    pub url: String,
+    /// Signs the requests with HMAC-SHA256 if set.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Event types to deliver, all of them if empty.
+    #[serde(default)]
+    pub event_types: Vec<String>,
+}
+
+impl WebhookEndpointConfig {
+    fn accepts(&self, event_type: &str) -> bool {
+        self.event_types.is_empty() || self.event_types.iter().any(|t| t == event_type)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub endpoints: Vec<WebhookEndpointConfig>,
+    /// A delivery is abandoned after this many failed attempts.
+    #[serde(default = "WebhookConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    #[serde(default = "WebhookConfig::default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "WebhookConfig::default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default = "WebhookConfig::default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// How often to send the due deliveries of the outbox.
+    #[serde(default = "WebhookConfig::default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Maximum number of deliveries sent per poll.
+    #[serde(default = "WebhookConfig::default_max_deliveries_per_poll")]
+    pub max_deliveries_per_poll: usize,
+}
+
+impl WebhookConfig {
+    pub const fn default_max_attempts() -> u32 {
+        10
+    }
+
+    pub const fn default_initial_backoff_ms() -> u64 {
+        1000
+    }
+
+    pub const fn default_max_backoff_ms() -> u64 {
+        10 * 60 * 1000
+    }
+
+    pub const fn default_request_timeout_ms() -> u64 {
+        10_000
+    }
+
+    pub const fn default_poll_interval_ms() -> u64 {
+        1000
+    }
+
+    pub const fn default_max_deliveries_per_poll() -> usize {
+        100
+    }
+
+    fn backoff(&self, attempts: u32) -> chrono::Duration {
+        let backoff_ms = self
+            .initial_backoff_ms
+            .saturating_mul(1 << attempts.saturating_sub(1).min(32));
+        chrono::Duration::milliseconds(backoff_ms.min(self.max_backoff_ms) as i64)
+    }
+}
+
+/// A delivery to store in the outbox.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewWebhookDelivery {
+    pub endpoint: String,
+    /// `<transaction_version>:<event_index>`, unique per endpoint
+    pub idempotency_key: String,
+    pub event_type: String,
+    pub transaction_version: i64,
+    pub payload: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// A delivery of the outbox that isn't delivered yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint: String,
+    pub idempotency_key: String,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub created_at: NaiveDateTime,
+}
+
+/// Durable storage of the deliveries of `WebhookSinkStep`, e.g. a Postgres table.
+#[async_trait]
+pub trait WebhookOutbox: Send + Sync {
+    /// Stores the deliveries, skipping those already stored, e.g. after a restart.
+    async fn enqueue(&self, deliveries: Vec<NewWebhookDelivery>) -> Result<(), ProcessorError>;
+
+    /// Pending deliveries due at `now`, oldest first. A delivery isn't due while an older
+    /// pending delivery of its endpoint waits for a retry, so endpoints get them in order.
+    async fn due(
+        &self,
+        now: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, ProcessorError>;
+
+    async fn mark_delivered(&self, id: i64, now: NaiveDateTime) -> Result<(), ProcessorError>;
+
+    /// Records a failed attempt. The delivery is retried at `next_attempt_at`, or abandoned if
+    /// it's `None`.
+    async fn mark_failed(
+        &self,
+        id: i64,
+        error: &str,
+        next_attempt_at: Option<NaiveDateTime>,
+    ) -> Result<(), ProcessorError>;
+}
+
+/// HMAC-SHA256 of `message` with `secret`, hex encoded.
+pub fn sign(secret: &str, message: &[u8]) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(message);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Sends the deliveries of a `WebhookOutbox`, in the background of `WebhookSinkStep`.
+struct WebhookDeliverer<O>
+where
+    O: WebhookOutbox + 'static,
+{
+    step_name: String,
+    config: WebhookConfig,
+    endpoints: HashMap<String, WebhookEndpointConfig>,
+    outbox: O,
+    client: reqwest::Client,
+}
+
+impl<O> WebhookDeliverer<O>
+where
+    O: WebhookOutbox + 'static,
+{
+    fn metric_labels(&self, endpoint: &str) -> WebhookMetricLabels {
+        WebhookMetricLabels {
+            step_name: self.step_name.clone(),
+            endpoint: endpoint.to_string(),
+        }
+    }
+
+    async fn send(
+        &self,
+        endpoint: &WebhookEndpointConfig,
+        delivery: &WebhookDelivery,
+    ) -> Result<()> {
+        let timestamp = chrono::Utc::now().timestamp();
+        let mut request = self
+            .client
+            .post(&endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(WEBHOOK_ID_HEADER, &delivery.idempotency_key)
+            .header(WEBHOOK_EVENT_HEADER, &delivery.event_type)
+            .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string());
+        if let Some(secret) = &endpoint.secret {
+            let message = format!("{}.{}", timestamp, delivery.payload);
+            request = request.header(
+                WEBHOOK_SIGNATURE_HEADER,
+                format!("sha256={}", sign(secret, message.as_bytes())),
+            );
+        }
+        request
+            .body(delivery.payload.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Sends a due delivery, and records the outcome in the outbox. Returns whether it was
+    /// delivered.
+    async fn deliver(&self, delivery: WebhookDelivery) -> Result<bool, ProcessorError> {
+        let labels = self.metric_labels(&delivery.endpoint);
+        let result = match self.endpoints.get(&delivery.endpoint) {
+            Some(endpoint) => self.send(endpoint, &delivery).await,
+            None => Err(anyhow::anyhow!(
+                "Endpoint {} is no longer configured",
+                delivery.endpoint
+            )),
+        };
+        let now = chrono::Utc::now().naive_utc();
+        match result {
+            Ok(()) => {
+                self.outbox.mark_delivered(delivery.id, now).await?;
+                WEBHOOK_DELIVERED_COUNT.get_or_create(&labels).inc();
+                let lag = now - delivery.created_at;
+                WEBHOOK_DELIVERY_LAG_SECS
+                    .get_or_create(&labels)
+                    .set(lag.num_milliseconds() as f64 / 1000.0);
+                Ok(true)
+            },
+            Err(e) => {
+                let attempts = delivery.attempts as u32 + 1;
+                let next_attempt_at = (attempts < self.config.max_attempts
+                    && self.endpoints.contains_key(&delivery.endpoint))
+                .then(|| now + self.config.backoff(attempts));
+                warn!(
+                    step_name = self.step_name.as_str(),
+                    endpoint = delivery.endpoint.as_str(),
+                    idempotency_key = delivery.idempotency_key.as_str(),
+                    attempts,
+                    error = format!("{:#}", e),
+                    "Failed to deliver webhook{}",
+                    if next_attempt_at.is_some() {
+                        ""
+                    } else {
+                        ", giving up"
+                    }
+                );
+                self.outbox
+                    .mark_failed(delivery.id, &format!("{:#}", e), next_attempt_at)
+                    .await?;
+                WEBHOOK_FAILED_ATTEMPT_COUNT.get_or_create(&labels).inc();
+                if next_attempt_at.is_none() {
+                    WEBHOOK_ABANDONED_DELIVERY_COUNT
+                        .get_or_create(&labels)
+                        .inc();
+                }
+                Ok(false)
+            },
+        }
+    }
+
+    /// Sends the due deliveries, concurrently across endpoints and in order within each. An
+    /// endpoint stops at its first failure, so that its later deliveries wait for the retry.
+    /// Returns the number of due deliveries.
+    async fn deliver_due(&self) -> Result<usize, ProcessorError> {
+        let now = chrono::Utc::now().naive_utc();
+        let deliveries = self
+            .outbox
+            .due(now, self.config.max_deliveries_per_poll)
+            .await?;
+        let num_deliveries = deliveries.len();
+        let mut deliveries_by_endpoint: HashMap<String, Vec<WebhookDelivery>> = HashMap::new();
+        for delivery in deliveries {
+            deliveries_by_endpoint
+                .entry(delivery.endpoint.clone())
+                .or_default()
+                .push(delivery);
+        }
+        futures::future::try_join_all(deliveries_by_endpoint.into_values().map(
+            |deliveries| async move {
+                for delivery in deliveries {
+                    if !self.deliver(delivery).await? {
+                        break;
+                    }
+                }
+                Ok::<(), ProcessorError>(())
+            },
+        ))
+        .await?;
+        Ok(num_deliveries)
+    }
+
+    /// Drains the outbox until the task is aborted.
+    async fn run(self: Arc<Self>) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval_ms);
+        loop {
+            match self.deliver_due().await {
+                // A full page may have more due deliveries behind it
+                Ok(num_deliveries) if num_deliveries >= self.config.max_deliveries_per_poll => {
+                    continue
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    warn!(
+                        step_name = self.step_name.as_str(),
+                        error = format!("{:#}", e),
+                        "Failed to send the due webhook deliveries"
+                    );
+                },
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// Delivers records to webhooks, at least once. Every record is serialized once, then stored in
+/// a `WebhookOutbox` for each endpoint that accepts its event type. A batch is passed on once
+/// its deliveries are stored, so a `VersionTrackerStep` after this step may checkpoint it.
+///
+/// A background task sends the due deliveries, without holding up the batches, and retries
+/// failures with exponential backoff. Each endpoint gets its deliveries in order: the ones after
+/// a failed delivery wait until it is delivered or abandoned.
+///
+/// Requests carry the `idempotency_key` of the record in `X-Webhook-Id`, for receivers to drop
+/// redeliveries. With a `secret`, `X-Webhook-Signature` is `sha256=<hex>`, the HMAC-SHA256 of
+/// `<X-Webhook-Timestamp>.<body>`.
+pub struct WebhookSinkStep<T, O>
+where
+    Self: Sized + Send + 'static,
+    T: WebhookPayload,
+    O: WebhookOutbox + 'static,
+{
+    deliverer: Arc<WebhookDeliverer<O>>,
+    delivery_task: Option<JoinHandle<()>>,
+    phantom: PhantomData<T>,
+}
+
+impl<T, O> WebhookSinkStep<T, O>
+where
+    Self: Sized + Send + 'static,
+    T: WebhookPayload,
+    O: WebhookOutbox + 'static,
+{
+    pub fn new(config: WebhookConfig, outbox: O) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.request_timeout_ms))
+            .build()?;
+        let endpoints = config
+            .endpoints
+            .iter()
+            .map(|endpoint| (endpoint.name.clone(), endpoint.clone()))
+            .collect();
+        Ok(Self {
+            deliverer: Arc::new(WebhookDeliverer {
+                step_name: format!("WebhookSinkStep: {}", std::any::type_name::<T>()),
+                config,
+                endpoints,
+                outbox,
+                client,
+            }),
+            delivery_task: None,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[async_trait]
+impl<T, O> Processable for WebhookSinkStep<T, O>
+where
+    Self: Sized + Send + 'static,
+    T: WebhookPayload,
+    O: WebhookOutbox + 'static,
+{
+    type Input = Vec<T>;
+    type Output = ();
+    type RunType = PollableAsyncRunType;
+
+    async fn init(&mut self) {
+        self.delivery_task = Some(tokio::spawn(self.deliverer.clone().run()));
+    }
+
+    async fn process(
+        &mut self,
+        item: TransactionContext<Vec<T>>,
+    ) -> Result<Option<TransactionContext<()>>, ProcessorError> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut deliveries = vec![];
+        for record in &item.data {
+            let event_type = record.event_type();
+            let endpoints = self
+                .deliverer
+                .config
+                .endpoints
+                .iter()
+                .filter(|endpoint| endpoint.accepts(event_type))
+                .collect::<Vec<_>>();
+            if endpoints.is_empty() {
+                continue;
+            }
+            let (transaction_version, event_index) = record.idempotency_key();
+            let payload =
+                serde_json::to_string(record).map_err(|e| ProcessorError::ProcessError {
+                    message: format!(
+                        "Could not serialize the webhook payload of version {} and event {}: {:#}",
+                        transaction_version, event_index, e
+                    ),
+                })?;
+            deliveries.extend(endpoints.into_iter().map(|endpoint| NewWebhookDelivery {
+                endpoint: endpoint.name.clone(),
+                idempotency_key: format!("{}:{}", transaction_version, event_index),
+                event_type: event_type.to_string(),
+                transaction_version,
+                payload: payload.clone(),
+                created_at: now,
+            }));
+        }
+        if !deliveries.is_empty() {
+            self.deliverer.outbox.enqueue(deliveries).await?;
+        }
+        Ok(Some(TransactionContext {
+            data: (),
+            metadata: item.metadata,
+        }))
+    }
+
+    async fn cleanup(
+        &mut self,
+    ) -> Result<Option<Vec<TransactionContext<Self::Output>>>, ProcessorError> {
+        // Pending deliveries stay in the outbox for the next run
+        if let Some(delivery_task) = self.delivery_task.take() {
+            delivery_task.abort();
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<T, O> PollableAsyncStep for WebhookSinkStep<T, O>
+where
+    Self: Sized + Send + Sync + 'static,
+    T: WebhookPayload,
+    O: WebhookOutbox + 'static,
+{
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.deliverer.config.poll_interval_ms)
+    }
+
+    /// Only checks that the delivery task is still running, since it never returns.
+    async fn poll(&mut self) -> Result<Option<Vec<TransactionContext<()>>>, ProcessorError> {
+        if self
+            .delivery_task
+            .as_ref()
+            .is_some_and(|delivery_task| delivery_task.is_finished())
+        {
+            return Err(ProcessorError::PollError {
+                message: format!("The webhook delivery task of {} stopped", self.name()),
+            });
+        }
+        Ok(None)
+    }
+}
+
+impl<T, O> NamedStep for WebhookSinkStep<T, O>
+where
+    Self: Sized + Send + 'static,
+    T: WebhookPayload,
+    O: WebhookOutbox + 'static,
+{
+    fn name(&self) -> String {
+        format!("WebhookSinkStep: {}", std::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transaction_context::TransactionMetadata;
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Serialize)]
+    struct RaffleEvent {
+        transaction_version: i64,
+        event_index: i64,
+        winner: Option<String>,
+    }
+
+    impl WebhookPayload for RaffleEvent {
+        fn event_type(&self) -> &str {
+            if self.winner.is_some() {
+                "raffle_winner"
+            } else {
+                "raffle_entry"
+            }
+        }
+
+        fn idempotency_key(&self) -> (i64, i64) {
+            (self.transaction_version, self.event_index)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestOutbox {
+        deliveries: Arc<Mutex<Vec<(WebhookDelivery, Option<NaiveDateTime>, bool)>>>,
+    }
+
+    #[async_trait]
+    impl WebhookOutbox for TestOutbox {
+        async fn enqueue(&self, deliveries: Vec<NewWebhookDelivery>) -> Result<(), ProcessorError> {
+            let mut stored = self.deliveries.lock().unwrap();
+            for delivery in deliveries {
+                if stored.iter().any(|(d, _, _)| {
+                    d.endpoint == delivery.endpoint && d.idempotency_key == delivery.idempotency_key
+                }) {
+                    continue;
+                }
+                let id = stored.len() as i64;
+                stored.push((
+                    WebhookDelivery {
+                        id,
+                        endpoint: delivery.endpoint,
+                        idempotency_key: delivery.idempotency_key,
+                        event_type: delivery.event_type,
+                        payload: delivery.payload,
+                        attempts: 0,
+                        created_at: delivery.created_at,
+                    },
+                    Some(delivery.created_at),
+                    false,
+                ));
+            }
+            Ok(())
+        }
+
+        async fn due(
+            &self,
+            now: NaiveDateTime,
+            limit: usize,
+        ) -> Result<Vec<WebhookDelivery>, ProcessorError> {
+            let deliveries = self.deliveries.lock().unwrap();
+            Ok(deliveries
+                .iter()
+                .filter(|(delivery, next_attempt_at, delivered)| {
+                    !delivered
+                        && next_attempt_at.is_some_and(|at| at <= now)
+                        // Behind an older delivery of the endpoint that awaits a retry
+                        && !deliveries.iter().any(|(older, older_next_attempt_at, older_delivered)| {
+                            older.endpoint == delivery.endpoint
+                                && older.id < delivery.id
+                                && !older_delivered
+                                && older_next_attempt_at.is_some_and(|at| at > now)
+                        })
+                })
+                .map(|(delivery, _, _)| delivery.clone())
+                .take(limit)
+                .collect())
+        }
+
+        async fn mark_delivered(&self, id: i64, _now: NaiveDateTime) -> Result<(), ProcessorError> {
+            self.deliveries.lock().unwrap()[id as usize].2 = true;
+            Ok(())
+        }
+
+        async fn mark_failed(
+            &self,
+            id: i64,
+            _error: &str,
+            next_attempt_at: Option<NaiveDateTime>,
+        ) -> Result<(), ProcessorError> {
+            let entry = &mut self.deliveries.lock().unwrap()[id as usize];
+            entry.0.attempts += 1;
+            entry.1 = next_attempt_at;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_webhook_sink_step() {
+        // Receives the webhooks, and checks their signature
+        let received = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .route(
+                "/winners",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<String>>>>,
+                     headers: HeaderMap,
+                     body: String| async move {
+                        let header =
+                            |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+                        let message = format!("{}.{}", header(WEBHOOK_TIMESTAMP_HEADER), body);
+                        if header(WEBHOOK_SIGNATURE_HEADER)
+                            != format!("sha256={}", sign("secret", message.as_bytes()))
+                        {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+                        received.lock().unwrap().push(header(WEBHOOK_ID_HEADER));
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let endpoint = |name: &str, path: &str| WebhookEndpointConfig {
+            name: name.to_string(),
+            url: format!("http://{}{}", address, path),
+            secret: Some("secret".to_string()),
+            event_types: vec!["raffle_winner".to_string()],
+        };
+        let config = WebhookConfig {
+            endpoints: vec![
+                endpoint("backend", "/winners"),
+                endpoint("broken", "/missing"),
+            ],
+            max_attempts: 2,
+            initial_backoff_ms: 0,
+            max_backoff_ms: 0,
+            request_timeout_ms: WebhookConfig::default_request_timeout_ms(),
+            poll_interval_ms: WebhookConfig::default_poll_interval_ms(),
+            max_deliveries_per_poll: WebhookConfig::default_max_deliveries_per_poll(),
+        };
+        let outbox = TestOutbox::default();
+        let mut step = WebhookSinkStep::new(config, outbox.clone()).unwrap();
+        let batch = || TransactionContext {
+            data: vec![
+                RaffleEvent {
+                    transaction_version: 10,
+                    event_index: 0,
+                    winner: None,
+                },
+                RaffleEvent {
+                    transaction_version: 10,
+                    event_index: 1,
+                    winner: Some("0xa".to_string()),
+                },
+            ],
+            metadata: TransactionMetadata {
+                start_version: 10,
+                end_version: 10,
+                ..Default::default()
+            },
+        };
+        step.process(batch()).await.unwrap();
+        // Replaying the batch doesn't deliver it twice
+        step.process(batch()).await.unwrap();
+        assert_eq!(outbox.deliveries.lock().unwrap().len(), 2);
+
+        step.deliverer.deliver_due().await.unwrap();
+        step.deliverer.deliver_due().await.unwrap();
+        assert_eq!(*received.lock().unwrap(), vec!["10:1".to_string()]);
+        let deliveries = outbox.deliveries.lock().unwrap();
+        let broken = deliveries
+            .iter()
+            .find(|(delivery, _, _)| delivery.endpoint == "broken")
+            .unwrap();
+        // The broken endpoint failed twice, then was given up on
+        assert_eq!(broken.0.attempts, 2);
+        assert_eq!(broken.1, None);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_webhook_deliveries_stay_in_order() {
+        // Fails the first request, then accepts the others
+        let received = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .route(
+                "/flaky",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                        let mut received = received.lock().unwrap();
+                        received.push(
+                            headers[WEBHOOK_ID_HEADER].to_str().unwrap().to_string(),
+                        );
+                        if received.len() == 1 {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = WebhookConfig {
+            endpoints: vec![WebhookEndpointConfig {
+                name: "flaky".to_string(),
+                url: format!("http://{}/flaky", address),
+                secret: None,
+                event_types: vec![],
+            }],
+            max_attempts: WebhookConfig::default_max_attempts(),
+            initial_backoff_ms: 200,
+            max_backoff_ms: 200,
+            request_timeout_ms: WebhookConfig::default_request_timeout_ms(),
+            poll_interval_ms: 10,
+            max_deliveries_per_poll: WebhookConfig::default_max_deliveries_per_poll(),
+        };
+        let outbox = TestOutbox::default();
+        let mut step = WebhookSinkStep::new(config, outbox.clone()).unwrap();
+        step.init().await;
+        let batch = |transaction_version: i64| TransactionContext {
+            data: vec![RaffleEvent {
+                transaction_version,
+                event_index: 0,
+                winner: Some("0xa".to_string()),
+            }],
+            metadata: TransactionMetadata {
+                start_version: transaction_version as u64,
+                end_version: transaction_version as u64,
+                ..Default::default()
+            },
+        };
+        // Batches are passed on without waiting for their deliveries
+        step.process(batch(10)).await.unwrap();
+        step.process(batch(11)).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while outbox
+                .deliveries
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(_, _, delivered)| !delivered)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // The second delivery waited for the retry of the first one
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["10:0".to_string(), "10:0".to_string(), "11:0".to_string(),]
+        );
+        step.poll().await.unwrap();
+        step.cleanup().await.unwrap();
+    }
+}
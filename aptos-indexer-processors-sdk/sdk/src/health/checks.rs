@@ -0,0 +1,205 @@
+use crate::health::{
+    readiness::{set_not_ready, set_ready},
+    status::head_lag_secs,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+pub const TRANSACTION_STREAM_READINESS_COMPONENT: &str = "transaction_stream";
+pub const LAG_READINESS_COMPONENT: &str = "lag";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadinessConfig {
+    #[serde(default = "ReadinessConfig::default_check_interval_secs")]
+    pub check_interval_secs: u64,
+    /// A health check that takes longer than this counts as failed.
+    #[serde(default = "ReadinessConfig::default_check_timeout_secs")]
+    pub check_timeout_secs: u64,
+    /// If set, the processor is not ready while it lags further behind the chain head than
+    /// this, e.g. while backfilling.
+    #[serde(default)]
+    pub max_lag_secs: Option<u64>,
+}
+
+impl ReadinessConfig {
+    pub const fn default_check_interval_secs() -> u64 {
+        5
+    }
+
+    pub const fn default_check_timeout_secs() -> u64 {
+        5
+    }
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_secs: Self::default_check_interval_secs(),
+            check_timeout_secs: Self::default_check_timeout_secs(),
+            max_lag_secs: None,
+        }
+    }
+}
+
+/// A dependency of the processor that has to be healthy for the processor to be ready, e.g.
+/// the database.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Returns an error describing the problem if the dependency is unhealthy.
+    async fn check(&self) -> Result<()>;
+}
+
+static HEALTH_CHECKS: Lazy<Mutex<Vec<Arc<dyn HealthCheck>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Registers a health check that is run periodically to decide readiness.
+pub fn register_health_check(health_check: Arc<dyn HealthCheck>) {
+    HEALTH_CHECKS.lock().unwrap().push(health_check);
+}
+
+fn health_check_component(name: &str) -> String {
+    format!("health_check::{}", name)
+}
+
+/// Periodically runs the registered health checks and the lag check, and updates readiness
+/// accordingly.
+pub struct ReadinessChecker {
+    config: ReadinessConfig,
+}
+
+impl ReadinessChecker {
+    pub fn new(config: ReadinessConfig) -> Self {
+        Self { config }
+    }
+
+    pub async fn run(self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.check_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            self.run_health_checks().await;
+            self.check_lag();
+        }
+    }
+
+    async fn run_health_checks(&self) {
+        let health_checks = HEALTH_CHECKS.lock().unwrap().clone();
+        let timeout = Duration::from_secs(self.config.check_timeout_secs);
+        for health_check in health_checks {
+            let name = health_check.name();
+            let component = health_check_component(&name);
+            match tokio::time::timeout(timeout, health_check.check()).await {
+                Ok(Ok(())) => set_ready(&component),
+                Ok(Err(e)) => {
+                    warn!(health_check = name, error = ?e, "Health check failed");
+                    set_not_ready(&component, format!("{:#}", e));
+                },
+                Err(_) => {
+                    warn!(health_check = name, "Health check timed out");
+                    set_not_ready(
+                        &component,
+                        format!("timed out after {}s", timeout.as_secs()),
+                    );
+                },
+            }
+        }
+    }
+
+    fn check_lag(&self) {
+        let Some(max_lag_secs) = self.config.max_lag_secs else {
+            return;
+        };
+        match head_lag_secs() {
+            Some(lag_secs) if lag_secs > max_lag_secs as f64 => set_not_ready(
+                LAG_READINESS_COMPONENT,
+                format!(
+                    "{:.0}s behind the chain head, more than {}s",
+                    lag_secs, max_lag_secs
+                ),
+            ),
+            // Nothing was processed yet, which is not lag
+            _ => set_ready(LAG_READINESS_COMPONENT),
+        }
+    }
+}
+
+/// Marks the transaction stream as connected or disconnected.
+pub fn set_transaction_stream_connected(connected: bool) {
+    if connected {
+        set_ready(TRANSACTION_STREAM_READINESS_COMPONENT);
+    } else {
+        info!("Transaction stream disconnected, reporting not ready until it reconnects");
+        set_not_ready(
+            TRANSACTION_STREAM_READINESS_COMPONENT,
+            "reconnecting to the transaction stream".to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{readiness::readiness, set_latest_tracked_transaction_timestamp};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    struct FailingHealthCheck;
+
+    #[async_trait]
+    impl HealthCheck for FailingHealthCheck {
+        fn name(&self) -> String {
+            "checks_test_failing".to_string()
+        }
+
+        async fn check(&self) -> Result<()> {
+            anyhow::bail!("database is down")
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_failing_health_check_flips_readiness() {
+        register_health_check(Arc::new(FailingHealthCheck));
+        ReadinessChecker::new(ReadinessConfig::default())
+            .run_health_checks()
+            .await;
+
+        let reasons = readiness().unwrap_err();
+        assert!(
+            reasons.contains(&"health_check::checks_test_failing: database is down".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lag_check_uses_tracked_transactions() {
+        let checker = ReadinessChecker::new(ReadinessConfig {
+            max_lag_secs: Some(60),
+            ..Default::default()
+        });
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let lag_reason = |reasons: Vec<String>| {
+            reasons
+                .into_iter()
+                .any(|reason| reason.starts_with(LAG_READINESS_COMPONENT))
+        };
+
+        set_latest_tracked_transaction_timestamp(now - 600.0);
+        checker.check_lag();
+        assert!(lag_reason(readiness().unwrap_err()));
+
+        set_latest_tracked_transaction_timestamp(now);
+        checker.check_lag();
+        assert!(!lag_reason(readiness().err().unwrap_or_default()));
+    }
+}
@@ -0,0 +1,15 @@
+pub mod checks;
+pub mod readiness;
+pub mod status;
+pub mod watchdog;
+
+pub use checks::{
+    register_health_check, set_transaction_stream_connected, HealthCheck, ReadinessChecker,
+    ReadinessConfig,
+};
+pub use readiness::{readiness, set_not_ready, set_ready};
+pub use status::{
+    register_status_provider, set_latest_tracked_transaction_timestamp, set_processor_name,
+    status_report, CheckpointStatus, StatusProvider, StatusReport,
+};
+pub use watchdog::{Watchdog, WatchdogAction, WatchdogConfig};
@@ -0,0 +1,50 @@
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Components that currently report the processor as not ready, with the reason why.
+static NOT_READY_COMPONENTS: Lazy<Mutex<BTreeMap<String, String>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Marks the processor as not ready because of `component`, e.g. a stalled step. The
+/// processor stays not ready until every component that flagged it calls `set_ready`.
+pub fn set_not_ready(component: &str, reason: String) {
+    NOT_READY_COMPONENTS
+        .lock()
+        .unwrap()
+        .insert(component.to_string(), reason);
+}
+
+pub fn set_ready(component: &str) {
+    NOT_READY_COMPONENTS.lock().unwrap().remove(component);
+}
+
+/// Returns `Ok` if the processor is ready, or the reasons reported by every component that
+/// is not ready.
+pub fn readiness() -> Result<(), Vec<String>> {
+    let not_ready_components = NOT_READY_COMPONENTS.lock().unwrap();
+    if not_ready_components.is_empty() {
+        return Ok(());
+    }
+    Err(not_ready_components
+        .iter()
+        .map(|(component, reason)| format!("{}: {}", component, reason))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_tracks_components() {
+        set_not_ready("readiness_test_a", "stalled".to_string());
+        set_not_ready("readiness_test_b", "down".to_string());
+        let reasons = readiness().unwrap_err();
+        assert!(reasons.contains(&"readiness_test_a: stalled".to_string()));
+
+        set_ready("readiness_test_a");
+        set_ready("readiness_test_b");
+        let reasons = readiness().err().unwrap_or_default();
+        assert!(!reasons.iter().any(|r| r.starts_with("readiness_test_")));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    builder::{live_graphs, StepStatus},
+    health::readiness::readiness,
+    utils::{
+        leader_election::is_leader,
+        step_metrics::{
+            StepMetricLabels, LATEST_POLLED_TRANSACTION_TIMESTAMP,
+            LATEST_PROCESSED_TRANSACTION_TIMESTAMP,
+        },
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// The last checkpoint the processor saved, e.g. its row in `processor_status`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckpointStatus {
+    pub last_success_version: u64,
+    pub last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+    pub last_updated: Option<chrono::NaiveDateTime>,
+}
+
+/// Reads the persisted state of the processor for the `/status` endpoint.
+#[async_trait]
+pub trait StatusProvider: Send + Sync {
+    async fn get_chain_id(&self) -> Result<Option<u64>>;
+
+    async fn get_checkpoint_status(&self) -> Result<Option<CheckpointStatus>>;
+}
+
+static PROCESSOR_NAME: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+static STATUS_PROVIDER: Lazy<Mutex<Option<Arc<dyn StatusProvider>>>> =
+    Lazy::new(|| Mutex::new(None));
+
+// Unix timestamp of the latest transaction that reached a `VersionTrackerStep`
+static LATEST_TRACKED_TRANSACTION_TIMESTAMP: Lazy<Mutex<Option<f64>>> =
+    Lazy::new(|| Mutex::new(None));
+
+pub fn set_processor_name(processor_name: String) {
+    *PROCESSOR_NAME.lock().unwrap() = Some(processor_name);
+}
+
+/// Registers the status provider of the processor, replacing any previous one.
+pub fn register_status_provider(status_provider: Arc<dyn StatusProvider>) {
+    *STATUS_PROVIDER.lock().unwrap() = Some(status_provider);
+}
+
+/// Records the timestamp of the latest transaction that went through the whole pipeline.
+/// `VersionTrackerStep` calls this for every batch it tracks.
+pub fn set_latest_tracked_transaction_timestamp(timestamp: f64) {
+    *LATEST_TRACKED_TRANSACTION_TIMESTAMP.lock().unwrap() = Some(timestamp);
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StepState {
+    pub name: String,
+    pub status: StepStatus,
+    pub last_processed_version: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StatusReport {
+    pub processor_name: Option<String>,
+    pub chain_id: Option<u64>,
+    pub last_checkpointed_version: Option<u64>,
+    pub last_checkpointed_transaction_timestamp: Option<chrono::NaiveDateTime>,
+    pub last_checkpoint_updated: Option<chrono::NaiveDateTime>,
+    /// Seconds between now and the latest transaction that went through the pipeline.
+    pub head_lag_secs: Option<f64>,
+    /// Whether this replica holds the processor lease. `None` if leader election is disabled.
+    pub is_leader: Option<bool>,
+    pub ready: bool,
+    pub not_ready_reasons: Vec<String>,
+    pub steps: Vec<StepState>,
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn latest_transaction_timestamp(step_name: &str) -> Option<f64> {
+    let labels = StepMetricLabels {
+        step_name: step_name.to_string(),
+    };
+    let timestamp = LATEST_PROCESSED_TRANSACTION_TIMESTAMP
+        .get_or_create(&labels)
+        .get()
+        .max(
+            LATEST_POLLED_TRANSACTION_TIMESTAMP
+                .get_or_create(&labels)
+                .get(),
+        );
+    (timestamp > 0.0).then_some(timestamp)
+}
+
+/// Returns how far behind the chain head the processor is, based on the latest transaction
+/// tracked by a `VersionTrackerStep`. Pipelines without one fall back to the latest
+/// transaction timestamp seen by their end steps. `None` if nothing was processed yet.
+pub fn head_lag_secs() -> Option<f64> {
+    let tracked_timestamp = *LATEST_TRACKED_TRANSACTION_TIMESTAMP.lock().unwrap();
+    tracked_timestamp
+        .or_else(|| {
+            live_graphs()
+                .iter()
+                .flat_map(|graph| graph.topology().nodes)
+                .filter(|node| node.end_step)
+                .filter_map(|node| latest_transaction_timestamp(&node.name))
+                .reduce(f64::max)
+        })
+        .map(|timestamp| (now_secs() - timestamp).max(0.0))
+}
+
+/// Builds the report served by `/status`.
+pub async fn status_report() -> StatusReport {
+    let status_provider = STATUS_PROVIDER.lock().unwrap().clone();
+    let (chain_id, checkpoint_status) = match status_provider {
+        Some(status_provider) => {
+            let chain_id = status_provider.get_chain_id().await.unwrap_or_else(|e| {
+                warn!(error = ?e, "Failed to get chain id for status");
+                None
+            });
+            let checkpoint_status = status_provider
+                .get_checkpoint_status()
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(error = ?e, "Failed to get checkpoint status for status");
+                    None
+                });
+            (chain_id, checkpoint_status)
+        },
+        None => (None, None),
+    };
+    let not_ready_reasons = readiness().err().unwrap_or_default();
+    let steps = live_graphs()
+        .iter()
+        .flat_map(|graph| graph.topology().nodes)
+        .map(|node| StepState {
+            name: node.name,
+            status: node.status,
+            last_processed_version: node.last_processed_version,
+        })
+        .collect();
+
+    StatusReport {
+        processor_name: PROCESSOR_NAME.lock().unwrap().clone(),
+        chain_id,
+        last_checkpointed_version: checkpoint_status
+            .as_ref()
+            .map(|status| status.last_success_version),
+        last_checkpointed_transaction_timestamp: checkpoint_status
+            .as_ref()
+            .and_then(|status| status.last_transaction_timestamp),
+        last_checkpoint_updated: checkpoint_status.and_then(|status| status.last_updated),
+        head_lag_secs: head_lag_secs(),
+        is_leader: is_leader(),
+        ready: not_ready_reasons.is_empty(),
+        not_ready_reasons,
+        steps,
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    admin::stream_control,
+    builder::{live_graphs, StepStatus},
+    health::readiness::{set_not_ready, set_ready},
+    utils::step_metrics::get_last_progress_timestamp_secs,
+};
+use instrumented_channel::channel_metrics::get_channel_size_and_capacity;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+
+/// What the watchdog does when a step has made no progress for too long.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogAction {
+    /// Log the stalled step.
+    #[default]
+    Log,
+    /// Log the stalled step and report the processor as not ready until it makes progress.
+    FlipReadiness,
+    /// Log the stalled step and exit the process, so that it gets restarted.
+    Exit,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// A running step that made no progress for this long is considered stalled.
+    #[serde(default = "WatchdogConfig::default_stall_timeout_secs")]
+    pub stall_timeout_secs: u64,
+    #[serde(default = "WatchdogConfig::default_check_interval_secs")]
+    pub check_interval_secs: u64,
+    #[serde(default)]
+    pub action: WatchdogAction,
+    /// Per step overrides of `stall_timeout_secs`, by step name. 0 disables the watchdog for
+    /// the step, e.g. for steps that legitimately stay idle.
+    #[serde(default)]
+    pub step_stall_timeout_secs: HashMap<String, u64>,
+}
+
+impl WatchdogConfig {
+    pub const fn default_stall_timeout_secs() -> u64 {
+        300
+    }
+
+    pub const fn default_check_interval_secs() -> u64 {
+        10
+    }
+
+    fn stall_timeout_for(&self, step_name: &str) -> Option<Duration> {
+        let timeout_secs = self
+            .step_stall_timeout_secs
+            .get(step_name)
+            .copied()
+            .unwrap_or(self.stall_timeout_secs);
+        (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs))
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout_secs: Self::default_stall_timeout_secs(),
+            check_interval_secs: Self::default_check_interval_secs(),
+            action: WatchdogAction::default(),
+            step_stall_timeout_secs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StalledStep {
+    pub step_name: String,
+    pub secs_since_progress: u64,
+    /// Whether the output channel of the step is full, i.e. the step is blocked by a slow
+    /// downstream step rather than stalled itself.
+    pub output_channel_full: bool,
+}
+
+/// Watches the running steps of all live pipelines and fires the configured action when one
+/// of them makes no progress, as reported by `StepMetrics`, for longer than its timeout.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    started_at: f64,
+    // Steps that are currently reported as stalled
+    stalled_steps: HashSet<String>,
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn readiness_component(step_name: &str) -> String {
+    format!("watchdog::{}", step_name)
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            started_at: now_secs(),
+            stalled_steps: HashSet::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        info!(
+            stall_timeout_secs = self.config.stall_timeout_secs,
+            action = ?self.config.action,
+            "Starting watchdog"
+        );
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.check_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            // Steps are expected to be idle while the stream is paused from the admin API
+            if stream_control().is_paused() {
+                continue;
+            }
+            self.check();
+        }
+    }
+
+    /// Checks the running steps of all live pipelines once, fires the configured action for
+    /// the stalled ones and returns them.
+    pub fn check(&mut self) -> Vec<StalledStep> {
+        let running_steps = live_graphs()
+            .iter()
+            .flat_map(|graph| graph.topology().nodes)
+            .filter(|node| node.status == StepStatus::Running)
+            .map(|node| node.name)
+            .collect::<Vec<_>>();
+        let stalled_steps = self.find_stalled_steps(&running_steps, now_secs());
+        self.handle_stalled_steps(stalled_steps.clone());
+        stalled_steps
+    }
+
+    /// Returns the steps among `step_names` that made no progress within their timeout. Steps
+    /// that never made progress are measured from the start of the watchdog, and time spent
+    /// paused does not count.
+    pub fn find_stalled_steps(&self, step_names: &[String], now: f64) -> Vec<StalledStep> {
+        step_names
+            .iter()
+            .filter_map(|step_name| {
+                let stall_timeout = self.config.stall_timeout_for(step_name)?;
+                let last_progress = get_last_progress_timestamp_secs(step_name)
+                    .unwrap_or(self.started_at)
+                    .max(stream_control().last_resumed_at_secs().unwrap_or_default());
+                let since_progress = (now - last_progress).max(0.0);
+                if since_progress < stall_timeout.as_secs_f64() {
+                    return None;
+                }
+                let (len, capacity) = get_channel_size_and_capacity(step_name);
+                Some(StalledStep {
+                    step_name: step_name.clone(),
+                    secs_since_progress: since_progress as u64,
+                    output_channel_full: capacity > 0 && len >= capacity,
+                })
+            })
+            .collect()
+    }
+
+    fn handle_stalled_steps(&mut self, stalled_steps: Vec<StalledStep>) {
+        let stalled_step_names = stalled_steps
+            .iter()
+            .map(|step| step.step_name.clone())
+            .collect::<HashSet<_>>();
+
+        // Steps that were stalled and made progress since
+        for step_name in self.stalled_steps.difference(&stalled_step_names) {
+            info!(step_name = step_name, "Step made progress again");
+            set_ready(&readiness_component(step_name));
+        }
+
+        for stalled_step in &stalled_steps {
+            warn!(
+                step_name = stalled_step.step_name,
+                secs_since_progress = stalled_step.secs_since_progress,
+                output_channel_full = stalled_step.output_channel_full,
+                "Step has made no progress"
+            );
+            match self.config.action {
+                WatchdogAction::Log => {},
+                WatchdogAction::FlipReadiness => set_not_ready(
+                    &readiness_component(&stalled_step.step_name),
+                    format!("no progress for {}s", stalled_step.secs_since_progress),
+                ),
+                WatchdogAction::Exit => {
+                    error!(
+                        step_name = stalled_step.step_name,
+                        "Exiting because of a stalled step"
+                    );
+                    std::process::exit(1);
+                },
+            }
+        }
+        self.stalled_steps = stalled_step_names;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        health::readiness::readiness,
+        utils::step_metrics::{StepMetricLabels, StepMetricsBuilder},
+    };
+
+    fn log_progress(step_name: &str) {
+        StepMetricsBuilder::default()
+            .labels(StepMetricLabels {
+                step_name: step_name.to_string(),
+            })
+            .build()
+            .unwrap()
+            .log_metrics();
+    }
+
+    #[test]
+    fn test_watchdog_finds_stalled_steps() {
+        log_progress("WatchdogActiveStep");
+        log_progress("WatchdogIdleStep");
+        let watchdog = Watchdog::new(WatchdogConfig {
+            stall_timeout_secs: 60,
+            step_stall_timeout_secs: HashMap::from([("WatchdogIdleStep".to_string(), 0)]),
+            ..Default::default()
+        });
+        let steps = vec![
+            "WatchdogActiveStep".to_string(),
+            "WatchdogIdleStep".to_string(),
+            "WatchdogSilentStep".to_string(),
+        ];
+
+        assert!(watchdog.find_stalled_steps(&steps, now_secs()).is_empty());
+
+        // Two minutes later, only the steps with a timeout are stalled
+        let stalled = watchdog.find_stalled_steps(&steps, now_secs() + 120.0);
+        let stalled_names = stalled
+            .iter()
+            .map(|step| step.step_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stalled_names,
+            vec!["WatchdogActiveStep", "WatchdogSilentStep"]
+        );
+        assert!(stalled[0].secs_since_progress >= 119);
+    }
+
+    #[test]
+    fn test_watchdog_flips_readiness() {
+        let mut watchdog = Watchdog::new(WatchdogConfig {
+            action: WatchdogAction::FlipReadiness,
+            ..Default::default()
+        });
+        let steps = vec!["WatchdogReadinessStep".to_string()];
+
+        let stalled = watchdog.find_stalled_steps(&steps, now_secs() + 600.0);
+        watchdog.handle_stalled_steps(stalled);
+        assert!(readiness()
+            .unwrap_err()
+            .iter()
+            .any(|reason| reason.starts_with("watchdog::WatchdogReadinessStep")));
+
+        watchdog.handle_stalled_steps(vec![]);
+        let reasons = readiness().err().unwrap_or_default();
+        assert!(!reasons
+            .iter()
+            .any(|reason| reason.starts_with("watchdog::WatchdogReadinessStep")));
+    }
+}
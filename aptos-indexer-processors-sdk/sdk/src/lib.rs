@@ -1,8 +1,12 @@
+pub mod admin;
 pub mod builder;
 pub mod common_steps; // TODO: Feature gate this?
+pub mod health;
 #[cfg(feature = "postgres_partial")]
 pub mod postgres;
 pub mod server_framework;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod test;
 #[cfg(feature = "testing_framework")]
 pub mod testing_framework;
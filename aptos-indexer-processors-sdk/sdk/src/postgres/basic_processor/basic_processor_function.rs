@@ -1,32 +1,47 @@
 use super::basic_processor_step::BasicProcessorStep;
 use crate::{
     aptos_indexer_transaction_stream::TransactionStreamConfig,
-    builder::ProcessorBuilder,
+    builder::{GraphBuilder, PipelineConfig, ProcessorBuilder, StepRegistry},
     common_steps::{
         TransactionStreamStep, VersionTrackerStep, DEFAULT_UPDATE_PROCESSOR_STATUS_SECS,
     },
+    health::{register_health_check, register_status_provider},
     postgres::{
         subconfigs::postgres_config::PostgresConfig,
         utils::{
-            checkpoint::{
-                get_starting_version, PostgresChainIdChecker, PostgresProcessorStatusSaver,
-            },
-            database::{new_db_pool, run_migrations, ArcDbPool},
+            checkpoint::{PostgresCheckpointStore, PostgresStatusProvider},
+            commands::{backfill_processor_name, migrate, print_status, reset_checkpoint},
+            copy::{register_copy_writer, CopyWriter},
+            database::{new_db_pools, ArcDbPool, PostgresHealthCheck},
+            lease::PostgresLeaseStore,
+            migrations::run_migrations_with_lock,
+            notify::{register_notifier, Notifier},
+            query_server::{run_query_server, QueryCheckpoint},
         },
-        SDK_MIGRATIONS,
     },
     server_framework::{
         load, register_probes_and_metrics_handler, setup_logging, setup_panic_handler,
-        GenericConfig, ServerArgs,
+        spawn_admin_server, spawn_health_monitors, GenericConfig, ServerArgs, ServerCommand,
     },
     traits::IntoRunnableStep,
-    utils::{chain_id_check::check_or_update_chain_id, errors::ProcessorError},
+    utils::{
+        chain_id_check::check_or_update_chain_id,
+        checkpoint_store::{
+            get_starting_version, print_checkpoint_store_status, reset_checkpoint_store,
+            CheckpointStore, CheckpointStoreStatusProvider,
+        },
+        errors::ProcessorError,
+        leader_election::{
+            register_leadership, LeaderElectionConfig, LeaderElector, LeaderFencedCheckpointStore,
+        },
+    },
 };
 use anyhow::Result;
 use aptos_protos::transaction::v1::Transaction;
 use clap::Parser;
 use diesel_migrations::EmbeddedMigrations;
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 use tracing::info;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -36,12 +51,56 @@ pub struct ProcessConfig {
     pub postgres_config: PostgresConfig,
 }
 
-/// Processes transactions with a custom handler function.
+/// Processes transactions with a custom handler function. Also handles the operational
+/// subcommands of `ServerArgs`, e.g. `migrate` or `backfill`.
 pub async fn process<F, Fut>(
     processor_name: String,
     embedded_migrations: EmbeddedMigrations,
     process_function: F,
 ) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    process_with_optional_checkpoint_store(
+        processor_name,
+        embedded_migrations,
+        None,
+        process_function,
+    )
+    .await
+}
+
+/// Like `process`, but keeps the chain ID and the checkpoint in `checkpoint_store` instead of
+/// `ledger_infos` and `processor_status`. The `status` and `reset-checkpoint` subcommands use
+/// it too. A backfill keeps its own checkpoint in `processor_status`, since `checkpoint_store`
+/// holds the checkpoint of the processor.
+pub async fn process_with_checkpoint_store<F, Fut, C>(
+    processor_name: String,
+    embedded_migrations: EmbeddedMigrations,
+    checkpoint_store: Arc<C>,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+    C: CheckpointStore + 'static,
+{
+    process_with_optional_checkpoint_store(
+        processor_name,
+        embedded_migrations,
+        Some(checkpoint_store),
+        process_function,
+    )
+    .await
+}
+
+async fn process_with_optional_checkpoint_store<F, Fut>(
+    processor_name: String,
+    embedded_migrations: EmbeddedMigrations,
+    mut checkpoint_store: Option<Arc<dyn CheckpointStore>>,
+    process_function: F,
+) -> Result<()>
 where
     F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
     Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
@@ -50,20 +109,94 @@ where
     setup_logging();
     setup_panic_handler();
     let config = load::<GenericConfig<ProcessConfig>>(&args.config_path)?;
+    let (processor_name, transaction_stream_config, health_port, is_backfill) = match args
+        .get_command()
+    {
+        ServerCommand::Run => (
+            processor_name,
+            config.server_config.transaction_stream_config.clone(),
+            config.health_check_port,
+            false,
+        ),
+        ServerCommand::Backfill {
+            from,
+            to,
+            health_check_port,
+        } => {
+            if from > to {
+                anyhow::bail!(
+                    "Cannot backfill from {} to {}, `from` is after `to`",
+                    from,
+                    to
+                );
+            }
+            // The backfill checkpoint lives under its own name, so that it starts at `from` and
+            // leaves the checkpoint of the processor in `checkpoint_store` alone
+            checkpoint_store = None;
+            (
+                backfill_processor_name(&processor_name, from, to),
+                TransactionStreamConfig {
+                    starting_version: Some(from),
+                    request_ending_version: Some(to),
+                    ..config.server_config.transaction_stream_config.clone()
+                },
+                health_check_port,
+                true,
+            )
+        },
+        ServerCommand::ValidateConfig => {
+            if let Some(pipeline_config) = &config.pipeline {
+                pipeline_config.validate(&basic_processor_registry::<F, Fut>(None, None, None))?;
+            }
+            println!("Config at {:?} is valid", args.config_path);
+            return Ok(());
+        },
+        ServerCommand::PrintDag => {
+            let graph = match &config.pipeline {
+                Some(pipeline_config) => {
+                    pipeline_config.graph(&basic_processor_registry::<F, Fut>(None, None, None))?
+                },
+                None => basic_processor_graph::<F, Fut>(),
+            };
+            println!("{}", graph.dot());
+            return Ok(());
+        },
+        command => {
+            return run_db_command(
+                &processor_name,
+                &config.server_config.postgres_config,
+                checkpoint_store,
+                embedded_migrations,
+                command,
+            )
+            .await;
+        },
+    };
     let handle = tokio::runtime::Handle::current();
 
-    let health_port = config.health_check_port;
     let additional_labels = config.metrics_config.additional_labels.clone();
     // Start liveness and readiness probes.
     let task_handler = handle.spawn(async move {
         register_probes_and_metrics_handler(health_port, additional_labels).await;
         anyhow::Ok(())
     });
+    spawn_health_monitors(&config, processor_name.clone(), &handle);
+    // A backfill runs next to the processor, which already serves the admin and query APIs
+    if !is_backfill {
+        spawn_admin_server(&config, &handle)?;
+    }
+    let mut postgres_config = config.server_config.postgres_config;
+    if is_backfill {
+        postgres_config.query_server_config = None;
+    }
     let main_task_handler = handle.spawn(async move {
         run_processor(
             processor_name,
-            config.server_config.transaction_stream_config,
-            config.server_config.postgres_config,
+            transaction_stream_config,
+            postgres_config,
+            config.leader_election,
+            config.pipeline,
+            checkpoint_store,
             embedded_migrations,
             process_function,
         )
@@ -79,10 +212,125 @@ where
     }
 }
 
+/// Runs the subcommands that only need the database, or the custom checkpoint store.
+async fn run_db_command(
+    processor_name: &str,
+    postgres_config: &PostgresConfig,
+    checkpoint_store: Option<Arc<dyn CheckpointStore>>,
+    embedded_migrations: EmbeddedMigrations,
+    command: ServerCommand,
+) -> Result<()> {
+    let (db_pool, read_db_pool) = new_db_pools(postgres_config).await?;
+    match (command, checkpoint_store) {
+        (ServerCommand::Migrate { dry_run }, _) => {
+            migrate(postgres_config, db_pool, embedded_migrations, dry_run).await
+        },
+        (ServerCommand::Status, Some(checkpoint_store)) => {
+            print_checkpoint_store_status(processor_name, checkpoint_store.as_ref()).await
+        },
+        (ServerCommand::Status, None) => print_status(processor_name, read_db_pool).await,
+        (ServerCommand::ResetCheckpoint { to }, Some(checkpoint_store)) => {
+            reset_checkpoint_store(processor_name, checkpoint_store.as_ref(), to).await
+        },
+        (ServerCommand::ResetCheckpoint { to }, None) => {
+            reset_checkpoint(processor_name, db_pool, to).await
+        },
+        (command, _) => anyhow::bail!("`{}` does not need the database", command.name()),
+    }
+}
+
+/// The graph of the steps connected in `run_processor`, without building them.
+fn basic_processor_graph<F, Fut>() -> GraphBuilder
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    let mut graph = GraphBuilder::new();
+    let transaction_stream = graph.add_node(
+        "TransactionStreamStep".to_string(),
+        std::any::type_name::<TransactionStreamStep>().to_string(),
+        std::any::type_name::<()>().to_string(),
+        std::any::type_name::<Vec<Transaction>>().to_string(),
+    );
+    let basic_processor_step = graph.add_node(
+        "BasicProcessorStep".to_string(),
+        std::any::type_name::<BasicProcessorStep<F, Fut>>().to_string(),
+        std::any::type_name::<Vec<Transaction>>().to_string(),
+        std::any::type_name::<()>().to_string(),
+    );
+    graph.add_edge_from_to(transaction_stream, basic_processor_step);
+    let version_tracker = graph.add_node(
+        format!("VersionTrackerStep: {}", std::any::type_name::<()>()),
+        std::any::type_name::<VersionTrackerStep<(), Arc<dyn CheckpointStore>>>().to_string(),
+        std::any::type_name::<()>().to_string(),
+        std::any::type_name::<()>().to_string(),
+    );
+    graph.add_edge_from_to(basic_processor_step, version_tracker);
+    graph.set_end_step();
+    graph
+}
+
+/// The step kinds that a `pipeline` from config can use instead of the hardcoded steps: the
+/// SDK's common steps, plus
+/// - `processor_stream`: the transaction stream of `transaction_stream_config`, starting from
+///   the checkpoint of the processor.
+/// - `process`: runs the process function on each batch of transactions.
+/// - `version_tracker`: saves the checkpoint of the processor.
+///
+/// These take no config and can each be used once. Their steps are only `None` when the
+/// pipeline is validated or printed rather than run.
+fn basic_processor_registry<F, Fut>(
+    transaction_stream_config: Option<TransactionStreamConfig>,
+    basic_processor_step: Option<BasicProcessorStep<F, Fut>>,
+    version_tracker: Option<VersionTrackerStep<(), Arc<dyn CheckpointStore>>>,
+) -> StepRegistry
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    let mut registry = StepRegistry::with_common_steps();
+    let transaction_stream_config = Arc::new(Mutex::new(transaction_stream_config));
+    registry
+        .register("processor_stream", move |_: ()| {
+            let config = take_step(&transaction_stream_config, "processor_stream");
+            async move {
+                Ok(TransactionStreamStep::new(config?)
+                    .await?
+                    .into_runnable_step())
+            }
+        })
+        .expect("Basic processor step kinds are unique");
+    let basic_processor_step = Arc::new(Mutex::new(basic_processor_step));
+    registry
+        .register("process", move |_: ()| {
+            let step = take_step(&basic_processor_step, "process");
+            async move { Ok(step?.into_runnable_step()) }
+        })
+        .expect("Basic processor step kinds are unique");
+    let version_tracker = Arc::new(Mutex::new(version_tracker));
+    registry
+        .register("version_tracker", move |_: ()| {
+            let step = take_step(&version_tracker, "version_tracker");
+            async move { Ok(step?.into_runnable_step()) }
+        })
+        .expect("Basic processor step kinds are unique");
+    registry
+}
+
+fn take_step<T>(step: &Mutex<Option<T>>, kind: &str) -> Result<T> {
+    step.lock()
+        .expect("Step mutex is poisoned")
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("Step kind `{}` can only be used once", kind))
+}
+
 async fn run_processor<F, Fut>(
     processor_name: String,
     transaction_stream_config: TransactionStreamConfig,
     postgres_config: PostgresConfig,
+    leader_election_config: Option<LeaderElectionConfig>,
+    pipeline_config: Option<PipelineConfig>,
+    checkpoint_store: Option<Arc<dyn CheckpointStore>>,
     embedded_migrations: EmbeddedMigrations,
     process_function: F,
 ) -> Result<()>
@@ -90,75 +338,178 @@ where
     F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
     Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
 {
-    // Create a connection pool
-    let db_pool = new_db_pool(
-        &postgres_config.connection_string,
-        Some(postgres_config.db_pool_size),
-    )
-    .await
-    .expect("Failed to create connection pool");
+    // Create the connection pools. Their connections find the processor's and the SDK's tables
+    // in the configured schemas. Read-only queries go to the read replica, if there is one.
+    let (db_pool, read_db_pool) = new_db_pools(&postgres_config)
+        .await
+        .expect("Failed to create connection pool");
+    if !postgres_config.copy_tables.is_empty() {
+        register_copy_writer(CopyWriter::new(&postgres_config)?);
+    }
+    if let Some(notify_config) = &postgres_config.notify_config {
+        register_notifier(Notifier::new(notify_config));
+    }
+    register_health_check(Arc::new(PostgresHealthCheck::new(db_pool.clone())));
+    let (checkpoint_store, query_checkpoint): (Arc<dyn CheckpointStore>, _) = match checkpoint_store
+    {
+        Some(checkpoint_store) => {
+            register_status_provider(Arc::new(CheckpointStoreStatusProvider::new(
+                checkpoint_store.clone(),
+            )));
+            (
+                checkpoint_store.clone(),
+                QueryCheckpoint::Store(checkpoint_store),
+            )
+        },
+        None => {
+            register_status_provider(Arc::new(PostgresStatusProvider::new(
+                processor_name.as_str(),
+                read_db_pool.clone(),
+            )));
+            (
+                Arc::new(PostgresCheckpointStore::new(
+                    processor_name.as_str(),
+                    db_pool.clone(),
+                )),
+                QueryCheckpoint::ProcessorStatus(processor_name.clone()),
+            )
+        },
+    };
 
-    // Run user migrations
-    run_migrations(
-        postgres_config.connection_string.clone(),
+    // Run user and SDK migrations, one replica at a time
+    run_migrations_with_lock(
         db_pool.clone(),
-        embedded_migrations,
+        postgres_config.migration_sets(embedded_migrations),
+        &postgres_config.migration_config,
     )
-    .await;
+    .await?;
 
-    // Run SDK migrations
-    run_migrations(
-        postgres_config.connection_string.clone(),
-        db_pool.clone(),
-        SDK_MIGRATIONS,
-    )
-    .await;
+    // Serve the registered tables once they exist
+    if let Some(query_server_config) = postgres_config.query_server_config.clone() {
+        query_server_config.validate()?;
+        tokio::spawn(run_query_server(
+            query_server_config,
+            read_db_pool.clone(),
+            query_checkpoint,
+        ));
+    }
 
-    check_or_update_chain_id(
-        &transaction_stream_config,
-        &PostgresChainIdChecker::new(db_pool.clone()),
+    run_processor_with_checkpoint_store(
+        processor_name,
+        transaction_stream_config,
+        leader_election_config,
+        pipeline_config,
+        db_pool,
+        checkpoint_store,
+        process_function,
     )
-    .await?;
+    .await
+}
 
-    // Merge the starting version from config and the latest processed version from the DB
-    let starting_version = get_starting_version(
-        processor_name.as_str(),
-        transaction_stream_config.clone(),
-        db_pool.clone(),
-    )
-    .await?;
+/// Runs the steps of the processor once the database is set up, keeping its progress in
+/// `checkpoint_store`.
+async fn run_processor_with_checkpoint_store<F, Fut>(
+    processor_name: String,
+    transaction_stream_config: TransactionStreamConfig,
+    leader_election_config: Option<LeaderElectionConfig>,
+    pipeline_config: Option<PipelineConfig>,
+    db_pool: ArcDbPool,
+    checkpoint_store: Arc<dyn CheckpointStore>,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    check_or_update_chain_id(&transaction_stream_config, &checkpoint_store).await?;
+
+    // With leader election, stay a warm standby until this replica holds the lease. Taking
+    // over starts from the checkpoint of the previous leader, read below.
+    let leader_elector = match leader_election_config {
+        Some(leader_election_config) => {
+            let leader_elector = Arc::new(LeaderElector::new(
+                leader_election_config,
+                PostgresLeaseStore::new(processor_name.as_str(), db_pool.clone()),
+            ));
+            register_leadership(leader_elector.leadership());
+            leader_elector.wait_for_leadership().await;
+            let maintained_elector = leader_elector.clone();
+            let resume_stream_config = transaction_stream_config.clone();
+            let resume_checkpoint_store = checkpoint_store.clone();
+            let maintain_handle = tokio::spawn(async move {
+                maintained_elector
+                    .maintain_leadership(move || {
+                        let transaction_stream_config = resume_stream_config.clone();
+                        let checkpoint_store = resume_checkpoint_store.clone();
+                        async move {
+                            get_starting_version(
+                                checkpoint_store.as_ref(),
+                                &transaction_stream_config,
+                            )
+                            .await
+                        }
+                    })
+                    .await
+            });
+            Some((leader_elector, maintain_handle))
+        },
+        None => None,
+    };
+
+    // Merge the starting version from config and the latest processed version from the
+    // checkpoint store
+    let starting_version =
+        get_starting_version(checkpoint_store.as_ref(), &transaction_stream_config).await?;
 
     // Define processor steps
-    let transaction_stream_config = transaction_stream_config.clone();
-    let transaction_stream = TransactionStreamStep::new(TransactionStreamConfig {
+    let transaction_stream_config = TransactionStreamConfig {
         starting_version: Some(starting_version),
         ..transaction_stream_config
-    })
-    .await?;
+    };
     let basic_processor_step = BasicProcessorStep {
         process_function,
         conn_pool: db_pool.clone(),
     };
-    let processor_status_saver =
-        PostgresProcessorStatusSaver::new(processor_name.as_str(), db_pool.clone());
+    // Only the leader moves the checkpoint
+    let checkpoint_store: Arc<dyn CheckpointStore> = match &leader_elector {
+        Some((leader_elector, _)) => Arc::new(LeaderFencedCheckpointStore::new(
+            checkpoint_store,
+            leader_elector.clone(),
+        )),
+        None => checkpoint_store,
+    };
     let version_tracker =
-        VersionTrackerStep::new(processor_status_saver, DEFAULT_UPDATE_PROCESSOR_STATUS_SECS);
+        VersionTrackerStep::new(checkpoint_store, DEFAULT_UPDATE_PROCESSOR_STATUS_SECS);
 
-    // Connect processor steps together
-    let (_, buffer_receiver) =
-        ProcessorBuilder::new_with_inputless_first_step(transaction_stream.into_runnable_step())
+    match pipeline_config {
+        // Connect the steps as described in config
+        Some(pipeline_config) => {
+            let registry = basic_processor_registry(
+                Some(transaction_stream_config),
+                Some(basic_processor_step),
+                Some(version_tracker),
+            );
+            pipeline_config.build(&registry).await?.wait().await?;
+            info!("Pipeline finished");
+        },
+        // Connect processor steps together
+        None => {
+            let transaction_stream = TransactionStreamStep::new(transaction_stream_config).await?;
+            let (_, buffer_receiver) = ProcessorBuilder::new_with_inputless_first_step(
+                transaction_stream.into_runnable_step(),
+            )
             .connect_to(basic_processor_step.into_runnable_step(), 10)
             .connect_to(version_tracker.into_runnable_step(), 10)
             .end_and_return_output_receiver(10);
 
-    // (Optional) Parse the results
-    loop {
-        match buffer_receiver.recv().await {
-            Ok(_) => {},
-            Err(_) => {
-                info!("Channel is closed");
-                return Ok(());
-            },
-        }
+            // (Optional) Parse the results
+            while buffer_receiver.recv().await.is_ok() {}
+            info!("Channel is closed");
+        },
+    }
+    if let Some((leader_elector, maintain_handle)) = leader_elector {
+        maintain_handle.abort();
+        leader_elector.release().await;
     }
+    Ok(())
 }
@@ -1,5 +1,8 @@
 use crate::{
-    postgres::utils::database::ArcDbPool,
+    postgres::utils::{
+        database::ArcDbPool,
+        notify::{notify_committed_batch, track_written_rows},
+    },
     traits::{AsyncRunType, AsyncStep, NamedStep, Processable},
     types::transaction_context::TransactionContext,
     utils::errors::ProcessorError,
@@ -7,6 +10,7 @@ use crate::{
 use anyhow::Result;
 use aptos_protos::transaction::v1::Transaction;
 use async_trait::async_trait;
+use tracing::warn;
 
 // Basic process step that runs a process function on each transaction
 pub struct BasicProcessorStep<F, Fut>
@@ -32,11 +36,27 @@ where
         &mut self,
         transactions: TransactionContext<Vec<Transaction>>,
     ) -> Result<Option<TransactionContext<()>>, ProcessorError> {
-        (self.process_function)(transactions.data, self.conn_pool.clone())
+        let (result, written_rows) = track_written_rows((self.process_function)(
+            transactions.data,
+            self.conn_pool.clone(),
+        ))
+        .await;
+        result.map_err(|e| ProcessorError::ProcessError {
+            message: format!("Processing transactionsfailed: {:?}", e),
+        })?;
+        // The rows are committed at this point, so a failed notification doesn't fail the batch
+        for (table_name, row_count) in written_rows {
+            if let Err(e) = notify_committed_batch(
+                self.conn_pool.clone(),
+                &table_name,
+                &transactions.metadata,
+                row_count,
+            )
             .await
-            .map_err(|e| ProcessorError::ProcessError {
-                message: format!("Processing transactionsfailed: {:?}", e),
-            })?;
+            {
+                warn!(table_name = %table_name, error = ?e, "Failed to notify a committed batch");
+            }
+        }
         Ok(Some(TransactionContext {
             data: (), // Stub out data since it's not used in the next step
             metadata: transactions.metadata,
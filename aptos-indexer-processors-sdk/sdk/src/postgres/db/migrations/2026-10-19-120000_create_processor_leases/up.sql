@@ -0,0 +1,20 @@
+-- Tracks which replica of a processor holds the lease to stream and write. Created in the
+-- schema the SDK migrations run in, or `processor_metadata` when run without one.
+DO $$
+DECLARE
+  metadata_schema TEXT := COALESCE(
+    NULLIF(current_setting('aptos_indexer.migration_schema', true), ''),
+    'processor_metadata'
+  );
+BEGIN
+  EXECUTE format('CREATE SCHEMA IF NOT EXISTS %I', metadata_schema);
+  EXECUTE format(
+    'CREATE TABLE IF NOT EXISTS %I.processor_leases (
+      processor VARCHAR(100) UNIQUE PRIMARY KEY NOT NULL,
+      holder VARCHAR(200) NOT NULL,
+      lease_expires_at TIMESTAMP NOT NULL,
+      last_updated TIMESTAMP NOT NULL DEFAULT NOW()
+    )',
+    metadata_schema
+  );
+END $$;
@@ -0,0 +1,14 @@
+-- In `processor_metadata`, these are the tables of the core schema migration, which drops them
+-- itself.
+DO $$
+DECLARE
+  metadata_schema TEXT := COALESCE(
+    NULLIF(current_setting('aptos_indexer.migration_schema', true), ''),
+    'processor_metadata'
+  );
+BEGIN
+  IF metadata_schema <> 'processor_metadata' THEN
+    EXECUTE format('DROP TABLE IF EXISTS %I.processor_status', metadata_schema);
+    EXECUTE format('DROP TABLE IF EXISTS %I.ledger_infos', metadata_schema);
+  END IF;
+END $$;
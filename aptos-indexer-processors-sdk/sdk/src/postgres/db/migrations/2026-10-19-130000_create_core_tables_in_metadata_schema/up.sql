@@ -0,0 +1,27 @@
+-- The core schema migration creates these tables in `processor_metadata`. Create them in the
+-- schema the SDK migrations run in, i.e. the configured `metadata_schema`. This is a no-op for
+-- the default `metadata_schema`, or when run without one.
+DO $$
+DECLARE
+  metadata_schema TEXT := COALESCE(
+    NULLIF(current_setting('aptos_indexer.migration_schema', true), ''),
+    'processor_metadata'
+  );
+BEGIN
+  EXECUTE format('CREATE SCHEMA IF NOT EXISTS %I', metadata_schema);
+  -- Tracks latest processed version per processor
+  EXECUTE format(
+    'CREATE TABLE IF NOT EXISTS %I.processor_status (
+      processor VARCHAR(100) UNIQUE PRIMARY KEY NOT NULL,
+      last_success_version BIGINT NOT NULL,
+      last_updated TIMESTAMP NOT NULL DEFAULT NOW(),
+      last_transaction_timestamp TIMESTAMP NULL
+    )',
+    metadata_schema
+  );
+  -- Tracks chain id
+  EXECUTE format(
+    'CREATE TABLE IF NOT EXISTS %I.ledger_infos (chain_id BIGINT UNIQUE PRIMARY KEY NOT NULL)',
+    metadata_schema
+  );
+END $$;
@@ -1,14 +1,29 @@
 // @generated automatically by Diesel CLI.
 
+// Tables are not qualified with their schema, so that they are found through the `search_path`
+// in `PostgresConfig::metadata_schema`. Pools from `new_db_pool` use the default
+// `processor_metadata`.
+
 pub mod processor_metadata {
     diesel::table! {
-        processor_metadata.ledger_infos (chain_id) {
+        ledger_infos (chain_id) {
             chain_id -> Int8,
         }
     }
 
     diesel::table! {
-        processor_metadata.processor_status (processor) {
+        processor_leases (processor) {
+            #[max_length = 100]
+            processor -> Varchar,
+            #[max_length = 200]
+            holder -> Varchar,
+            lease_expires_at -> Timestamp,
+            last_updated -> Timestamp,
+        }
+    }
+
+    diesel::table! {
+        processor_status (processor) {
             #[max_length = 100]
             processor -> Varchar,
             last_success_version -> Int8,
@@ -17,5 +32,31 @@ pub mod processor_metadata {
         }
     }
 
-    diesel::allow_tables_to_appear_in_same_query!(ledger_infos, processor_status,);
+    diesel::table! {
+        webhook_outbox (id) {
+            id -> Int8,
+            #[max_length = 100]
+            processor -> Varchar,
+            #[max_length = 100]
+            endpoint -> Varchar,
+            #[max_length = 100]
+            idempotency_key -> Varchar,
+            #[max_length = 200]
+            event_type -> Varchar,
+            transaction_version -> Int8,
+            payload -> Text,
+            attempts -> Int4,
+            next_attempt_at -> Nullable<Timestamp>,
+            last_error -> Nullable<Text>,
+            created_at -> Timestamp,
+            delivered_at -> Nullable<Timestamp>,
+        }
+    }
+
+    diesel::allow_tables_to_appear_in_same_query!(
+        ledger_infos,
+        processor_leases,
+        processor_status,
+        webhook_outbox,
+    );
 }
@@ -0,0 +1,9 @@
+DO $$
+DECLARE
+  metadata_schema TEXT := COALESCE(
+    NULLIF(current_setting('aptos_indexer.migration_schema', true), ''),
+    'processor_metadata'
+  );
+BEGIN
+  EXECUTE format('DROP TABLE IF EXISTS %I.webhook_outbox', metadata_schema);
+END $$;
@@ -0,0 +1,35 @@
+-- Webhook deliveries of WebhookSinkStep, kept once delivered or abandoned for auditing. Created
+-- in the schema the SDK migrations run in, or `processor_metadata` when run without one.
+DO $$
+DECLARE
+  metadata_schema TEXT := COALESCE(
+    NULLIF(current_setting('aptos_indexer.migration_schema', true), ''),
+    'processor_metadata'
+  );
+BEGIN
+  EXECUTE format('CREATE SCHEMA IF NOT EXISTS %I', metadata_schema);
+  EXECUTE format(
+    'CREATE TABLE IF NOT EXISTS %I.webhook_outbox (
+      id BIGSERIAL PRIMARY KEY,
+      processor VARCHAR(100) NOT NULL,
+      endpoint VARCHAR(100) NOT NULL,
+      idempotency_key VARCHAR(100) NOT NULL,
+      event_type VARCHAR(200) NOT NULL,
+      transaction_version BIGINT NOT NULL,
+      payload TEXT NOT NULL,
+      attempts INT NOT NULL DEFAULT 0,
+      -- NULL once delivered or abandoned
+      next_attempt_at TIMESTAMP,
+      last_error TEXT,
+      created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+      delivered_at TIMESTAMP,
+      UNIQUE (processor, endpoint, idempotency_key)
+    )',
+    metadata_schema
+  );
+  EXECUTE format(
+    'CREATE INDEX IF NOT EXISTS webhook_outbox_due_idx ON %I.webhook_outbox (processor, next_attempt_at)
+    WHERE next_attempt_at IS NOT NULL',
+    metadata_schema
+  );
+END $$;
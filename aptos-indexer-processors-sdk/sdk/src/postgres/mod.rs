@@ -9,3 +9,9 @@ pub mod utils;
 pub mod processor_metadata_schema;
 
 pub const SDK_MIGRATIONS: EmbeddedMigrations = embed_migrations!("./src/postgres/db/migrations");
+
+/// Creates `webhook_outbox` for `PostgresWebhookOutbox`, next to the SDK tables. Only
+/// processors built with the `webhook` feature run it.
+#[cfg(feature = "webhook")]
+pub const WEBHOOK_MIGRATIONS: EmbeddedMigrations =
+    embed_migrations!("./src/postgres/db/webhook_migrations");
@@ -1,2 +1,4 @@
 pub mod ledger_info;
 pub mod processor_status;
+#[cfg(feature = "webhook")]
+pub mod webhook_outbox;
@@ -0,0 +1,33 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::postgres::processor_metadata_schema::processor_metadata::webhook_outbox;
+use diesel::{Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Insertable, Serialize)]
+#[diesel(table_name = webhook_outbox)]
+pub struct NewWebhookOutboxEntry {
+    pub processor: String,
+    pub endpoint: String,
+    pub idempotency_key: String,
+    pub event_type: String,
+    pub transaction_version: i64,
+    pub payload: String,
+    pub next_attempt_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// The columns of a pending delivery, selected by `PostgresWebhookOutbox`.
+#[derive(Debug, Queryable)]
+pub struct WebhookOutboxEntry {
+    pub id: i64,
+    pub endpoint: String,
+    pub idempotency_key: String,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub created_at: chrono::NaiveDateTime,
+}
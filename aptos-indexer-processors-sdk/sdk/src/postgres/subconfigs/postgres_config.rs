@@ -1,4 +1,18 @@
+#[cfg(feature = "webhook")]
+use crate::postgres::WEBHOOK_MIGRATIONS;
+use crate::postgres::{
+    utils::{
+        database::add_connection_options,
+        migrations::{MigrationConfig, SchemaMigrations},
+        notify::NotifyConfig,
+        query_server::QueryServerConfig,
+    },
+    SDK_MIGRATIONS,
+};
+use anyhow::Result;
+use diesel_migrations::EmbeddedMigrations;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -7,10 +21,253 @@ pub struct PostgresConfig {
     // Size of the pool for writes/reads to the DB. Limits maximum number of queries in flight
     #[serde(default = "PostgresConfig::default_db_pool_size")]
     pub db_pool_size: u32,
+    #[serde(default)]
+    pub migration_config: MigrationConfig,
+    // Schema of the processor's tables
+    #[serde(default = "PostgresConfig::default_schema")]
+    pub schema: String,
+    // Schema of the SDK tables, e.g. `processor_status`
+    #[serde(default = "PostgresConfig::default_metadata_schema")]
+    pub metadata_schema: String,
+    // TLS settings, replacing `sslrootcert` in the connection string
+    #[serde(default)]
+    pub tls_config: Option<PostgresTlsConfig>,
+    #[serde(default)]
+    pub pool_config: PoolConfig,
+    // Replica for read-only queries, e.g. the starting version. Uses the primary if not set.
+    #[serde(default)]
+    pub read_replica_connection_string: Option<String>,
+    // Size of the read replica pool. Defaults to `db_pool_size`.
+    #[serde(default)]
+    pub read_replica_db_pool_size: Option<u32>,
+    // Tables written with `COPY` instead of `INSERT`, see `execute_in_chunks_or_copy`
+    #[serde(default)]
+    pub copy_tables: HashSet<String>,
+    // `pg_notify` after committed batches, see `notify_committed_batch`
+    #[serde(default)]
+    pub notify_config: Option<NotifyConfig>,
+    // Read-only HTTP API over the tables registered with `register_query_table`. Disabled if
+    // not set.
+    #[serde(default)]
+    pub query_server_config: Option<QueryServerConfig>,
+}
+
+/// How much of the server certificate is verified, named after the `sslmode` values of libpq.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsVerifyMode {
+    /// Encrypts the connection without verifying the certificate
+    Require,
+    /// Verifies that the certificate is signed by a trusted CA
+    VerifyCa,
+    /// Also verifies that the certificate matches the host
+    #[default]
+    VerifyFull,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresTlsConfig {
+    /// PEM file of the CA that signed the server certificate, in addition to the system roots
+    #[serde(default)]
+    pub root_cert_path: Option<String>,
+    /// PEM file of the client certificate, for servers that authenticate clients with TLS
+    #[serde(default)]
+    pub client_cert_path: Option<String>,
+    /// PEM file of the PKCS#8 key of the client certificate
+    #[serde(default)]
+    pub client_key_path: Option<String>,
+    #[serde(default)]
+    pub verify_mode: TlsVerifyMode,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    /// How long to wait for a connection from the pool before failing
+    #[serde(default = "PoolConfig::default_connection_timeout_secs")]
+    pub connection_timeout_secs: u64,
+    /// Idle connections are closed after this long. 0 keeps them open.
+    #[serde(default = "PoolConfig::default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    /// Connections are closed after this long, e.g. to rebalance after a failover. 0 keeps them
+    /// open.
+    #[serde(default = "PoolConfig::default_max_lifetime_secs")]
+    pub max_lifetime_secs: u64,
+    /// `statement_timeout` of every connection. 0 disables it.
+    #[serde(default)]
+    pub statement_timeout_ms: u64,
+}
+
+impl PoolConfig {
+    pub const fn default_connection_timeout_secs() -> u64 {
+        30
+    }
+
+    pub const fn default_idle_timeout_secs() -> u64 {
+        600
+    }
+
+    pub const fn default_max_lifetime_secs() -> u64 {
+        1800
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            connection_timeout_secs: Self::default_connection_timeout_secs(),
+            idle_timeout_secs: Self::default_idle_timeout_secs(),
+            max_lifetime_secs: Self::default_max_lifetime_secs(),
+            statement_timeout_ms: 0,
+        }
+    }
 }
 
 impl PostgresConfig {
     pub const fn default_db_pool_size() -> u32 {
         150
     }
+
+    pub fn default_schema() -> String {
+        "public".to_string()
+    }
+
+    pub fn default_metadata_schema() -> String {
+        "processor_metadata".to_string()
+    }
+
+    /// Checks that the schema names are plain identifiers, since they end up unquoted in the
+    /// `search_path` and in `CREATE SCHEMA`.
+    pub fn validate_schemas(&self) -> Result<()> {
+        for schema in [&self.schema, &self.metadata_schema] {
+            let mut chars = schema.chars();
+            let is_identifier = chars
+                .next()
+                .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+                && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !is_identifier {
+                anyhow::bail!(
+                    "Invalid schema name `{}`, only lowercase letters, digits and underscores are allowed",
+                    schema
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The `search_path` of the processor's connections: its own tables first, then the SDK's.
+    pub fn search_path(&self) -> String {
+        format!("{},{}", self.schema, self.metadata_schema)
+    }
+
+    /// Returns `connection_string` with the `search_path` and `statement_timeout` set through the
+    /// `options` parameter, so that every connection of the pool resolves unqualified tables
+    /// in the configured schemas.
+    pub fn primary_connection_string(&self) -> Result<String> {
+        self.with_connection_options(&self.connection_string)
+    }
+
+    /// Returns `read_replica_connection_string` with the same options as
+    /// `primary_connection_string`, if a read replica is set.
+    pub fn read_replica_connection_string(&self) -> Result<Option<String>> {
+        self.read_replica_connection_string
+            .as_deref()
+            .map(|connection_string| self.with_connection_options(connection_string))
+            .transpose()
+    }
+
+    fn with_connection_options(&self, connection_string: &str) -> Result<String> {
+        self.validate_schemas()?;
+        let mut connection_options = format!("-c search_path={}", self.search_path());
+        if self.pool_config.statement_timeout_ms > 0 {
+            connection_options.push_str(&format!(
+                " -c statement_timeout={}",
+                self.pool_config.statement_timeout_ms
+            ));
+        }
+        add_connection_options(connection_string, &connection_options)
+    }
+
+    /// The processor's migrations in `schema`, followed by the SDK's in `metadata_schema`.
+    pub fn migration_sets(&self, embedded_migrations: EmbeddedMigrations) -> Vec<SchemaMigrations> {
+        vec![
+            SchemaMigrations::new(&self.schema, embedded_migrations),
+            SchemaMigrations::new(&self.metadata_schema, SDK_MIGRATIONS),
+            #[cfg(feature = "webhook")]
+            SchemaMigrations::new(&self.metadata_schema, WEBHOOK_MIGRATIONS),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::utils::database::with_default_search_path;
+
+    fn config(connection_string: &str, schema: &str) -> PostgresConfig {
+        PostgresConfig {
+            connection_string: connection_string.to_string(),
+            db_pool_size: PostgresConfig::default_db_pool_size(),
+            migration_config: MigrationConfig::default(),
+            schema: schema.to_string(),
+            metadata_schema: "testnet_metadata".to_string(),
+            tls_config: None,
+            pool_config: PoolConfig::default(),
+            read_replica_connection_string: None,
+            read_replica_db_pool_size: None,
+            copy_tables: HashSet::new(),
+            notify_config: None,
+            query_server_config: None,
+        }
+    }
+
+    #[test]
+    fn test_connection_string_options() {
+        let connection_string = config("postgresql://postgres@localhost:5432/db", "testnet")
+            .primary_connection_string()
+            .unwrap();
+        assert_eq!(
+            connection_string,
+            "postgresql://postgres@localhost:5432/db?options=-c%20search_path%3Dtestnet%2Ctestnet_metadata"
+        );
+
+        // Existing parameters and options are kept
+        let connection_string = config(
+            "postgresql://postgres@localhost:5432/db?sslmode=require&options=-c%20statement_timeout%3D5000",
+            "testnet",
+        )
+        .primary_connection_string()
+        .unwrap();
+        assert_eq!(
+            connection_string,
+            "postgresql://postgres@localhost:5432/db?sslmode=require&options=-c%20statement_timeout%3D5000%20-c%20search_path%3Dtestnet%2Ctestnet_metadata"
+        );
+
+        let mut replica_config = config("postgresql://postgres@localhost:5432/db", "testnet");
+        replica_config.read_replica_connection_string =
+            Some("postgresql://postgres@replica:5432/db".to_string());
+        replica_config.pool_config.statement_timeout_ms = 5000;
+        assert_eq!(
+            replica_config.read_replica_connection_string().unwrap(),
+            Some("postgresql://postgres@replica:5432/db?options=-c%20search_path%3Dtestnet%2Ctestnet_metadata%20-c%20statement_timeout%3D5000".to_string())
+        );
+
+        assert!(config("postgresql://localhost/db", "public; DROP TABLE x")
+            .primary_connection_string()
+            .is_err());
+
+        // Without a config, the SDK tables are found in the default schemas
+        assert_eq!(
+            with_default_search_path("postgresql://postgres@localhost:5432/db?sslmode=require")
+                .unwrap(),
+            "postgresql://postgres@localhost:5432/db?sslmode=require&options=-c%20search_path%3Dpublic%2Cprocessor_metadata"
+        );
+        let connection_string =
+            "postgresql://postgres@localhost:5432/db?options=-c%20search_path%3Dtestnet";
+        assert_eq!(
+            with_default_search_path(connection_string).unwrap(),
+            connection_string
+        );
+    }
 }
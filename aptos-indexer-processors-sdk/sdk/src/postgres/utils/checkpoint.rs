@@ -2,6 +2,7 @@ use super::database::{execute_with_better_error, execute_with_better_error_conn,
 use crate::{
     aptos_indexer_transaction_stream::{utils::time::parse_timestamp, TransactionStreamConfig},
     common_steps::ProcessorStatusSaver,
+    health::{CheckpointStatus, StatusProvider},
     postgres::{
         models::{
             ledger_info::LedgerInfo,
@@ -10,7 +11,11 @@ use crate::{
         processor_metadata_schema::processor_metadata::{ledger_infos, processor_status},
     },
     types::transaction_context::TransactionContext,
-    utils::{chain_id_check::ChainIdChecker, errors::ProcessorError},
+    utils::{
+        chain_id_check::ChainIdChecker,
+        checkpoint_store::{self, CheckpointStore},
+        errors::ProcessorError,
+    },
 };
 use anyhow::{Context, Result};
 use async_trait::async_trait;
@@ -113,6 +118,142 @@ impl ProcessorStatusSaver for PostgresProcessorStatusSaver {
         .await?;
         Ok(())
     }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        let status = ProcessorStatus {
+            processor: self.processor_name.clone(),
+            last_success_version: last_success_version as i64,
+            last_transaction_timestamp: None,
+        };
+
+        // Unlike saving, resetting may move the status backwards
+        execute_with_better_error(
+            self.db_pool.clone(),
+            diesel::insert_into(processor_status::table)
+                .values(&status)
+                .on_conflict(processor_status::processor)
+                .do_update()
+                .set((
+                    processor_status::last_success_version
+                        .eq(excluded(processor_status::last_success_version)),
+                    processor_status::last_updated.eq(excluded(processor_status::last_updated)),
+                    processor_status::last_transaction_timestamp
+                        .eq(excluded(processor_status::last_transaction_timestamp)),
+                )),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// A trait implementation of StatusProvider for Postgres, reading `ledger_infos` and
+/// `processor_status`.
+pub struct PostgresStatusProvider {
+    pub db_pool: ArcDbPool,
+    pub processor_name: String,
+}
+
+impl PostgresStatusProvider {
+    pub fn new(processor_name: &str, db_pool: ArcDbPool) -> Self {
+        Self {
+            db_pool,
+            processor_name: processor_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl StatusProvider for PostgresStatusProvider {
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        PostgresChainIdChecker::new(self.db_pool.clone())
+            .get_chain_id()
+            .await
+    }
+
+    async fn get_checkpoint_status(&self) -> Result<Option<CheckpointStatus>> {
+        let mut conn = self
+            .db_pool
+            .get()
+            .await
+            .context("Error getting db connection")?;
+        let status = ProcessorStatusQuery::get_by_processor(&self.processor_name, &mut conn)
+            .await?
+            .map(|status| CheckpointStatus {
+                last_success_version: status.last_success_version as u64,
+                last_transaction_timestamp: status.last_transaction_timestamp,
+                last_updated: Some(status.last_updated),
+            });
+        Ok(status)
+    }
+}
+
+/// A trait implementation of CheckpointStore for Postgres, keeping the checkpoint in
+/// `processor_status` and the chain ID in `ledger_infos`. The checkpoint is read from the
+/// primary: a replica may lag behind the last save, e.g. of a leader that was just replaced.
+pub struct PostgresCheckpointStore {
+    processor_status_saver: PostgresProcessorStatusSaver,
+    chain_id_checker: PostgresChainIdChecker,
+}
+
+impl PostgresCheckpointStore {
+    pub fn new(processor_name: &str, db_pool: ArcDbPool) -> Self {
+        Self {
+            processor_status_saver: PostgresProcessorStatusSaver::new(
+                processor_name,
+                db_pool.clone(),
+            ),
+            chain_id_checker: PostgresChainIdChecker::new(db_pool),
+        }
+    }
+}
+
+#[async_trait]
+impl ProcessorStatusSaver for PostgresCheckpointStore {
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        self.processor_status_saver
+            .save_processor_status(last_success_batch)
+            .await
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        self.processor_status_saver
+            .reset_processor_status(last_success_version)
+            .await
+    }
+}
+
+#[async_trait]
+impl ChainIdChecker for PostgresCheckpointStore {
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        self.chain_id_checker.save_chain_id(chain_id).await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        self.chain_id_checker.get_chain_id().await
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for PostgresCheckpointStore {
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        let mut conn = self.processor_status_saver.db_pool.get().await?;
+        let latest_processed_version = ProcessorStatusQuery::get_by_processor(
+            &self.processor_status_saver.processor_name,
+            &mut conn,
+        )
+        .await?
+        .map(|ps| ps.last_success_version as u64);
+        Ok(latest_processed_version)
+    }
 }
 
 pub async fn get_starting_version(
@@ -120,11 +261,6 @@ pub async fn get_starting_version(
     transaction_stream_config: TransactionStreamConfig,
     conn_pool: ArcDbPool,
 ) -> Result<u64> {
-    let mut conn = conn_pool.get().await?;
-    let latest_processed_version =
-        ProcessorStatusQuery::get_by_processor(processor_name, &mut conn)
-            .await?
-            .map(|ps| ps.last_success_version as u64);
-    // If nothing checkpointed, return the `starting_version` from the config, or 0 if not set.
-    Ok(latest_processed_version.unwrap_or(transaction_stream_config.starting_version.unwrap_or(0)))
+    let checkpoint_store = PostgresCheckpointStore::new(processor_name, conn_pool);
+    checkpoint_store::get_starting_version(&checkpoint_store, &transaction_stream_config).await
 }
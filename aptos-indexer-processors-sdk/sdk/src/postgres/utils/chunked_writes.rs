@@ -0,0 +1,550 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Error classification, retries, bisection and adaptive chunk sizes for `execute_in_chunks`.
+
+use super::database::{clean_data_for_db, ArcDbPool, Backend};
+use crate::utils::errors::ProcessorError;
+use diesel::{
+    query_builder::QueryFragment,
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+use diesel_async::RunQueryDsl;
+use once_cell::sync::Lazy;
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{atomic::AtomicU64, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_postgres::error::SqlState;
+use tracing::{error, warn};
+
+// Chunks are resized to take about this long to write
+const TARGET_CHUNK_LATENCY: Duration = Duration::from_secs(1);
+const MIN_CHUNK_SIZE: usize = 10;
+
+const MAX_RETRIES: usize = 5;
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbErrorKind {
+    Deadlock,
+    SerializationFailure,
+    StatementTimeout,
+    TooManyParameters,
+    ConstraintViolation,
+    Connection,
+    Other,
+}
+
+impl DbErrorKind {
+    /// Whether the same write may succeed if retried as is.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            DbErrorKind::Deadlock | DbErrorKind::SerializationFailure | DbErrorKind::Connection
+        )
+    }
+
+    /// Whether a smaller chunk may succeed, because the chunk is too big or because some of
+    /// its rows are bad.
+    fn should_bisect(&self) -> bool {
+        !self.is_transient()
+    }
+
+    /// Whether the chunk size should shrink for the next writes.
+    fn is_chunk_too_big(&self) -> bool {
+        matches!(
+            self,
+            DbErrorKind::StatementTimeout | DbErrorKind::TooManyParameters
+        )
+    }
+}
+
+impl fmt::Display for DbErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            DbErrorKind::Deadlock => "deadlock",
+            DbErrorKind::SerializationFailure => "serialization_failure",
+            DbErrorKind::StatementTimeout => "statement_timeout",
+            DbErrorKind::TooManyParameters => "too_many_parameters",
+            DbErrorKind::ConstraintViolation => "constraint_violation",
+            DbErrorKind::Connection => "connection",
+            DbErrorKind::Other => "other",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+fn classify_message(message: &str) -> DbErrorKind {
+    let message = message.to_lowercase();
+    if message.contains("deadlock detected") {
+        DbErrorKind::Deadlock
+    } else if message.contains("could not serialize access") {
+        DbErrorKind::SerializationFailure
+    } else if message.contains("statement timeout") {
+        DbErrorKind::StatementTimeout
+    } else if message.contains("number of parameters") || message.contains("too many bind") {
+        DbErrorKind::TooManyParameters
+    } else if message.contains("violates") && message.contains("constraint") {
+        DbErrorKind::ConstraintViolation
+    } else if message.contains("connection") {
+        DbErrorKind::Connection
+    } else {
+        DbErrorKind::Other
+    }
+}
+
+/// Classifies a diesel error. Postgres errors that diesel doesn't have a kind for are
+/// classified by their message.
+pub fn classify_db_error(error: &DieselError) -> DbErrorKind {
+    match error {
+        DieselError::DatabaseError(kind, info) => match kind {
+            DatabaseErrorKind::SerializationFailure => DbErrorKind::SerializationFailure,
+            DatabaseErrorKind::UniqueViolation
+            | DatabaseErrorKind::ForeignKeyViolation
+            | DatabaseErrorKind::NotNullViolation
+            | DatabaseErrorKind::CheckViolation => DbErrorKind::ConstraintViolation,
+            DatabaseErrorKind::ClosedConnection => DbErrorKind::Connection,
+            _ => classify_message(info.message()),
+        },
+        error => classify_message(&error.to_string()),
+    }
+}
+
+/// Classifies an error of the `tokio_postgres` client used for `COPY`, by its SQLSTATE.
+/// Errors without one come from the connection itself, e.g. a refused or closed socket.
+pub fn classify_postgres_error(error: &tokio_postgres::Error) -> DbErrorKind {
+    let Some(db_error) = error.as_db_error() else {
+        let is_io_error =
+            std::error::Error::source(error).is_some_and(|source| source.is::<std::io::Error>());
+        return if error.is_closed() || is_io_error {
+            DbErrorKind::Connection
+        } else {
+            classify_message(&error.to_string())
+        };
+    };
+    let code = db_error.code();
+    if code == &SqlState::T_R_DEADLOCK_DETECTED {
+        DbErrorKind::Deadlock
+    } else if code == &SqlState::T_R_SERIALIZATION_FAILURE {
+        DbErrorKind::SerializationFailure
+    } else if code == &SqlState::QUERY_CANCELED {
+        DbErrorKind::StatementTimeout
+    } else if code.code().starts_with("23") {
+        // Class 23 is integrity constraint violations
+        DbErrorKind::ConstraintViolation
+    } else if code.code().starts_with("08")
+        || code == &SqlState::ADMIN_SHUTDOWN
+        || code == &SqlState::CRASH_SHUTDOWN
+        || code == &SqlState::CANNOT_CONNECT_NOW
+    {
+        // Class 08 is connection exceptions
+        DbErrorKind::Connection
+    } else {
+        classify_message(db_error.message())
+    }
+}
+
+/// Chunk size of a table, adjusted to keep writes around `TARGET_CHUNK_LATENCY`: halved when a
+/// chunk is much slower or too big, grown by a quarter when a full chunk is much faster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AdaptiveChunkSize {
+    current: usize,
+}
+
+impl AdaptiveChunkSize {
+    fn shrink(&mut self) {
+        self.current = (self.current / 2).max(MIN_CHUNK_SIZE);
+    }
+
+    fn record_latency(&mut self, chunk_len: usize, latency: Duration, max_chunk_size: usize) {
+        if latency > TARGET_CHUNK_LATENCY * 2 {
+            self.shrink();
+        } else if latency < TARGET_CHUNK_LATENCY / 2 && chunk_len >= self.current {
+            self.current += (self.current / 4).max(1);
+        }
+        self.current = self.current.min(max_chunk_size);
+    }
+}
+
+static CHUNK_SIZES: Lazy<Mutex<HashMap<String, AdaptiveChunkSize>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The chunk size to use for `table_name`, at most `max_chunk_size`.
+pub(crate) fn chunk_size_for(table_name: &str, max_chunk_size: usize) -> usize {
+    let max_chunk_size = max_chunk_size.max(1);
+    let chunk_size = CHUNK_SIZES
+        .lock()
+        .unwrap()
+        .entry(table_name.to_string())
+        .or_insert(AdaptiveChunkSize {
+            current: max_chunk_size,
+        })
+        .current
+        .min(max_chunk_size);
+    CHUNK_SIZE
+        .get_or_create(&TableLabels::new(table_name))
+        .set(chunk_size as i64);
+    chunk_size
+}
+
+fn update_chunk_size(table_name: &str, update: impl FnOnce(&mut AdaptiveChunkSize)) {
+    let mut chunk_sizes = CHUNK_SIZES.lock().unwrap();
+    if let Some(chunk_size) = chunk_sizes.get_mut(table_name) {
+        update(chunk_size);
+        CHUNK_SIZE
+            .get_or_create(&TableLabels::new(table_name))
+            .set(chunk_size.current as i64);
+    }
+}
+
+// Tables of the query builders passed to `execute_in_chunks`, by address of the builder
+static TABLE_NAMES: Lazy<Mutex<HashMap<usize, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The table that `build_query` writes to. Its SQL is only rendered the first time the builder
+/// is seen.
+pub(crate) fn table_name_of<U, T>(build_query: fn(Vec<T>) -> U, item: &T) -> String
+where
+    U: QueryFragment<Backend>,
+    T: Clone,
+{
+    TABLE_NAMES
+        .lock()
+        .unwrap()
+        .entry(build_query as usize)
+        .or_insert_with(|| {
+            table_name_from_sql(
+                &diesel::debug_query::<Backend, _>(&build_query(vec![item.clone()])).to_string(),
+            )
+        })
+        .clone()
+}
+
+/// Finds the table of a write from its SQL, for metrics.
+pub(crate) fn table_name_from_sql(sql: &str) -> String {
+    let lowercase_sql = sql.to_lowercase();
+    ["insert into ", "update ", "delete from "]
+        .iter()
+        .find_map(|prefix| {
+            let start = lowercase_sql.find(prefix)? + prefix.len();
+            let table = sql[start..]
+                .split(|c: char| c.is_whitespace() || c == '(')
+                .next()?;
+            // Drop the schema and the quotes
+            let table = table.rsplit('.').next()?.trim_matches('"');
+            (!table.is_empty()).then(|| table.to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+struct WriteError {
+    kind: DbErrorKind,
+    message: String,
+}
+
+async fn try_execute<U>(pool: &ArcDbPool, query: U) -> Result<(), WriteError>
+where
+    U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send,
+{
+    let mut conn = pool.get().await.map_err(|e| WriteError {
+        kind: DbErrorKind::Connection,
+        message: format!("Error getting connection from pool: {:#}", e),
+    })?;
+    query.execute(&mut conn).await.map_err(|e| WriteError {
+        kind: classify_db_error(&e),
+        message: format!("{:#}", e),
+    })?;
+    Ok(())
+}
+
+/// Writes `items` as one query, retrying transient errors with exponential backoff.
+async fn execute_with_retries<U, T>(
+    pool: &ArcDbPool,
+    build_query: fn(Vec<T>) -> U,
+    items: &[T],
+    table_name: &str,
+    max_chunk_size: usize,
+) -> Result<(), WriteError>
+where
+    U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send,
+    T: Clone,
+{
+    let mut backoff = INITIAL_RETRY_BACKOFF;
+    let mut retries = 0;
+    loop {
+        let start_time = Instant::now();
+        match try_execute(pool, build_query(items.to_vec())).await {
+            Ok(()) => {
+                let latency = start_time.elapsed();
+                CHUNK_LATENCY_SECS
+                    .get_or_create(&TableLabels::new(table_name))
+                    .set(latency.as_secs_f64());
+                update_chunk_size(table_name, |chunk_size| {
+                    chunk_size.record_latency(items.len(), latency, max_chunk_size)
+                });
+                return Ok(());
+            },
+            Err(e) => {
+                WRITE_ERROR_COUNT
+                    .get_or_create(&ErrorLabels::new(table_name, e.kind))
+                    .inc();
+                if e.kind.is_chunk_too_big() {
+                    update_chunk_size(table_name, AdaptiveChunkSize::shrink);
+                }
+                if !e.kind.is_transient() || retries >= MAX_RETRIES {
+                    return Err(e);
+                }
+                warn!(
+                    table_name = table_name,
+                    kind = %e.kind,
+                    retries = retries,
+                    backoff_ms = backoff.as_millis() as u64,
+                    "Retrying write: {}",
+                    e.message
+                );
+                WRITE_RETRY_COUNT
+                    .get_or_create(&ErrorLabels::new(table_name, e.kind))
+                    .inc();
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                retries += 1;
+            },
+        }
+    }
+}
+
+/// Writes a chunk of `items`. Transient errors are retried with backoff. On other errors, the
+/// chunk is retried once with null bytes removed, then split in halves until the offending
+/// row is isolated, so that the error points at it.
+pub(crate) async fn execute_chunk<U, T>(
+    pool: ArcDbPool,
+    build_query: fn(Vec<T>) -> U,
+    items: Vec<T>,
+    table_name: String,
+    max_chunk_size: usize,
+) -> Result<(), ProcessorError>
+where
+    U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send,
+    T: serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
+{
+    // Chunks still to write, and whether their null bytes were removed
+    let mut pending = vec![(items, false)];
+    while let Some((items, is_cleaned)) = pending.pop() {
+        let e = match execute_with_retries(&pool, build_query, &items, &table_name, max_chunk_size)
+            .await
+        {
+            Ok(()) => continue,
+            Err(e) => e,
+        };
+        if e.kind.should_bisect() {
+            if !is_cleaned && !e.kind.is_chunk_too_big() {
+                pending.push((clean_data_for_db(items, true), true));
+                continue;
+            }
+            if items.len() > 1 {
+                BISECTION_COUNT
+                    .get_or_create(&TableLabels::new(&table_name))
+                    .inc();
+                let mut first_half = items;
+                let second_half = first_half.split_off(first_half.len() / 2);
+                pending.push((second_half, is_cleaned));
+                pending.push((first_half, is_cleaned));
+                continue;
+            }
+            error!(
+                table_name = table_name,
+                kind = %e.kind,
+                row = serde_json::to_string(&items[0]).unwrap_or_default(),
+                "Isolated the row that fails to write: {}",
+                e.message
+            );
+        }
+        let query = diesel::debug_query::<Backend, _>(&build_query(items)).to_string();
+        return Err(db_write_error(e.kind, e.message, Some(query)));
+    }
+    Ok(())
+}
+
+/// The error of a write that failed with `kind`. Writes that failed because the database is
+/// unreachable are `DBUnavailableError`s, the others `DBStoreError`s.
+pub(crate) fn db_write_error(
+    kind: DbErrorKind,
+    message: String,
+    query: Option<String>,
+) -> ProcessorError {
+    let message = format!("[{}] {}", kind, message);
+    match kind {
+        DbErrorKind::Connection => ProcessorError::DBUnavailableError { message, query },
+        _ => ProcessorError::DBStoreError { message, query },
+    }
+}
+
+/// Whether `error` is a write that failed because the database is unreachable, e.g. for
+/// `SpoolSink::is_unavailable`.
+pub fn is_unavailable_error(error: &ProcessorError) -> bool {
+    matches!(error, ProcessorError::DBUnavailableError { .. })
+}
+
+pub const METRICS_PREFIX: &str = "aptos_procsdk_db_write";
+
+pub fn init_db_write_metrics_registry(registry: &mut Registry) {
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "errors_count"),
+        "Failed writes by table and kind of error",
+        WRITE_ERROR_COUNT.clone(),
+    );
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "retries_count"),
+        "Writes retried after a transient error, by table and kind of error",
+        WRITE_RETRY_COUNT.clone(),
+    );
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "bisections_count"),
+        "Failing chunks split in halves to isolate a bad row, by table",
+        BISECTION_COUNT.clone(),
+    );
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "chunk_size"),
+        "Current adaptive chunk size, by table",
+        CHUNK_SIZE.clone(),
+    );
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "chunk_latency_secs"),
+        "Latency of the last successful chunk write, by table",
+        CHUNK_LATENCY_SECS.clone(),
+    );
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TableLabels {
+    pub table_name: String,
+}
+
+impl TableLabels {
+    fn new(table_name: &str) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ErrorLabels {
+    pub table_name: String,
+    pub kind: String,
+}
+
+impl ErrorLabels {
+    fn new(table_name: &str, kind: DbErrorKind) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+}
+
+pub static WRITE_ERROR_COUNT: Lazy<Family<ErrorLabels, Counter>> =
+    Lazy::new(Family::<ErrorLabels, Counter>::default);
+
+pub static WRITE_RETRY_COUNT: Lazy<Family<ErrorLabels, Counter>> =
+    Lazy::new(Family::<ErrorLabels, Counter>::default);
+
+pub static BISECTION_COUNT: Lazy<Family<TableLabels, Counter>> =
+    Lazy::new(Family::<TableLabels, Counter>::default);
+
+pub static CHUNK_SIZE: Lazy<Family<TableLabels, Gauge>> =
+    Lazy::new(Family::<TableLabels, Gauge>::default);
+
+pub static CHUNK_LATENCY_SECS: Lazy<Family<TableLabels, Gauge<f64, AtomicU64>>> =
+    Lazy::new(Family::<TableLabels, Gauge<f64, AtomicU64>>::default);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_message() {
+        assert_eq!(classify_message("deadlock detected"), DbErrorKind::Deadlock);
+        assert_eq!(
+            classify_message("canceling statement due to statement timeout"),
+            DbErrorKind::StatementTimeout
+        );
+        assert_eq!(
+            classify_message("number of parameters must be between 0 and 65535"),
+            DbErrorKind::TooManyParameters
+        );
+        assert_eq!(
+            classify_message("null value in column \"winner\" violates not-null constraint"),
+            DbErrorKind::ConstraintViolation
+        );
+        assert_eq!(
+            classify_message("invalid byte sequence for encoding \"UTF8\": 0x00"),
+            DbErrorKind::Other
+        );
+        assert!(DbErrorKind::Deadlock.is_transient());
+        assert!(!DbErrorKind::ConstraintViolation.is_transient());
+    }
+
+    #[test]
+    fn test_unavailable_errors() {
+        let error = db_write_error(
+            DbErrorKind::Connection,
+            "connection refused".to_string(),
+            None,
+        );
+        assert!(is_unavailable_error(&error));
+        let error = db_write_error(
+            DbErrorKind::ConstraintViolation,
+            "duplicate key value violates unique constraint".to_string(),
+            None,
+        );
+        assert!(!is_unavailable_error(&error));
+        // Only the variant matters, not the message
+        let error = ProcessorError::DBStoreError {
+            message: "[connection] lost".to_string(),
+            query: None,
+        };
+        assert!(!is_unavailable_error(&error));
+    }
+
+    #[test]
+    fn test_adaptive_chunk_size() {
+        let mut chunk_size = AdaptiveChunkSize { current: 1000 };
+        chunk_size.record_latency(1000, Duration::from_secs(5), 1000);
+        assert_eq!(chunk_size.current, 500);
+        // Only full chunks grow the size
+        chunk_size.record_latency(100, Duration::from_millis(10), 1000);
+        assert_eq!(chunk_size.current, 500);
+        chunk_size.record_latency(500, Duration::from_millis(10), 1000);
+        assert_eq!(chunk_size.current, 625);
+        chunk_size.record_latency(625, Duration::from_millis(10), 700);
+        assert_eq!(chunk_size.current, 700);
+        for _ in 0..10 {
+            chunk_size.shrink();
+        }
+        assert_eq!(chunk_size.current, MIN_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_table_name_from_sql() {
+        assert_eq!(
+            table_name_from_sql(
+                "INSERT INTO \"raffle_events\" (\"sequence_number\") VALUES ($1) -- binds: [1]"
+            ),
+            "raffle_events"
+        );
+        assert_eq!(
+            table_name_from_sql("UPDATE \"processor_metadata\".\"processor_status\" SET"),
+            "processor_status"
+        );
+        assert_eq!(table_name_from_sql("SELECT 1"), "unknown");
+    }
+}
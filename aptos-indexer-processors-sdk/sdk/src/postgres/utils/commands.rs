@@ -0,0 +1,87 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Operational subcommands of Postgres processors, see `ServerCommand`.
+
+use super::{
+    checkpoint::{PostgresChainIdChecker, PostgresProcessorStatusSaver},
+    database::ArcDbPool,
+    migrations::{get_migration_report, run_migrations_with_lock},
+};
+pub use crate::server_framework::backfill_processor_name;
+use crate::{
+    common_steps::ProcessorStatusSaver,
+    postgres::{
+        models::processor_status::ProcessorStatusQuery, subconfigs::postgres_config::PostgresConfig,
+    },
+    utils::chain_id_check::ChainIdChecker,
+};
+use anyhow::{Context, Result};
+use diesel_migrations::EmbeddedMigrations;
+use serde::Serialize;
+
+/// Runs the pending processor and SDK migrations, then prints the applied, pending and
+/// unknown migrations. With `dry_run`, only prints them.
+pub async fn migrate(
+    postgres_config: &PostgresConfig,
+    db_pool: ArcDbPool,
+    embedded_migrations: EmbeddedMigrations,
+    dry_run: bool,
+) -> Result<()> {
+    let migration_sets = postgres_config.migration_sets(embedded_migrations);
+    let report = if dry_run {
+        get_migration_report(db_pool, migration_sets).await?
+    } else {
+        let mut report =
+            run_migrations_with_lock(db_pool, migration_sets, &postgres_config.migration_config)
+                .await?;
+        report.applied.append(&mut report.pending);
+        report
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct ProcessorStatusOutput {
+    processor: String,
+    chain_id: Option<u64>,
+    last_success_version: Option<i64>,
+    last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+    last_updated: Option<chrono::NaiveDateTime>,
+}
+
+/// Prints the `processor_status` row and the chain ID from `ledger_infos` as JSON.
+pub async fn print_status(processor_name: &str, db_pool: ArcDbPool) -> Result<()> {
+    let chain_id = PostgresChainIdChecker::new(db_pool.clone())
+        .get_chain_id()
+        .await?;
+    let mut conn = db_pool.get().await.context("Error getting db connection")?;
+    let processor_status =
+        ProcessorStatusQuery::get_by_processor(processor_name, &mut conn).await?;
+    let output = ProcessorStatusOutput {
+        processor: processor_name.to_string(),
+        chain_id,
+        last_success_version: processor_status
+            .as_ref()
+            .map(|status| status.last_success_version),
+        last_transaction_timestamp: processor_status
+            .as_ref()
+            .and_then(|status| status.last_transaction_timestamp),
+        last_updated: processor_status.map(|status| status.last_updated),
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Overwrites the checkpoint of the processor, so that it restarts from `to`.
+pub async fn reset_checkpoint(processor_name: &str, db_pool: ArcDbPool, to: u64) -> Result<()> {
+    PostgresProcessorStatusSaver::new(processor_name, db_pool)
+        .reset_processor_status(to)
+        .await?;
+    println!(
+        "Reset the checkpoint of {} to version {}",
+        processor_name, to
+    );
+    Ok(())
+}
@@ -0,0 +1,424 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bulk writes with binary `COPY FROM STDIN`, for tables where batched `INSERT`s are the
+//! bottleneck, e.g. during backfills.
+
+use super::{
+    chunked_writes::{classify_postgres_error, db_write_error, DbErrorKind},
+    database::{
+        build_tls_connector, clean_data_for_db, execute_in_chunks, parse_and_clean_db_url,
+        require_ssl, url_tls_config, ArcDbPool, Backend,
+    },
+    notify::record_written_rows,
+};
+use crate::{
+    postgres::subconfigs::postgres_config::{PostgresConfig, PostgresTlsConfig},
+    utils::errors::ProcessorError,
+};
+use diesel::query_builder::QueryFragment;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+pub use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{binary_copy::BinaryCopyInWriter, Client, NoTls};
+use tracing::warn;
+
+/// A row that can be written with `COPY`. Rows are copied into a temporary staging table, then
+/// merged into `TABLE_NAME` with `INSERT ... SELECT ... ON CONFLICT`.
+pub trait CopyRow: Send + Sync {
+    /// Table the rows are merged into.
+    const TABLE_NAME: &'static str;
+
+    /// Columns and their Postgres types, in the order of `copy_values`. Columns that are left
+    /// out get their default, e.g. `inserted_at`.
+    fn copy_columns() -> Vec<(&'static str, Type)>;
+
+    /// Columns of the unique constraint the merge conflicts on.
+    fn conflict_columns() -> Vec<&'static str>;
+
+    /// What to do on conflict, after `ON CONFLICT (<conflict_columns>)`.
+    fn conflict_action() -> String {
+        "DO NOTHING".to_string()
+    }
+
+    /// Values of the row, in the order of `copy_columns`.
+    fn copy_values(&self) -> Vec<&(dyn ToSql + Sync)>;
+}
+
+fn quote_columns(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|column| format!("\"{}\"", column))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn staging_table_name<T: CopyRow>() -> String {
+    format!("{}_copy_staging", T::TABLE_NAME)
+}
+
+fn create_staging_table_statement<T: CopyRow>() -> String {
+    format!(
+        "CREATE TEMP TABLE \"{}\" (LIKE \"{}\" INCLUDING DEFAULTS) ON COMMIT DROP",
+        staging_table_name::<T>(),
+        T::TABLE_NAME
+    )
+}
+
+fn copy_statement<T: CopyRow>() -> String {
+    let columns = T::copy_columns()
+        .into_iter()
+        .map(|(column, _)| column)
+        .collect::<Vec<_>>();
+    format!(
+        "COPY \"{}\" ({}) FROM STDIN BINARY",
+        staging_table_name::<T>(),
+        quote_columns(&columns)
+    )
+}
+
+fn merge_statement<T: CopyRow>() -> String {
+    let columns = T::copy_columns()
+        .into_iter()
+        .map(|(column, _)| column)
+        .collect::<Vec<_>>();
+    let columns = quote_columns(&columns);
+    format!(
+        "INSERT INTO \"{}\" ({}) SELECT {} FROM \"{}\" ON CONFLICT ({}) {}",
+        T::TABLE_NAME,
+        columns,
+        columns,
+        staging_table_name::<T>(),
+        quote_columns(&T::conflict_columns()),
+        T::conflict_action()
+    )
+}
+
+/// Classified like the errors of `execute_in_chunks`, so that e.g. `is_unavailable_error`
+/// recognizes an unreachable database.
+fn copy_error(e: tokio_postgres::Error, query: String) -> ProcessorError {
+    warn!("Error running query: {:?}\n{:?}", e, query);
+    db_write_error(classify_postgres_error(&e), format!("{:#}", e), Some(query))
+}
+
+/// Writes rows of the tables in `PostgresConfig::copy_tables` with `COPY`. `COPY` needs the
+/// `tokio_postgres` client, which the diesel pool doesn't expose, so the writer keeps its own
+/// connections.
+pub struct CopyWriter {
+    connection_string: String,
+    tls_config: Option<PostgresTlsConfig>,
+    tables: HashSet<String>,
+    idle_clients: tokio::sync::Mutex<Vec<Client>>,
+}
+
+impl CopyWriter {
+    pub fn new(postgres_config: &PostgresConfig) -> anyhow::Result<Self> {
+        let connection_string = postgres_config.primary_connection_string()?;
+        let connection_string = match &postgres_config.tls_config {
+            Some(tls_config) => {
+                build_tls_connector(tls_config)?;
+                require_ssl(&connection_string)?
+            },
+            None => connection_string,
+        };
+        let tls_config = postgres_config
+            .tls_config
+            .clone()
+            .or_else(|| url_tls_config(&connection_string));
+        Ok(Self {
+            connection_string,
+            tls_config,
+            tables: postgres_config.copy_tables.clone(),
+            idle_clients: tokio::sync::Mutex::new(vec![]),
+        })
+    }
+
+    pub fn is_enabled_for(&self, table_name: &str) -> bool {
+        self.tables.contains(table_name)
+    }
+
+    async fn connect(&self) -> Result<Client, ProcessorError> {
+        let to_processor_error = |kind: DbErrorKind, e: String| {
+            db_write_error(kind, format!("Error connecting to run COPY: {}", e), None)
+        };
+        let (url, _) = parse_and_clean_db_url(&self.connection_string);
+        let client = match &self.tls_config {
+            Some(tls_config) => {
+                let connector = build_tls_connector(tls_config)
+                    .map_err(|e| to_processor_error(DbErrorKind::Other, format!("{:#}", e)))?;
+                let (client, connection) = tokio_postgres::connect(&url, connector)
+                    .await
+                    .map_err(|e| to_processor_error(classify_postgres_error(&e), e.to_string()))?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        eprintln!("connection error: {}", e);
+                    }
+                });
+                client
+            },
+            None => {
+                let (client, connection) = tokio_postgres::connect(&url, NoTls)
+                    .await
+                    .map_err(|e| to_processor_error(classify_postgres_error(&e), e.to_string()))?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        eprintln!("connection error: {}", e);
+                    }
+                });
+                client
+            },
+        };
+        Ok(client)
+    }
+
+    /// Copies `rows` into a staging table and merges them into `T::TABLE_NAME`, in one
+    /// transaction.
+    pub async fn copy_rows<T: CopyRow>(&self, rows: &[T]) -> Result<(), ProcessorError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let idle_client = self.idle_clients.lock().await.pop();
+        let mut client = match idle_client {
+            Some(client) if !client.is_closed() => client,
+            _ => self.connect().await?,
+        };
+
+        let transaction = client
+            .transaction()
+            .await
+            .map_err(|e| copy_error(e, "BEGIN".to_string()))?;
+        let statement = create_staging_table_statement::<T>();
+        transaction
+            .batch_execute(&statement)
+            .await
+            .map_err(|e| copy_error(e, statement))?;
+
+        let statement = copy_statement::<T>();
+        let sink = transaction
+            .copy_in(&statement)
+            .await
+            .map_err(|e| copy_error(e, statement.clone()))?;
+        let types = T::copy_columns()
+            .into_iter()
+            .map(|(_, column_type)| column_type)
+            .collect::<Vec<_>>();
+        let mut writer = std::pin::pin!(BinaryCopyInWriter::new(sink, &types));
+        for row in rows {
+            writer
+                .as_mut()
+                .write(&row.copy_values())
+                .await
+                .map_err(|e| copy_error(e, statement.clone()))?;
+        }
+        writer
+            .finish()
+            .await
+            .map_err(|e| copy_error(e, statement))?;
+
+        let statement = merge_statement::<T>();
+        transaction
+            .batch_execute(&statement)
+            .await
+            .map_err(|e| copy_error(e, statement))?;
+        transaction
+            .commit()
+            .await
+            .map_err(|e| copy_error(e, "COMMIT".to_string()))?;
+
+        self.idle_clients.lock().await.push(client);
+        Ok(())
+    }
+}
+
+static COPY_WRITER: Lazy<Mutex<Option<Arc<CopyWriter>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Makes `copy_writer` the writer used by `execute_in_chunks_or_copy`.
+pub fn register_copy_writer(copy_writer: CopyWriter) {
+    *COPY_WRITER.lock().unwrap() = Some(Arc::new(copy_writer));
+}
+
+pub fn copy_writer() -> Option<Arc<CopyWriter>> {
+    COPY_WRITER.lock().unwrap().clone()
+}
+
+/// Writes `items` with `COPY` if their table is in `copy_tables`, and with `execute_in_chunks`
+/// otherwise. As with `execute_in_chunks`, a failed write is retried once with null bytes
+/// removed from the strings. The rows are committed on their own, so the write can't be part
+/// of a larger transaction.
+pub async fn execute_in_chunks_or_copy<U, T>(
+    conn: ArcDbPool,
+    build_query: fn(Vec<T>) -> U,
+    items_to_insert: &[T],
+    chunk_size: usize,
+) -> Result<(), ProcessorError>
+where
+    U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send + 'static,
+    T: CopyRow + serde::Serialize + for<'de> serde::Deserialize<'de> + Clone + Send + 'static,
+{
+    match copy_writer() {
+        Some(copy_writer) if copy_writer.is_enabled_for(T::TABLE_NAME) => {
+            if copy_writer.copy_rows(items_to_insert).await.is_err() {
+                let cleaned_items = clean_data_for_db(items_to_insert.to_vec(), true);
+                copy_writer.copy_rows(&cleaned_items).await?;
+            }
+            record_written_rows(T::TABLE_NAME, items_to_insert.len());
+            Ok(())
+        },
+        _ => execute_in_chunks(conn, build_query, items_to_insert, chunk_size).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::{
+        subconfigs::postgres_config::PoolConfig,
+        utils::{chunked_writes::is_unavailable_error, migrations::MigrationConfig},
+    };
+    #[cfg(feature = "testing_framework")]
+    use crate::testing_framework::database::{PostgresTestDatabase, TestDatabase};
+
+    struct TestRow {
+        transaction_version: i64,
+        type_: String,
+    }
+
+    impl CopyRow for TestRow {
+        const TABLE_NAME: &'static str = "test_events";
+
+        fn copy_columns() -> Vec<(&'static str, Type)> {
+            vec![("transaction_version", Type::INT8), ("type", Type::TEXT)]
+        }
+
+        fn conflict_columns() -> Vec<&'static str> {
+            vec!["transaction_version"]
+        }
+
+        fn copy_values(&self) -> Vec<&(dyn ToSql + Sync)> {
+            vec![&self.transaction_version, &self.type_]
+        }
+    }
+
+    fn test_row(transaction_version: i64, type_: &str) -> TestRow {
+        TestRow {
+            transaction_version,
+            type_: type_.to_string(),
+        }
+    }
+
+    fn test_postgres_config(connection_string: &str) -> PostgresConfig {
+        PostgresConfig {
+            connection_string: connection_string.to_string(),
+            db_pool_size: PostgresConfig::default_db_pool_size(),
+            migration_config: MigrationConfig::default(),
+            schema: PostgresConfig::default_schema(),
+            metadata_schema: PostgresConfig::default_metadata_schema(),
+            tls_config: None,
+            pool_config: PoolConfig::default(),
+            read_replica_connection_string: None,
+            read_replica_db_pool_size: None,
+            copy_tables: HashSet::from([TestRow::TABLE_NAME.to_string()]),
+            notify_config: None,
+            query_server_config: None,
+        }
+    }
+
+    #[test]
+    fn test_copy_statements() {
+        let row = TestRow {
+            transaction_version: 1,
+            type_: "0x1::test::Event".to_string(),
+        };
+        assert_eq!(row.copy_values().len(), TestRow::copy_columns().len());
+        assert_eq!(
+            create_staging_table_statement::<TestRow>(),
+            "CREATE TEMP TABLE \"test_events_copy_staging\" (LIKE \"test_events\" INCLUDING DEFAULTS) ON COMMIT DROP"
+        );
+        assert_eq!(
+            copy_statement::<TestRow>(),
+            "COPY \"test_events_copy_staging\" (\"transaction_version\", \"type\") FROM STDIN BINARY"
+        );
+        assert_eq!(
+            merge_statement::<TestRow>(),
+            "INSERT INTO \"test_events\" (\"transaction_version\", \"type\") SELECT \"transaction_version\", \"type\" FROM \"test_events_copy_staging\" ON CONFLICT (\"transaction_version\") DO NOTHING"
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_copy_connection_error_is_unavailable() {
+        // Nothing listens on port 1
+        let copy_writer = CopyWriter::new(&test_postgres_config(
+            "postgresql://postgres@127.0.0.1:1/postgres",
+        ))
+        .unwrap();
+        let error = copy_writer
+            .copy_rows(&[test_row(1, "0x1::test::Event")])
+            .await
+            .unwrap_err();
+        assert!(is_unavailable_error(&error));
+    }
+
+    #[cfg(feature = "testing_framework")]
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_copy_rows_round_trip() {
+        let mut db = PostgresTestDatabase::new();
+        db.setup().await.unwrap();
+        let copy_writer = CopyWriter::new(&test_postgres_config(&db.get_db_url())).unwrap();
+        let client = copy_writer.connect().await.unwrap();
+        client
+            .batch_execute(
+                "CREATE TABLE test_events (
+                    transaction_version BIGINT PRIMARY KEY CHECK (transaction_version > 0),
+                    type TEXT NOT NULL,
+                    inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+                )",
+            )
+            .await
+            .unwrap();
+
+        copy_writer
+            .copy_rows(&[test_row(1, "0x1::a::A"), test_row(2, "0x1::b::B")])
+            .await
+            .unwrap();
+        // Rows that conflict are left as they are
+        copy_writer
+            .copy_rows(&[test_row(2, "0x1::c::C"), test_row(3, "0x1::d::D")])
+            .await
+            .unwrap();
+        let rows = client
+            .query(
+                "SELECT transaction_version, type FROM test_events ORDER BY transaction_version",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get::<_, i64>(0), row.get::<_, String>(1)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (1, "0x1::a::A".to_string()),
+                (2, "0x1::b::B".to_string()),
+                (3, "0x1::d::D".to_string()),
+            ]
+        );
+
+        // A bad row fails the write without making the database look unavailable
+        let error = copy_writer
+            .copy_rows(&[test_row(-1, "0x1::e::E")])
+            .await
+            .unwrap_err();
+        assert!(!is_unavailable_error(&error));
+        assert!(matches!(
+            error,
+            ProcessorError::DBStoreError { message, .. }
+                if message.starts_with("[constraint_violation]")
+        ));
+    }
+}
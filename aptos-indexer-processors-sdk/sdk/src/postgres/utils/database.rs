@@ -4,9 +4,19 @@
 //! Database-related functions
 #![allow(clippy::extra_unused_lifetimes)]
 
-use crate::utils::{convert::remove_null_bytes, errors::ProcessorError};
+use super::{
+    chunked_writes::{chunk_size_for, execute_chunk, table_name_of},
+    notify::record_written_rows,
+};
+use crate::{
+    health::HealthCheck,
+    postgres::subconfigs::postgres_config::{PostgresConfig, PostgresTlsConfig, TlsVerifyMode},
+    utils::{convert::remove_null_bytes, errors::ProcessorError},
+};
 use ahash::AHashMap;
-use diesel::{query_builder::QueryFragment, ConnectionResult, QueryResult};
+use anyhow::Context;
+use async_trait::async_trait;
+use diesel::{query_builder::QueryFragment, ConnectionError, ConnectionResult, QueryResult};
 use diesel_async::{
     pooled_connection::{
         bb8::{Pool, PooledConnection},
@@ -16,7 +26,9 @@ use diesel_async::{
 };
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
 use futures_util::{future::BoxFuture, FutureExt};
-use std::sync::Arc;
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use std::{sync::Arc, time::Duration};
 use tracing::{info, warn};
 
 pub type Backend = diesel::pg::Pg;
@@ -44,25 +56,56 @@ pub fn clean_data_for_db<T: serde::Serialize + for<'de> serde::Deserialize<'de>>
     }
 }
 
-fn establish_connection(database_url: &str) -> BoxFuture<ConnectionResult<AsyncPgConnection>> {
-    use native_tls::{Certificate, TlsConnector};
-    use postgres_native_tls::MakeTlsConnector;
+pub(crate) fn build_tls_connector(
+    tls_config: &PostgresTlsConfig,
+) -> anyhow::Result<MakeTlsConnector> {
+    let mut builder = TlsConnector::builder();
+    if let Some(root_cert_path) = &tls_config.root_cert_path {
+        let cert = std::fs::read(root_cert_path)
+            .with_context(|| format!("Could not read certificate {}", root_cert_path))?;
+        builder.add_root_certificate(
+            Certificate::from_pem(&cert).context("Could not parse certificate")?,
+        );
+    }
+    match (&tls_config.client_cert_path, &tls_config.client_key_path) {
+        (Some(client_cert_path), Some(client_key_path)) => {
+            let cert = std::fs::read(client_cert_path).with_context(|| {
+                format!("Could not read client certificate {}", client_cert_path)
+            })?;
+            let key = std::fs::read(client_key_path)
+                .with_context(|| format!("Could not read client key {}", client_key_path))?;
+            builder.identity(
+                Identity::from_pkcs8(&cert, &key)
+                    .context("Could not parse client certificate and key")?,
+            );
+        },
+        (None, None) => {},
+        _ => anyhow::bail!("client_cert_path and client_key_path must be set together"),
+    }
+    match tls_config.verify_mode {
+        TlsVerifyMode::Require => {
+            builder.danger_accept_invalid_certs(true);
+        },
+        TlsVerifyMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        },
+        TlsVerifyMode::VerifyFull => {},
+    }
+    let connector = builder.build().context("Could not build TLS connector")?;
+    Ok(MakeTlsConnector::new(connector))
+}
 
+fn establish_connection(
+    database_url: &str,
+    tls_config: Arc<PostgresTlsConfig>,
+) -> BoxFuture<ConnectionResult<AsyncPgConnection>> {
+    let (url, _) = parse_and_clean_db_url(database_url);
     (async move {
-        let (url, cert_path) = parse_and_clean_db_url(database_url);
-        let cert = std::fs::read(cert_path.unwrap()).expect("Could not read certificate");
-
-        let cert = Certificate::from_pem(&cert).expect("Could not parse certificate");
-        let connector = TlsConnector::builder()
-            .danger_accept_invalid_certs(true)
-            .add_root_certificate(cert)
-            .build()
-            .expect("Could not build TLS connector");
-        let connector = MakeTlsConnector::new(connector);
-
+        let connector = build_tls_connector(&tls_config)
+            .map_err(|e| ConnectionError::BadConnection(format!("{:#}", e)))?;
         let (client, connection) = tokio_postgres::connect(&url, connector)
             .await
-            .expect("Could not connect to database");
+            .map_err(|e| ConnectionError::BadConnection(e.to_string()))?;
         tokio::spawn(async move {
             if let Err(e) = connection.await {
                 eprintln!("connection error: {}", e);
@@ -73,7 +116,7 @@ fn establish_connection(database_url: &str) -> BoxFuture<ConnectionResult<AsyncP
     .boxed()
 }
 
-fn parse_and_clean_db_url(url: &str) -> (String, Option<String>) {
+pub(crate) fn parse_and_clean_db_url(url: &str) -> (String, Option<String>) {
     let mut db_url = url::Url::parse(url).expect("Could not parse database url");
     let mut cert_path = None;
 
@@ -82,7 +125,7 @@ fn parse_and_clean_db_url(url: &str) -> (String, Option<String>) {
         if k == "sslrootcert" {
             cert_path = Some(v.parse().unwrap());
         } else {
-            query.push_str(&format!("{}={}&", k, v));
+            query.push_str(&format!("{}={}&", k, encode_query_value(&v)));
         }
     });
     db_url.set_query(Some(&query));
@@ -90,26 +133,218 @@ fn parse_and_clean_db_url(url: &str) -> (String, Option<String>) {
     (db_url.to_string(), cert_path)
 }
 
+/// Percent-encodes a value of the query of a connection string. Postgres only decodes
+/// percent-encoding, so `form_urlencoded`, which encodes spaces as `+`, can't be used.
+pub(crate) fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            },
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Appends `connection_options` to the `options` parameter of `connection_string`, which sets
+/// server settings such as `search_path` for every connection made with it.
+pub(crate) fn add_connection_options(
+    connection_string: &str,
+    connection_options: &str,
+) -> anyhow::Result<String> {
+    let mut url = url::Url::parse(connection_string)
+        .context("Could not parse the Postgres connection string")?;
+    let mut query = vec![];
+    let mut options = None;
+    for (key, value) in url.query_pairs() {
+        if key == "options" {
+            options = Some(format!("{} {}", value, connection_options));
+        } else {
+            query.push(format!("{}={}", key, encode_query_value(&value)));
+        }
+    }
+    let options = options.unwrap_or_else(|| connection_options.to_string());
+    query.push(format!("options={}", encode_query_value(&options)));
+    url.set_query(Some(&query.join("&")));
+    Ok(url.to_string())
+}
+
+/// Returns `database_url` with the `search_path` of the default `schema` and `metadata_schema`
+/// of `PostgresConfig`, unless its `options` already set one. Connections made without a
+/// `PostgresConfig`, e.g. by `new_db_pool`, then find the SDK tables in `processor_metadata`.
+pub(crate) fn with_default_search_path(database_url: &str) -> anyhow::Result<String> {
+    let url = url::Url::parse(database_url).context("Could not parse database url")?;
+    let sets_search_path = url
+        .query_pairs()
+        .any(|(key, value)| key == "options" && value.contains("search_path"));
+    if sets_search_path {
+        return Ok(database_url.to_string());
+    }
+    add_connection_options(
+        database_url,
+        &format!(
+            "-c search_path={},{}",
+            PostgresConfig::default_schema(),
+            PostgresConfig::default_metadata_schema()
+        ),
+    )
+}
+
+/// The TLS config implied by a `sslrootcert` in `database_url`, which encrypts without
+/// verifying the server certificate. Used when there is no `tls_config`.
+pub(crate) fn url_tls_config(database_url: &str) -> Option<PostgresTlsConfig> {
+    let (_url, cert_path) = parse_and_clean_db_url(database_url);
+    cert_path.map(|cert_path| PostgresTlsConfig {
+        root_cert_path: Some(cert_path),
+        verify_mode: TlsVerifyMode::Require,
+        ..Default::default()
+    })
+}
+
+/// Creates a pool for `database_url`. Its connections find the SDK tables in the default
+/// `processor_metadata` schema, see `with_default_search_path`.
 pub async fn new_db_pool(
     database_url: &str,
     max_pool_size: Option<u32>,
 ) -> Result<ArcDbPool, PoolError> {
-    let (_url, cert_path) = parse_and_clean_db_url(database_url);
-
-    let config = if cert_path.is_some() {
-        let mut config = ManagerConfig::<MyDbConnection>::default();
-        config.custom_setup = Box::new(|conn| Box::pin(establish_connection(conn)));
-        AsyncDieselConnectionManager::<MyDbConnection>::new_with_config(database_url, config)
-    } else {
-        AsyncDieselConnectionManager::<MyDbConnection>::new(database_url)
-    };
+    let tls_config = url_tls_config(database_url);
+    let database_url = with_default_search_path(database_url).map_err(|e| {
+        PoolError::ConnectionError(ConnectionError::InvalidConnectionUrl(format!("{:#}", e)))
+    })?;
     let pool = Pool::builder()
         .max_size(max_pool_size.unwrap_or(DEFAULT_MAX_POOL_SIZE))
-        .build(config)
+        .build(connection_manager(&database_url, tls_config))
         .await?;
     Ok(Arc::new(pool))
 }
 
+fn connection_manager(
+    database_url: &str,
+    tls_config: Option<PostgresTlsConfig>,
+) -> AsyncDieselConnectionManager<MyDbConnection> {
+    match tls_config {
+        Some(tls_config) => {
+            let tls_config = Arc::new(tls_config);
+            let mut config = ManagerConfig::<MyDbConnection>::default();
+            config.custom_setup =
+                Box::new(move |conn| Box::pin(establish_connection(conn, tls_config.clone())));
+            AsyncDieselConnectionManager::<MyDbConnection>::new_with_config(database_url, config)
+        },
+        None => AsyncDieselConnectionManager::<MyDbConnection>::new(database_url),
+    }
+}
+
+/// Returns `database_url` with `sslmode=require`, so that a connection with `tls_config` never
+/// falls back to plaintext. The verification itself is done by the TLS connector.
+pub(crate) fn require_ssl(database_url: &str) -> anyhow::Result<String> {
+    let mut db_url = url::Url::parse(database_url).context("Could not parse database url")?;
+    let mut query = db_url
+        .query_pairs()
+        .filter(|(k, _)| k != "sslmode")
+        .map(|(k, v)| format!("{}={}", k, encode_query_value(&v)))
+        .collect::<Vec<_>>();
+    query.push("sslmode=require".to_string());
+    db_url.set_query(Some(&query.join("&")));
+    Ok(db_url.to_string())
+}
+
+/// Creates a pool for `database_url` with the TLS settings and timeouts of `postgres_config`.
+/// Without `tls_config`, a `sslrootcert` in `database_url` is used as in `new_db_pool`.
+pub async fn new_db_pool_with_config(
+    database_url: &str,
+    max_pool_size: u32,
+    postgres_config: &PostgresConfig,
+) -> anyhow::Result<ArcDbPool> {
+    let pool_config = &postgres_config.pool_config;
+    let (database_url, tls_config) = match &postgres_config.tls_config {
+        Some(tls_config) => {
+            // Fail on a bad certificate now rather than on every connection attempt
+            build_tls_connector(tls_config)?;
+            (require_ssl(database_url)?, Some(tls_config.clone()))
+        },
+        None => (database_url.to_string(), url_tls_config(database_url)),
+    };
+    let as_option = |secs: u64| {
+        if secs > 0 {
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        }
+    };
+    let pool = Pool::builder()
+        .max_size(max_pool_size)
+        .connection_timeout(Duration::from_secs(pool_config.connection_timeout_secs))
+        .idle_timeout(as_option(pool_config.idle_timeout_secs))
+        .max_lifetime(as_option(pool_config.max_lifetime_secs))
+        .build(connection_manager(&database_url, tls_config))
+        .await
+        .context("Failed to create connection pool")?;
+    Ok(Arc::new(pool))
+}
+
+/// Creates the pools of the processor: one for the primary, and one for read-only queries.
+/// The read pool is the primary pool if no read replica is configured.
+pub async fn new_db_pools(
+    postgres_config: &PostgresConfig,
+) -> anyhow::Result<(ArcDbPool, ArcDbPool)> {
+    let db_pool = new_db_pool_with_config(
+        &postgres_config.primary_connection_string()?,
+        postgres_config.db_pool_size,
+        postgres_config,
+    )
+    .await?;
+    let read_db_pool = match postgres_config.read_replica_connection_string()? {
+        Some(read_replica_connection_string) => {
+            new_db_pool_with_config(
+                &read_replica_connection_string,
+                postgres_config
+                    .read_replica_db_pool_size
+                    .unwrap_or(postgres_config.db_pool_size),
+                postgres_config,
+            )
+            .await?
+        },
+        None => db_pool.clone(),
+    };
+    Ok((db_pool, read_db_pool))
+}
+
+/// A HealthCheck for the DB pool: the processor is only ready if it can get a connection and
+/// run a query.
+pub struct PostgresHealthCheck {
+    pub db_pool: ArcDbPool,
+}
+
+impl PostgresHealthCheck {
+    pub fn new(db_pool: ArcDbPool) -> Self {
+        Self { db_pool }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for PostgresHealthCheck {
+    fn name(&self) -> String {
+        "postgres".to_string()
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        let mut conn = self
+            .db_pool
+            .get()
+            .await
+            .context("Error getting db connection")?;
+        diesel::sql_query("SELECT 1")
+            .execute(&mut conn)
+            .await
+            .context("Error querying the database")?;
+        Ok(())
+    }
+}
+
+/// Writes `items_to_insert` in concurrent chunks. `chunk_size` is the maximum; the chunk size
+/// of each table adapts to the observed write latency, see `chunked_writes`. Transient errors
+/// are retried with backoff, and failing chunks are bisected to isolate the offending row.
 pub async fn execute_in_chunks<U, T>(
     conn: ArcDbPool,
     build_query: fn(Vec<T>) -> U,
@@ -120,14 +355,21 @@ where
     U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send + 'static,
     T: serde::Serialize + for<'de> serde::Deserialize<'de> + Clone + Send + 'static,
 {
+    let Some(first_item) = items_to_insert.first() else {
+        return Ok(());
+    };
+    let table_name = table_name_of(build_query, first_item);
+    let max_chunk_size = chunk_size;
+    let chunk_size = chunk_size_for(&table_name, max_chunk_size);
+
     let tasks = items_to_insert
         .chunks(chunk_size)
         .map(|chunk| {
             let conn = conn.clone();
             let items = chunk.to_vec();
+            let table_name = table_name.clone();
             tokio::spawn(async move {
-                let query = build_query(items.clone());
-                execute_or_retry_cleaned(conn, build_query, items, query).await
+                execute_chunk(conn, build_query, items, table_name, max_chunk_size).await
             })
         })
         .collect::<Vec<_>>();
@@ -138,6 +380,7 @@ where
     for res in results {
         res?
     }
+    record_written_rows(&table_name, items_to_insert.len());
 
     Ok(())
 }
@@ -197,32 +440,6 @@ where
     res
 }
 
-async fn execute_or_retry_cleaned<U, T>(
-    conn: ArcDbPool,
-    build_query: fn(Vec<T>) -> U,
-    items: Vec<T>,
-    query: U,
-) -> Result<(), ProcessorError>
-where
-    U: QueryFragment<Backend> + diesel::query_builder::QueryId + Send,
-    T: serde::Serialize + for<'de> serde::Deserialize<'de> + Clone,
-{
-    match execute_with_better_error(conn.clone(), query).await {
-        Ok(_) => {},
-        Err(_) => {
-            let cleaned_items = clean_data_for_db(items, true);
-            let cleaned_query = build_query(cleaned_items);
-            match execute_with_better_error(conn.clone(), cleaned_query).await {
-                Ok(_) => {},
-                Err(e) => {
-                    return Err(e);
-                },
-            }
-        },
-    }
-    Ok(())
-}
-
 pub fn run_pending_migrations<DB: diesel::backend::Backend>(
     conn: &mut impl MigrationHarness<DB>,
     migrations: EmbeddedMigrations,
@@ -244,6 +461,9 @@ pub async fn run_migrations(
 
     info!("Running migrations: {:?}", postgres_connection_string);
     let migration_time = std::time::Instant::now();
+    // Same `search_path` as the connections of `new_db_pool`
+    let postgres_connection_string = with_default_search_path(&postgres_connection_string)
+        .expect("Could not parse database url");
     let mut conn =
         PgConnection::establish(&postgres_connection_string).expect("migrations failed!");
     run_pending_migrations(&mut conn, migrations);
@@ -0,0 +1,88 @@
+use super::database::{execute_with_better_error_conn, ArcDbPool};
+use crate::{
+    postgres::processor_metadata_schema::processor_metadata::processor_leases,
+    utils::leader_election::LeaseStore,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    query_dsl::methods::FilterDsl,
+    upsert::excluded,
+    BoolExpressionMethods, ExpressionMethods,
+};
+use std::time::Duration;
+
+/// A trait implementation of LeaseStore for Postgres, using a row per processor in
+/// `processor_leases`. Expiry is computed with the clock of the database, so the clocks of the
+/// replicas don't matter.
+pub struct PostgresLeaseStore {
+    pub db_pool: ArcDbPool,
+    pub processor_name: String,
+}
+
+impl PostgresLeaseStore {
+    pub fn new(processor_name: &str, db_pool: ArcDbPool) -> Self {
+        Self {
+            db_pool,
+            processor_name: processor_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseStore for PostgresLeaseStore {
+    async fn try_acquire_lease(&self, holder_id: &str, lease_duration: Duration) -> Result<bool> {
+        let mut conn = self
+            .db_pool
+            .get()
+            .await
+            .context("Error getting db connection")?;
+        let lease_secs = lease_duration.as_secs() as i64;
+        // Only takes over a lease that expired, or renews one this holder already has
+        let num_rows = execute_with_better_error_conn(
+            &mut conn,
+            diesel::insert_into(processor_leases::table)
+                .values((
+                    processor_leases::processor.eq(&self.processor_name),
+                    processor_leases::holder.eq(holder_id),
+                    processor_leases::lease_expires_at.eq(now + lease_secs.seconds()),
+                ))
+                .on_conflict(processor_leases::processor)
+                .do_update()
+                .set((
+                    processor_leases::holder.eq(excluded(processor_leases::holder)),
+                    processor_leases::lease_expires_at
+                        .eq(excluded(processor_leases::lease_expires_at)),
+                    processor_leases::last_updated.eq(now),
+                ))
+                .filter(
+                    processor_leases::holder
+                        .eq(holder_id)
+                        .or(processor_leases::lease_expires_at.lt(now)),
+                ),
+        )
+        .await
+        .context("Error acquiring processor lease")?;
+        Ok(num_rows == 1)
+    }
+
+    async fn release_lease(&self, holder_id: &str) -> Result<()> {
+        let mut conn = self
+            .db_pool
+            .get()
+            .await
+            .context("Error getting db connection")?;
+        execute_with_better_error_conn(
+            &mut conn,
+            diesel::delete(
+                processor_leases::table
+                    .filter(processor_leases::processor.eq(&self.processor_name))
+                    .filter(processor_leases::holder.eq(holder_id)),
+            ),
+        )
+        .await
+        .context("Error releasing processor lease")?;
+        Ok(())
+    }
+}
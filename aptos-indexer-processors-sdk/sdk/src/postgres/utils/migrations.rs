@@ -0,0 +1,262 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Migrations that are safe to run from several replicas starting at the same time.
+
+use super::database::ArcDbPool;
+use anyhow::{Context, Result};
+use diesel::{
+    pg::Pg,
+    sql_types::{BigInt, Text},
+    RunQueryDsl,
+};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, MigrationSource};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// Key of the Postgres advisory lock held while migrating, shared by all processors that use
+// the SDK since they may share schemas and their `__diesel_schema_migrations`.
+const MIGRATION_LOCK_KEY: i64 = 0x6170_746f_735f_6d69;
+
+const MIGRATION_LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+// Session setting read by the SDK migrations for the schema of their tables. They fall back to
+// `processor_metadata` when it isn't set, e.g. when run by `run_migrations`.
+const MIGRATION_SCHEMA_SETTING: &str = "aptos_indexer.migration_schema";
+
+diesel::define_sql_function! {
+    fn pg_try_advisory_lock(key: BigInt) -> Bool;
+}
+
+diesel::define_sql_function! {
+    fn pg_advisory_unlock(key: BigInt) -> Bool;
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationConfig {
+    /// How long to wait for another replica to finish migrating before giving up.
+    #[serde(default = "MigrationConfig::default_lock_timeout_secs")]
+    pub lock_timeout_secs: u64,
+    /// Refuse to start if the database has migrations this binary does not know about, e.g.
+    /// after rolling back to an older release.
+    #[serde(default)]
+    pub fail_on_unknown_migrations: bool,
+}
+
+impl MigrationConfig {
+    pub const fn default_lock_timeout_secs() -> u64 {
+        300
+    }
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            lock_timeout_secs: Self::default_lock_timeout_secs(),
+            fail_on_unknown_migrations: false,
+        }
+    }
+}
+
+/// Migrations by state. Applied and pending migrations are listed as `<schema>.<name>`, unknown
+/// ones, i.e. applied to the database but not embedded in the binary, by version.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+    pub unknown: Vec<String>,
+}
+
+// Migrations run on a connection of the pool, so that they use the same TLS settings as the
+// processor, wrapped to be usable as a blocking connection.
+type MigrationConnection =
+    diesel_async::async_connection_wrapper::AsyncConnectionWrapper<diesel_async::AsyncPgConnection>;
+
+/// Runs `f` on a blocking task with a connection that can run migrations.
+async fn with_migration_connection<T, F>(conn_pool: ArcDbPool, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut MigrationConnection) -> Result<T> + Send + 'static,
+{
+    let conn = conn_pool
+        .dedicated_connection()
+        .await
+        .context("Failed to get a connection to run migrations")?;
+    tokio::task::spawn_blocking(move || {
+        let mut conn = MigrationConnection::from(conn);
+        f(&mut conn)
+    })
+    .await?
+}
+
+/// Migrations to run in a schema. Each schema gets its own `__diesel_schema_migrations`.
+pub struct SchemaMigrations {
+    pub schema: String,
+    pub migrations: EmbeddedMigrations,
+}
+
+impl SchemaMigrations {
+    pub fn new(schema: &str, migrations: EmbeddedMigrations) -> Self {
+        Self {
+            schema: schema.to_string(),
+            migrations,
+        }
+    }
+}
+
+/// Creates `schema` if needed and puts it first in the connection's `search_path`, so that
+/// migrations and their bookkeeping land in it. `public` follows, so that migrations can use
+/// the extensions and functions installed there. The SDK migrations create their tables in
+/// `MIGRATION_SCHEMA_SETTING`.
+fn use_schema(conn: &mut MigrationConnection, schema: &str) -> Result<()> {
+    diesel::sql_query(format!("CREATE SCHEMA IF NOT EXISTS \"{}\"", schema))
+        .execute(conn)
+        .with_context(|| format!("Failed to create schema {}", schema))?;
+    let search_path = if schema == "public" {
+        "\"public\"".to_string()
+    } else {
+        format!("\"{}\", \"public\"", schema)
+    };
+    diesel::sql_query(format!("SET search_path TO {}", search_path))
+        .execute(conn)
+        .with_context(|| format!("Failed to set the search_path to {}", schema))?;
+    diesel::sql_query("SELECT set_config($1, $2, false)")
+        .bind::<Text, _>(MIGRATION_SCHEMA_SETTING)
+        .bind::<Text, _>(schema)
+        .execute(conn)
+        .with_context(|| format!("Failed to set {} to {}", MIGRATION_SCHEMA_SETTING, schema))?;
+    Ok(())
+}
+
+fn build_report(
+    conn: &mut MigrationConnection,
+    migration_sets: &[SchemaMigrations],
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    let mut all_applied_versions = HashSet::new();
+    let mut known_versions = HashSet::new();
+    for set in migration_sets {
+        use_schema(conn, &set.schema)?;
+        let applied_versions = conn
+            .applied_migrations()
+            .map_err(|e| anyhow::anyhow!("Failed to get applied migrations: {}", e))?
+            .into_iter()
+            .map(|version| version.to_string())
+            .collect::<HashSet<_>>();
+        let migrations = MigrationSource::<Pg>::migrations(&set.migrations)
+            .map_err(|e| anyhow::anyhow!("Failed to load embedded migrations: {}", e))?;
+        for migration in migrations {
+            let version = migration.name().version().to_string();
+            let name = format!("{}.{}", set.schema, migration.name());
+            if applied_versions.contains(&version) {
+                report.applied.push(name);
+            } else {
+                report.pending.push(name);
+            }
+            known_versions.insert(version);
+        }
+        all_applied_versions.extend(applied_versions);
+    }
+    report.unknown = all_applied_versions
+        .difference(&known_versions)
+        .cloned()
+        .collect();
+    report.unknown.sort();
+    Ok(report)
+}
+
+fn acquire_migration_lock(conn: &mut MigrationConnection, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let locked = diesel::select(pg_try_advisory_lock(MIGRATION_LOCK_KEY))
+            .get_result::<bool>(conn)
+            .context("Failed to take the migration lock")?;
+        if locked {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "Timed out after {}s waiting for another instance to finish migrating",
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(MIGRATION_LOCK_RETRY_INTERVAL);
+    }
+}
+
+/// Returns which migrations of `migration_sets` are applied and pending, and which applied
+/// migrations are not in any of the sets.
+pub async fn get_migration_report(
+    conn_pool: ArcDbPool,
+    migration_sets: Vec<SchemaMigrations>,
+) -> Result<MigrationReport> {
+    with_migration_connection(conn_pool, move |conn| build_report(conn, &migration_sets)).await
+}
+
+/// Runs the pending migrations of `migration_sets` in order while holding a Postgres advisory
+/// lock, so that replicas starting together don't race. Replicas that wait for the lock find
+/// nothing left to run. Returns the report from before the pending migrations ran.
+///
+/// Each set runs in its schema, which is created if needed. All sets, e.g. the processor's and
+/// `SDK_MIGRATIONS`, have to be passed together for unknown migrations to be detected.
+pub async fn run_migrations_with_lock(
+    conn_pool: ArcDbPool,
+    migration_sets: Vec<SchemaMigrations>,
+    config: &MigrationConfig,
+) -> Result<MigrationReport> {
+    let config = config.clone();
+    with_migration_connection(conn_pool, move |conn| {
+        let lock_wait_time = Instant::now();
+        acquire_migration_lock(conn, Duration::from_secs(config.lock_timeout_secs))?;
+        info!(
+            lock_wait_secs = lock_wait_time.elapsed().as_secs_f64(),
+            "Took the migration lock"
+        );
+
+        let result = build_report(conn, &migration_sets).and_then(|report| {
+            if !report.unknown.is_empty() {
+                if config.fail_on_unknown_migrations {
+                    anyhow::bail!(
+                        "The database has migrations this binary does not know about: {:?}",
+                        report.unknown
+                    );
+                }
+                warn!(
+                    unknown_migrations = ?report.unknown,
+                    "The database has migrations this binary does not know about"
+                );
+            }
+            info!(
+                num_applied = report.applied.len(),
+                pending = ?report.pending,
+                "Running pending migrations"
+            );
+            let migration_time = Instant::now();
+            for set in migration_sets {
+                use_schema(conn, &set.schema)?;
+                conn.run_pending_migrations(set.migrations).map_err(|e| {
+                    anyhow::anyhow!("Migrations in schema {} failed: {}", set.schema, e)
+                })?;
+            }
+            info!(
+                duration_in_secs = migration_time.elapsed().as_secs_f64(),
+                "[Parser] Finished migrations"
+            );
+            Ok(report)
+        });
+
+        // Release the lock even if migrating failed
+        if let Err(e) =
+            diesel::select(pg_advisory_unlock(MIGRATION_LOCK_KEY)).get_result::<bool>(conn)
+        {
+            warn!(error = ?e, "Failed to release the migration lock");
+        }
+        result
+    })
+    .await
+}
@@ -1,2 +1,11 @@
 pub mod checkpoint;
+pub mod chunked_writes;
+pub mod commands;
+pub mod copy;
 pub mod database;
+pub mod lease;
+pub mod migrations;
+pub mod notify;
+pub mod query_server;
+#[cfg(feature = "webhook")]
+pub mod webhook_outbox;
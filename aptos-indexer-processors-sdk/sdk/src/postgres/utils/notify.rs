@@ -0,0 +1,266 @@
+use super::database::{execute_with_better_error, ArcDbPool};
+use crate::{types::transaction_context::TransactionMetadata, utils::errors::ProcessorError};
+use diesel::sql_types::Text;
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Channel that listeners `LISTEN` on.
+    pub channel: String,
+    /// Tables to notify about, all of them if empty.
+    #[serde(default)]
+    pub tables: HashSet<String>,
+}
+
+/// Payload of a notification, as JSON.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BatchNotification {
+    pub table: String,
+    pub start_version: u64,
+    pub end_version: u64,
+    pub row_count: usize,
+}
+
+/// Sends a `pg_notify` on `NotifyConfig::channel` once a batch of rows is committed, so that
+/// services holding a Postgres connection can `LISTEN` instead of polling the tables.
+/// Notifications are best effort: a listener that isn't connected misses them.
+pub struct Notifier {
+    channel: String,
+    tables: HashSet<String>,
+}
+
+impl Notifier {
+    pub fn new(notify_config: &NotifyConfig) -> Self {
+        Self {
+            channel: notify_config.channel.clone(),
+            tables: notify_config.tables.clone(),
+        }
+    }
+
+    pub fn is_enabled_for(&self, table_name: &str) -> bool {
+        self.tables.is_empty() || self.tables.contains(table_name)
+    }
+
+    pub async fn notify(
+        &self,
+        conn: ArcDbPool,
+        notification: &BatchNotification,
+    ) -> Result<(), ProcessorError> {
+        let payload = serde_json::to_string(notification).expect("notifications serialize");
+        execute_with_better_error(
+            conn,
+            diesel::sql_query("SELECT pg_notify($1, $2)")
+                .bind::<Text, _>(&self.channel)
+                .bind::<Text, _>(payload),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+static NOTIFIER: Lazy<Mutex<Option<Arc<Notifier>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Makes `notifier` the notifier used by `notify_committed_batch`.
+pub fn register_notifier(notifier: Notifier) {
+    *NOTIFIER.lock().unwrap() = Some(Arc::new(notifier));
+}
+
+pub fn notifier() -> Option<Arc<Notifier>> {
+    NOTIFIER.lock().unwrap().clone()
+}
+
+/// Notifies listeners that `row_count` rows of `table_name` were committed for the versions of
+/// `metadata`, if a notifier is registered for the table. `BasicProcessorStep` calls it for
+/// each table written by the batch; custom steps call it once the whole batch is written.
+pub async fn notify_committed_batch(
+    conn: ArcDbPool,
+    table_name: &str,
+    metadata: &TransactionMetadata,
+    row_count: usize,
+) -> Result<(), ProcessorError> {
+    match notifier() {
+        Some(notifier) if notifier.is_enabled_for(table_name) => {
+            let notification = BatchNotification {
+                table: table_name.to_string(),
+                start_version: metadata.start_version,
+                end_version: metadata.end_version,
+                row_count,
+            };
+            notifier.notify(conn, &notification).await
+        },
+        _ => Ok(()),
+    }
+}
+
+tokio::task_local! {
+    static WRITTEN_ROWS: Mutex<IndexMap<String, usize>>;
+}
+
+/// Counts `row_count` rows written to `table_name` for the batch tracked by
+/// `track_written_rows`, if any. `execute_in_chunks` and `execute_in_chunks_or_copy` call it;
+/// process functions call it for the rows they write otherwise, e.g. in a transaction.
+pub fn record_written_rows(table_name: &str, row_count: usize) {
+    if row_count == 0 {
+        return;
+    }
+    let _ = WRITTEN_ROWS.try_with(|written_rows| {
+        *written_rows
+            .lock()
+            .unwrap()
+            .entry(table_name.to_string())
+            .or_default() += row_count;
+    });
+}
+
+/// Runs `future` and returns its output with the number of rows it wrote per table, in the
+/// order the tables were first written. Writes from tasks spawned by `future` aren't counted.
+pub(crate) async fn track_written_rows<F: Future>(future: F) -> (F::Output, Vec<(String, usize)>) {
+    let written_rows = Mutex::new(IndexMap::new());
+    WRITTEN_ROWS
+        .scope(written_rows, async move {
+            let output = future.await;
+            let written_rows = WRITTEN_ROWS.with(|written_rows| {
+                std::mem::take(&mut *written_rows.lock().unwrap())
+                    .into_iter()
+                    .collect()
+            });
+            (output, written_rows)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "testing_framework")]
+    use crate::{
+        postgres::{
+            basic_processor::basic_processor_step::BasicProcessorStep,
+            utils::database::{execute_in_chunks, new_db_pool, Backend},
+        },
+        testing_framework::database::{PostgresTestDatabase, TestDatabase},
+        traits::Processable,
+        types::transaction_context::TransactionContext,
+    };
+    #[cfg(feature = "testing_framework")]
+    use aptos_protos::transaction::v1::Transaction;
+    #[cfg(feature = "testing_framework")]
+    use diesel::{
+        query_builder::{QueryFragment, QueryId},
+        ExpressionMethods,
+    };
+    #[cfg(feature = "testing_framework")]
+    use futures::StreamExt;
+
+    #[cfg(feature = "testing_framework")]
+    diesel::table! {
+        test_notify_events (transaction_version) {
+            transaction_version -> Int8,
+        }
+    }
+
+    #[cfg(feature = "testing_framework")]
+    fn insert_test_notify_events_query(
+        versions: Vec<i64>,
+    ) -> impl QueryFragment<Backend> + QueryId + Send {
+        diesel::insert_into(test_notify_events::table).values(
+            versions
+                .into_iter()
+                .map(|version| test_notify_events::transaction_version.eq(version))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_batch_notification() {
+        let notifier = Notifier::new(&NotifyConfig {
+            channel: "indexer_batches".to_string(),
+            tables: HashSet::from(["raffle_events".to_string()]),
+        });
+        assert!(notifier.is_enabled_for("raffle_events"));
+        assert!(!notifier.is_enabled_for("ledger_infos"));
+
+        let notification = BatchNotification {
+            table: "raffle_events".to_string(),
+            start_version: 100,
+            end_version: 199,
+            row_count: 12,
+        };
+        assert_eq!(
+            serde_json::to_string(&notification).unwrap(),
+            r#"{"table":"raffle_events","start_version":100,"end_version":199,"row_count":12}"#
+        );
+    }
+
+    #[cfg(feature = "testing_framework")]
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_committed_batches_are_notified() {
+        let mut db = PostgresTestDatabase::new();
+        db.setup().await.unwrap();
+        let db_url = db.get_db_url();
+
+        let (client, mut connection) = tokio_postgres::connect(&db_url, tokio_postgres::NoTls)
+            .await
+            .unwrap();
+        let (payloads_sender, mut payloads) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(Ok(message)) = messages.next().await {
+                if let tokio_postgres::AsyncMessage::Notification(notification) = message {
+                    let _ = payloads_sender.send(notification.payload().to_string());
+                }
+            }
+        });
+        client
+            .batch_execute(
+                "CREATE TABLE test_notify_events (transaction_version BIGINT PRIMARY KEY);
+                LISTEN indexer_batches",
+            )
+            .await
+            .unwrap();
+
+        register_notifier(Notifier::new(&NotifyConfig {
+            channel: "indexer_batches".to_string(),
+            tables: HashSet::new(),
+        }));
+        let mut step = BasicProcessorStep {
+            process_function: |_transactions: Vec<Transaction>, conn_pool: ArcDbPool| async move {
+                execute_in_chunks(conn_pool, insert_test_notify_events_query, &[1, 2, 3], 100).await
+            },
+            conn_pool: new_db_pool(&db_url, Some(2)).await.unwrap(),
+        };
+        step.process(TransactionContext {
+            data: vec![],
+            metadata: TransactionMetadata {
+                start_version: 1,
+                end_version: 3,
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+
+        let payload = tokio::time::timeout(std::time::Duration::from_secs(5), payloads.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<BatchNotification>(&payload).unwrap(),
+            BatchNotification {
+                table: "test_notify_events".to_string(),
+                start_version: 1,
+                end_version: 3,
+                row_count: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,857 @@
+use super::database::{ArcDbPool, Backend, MyDbConnection};
+use crate::{
+    admin::server::{auth_middleware, is_loopback_address},
+    postgres::processor_metadata_schema::processor_metadata::processor_status,
+    utils::checkpoint_store::CheckpointStore,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use diesel::{
+    query_builder::QueryFragment,
+    sql_types::{
+        BigInt, Bool, Integer, Jsonb, Nullable, Numeric, SmallInt, Text, Timestamp, Timestamptz,
+    },
+    Column, Expression, ExpressionMethods, OptionalExtension, QueryDsl, QuerySource,
+    QueryableByName, Table,
+};
+use diesel_async::{scoped_futures::ScopedFutureExt, RunQueryDsl};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+const TRANSACTION_VERSION_COLUMN: &str = "transaction_version";
+const EVENT_INDEX_COLUMN: &str = "event_index";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryServerConfig {
+    /// Address the query API listens on. Defaults to localhost only.
+    #[serde(default = "QueryServerConfig::default_bind_address")]
+    pub bind_address: String,
+    /// If set, every request must send `Authorization: Bearer <auth_token>`. Required unless
+    /// `bind_address` is a loopback address.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Rows per page if the request has no `limit`.
+    #[serde(default = "QueryServerConfig::default_page_size")]
+    pub default_page_size: usize,
+    /// Upper bound of `limit`.
+    #[serde(default = "QueryServerConfig::default_max_page_size")]
+    pub max_page_size: usize,
+}
+
+impl QueryServerConfig {
+    pub fn default_bind_address() -> String {
+        "127.0.0.1:8087".to_string()
+    }
+
+    pub const fn default_page_size() -> usize {
+        100
+    }
+
+    pub const fn default_max_page_size() -> usize {
+        1000
+    }
+
+    /// Checks that the query API can't be reached from outside the host without a token.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.auth_token.is_none() && !is_loopback_address(&self.bind_address) {
+            anyhow::bail!(
+                "query_server_config.auth_token is required to bind the query API to {}, which is not a loopback address",
+                self.bind_address
+            );
+        }
+        Ok(())
+    }
+}
+
+/// SQL types of the columns that can be served, with the type that query parameters are cast
+/// to.
+pub trait QueryColumnType {
+    const CAST: &'static str;
+    const NULLABLE: bool = false;
+}
+
+macro_rules! query_column_type {
+    ($sql_type:ty, $cast:literal) => {
+        impl QueryColumnType for $sql_type {
+            const CAST: &'static str = $cast;
+        }
+    };
+}
+
+query_column_type!(SmallInt, "smallint");
+query_column_type!(Integer, "integer");
+query_column_type!(BigInt, "bigint");
+query_column_type!(Numeric, "numeric");
+query_column_type!(Bool, "boolean");
+query_column_type!(Text, "text");
+query_column_type!(Timestamp, "timestamp");
+query_column_type!(Timestamptz, "timestamptz");
+query_column_type!(Jsonb, "jsonb");
+
+impl<T> QueryColumnType for Nullable<T>
+where
+    T: QueryColumnType,
+{
+    const CAST: &'static str = T::CAST;
+    const NULLABLE: bool = true;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QueryColumn {
+    pub name: &'static str,
+    pub sql_type: &'static str,
+    pub nullable: bool,
+}
+
+/// A table served by the query API, built from a Diesel `table!`. Only the registered columns
+/// are returned and can be filtered and sorted on.
+///
+/// Tables with `transaction_version` and `event_index` columns are paged with a cursor and
+/// bounded by `as_of_version`. Other tables, e.g. tables updated in place, only return their
+/// first page.
+#[derive(Clone, Debug)]
+pub struct QueryTable<T> {
+    table: ServedTable,
+    _table: PhantomData<T>,
+}
+
+impl<T> QueryTable<T>
+where
+    T: Table,
+    T::FromClause: QueryFragment<Backend>,
+{
+    /// E.g. `QueryTable::new(raffle_events::table)`.
+    pub fn new(table: T) -> Self {
+        // Renders as `"raffle_events" -- binds: []`; drop the schema and the quotes
+        let from_clause = diesel::debug_query::<Backend, _>(&table.from_clause()).to_string();
+        let name = from_clause
+            .split_whitespace()
+            .next()
+            .and_then(|name| name.rsplit('.').next())
+            .unwrap_or_default()
+            .trim_matches('"');
+        Self {
+            table: ServedTable {
+                name: name.to_string(),
+                columns: vec![],
+            },
+            _table: PhantomData,
+        }
+    }
+
+    /// Registers a column of the table, e.g. `.column(raffle_events::creator)`. Columns of
+    /// other tables don't compile.
+    pub fn column<C>(mut self, _column: C) -> Self
+    where
+        C: Column<Table = T>,
+        <C as Expression>::SqlType: QueryColumnType,
+    {
+        self.table.columns.push(QueryColumn {
+            name: C::NAME,
+            sql_type: <<C as Expression>::SqlType as QueryColumnType>::CAST,
+            nullable: <<C as Expression>::SqlType as QueryColumnType>::NULLABLE,
+        });
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.table.name
+    }
+}
+
+/// A registered table, whatever its Diesel type.
+#[derive(Clone, Debug)]
+struct ServedTable {
+    name: String,
+    columns: Vec<QueryColumn>,
+}
+
+impl ServedTable {
+    fn find_column(&self, name: &str) -> Option<&QueryColumn> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    fn is_versioned(&self) -> bool {
+        self.find_column(TRANSACTION_VERSION_COLUMN).is_some()
+    }
+
+    fn is_pageable(&self) -> bool {
+        self.is_versioned() && self.find_column(EVENT_INDEX_COLUMN).is_some()
+    }
+}
+
+/// Tables registered by the processor, served once the query server starts.
+static QUERY_TABLES: Lazy<Mutex<Vec<ServedTable>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Serves `table` on the query API. Tables have to be registered before the query server
+/// starts, e.g. at the top of `main`.
+pub fn register_query_table<T>(table: QueryTable<T>) {
+    QUERY_TABLES.lock().unwrap().push(table.table);
+}
+
+type QueryError = (StatusCode, String);
+
+fn bad_request(message: String) -> QueryError {
+    (StatusCode::BAD_REQUEST, message)
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Position after the last row of a page: the value of the sort column, unless sorting by
+/// version, and the version and event index of the row.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct PageCursor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort_value: Option<Value>,
+    transaction_version: i64,
+    event_index: i64,
+}
+
+impl PageCursor {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursors serialize"))
+    }
+
+    fn decode(cursor: &str) -> Result<Self, QueryError> {
+        hex::decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| bad_request(format!("Invalid cursor `{}`", cursor)))
+    }
+}
+
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// A value of the request and the type it is cast to in the query.
+#[derive(Debug, PartialEq)]
+struct ParamCast {
+    param: String,
+    value: String,
+    cast: &'static str,
+}
+
+#[derive(Debug, PartialEq)]
+struct QueryPlan {
+    sql: String,
+    binds: Vec<String>,
+    param_casts: Vec<ParamCast>,
+    sort_column: Option<&'static str>,
+    limit: usize,
+    as_of_version: Option<u64>,
+}
+
+struct QueryBuilder {
+    conditions: Vec<String>,
+    binds: Vec<String>,
+    param_casts: Vec<ParamCast>,
+}
+
+impl QueryBuilder {
+    fn bind(&mut self, value: String, cast: &str) -> String {
+        self.binds.push(value);
+        format!("CAST(${} AS {})", self.binds.len(), cast)
+    }
+
+    /// Binds a value of the request, which is checked to cast before the query runs.
+    fn bind_param(&mut self, param: &str, value: String, cast: &'static str) -> String {
+        self.param_casts.push(ParamCast {
+            param: param.to_string(),
+            value: value.clone(),
+            cast,
+        });
+        self.bind(value, cast)
+    }
+}
+
+/// Builds the query of a page of `table`. Parameters other than `limit`, `cursor`, `sort`,
+/// `order` and `as_of_version` are filters, `<column>=<value>` or `<column>__<op>=<value>` with
+/// `ne`, `gt`, `gte`, `lt` or `lte`. Rows of versioned tables are bounded by `as_of_version`,
+/// capped at `checkpoint`, so that rows of batches still being written are never returned.
+fn build_query(
+    table: &ServedTable,
+    params: &BTreeMap<String, String>,
+    checkpoint: Option<u64>,
+    config: &QueryServerConfig,
+) -> Result<QueryPlan, QueryError> {
+    let mut builder = QueryBuilder {
+        conditions: vec![],
+        binds: vec![],
+        param_casts: vec![],
+    };
+
+    let mut limit = config.default_page_size;
+    let mut cursor = None;
+    let mut sort_column = None;
+    let mut descending = false;
+    let mut requested_version = None;
+    for (key, value) in params {
+        match key.as_str() {
+            "limit" => {
+                limit = value
+                    .parse::<usize>()
+                    .map_err(|_| bad_request(format!("Invalid limit `{}`", value)))?
+                    .min(config.max_page_size)
+                    .max(1);
+            },
+            "cursor" => cursor = Some(PageCursor::decode(value)?),
+            "sort" => {
+                let column = table
+                    .find_column(value)
+                    .ok_or_else(|| bad_request(format!("Unknown column `{}` in sort", value)))?;
+                if column.nullable {
+                    return Err(bad_request(format!(
+                        "Cannot sort on nullable column `{}`",
+                        value
+                    )));
+                }
+                sort_column = Some(column.name);
+            },
+            "order" => {
+                descending = match value.as_str() {
+                    "asc" => false,
+                    "desc" => true,
+                    _ => return Err(bad_request(format!("Invalid order `{}`", value))),
+                }
+            },
+            "as_of_version" => {
+                requested_version = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| bad_request(format!("Invalid as_of_version `{}`", value)))?,
+                );
+            },
+            filter => {
+                let (column_name, operator) = match filter.rsplit_once("__") {
+                    Some((column_name, "ne")) => (column_name, "<>"),
+                    Some((column_name, "gt")) => (column_name, ">"),
+                    Some((column_name, "gte")) => (column_name, ">="),
+                    Some((column_name, "lt")) => (column_name, "<"),
+                    Some((column_name, "lte")) => (column_name, "<="),
+                    _ => (filter, "="),
+                };
+                let column = table.find_column(column_name).ok_or_else(|| {
+                    bad_request(format!("Unknown column `{}` in filter", column_name))
+                })?;
+                let placeholder = builder.bind_param(filter, value.clone(), column.sql_type);
+                builder.conditions.push(format!(
+                    "{} {} {}",
+                    quote_identifier(column.name),
+                    operator,
+                    placeholder
+                ));
+            },
+        }
+    }
+
+    let as_of_version = if table.is_versioned() {
+        // Nothing is committed before the first checkpoint
+        let as_of_version = checkpoint.map(|checkpoint| {
+            requested_version.map_or(checkpoint, |version| version.min(checkpoint))
+        });
+        match as_of_version {
+            Some(version) => {
+                let placeholder = builder.bind(version.to_string(), "bigint");
+                builder.conditions.push(format!(
+                    "{} <= {}",
+                    quote_identifier(TRANSACTION_VERSION_COLUMN),
+                    placeholder
+                ));
+            },
+            None => builder.conditions.push("FALSE".to_string()),
+        }
+        as_of_version
+    } else if requested_version.is_some() {
+        return Err(bad_request(format!(
+            "Table `{}` has no `{}` to bound by as_of_version",
+            table.name, TRANSACTION_VERSION_COLUMN
+        )));
+    } else {
+        None
+    };
+
+    let direction = if descending { "DESC" } else { "ASC" };
+    let order_columns = if table.is_pageable() {
+        // Ties on the sort column are broken by the version and event index, so that pages
+        // don't overlap
+        let sort_column = sort_column.filter(|column| *column != TRANSACTION_VERSION_COLUMN);
+        let mut order_columns = sort_column.into_iter().collect::<Vec<_>>();
+        order_columns.extend([TRANSACTION_VERSION_COLUMN, EVENT_INDEX_COLUMN]);
+        if let Some(cursor) = cursor {
+            let mut values = vec![];
+            if let Some(sort_column) = sort_column {
+                let sort_value = cursor.sort_value.as_ref().ok_or_else(|| {
+                    bad_request(format!("Cursor is not sorted by `{}`", sort_column))
+                })?;
+                let cast = table.find_column(sort_column).unwrap().sql_type;
+                values.push(builder.bind_param("cursor", value_to_text(sort_value), cast));
+            }
+            values.push(builder.bind(cursor.transaction_version.to_string(), "bigint"));
+            values.push(builder.bind(cursor.event_index.to_string(), "bigint"));
+            builder.conditions.push(format!(
+                "({}) {} ({})",
+                order_columns
+                    .iter()
+                    .map(|column| quote_identifier(column))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if descending { "<" } else { ">" },
+                values.join(", ")
+            ));
+        }
+        order_columns
+    } else if cursor.is_some() {
+        return Err(bad_request(format!(
+            "Table `{}` has no `{}` and `{}` to page by",
+            table.name, TRANSACTION_VERSION_COLUMN, EVENT_INDEX_COLUMN
+        )));
+    } else {
+        sort_column.into_iter().collect()
+    };
+
+    let mut sql = format!(
+        "SELECT json_build_object({})::text AS row FROM {}",
+        table
+            .columns
+            .iter()
+            .map(|column| format!("'{}', {}", column.name, quote_identifier(column.name)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        quote_identifier(&table.name)
+    );
+    if !builder.conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", builder.conditions.join(" AND ")));
+    }
+    if !order_columns.is_empty() {
+        sql.push_str(&format!(
+            " ORDER BY {}",
+            order_columns
+                .iter()
+                .map(|column| format!("{} {}", quote_identifier(column), direction))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    // One more row than the page tells whether there is a next page
+    sql.push_str(&format!(" LIMIT {}", limit + 1));
+
+    Ok(QueryPlan {
+        sql,
+        binds: builder.binds,
+        param_casts: builder.param_casts,
+        sort_column: sort_column.filter(|column| *column != TRANSACTION_VERSION_COLUMN),
+        limit,
+        as_of_version,
+    })
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[diesel(sql_type = Text)]
+    row: String,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryPage {
+    rows: Vec<Value>,
+    /// Pass it as `cursor` to get the next page.
+    next_cursor: Option<String>,
+    as_of_version: Option<u64>,
+}
+
+/// Where the query API reads the checkpoint that bounds the rows of versioned tables.
+#[derive(Clone)]
+pub enum QueryCheckpoint {
+    /// The `processor_status` row of the processor, read in the same snapshot as the rows, so
+    /// that the bound never gets ahead of a lagging read replica.
+    ProcessorStatus(String),
+    /// A custom checkpoint store, read before the rows. With a read replica, the bound can get
+    /// ahead of it by the replica's lag.
+    Store(Arc<dyn CheckpointStore>),
+}
+
+#[derive(Clone)]
+struct QueryServerState {
+    config: Arc<QueryServerConfig>,
+    tables: Arc<HashMap<String, ServedTable>>,
+    read_db_pool: ArcDbPool,
+    checkpoint: QueryCheckpoint,
+}
+
+fn internal_error(e: impl std::fmt::Display) -> QueryError {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+}
+
+/// Error of the query transaction, which Diesel has to be able to build from its own errors.
+struct TransactionError(QueryError);
+
+impl From<diesel::result::Error> for TransactionError {
+    fn from(e: diesel::result::Error) -> Self {
+        Self(internal_error(e))
+    }
+}
+
+/// Casts the values of the request on their own, so that a value of the wrong type, e.g. a
+/// filter on a `bigint` column with `abc`, is a bad request rather than a failed query.
+async fn check_param_casts(
+    conn: &mut MyDbConnection,
+    param_casts: &[ParamCast],
+) -> Result<(), TransactionError> {
+    for param_cast in param_casts {
+        diesel::sql_query(format!("SELECT CAST($1 AS {})", param_cast.cast))
+            .bind::<Text, _>(param_cast.value.clone())
+            .execute(conn)
+            .await
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(_, info) => {
+                    TransactionError(bad_request(format!(
+                        "Invalid value `{}` of `{}`: {}",
+                        param_cast.value,
+                        param_cast.param,
+                        info.message()
+                    )))
+                },
+                e => e.into(),
+            })?;
+    }
+    Ok(())
+}
+
+async fn query_table(
+    state: &QueryServerState,
+    table_name: &str,
+    params: &BTreeMap<String, String>,
+) -> Result<QueryPage, QueryError> {
+    let table = state.tables.get(table_name).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Table `{}` is not served", table_name),
+        )
+    })?;
+    let mut conn = state.read_db_pool.get().await.map_err(internal_error)?;
+    // Read the checkpoint and the rows in one snapshot, so that the rows up to the checkpoint
+    // are all there
+    let (plan, json_rows) = conn
+        .build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(|conn| {
+            async move {
+                let checkpoint = match &state.checkpoint {
+                    QueryCheckpoint::ProcessorStatus(processor_name) => processor_status::table
+                        .filter(processor_status::processor.eq(processor_name))
+                        .select(processor_status::last_success_version)
+                        .first::<i64>(conn)
+                        .await
+                        .optional()?
+                        .map(|version| version as u64),
+                    QueryCheckpoint::Store(checkpoint_store) => checkpoint_store
+                        .load_checkpoint()
+                        .await
+                        .map_err(|e| TransactionError(internal_error(e)))?,
+                };
+                let plan = build_query(table, params, checkpoint, &state.config)
+                    .map_err(TransactionError)?;
+                check_param_casts(conn, &plan.param_casts).await?;
+
+                let mut query = diesel::sql_query(plan.sql.clone()).into_boxed::<Backend>();
+                for bind in &plan.binds {
+                    query = query.bind::<Text, _>(bind.clone());
+                }
+                let json_rows = query.load::<JsonRow>(conn).await?;
+                Ok::<_, TransactionError>((plan, json_rows))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|TransactionError(error)| error)?;
+    let mut rows = json_rows
+        .iter()
+        .map(|json_row| serde_json::from_str::<Value>(&json_row.row))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(internal_error)?;
+
+    let next_cursor = if rows.len() > plan.limit && table.is_pageable() {
+        rows.truncate(plan.limit);
+        let last_row = rows.last().expect("pages have at least a row");
+        let version_of = |column: &str| {
+            last_row[column]
+                .as_i64()
+                .ok_or_else(|| internal_error(format!("Row has no `{}`", column)))
+        };
+        Some(
+            PageCursor {
+                sort_value: plan.sort_column.map(|column| last_row[column].clone()),
+                transaction_version: version_of(TRANSACTION_VERSION_COLUMN)?,
+                event_index: version_of(EVENT_INDEX_COLUMN)?,
+            }
+            .encode(),
+        )
+    } else {
+        rows.truncate(plan.limit);
+        None
+    };
+    Ok(QueryPage {
+        rows,
+        next_cursor,
+        as_of_version: plan.as_of_version,
+    })
+}
+
+async fn query_table_handler(
+    State(state): State<QueryServerState>,
+    Path(table_name): Path<String>,
+    Query(params): Query<BTreeMap<String, String>>,
+) -> Response {
+    match query_table(&state, &table_name, &params).await {
+        Ok(page) => Json(page).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TableDescription {
+    name: String,
+    columns: Vec<QueryColumn>,
+    pageable: bool,
+}
+
+async fn list_tables_handler(State(state): State<QueryServerState>) -> impl IntoResponse {
+    let mut tables = state
+        .tables
+        .values()
+        .map(|table| TableDescription {
+            name: table.name.clone(),
+            columns: table.columns.clone(),
+            pageable: table.is_pageable(),
+        })
+        .collect::<Vec<_>>();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(tables)
+}
+
+/// Serves the registered tables read-only: `GET /tables` lists them and
+/// `GET /tables/:table` returns a page of rows. Only committed rows are returned: rows of
+/// versioned tables are bounded by `checkpoint`.
+/// `config` has to be valid, see `QueryServerConfig::validate`.
+pub async fn run_query_server(
+    config: QueryServerConfig,
+    read_db_pool: ArcDbPool,
+    checkpoint: QueryCheckpoint,
+) {
+    let tables = QUERY_TABLES
+        .lock()
+        .unwrap()
+        .drain(..)
+        .map(|table| (table.name.clone(), table))
+        .collect::<HashMap<_, _>>();
+    info!(
+        bind_address = config.bind_address,
+        tables = ?tables.keys().collect::<Vec<_>>(),
+        "Starting query API"
+    );
+    let listener = tokio::net::TcpListener::bind(&config.bind_address)
+        .await
+        .expect("Failed to bind query TCP listener");
+    let router = Router::new()
+        .route("/tables", get(list_tables_handler))
+        .route("/tables/:table", get(query_table_handler))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(config.auth_token.clone()),
+            auth_middleware,
+        ))
+        .with_state(QueryServerState {
+            config: Arc::new(config),
+            tables: Arc::new(tables),
+            read_db_pool,
+            checkpoint,
+        });
+    axum::serve(listener, router).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    diesel::table! {
+        raffle_events (transaction_version, event_index) {
+            transaction_version -> Int8,
+            event_index -> Int8,
+            creator -> Varchar,
+            ticket_price -> Int8,
+            winner -> Nullable<Varchar>,
+        }
+    }
+
+    diesel::table! {
+        raffle_games (module_address) {
+            module_address -> Varchar,
+            active -> Bool,
+        }
+    }
+
+    fn raffle_events_table() -> ServedTable {
+        let table = QueryTable::new(raffle_events::table)
+            .column(raffle_events::transaction_version)
+            .column(raffle_events::event_index)
+            .column(raffle_events::creator)
+            .column(raffle_events::ticket_price)
+            .column(raffle_events::winner);
+        assert_eq!(table.name(), "raffle_events");
+        table.table
+    }
+
+    fn config() -> QueryServerConfig {
+        QueryServerConfig {
+            bind_address: QueryServerConfig::default_bind_address(),
+            auth_token: None,
+            default_page_size: 10,
+            max_page_size: 100,
+        }
+    }
+
+    fn params(params: &[(&str, &str)]) -> BTreeMap<String, String> {
+        params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_query() {
+        let table = raffle_events_table();
+        let cursor = PageCursor {
+            sort_value: Some(Value::from(500)),
+            transaction_version: 42,
+            event_index: 1,
+        };
+        let plan = build_query(
+            &table,
+            &params(&[
+                ("creator", "0xcafe"),
+                ("ticket_price__gte", "100"),
+                ("sort", "ticket_price"),
+                ("order", "desc"),
+                ("cursor", &cursor.encode()),
+                ("as_of_version", "1000"),
+                ("limit", "1000"),
+            ]),
+            Some(800),
+            &config(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.sql,
+            "SELECT json_build_object('transaction_version', \"transaction_version\", \
+             'event_index', \"event_index\", 'creator', \"creator\", 'ticket_price', \
+             \"ticket_price\", 'winner', \"winner\")::text AS row FROM \"raffle_events\" \
+             WHERE \"creator\" = CAST($1 AS text) AND \"ticket_price\" >= CAST($2 AS bigint) \
+             AND \"transaction_version\" <= CAST($3 AS bigint) AND (\"ticket_price\", \
+             \"transaction_version\", \"event_index\") < (CAST($4 AS bigint), CAST($5 AS \
+             bigint), CAST($6 AS bigint)) ORDER BY \"ticket_price\" DESC, \
+             \"transaction_version\" DESC, \"event_index\" DESC LIMIT 101"
+        );
+        assert_eq!(plan.binds, vec!["0xcafe", "100", "800", "500", "42", "1"]);
+        // Only the values of the request are checked to cast
+        let param_casts = plan
+            .param_casts
+            .iter()
+            .map(|param_cast| {
+                (
+                    param_cast.param.as_str(),
+                    param_cast.value.as_str(),
+                    param_cast.cast,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            param_casts,
+            vec![
+                ("creator", "0xcafe", "text"),
+                ("ticket_price__gte", "100", "bigint"),
+                ("cursor", "500", "bigint"),
+            ]
+        );
+        assert_eq!(plan.sort_column, Some("ticket_price"));
+        // Capped at the checkpoint
+        assert_eq!(plan.as_of_version, Some(800));
+
+        // Nothing is returned before the first checkpoint
+        let plan = build_query(&table, &params(&[]), None, &config()).unwrap();
+        assert!(plan.sql.contains("WHERE FALSE ORDER BY"));
+        assert_eq!(plan.as_of_version, None);
+    }
+
+    #[test]
+    fn test_query_server_config_validation() {
+        let config = |bind_address: &str, auth_token: Option<&str>| QueryServerConfig {
+            bind_address: bind_address.to_string(),
+            auth_token: auth_token.map(str::to_string),
+            ..config()
+        };
+        assert!(config("127.0.0.1:8087", None).validate().is_ok());
+        assert!(config("localhost:8087", None).validate().is_ok());
+        assert!(config("0.0.0.0:8087", None).validate().is_err());
+        assert!(config("0.0.0.0:8087", Some("secret")).validate().is_ok());
+    }
+
+    #[test]
+    fn test_build_query_errors() {
+        let table = raffle_events_table();
+        for bad_params in [
+            params(&[("owner", "0xcafe")]),
+            params(&[("sort", "winner")]),
+            params(&[("order", "up")]),
+            params(&[("limit", "-1")]),
+            params(&[("cursor", "not a cursor")]),
+        ] {
+            let (status, _) = build_query(&table, &bad_params, Some(800), &config()).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        // Without versions, tables can't be paged nor bounded
+        let table = QueryTable::new(raffle_games::table)
+            .column(raffle_games::module_address)
+            .column(raffle_games::active)
+            .table;
+        assert!(build_query(
+            &table,
+            &params(&[("as_of_version", "1")]),
+            Some(800),
+            &config()
+        )
+        .is_err());
+        let cursor = PageCursor {
+            sort_value: None,
+            transaction_version: 42,
+            event_index: 1,
+        };
+        assert!(build_query(
+            &table,
+            &params(&[("cursor", &cursor.encode())]),
+            Some(800),
+            &config()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use super::database::{
+    execute_in_chunks, execute_with_better_error, ArcDbPool, Backend, MAX_DIESEL_PARAM_SIZE,
+};
+use crate::{
+    common_steps::webhook_sink_step::{NewWebhookDelivery, WebhookDelivery, WebhookOutbox},
+    postgres::{
+        models::webhook_outbox::{NewWebhookOutboxEntry, WebhookOutboxEntry},
+        processor_metadata_schema::processor_metadata::webhook_outbox,
+    },
+    utils::errors::ProcessorError,
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::sql,
+    query_builder::{QueryFragment, QueryId},
+    sql_types::{Bool, Timestamp},
+    ExpressionMethods, QueryDsl,
+};
+use diesel_async::RunQueryDsl;
+
+const NUM_INSERTED_COLUMNS: usize = 8;
+
+/// A trait implementation of WebhookOutbox for Postgres, using a row per delivery in
+/// `webhook_outbox`. Rows are kept once delivered or abandoned, and can be retried by setting
+/// `next_attempt_at`.
+pub struct PostgresWebhookOutbox {
+    pub db_pool: ArcDbPool,
+    pub processor_name: String,
+}
+
+impl PostgresWebhookOutbox {
+    pub fn new(processor_name: &str, db_pool: ArcDbPool) -> Self {
+        Self {
+            db_pool,
+            processor_name: processor_name.to_string(),
+        }
+    }
+}
+
+fn insert_deliveries_query(
+    items_to_insert: Vec<NewWebhookOutboxEntry>,
+) -> impl QueryFragment<Backend> + QueryId + Send {
+    diesel::insert_into(webhook_outbox::table)
+        .values(items_to_insert)
+        .on_conflict((
+            webhook_outbox::processor,
+            webhook_outbox::endpoint,
+            webhook_outbox::idempotency_key,
+        ))
+        .do_nothing()
+}
+
+#[async_trait]
+impl WebhookOutbox for PostgresWebhookOutbox {
+    async fn enqueue(&self, deliveries: Vec<NewWebhookDelivery>) -> Result<(), ProcessorError> {
+        let entries = deliveries
+            .into_iter()
+            .map(|delivery| NewWebhookOutboxEntry {
+                processor: self.processor_name.clone(),
+                endpoint: delivery.endpoint,
+                idempotency_key: delivery.idempotency_key,
+                event_type: delivery.event_type,
+                transaction_version: delivery.transaction_version,
+                payload: delivery.payload,
+                next_attempt_at: Some(delivery.created_at),
+                created_at: delivery.created_at,
+            })
+            .collect::<Vec<_>>();
+        execute_in_chunks(
+            self.db_pool.clone(),
+            insert_deliveries_query,
+            &entries,
+            MAX_DIESEL_PARAM_SIZE / NUM_INSERTED_COLUMNS,
+        )
+        .await
+    }
+
+    async fn due(
+        &self,
+        now: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, ProcessorError> {
+        let to_processor_error = |e: &dyn std::fmt::Display| ProcessorError::DBStoreError {
+            message: format!("Error loading due webhook deliveries: {:#}", e),
+            query: None,
+        };
+        let mut conn = self
+            .db_pool
+            .get()
+            .await
+            .map_err(|e| to_processor_error(&e))?;
+        let entries = webhook_outbox::table
+            .filter(webhook_outbox::processor.eq(&self.processor_name))
+            .filter(webhook_outbox::next_attempt_at.le(now))
+            // Keep the deliveries of an endpoint in order, behind an older one awaiting a retry
+            .filter(
+                sql::<Bool>(
+                    "NOT EXISTS (SELECT 1 FROM webhook_outbox AS older \
+                     WHERE older.processor = webhook_outbox.processor \
+                     AND older.endpoint = webhook_outbox.endpoint \
+                     AND older.id < webhook_outbox.id \
+                     AND older.next_attempt_at > ",
+                )
+                .bind::<Timestamp, _>(now)
+                .sql(")"),
+            )
+            .order(webhook_outbox::id)
+            .limit(limit as i64)
+            .select((
+                webhook_outbox::id,
+                webhook_outbox::endpoint,
+                webhook_outbox::idempotency_key,
+                webhook_outbox::event_type,
+                webhook_outbox::payload,
+                webhook_outbox::attempts,
+                webhook_outbox::created_at,
+            ))
+            .load::<WebhookOutboxEntry>(&mut conn)
+            .await
+            .map_err(|e| to_processor_error(&e))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| WebhookDelivery {
+                id: entry.id,
+                endpoint: entry.endpoint,
+                idempotency_key: entry.idempotency_key,
+                event_type: entry.event_type,
+                payload: entry.payload,
+                attempts: entry.attempts,
+                created_at: entry.created_at,
+            })
+            .collect())
+    }
+
+    async fn mark_delivered(&self, id: i64, now: NaiveDateTime) -> Result<(), ProcessorError> {
+        execute_with_better_error(
+            self.db_pool.clone(),
+            diesel::update(webhook_outbox::table.filter(webhook_outbox::id.eq(id))).set((
+                webhook_outbox::attempts.eq(webhook_outbox::attempts + 1),
+                webhook_outbox::next_attempt_at.eq(None::<NaiveDateTime>),
+                webhook_outbox::delivered_at.eq(now),
+            )),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: i64,
+        error: &str,
+        next_attempt_at: Option<NaiveDateTime>,
+    ) -> Result<(), ProcessorError> {
+        execute_with_better_error(
+            self.db_pool.clone(),
+            diesel::update(webhook_outbox::table.filter(webhook_outbox::id.eq(id))).set((
+                webhook_outbox::attempts.eq(webhook_outbox::attempts + 1),
+                webhook_outbox::next_attempt_at.eq(next_attempt_at),
+                webhook_outbox::last_error.eq(error),
+            )),
+        )
+        .await?;
+        Ok(())
+    }
+}
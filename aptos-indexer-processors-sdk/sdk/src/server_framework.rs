@@ -1,34 +1,108 @@
 // Copyright © Aptos Foundation
 
 use crate::{
+    admin::{run_admin_server, AdminConfig},
+    builder::{live_graphs, PipelineConfig},
+    health::{
+        readiness, set_processor_name, status_report, ReadinessChecker, ReadinessConfig, Watchdog,
+        WatchdogConfig,
+    },
     instrumented_channel::channel_metrics::init_channel_metrics_registry,
-    utils::step_metrics::init_step_metrics_registry,
+    utils::{
+        leader_election::{init_leader_election_metrics_registry, LeaderElectionConfig},
+        step_metrics::init_step_metrics_registry,
+    },
 };
 use anyhow::{Context, Result};
 #[cfg(target_os = "linux")]
 use aptos_system_utils::profiling::start_cpu_profiling;
 use autometrics::settings::AutometricsSettings;
-use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
+use axum::{http::StatusCode, response::IntoResponse, routing::get, Json, Router};
 use backtrace::Backtrace;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use prometheus_client::registry::Registry;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 // TODO: remove deprecated lint when new clippy nightly is released
 #[allow(deprecated)]
 use std::{fs::File, io::Read, panic::PanicInfo, path::PathBuf, process};
 use tokio::runtime::Handle;
-use tracing::error;
+use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
 
 /// ServerArgs bootstraps a server with all common pieces. And then triggers the run method for
-/// the specific service.
+/// the specific service, or one of the operational subcommands.
 #[derive(Parser)]
 pub struct ServerArgs {
     #[clap(short, long, value_parser)]
     pub config_path: PathBuf,
+
+    #[clap(subcommand)]
+    pub command: Option<ServerCommand>,
+}
+
+/// Subcommands of the processor binary, e.g. `processor --config-path config.yaml status`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Subcommand)]
+pub enum ServerCommand {
+    /// Runs the processor. This is the default.
+    #[default]
+    Run,
+    /// Runs the pending SDK and processor migrations.
+    Migrate {
+        /// Lists the pending migrations without running them.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Prints the saved checkpoint and chain ID of the processor.
+    Status,
+    /// Overwrites the checkpoint, so that the processor restarts from `to`.
+    ResetCheckpoint {
+        #[clap(long)]
+        to: u64,
+    },
+    /// Processes the versions from `from` to `to` inclusive, then exits. Backfills keep their
+    /// own checkpoint, so they can be resumed and don't move the checkpoint of the processor.
+    /// They can run next to the processor: the admin and query APIs are not served, and the
+    /// probes and metrics are served on `health_check_port`, by default any free port.
+    Backfill {
+        #[clap(long)]
+        from: u64,
+        #[clap(long)]
+        to: u64,
+        #[clap(long, default_value_t = 0)]
+        health_check_port: u16,
+    },
+    /// Checks that the config parses and is valid, then exits.
+    ValidateConfig,
+    /// Prints the DAG of the processor in DOT format.
+    PrintDag,
+}
+
+impl ServerCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerCommand::Run => "run",
+            ServerCommand::Migrate { .. } => "migrate",
+            ServerCommand::Status => "status",
+            ServerCommand::ResetCheckpoint { .. } => "reset-checkpoint",
+            ServerCommand::Backfill { .. } => "backfill",
+            ServerCommand::ValidateConfig => "validate-config",
+            ServerCommand::PrintDag => "print-dag",
+        }
+    }
+}
+
+/// Name under which a backfill saves its checkpoint, so that it can be resumed without
+/// moving the checkpoint of the processor itself.
+pub fn backfill_processor_name(processor_name: &str, from: u64, to: u64) -> String {
+    format!("{}_backfill_{}_{}", processor_name, from, to)
 }
 
 impl ServerArgs {
+    /// Returns the subcommand to run, `run` if none was given.
+    pub fn get_command(&self) -> ServerCommand {
+        self.command.clone().unwrap_or_default()
+    }
+
     pub async fn run<C>(&self, handle: Handle) -> Result<()>
     where
         C: RunnableConfig,
@@ -37,7 +111,16 @@ impl ServerArgs {
         setup_logging();
         setup_panic_handler();
         let config = load::<GenericConfig<C>>(&self.config_path)?;
-        run_server_with_config(config, handle).await
+        match self.get_command() {
+            ServerCommand::Run => run_server_with_config(config, handle).await,
+            command => {
+                config.run_command(&command).await?;
+                if command == ServerCommand::ValidateConfig {
+                    println!("Config at {:?} is valid", self.config_path);
+                }
+                Ok(())
+            },
+        }
     }
 }
 
@@ -54,6 +137,8 @@ where
         register_probes_and_metrics_handler(health_port, additional_labels).await;
         anyhow::Ok(())
     });
+    spawn_health_monitors(&config, config.get_server_name(), &handle);
+    spawn_admin_server(&config, &handle)?;
     let main_task_handler = handle.spawn(async move { config.run().await });
     tokio::select! {
         res = task_handler => {
@@ -65,6 +150,30 @@ where
     }
 }
 
+/// Spawns the tasks that keep readiness and `/status` up to date: the readiness checker and,
+/// if configured, the watchdog.
+pub fn spawn_health_monitors<T>(
+    config: &GenericConfig<T>,
+    processor_name: String,
+    handle: &Handle,
+) {
+    set_processor_name(processor_name);
+    handle.spawn(ReadinessChecker::new(config.readiness_config.clone()).run());
+    if let Some(watchdog_config) = config.watchdog.clone() {
+        handle.spawn(Watchdog::new(watchdog_config).run());
+    }
+}
+
+/// Spawns the admin API if it is configured. Fails if the config is unsafe, see
+/// `AdminConfig::validate`.
+pub fn spawn_admin_server<T>(config: &GenericConfig<T>, handle: &Handle) -> Result<()> {
+    if let Some(admin_config) = config.admin_config.clone() {
+        admin_config.validate()?;
+        handle.spawn(run_admin_server(admin_config));
+    }
+    Ok(())
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 pub struct GenericConfig<T> {
     // Shared configuration among all services.
@@ -73,8 +182,27 @@ pub struct GenericConfig<T> {
     #[serde(default)]
     pub metrics_config: MetricsConfig,
 
+    #[serde(default)]
+    pub readiness_config: ReadinessConfig,
+
+    // Admin API for controlling the processor at runtime. Disabled if not set.
+    #[serde(default)]
+    pub admin_config: Option<AdminConfig>,
+
     // Specific configuration for each service.
     pub server_config: T,
+
+    // Optional DAG of steps to run instead of the processor's hardcoded one.
+    #[serde(default)]
+    pub pipeline: Option<PipelineConfig>,
+
+    // Detects steps that stop making progress. Disabled if not set.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+
+    // Lets only one of several replicas stream and write at a time. Disabled if not set.
+    #[serde(default)]
+    pub leader_election: Option<LeaderElectionConfig>,
 }
 
 #[derive(Clone, Deserialize, Debug, Default, Serialize)]
@@ -89,7 +217,16 @@ where
     T: RunnableConfig,
 {
     async fn run(&self) -> Result<()> {
-        self.server_config.run().await
+        match &self.pipeline {
+            Some(pipeline) => self.server_config.run_with_pipeline(pipeline).await,
+            None => self.server_config.run().await,
+        }
+    }
+
+    async fn run_command(&self, command: &ServerCommand) -> Result<()> {
+        self.server_config
+            .run_command_with_pipeline(command, self.pipeline.as_ref())
+            .await
     }
 
     fn get_server_name(&self) -> String {
@@ -101,6 +238,41 @@ where
 #[async_trait::async_trait]
 pub trait RunnableConfig: DeserializeOwned + Send + Sync + 'static {
     async fn run(&self) -> Result<()>;
+
+    /// Runs the service with a pipeline described in config rather than the hardcoded one.
+    /// Services that support this register their steps in a `StepRegistry` and build the
+    /// pipeline with `PipelineConfig::build`.
+    async fn run_with_pipeline(&self, _pipeline: &PipelineConfig) -> Result<()> {
+        anyhow::bail!(
+            "{} does not support pipelines from config",
+            self.get_server_name()
+        )
+    }
+
+    /// Runs an operational subcommand other than `run`, e.g. `migrate` or `status`. Services
+    /// that support subcommands override this or `run_command_with_pipeline`. By default only
+    /// `validate-config` is supported, which succeeds since the config was parsed.
+    async fn run_command(&self, command: &ServerCommand) -> Result<()> {
+        match command {
+            ServerCommand::ValidateConfig => Ok(()),
+            _ => anyhow::bail!(
+                "{} does not support the `{}` command",
+                self.get_server_name(),
+                command.name()
+            ),
+        }
+    }
+
+    /// Like `run_command`, with the pipeline from config if there is one, e.g. to validate it
+    /// or print it for `validate-config` and `print-dag`.
+    async fn run_command_with_pipeline(
+        &self,
+        command: &ServerCommand,
+        _pipeline: Option<&PipelineConfig>,
+    ) -> Result<()> {
+        self.run_command(command).await
+    }
+
     fn get_server_name(&self) -> String;
 }
 
@@ -179,23 +351,47 @@ pub async fn register_probes_and_metrics_handler(
     );
     init_step_metrics_registry(&mut registry);
     init_channel_metrics_registry(&mut registry);
+    init_leader_election_metrics_registry(&mut registry);
+    #[cfg(feature = "postgres_partial")]
+    crate::postgres::utils::chunked_writes::init_db_write_metrics_registry(&mut registry);
     AutometricsSettings::builder()
         .prometheus_client_registry(registry)
         .init();
 
     let router = Router::new()
-        .route("/readiness", get(StatusCode::OK))
-        .route("/metrics", get(metrics_handler));
+        .route("/readiness", get(readiness_handler))
+        .route("/status", get(status_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/topology", get(topology_handler))
+        .route("/topology/dot", get(topology_dot_handler))
+        .route("/topology/mermaid", get(topology_mermaid_handler));
 
     #[cfg(target_os = "linux")]
     let router = router.merge(Router::new().route("/profilez", get(profilez_handler)));
 
+    #[cfg(feature = "stream")]
+    let router = router.merge(crate::utils::stream_hub::stream_router());
+
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port))
         .await
         .expect("Failed to bind TCP listener");
+    if let Ok(address) = listener.local_addr() {
+        info!(address = %address, "Serving probes and metrics");
+    }
     axum::serve(listener, router).await.unwrap();
 }
 
+async fn readiness_handler() -> impl IntoResponse {
+    match readiness() {
+        Ok(()) => (StatusCode::OK, "ready".to_string()),
+        Err(reasons) => (StatusCode::SERVICE_UNAVAILABLE, reasons.join("\n")),
+    }
+}
+
+async fn status_handler() -> impl IntoResponse {
+    Json(status_report().await)
+}
+
 async fn metrics_handler() -> impl IntoResponse {
     match autometrics::prometheus_exporter::encode_to_string() {
         Ok(prometheus_client_rust_metrics) => (
@@ -208,6 +404,40 @@ async fn metrics_handler() -> impl IntoResponse {
     }
 }
 
+/// Serves the topology of every live pipeline, annotated with step statuses and channel
+/// fill levels.
+async fn topology_handler() -> impl IntoResponse {
+    let topologies = live_graphs()
+        .iter()
+        .map(|graph| graph.topology())
+        .collect::<Vec<_>>();
+    Json(topologies)
+}
+
+async fn topology_dot_handler() -> impl IntoResponse {
+    let dots = live_graphs()
+        .iter()
+        .map(|graph| graph.dot())
+        .collect::<Vec<_>>();
+    (
+        StatusCode::OK,
+        [("Content-Type", "text/vnd.graphviz")],
+        dots.join("\n"),
+    )
+}
+
+async fn topology_mermaid_handler() -> impl IntoResponse {
+    let mermaids = live_graphs()
+        .iter()
+        .map(|graph| graph.mermaid())
+        .collect::<Vec<_>>();
+    (
+        StatusCode::OK,
+        [("Content-Type", "text/plain")],
+        mermaids.join("\n"),
+    )
+}
+
 #[cfg(target_os = "linux")]
 async fn profilez_handler() -> impl IntoResponse {
     match start_cpu_profiling(10, 99, false).await {
@@ -273,6 +503,10 @@ mod tests {
         assert_eq!(config.health_check_port, 12345);
         assert_eq!(config.server_config.test, 123);
         assert_eq!(config.server_config.test_name, "test");
+        assert!(config.pipeline.is_none());
+        assert!(config.watchdog.is_none());
+        assert!(config.admin_config.is_none());
+        assert!(config.leader_election.is_none());
     }
 
     #[test]
@@ -280,4 +514,34 @@ mod tests {
         use clap::CommandFactory;
         ServerArgs::command().debug_assert()
     }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let args = ServerArgs::parse_from(["processor", "--config-path", "config.yaml"]);
+        assert_eq!(args.get_command(), ServerCommand::Run);
+
+        let args =
+            ServerArgs::parse_from(["processor", "-c", "config.yaml", "migrate", "--dry-run"]);
+        assert_eq!(args.get_command(), ServerCommand::Migrate { dry_run: true });
+
+        let args = ServerArgs::parse_from([
+            "processor",
+            "-c",
+            "config.yaml",
+            "backfill",
+            "--from",
+            "10",
+            "--to",
+            "20",
+        ]);
+        assert_eq!(
+            args.get_command(),
+            ServerCommand::Backfill {
+                from: 10,
+                to: 20,
+                health_check_port: 0,
+            }
+        );
+        assert_eq!(args.get_command().name(), "backfill");
+    }
 }
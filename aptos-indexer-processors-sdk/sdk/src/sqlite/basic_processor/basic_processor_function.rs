@@ -0,0 +1,496 @@
+use super::basic_processor_step::BasicProcessorStep;
+use crate::{
+    aptos_indexer_transaction_stream::TransactionStreamConfig,
+    builder::{GraphBuilder, ProcessorBuilder},
+    common_steps::{
+        ProcessorStatusSaver, TransactionStreamStep, VersionTrackerStep,
+        DEFAULT_UPDATE_PROCESSOR_STATUS_SECS,
+    },
+    health::{register_status_provider, StatusProvider},
+    server_framework::{
+        backfill_processor_name, load, register_probes_and_metrics_handler, setup_logging,
+        setup_panic_handler, spawn_admin_server, spawn_health_monitors, GenericConfig, ServerArgs,
+        ServerCommand,
+    },
+    sqlite::{
+        subconfigs::sqlite_config::SqliteConfig,
+        utils::{
+            checkpoint::SqliteCheckpointStore,
+            database::{new_db_pool, ArcDbPool},
+            migrations::{get_pending_migrations, run_migrations},
+        },
+    },
+    traits::IntoRunnableStep,
+    utils::{
+        chain_id_check::check_or_update_chain_id,
+        checkpoint_store::{
+            get_starting_version, print_checkpoint_store_status, reset_checkpoint_store,
+            CheckpointStore, CheckpointStoreStatusProvider,
+        },
+        errors::ProcessorError,
+    },
+};
+use anyhow::Result;
+use aptos_protos::transaction::v1::Transaction;
+use clap::Parser;
+use diesel_migrations::EmbeddedMigrations;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessConfig {
+    pub transaction_stream_config: TransactionStreamConfig,
+    pub sqlite_config: SqliteConfig,
+}
+
+/// Processes transactions with a custom handler function, storing data and checkpoints in a
+/// SQLite file. Also handles the operational subcommands of `ServerArgs`, like the Postgres
+/// `process`.
+pub async fn process<F, Fut>(
+    processor_name: String,
+    embedded_migrations: EmbeddedMigrations,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    process_with_optional_checkpoint_store(
+        processor_name,
+        embedded_migrations,
+        None,
+        process_function,
+    )
+    .await
+}
+
+/// Like `process`, but keeps the chain ID and the checkpoint in `checkpoint_store` instead of
+/// the SQLite file. The `status` and `reset-checkpoint` subcommands use it too.
+pub async fn process_with_checkpoint_store<F, Fut, C>(
+    processor_name: String,
+    embedded_migrations: EmbeddedMigrations,
+    checkpoint_store: Arc<C>,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+    C: CheckpointStore + 'static,
+{
+    process_with_optional_checkpoint_store(
+        processor_name,
+        embedded_migrations,
+        Some(checkpoint_store),
+        process_function,
+    )
+    .await
+}
+
+async fn process_with_optional_checkpoint_store<F, Fut>(
+    processor_name: String,
+    embedded_migrations: EmbeddedMigrations,
+    checkpoint_store: Option<Arc<dyn CheckpointStore>>,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    let args = ServerArgs::parse();
+    setup_logging();
+    setup_panic_handler();
+    let config = load::<GenericConfig<ProcessConfig>>(&args.config_path)?;
+    if config.leader_election.is_some() {
+        anyhow::bail!("Leader election is not supported with SQLite, run a single replica");
+    }
+    let (processor_name, transaction_stream_config, health_port, is_backfill) =
+        match args.get_command() {
+            ServerCommand::Run => (
+                processor_name,
+                config.server_config.transaction_stream_config.clone(),
+                config.health_check_port,
+                false,
+            ),
+            ServerCommand::Backfill {
+                from,
+                to,
+                health_check_port,
+            } => {
+                if from > to {
+                    anyhow::bail!(
+                        "Cannot backfill from {} to {}, `from` is after `to`",
+                        from,
+                        to
+                    );
+                }
+                (
+                    backfill_processor_name(&processor_name, from, to),
+                    TransactionStreamConfig {
+                        starting_version: Some(from),
+                        request_ending_version: Some(to),
+                        ..config.server_config.transaction_stream_config.clone()
+                    },
+                    health_check_port,
+                    true,
+                )
+            },
+            ServerCommand::ValidateConfig => {
+                println!("Config at {:?} is valid", args.config_path);
+                return Ok(());
+            },
+            ServerCommand::PrintDag => {
+                println!("{}", basic_processor_graph::<F, Fut>().dot());
+                return Ok(());
+            },
+            command => {
+                return run_db_command(
+                    &processor_name,
+                    &config.server_config.sqlite_config,
+                    checkpoint_store,
+                    embedded_migrations,
+                    command,
+                )
+                .await;
+            },
+        };
+    let handle = tokio::runtime::Handle::current();
+
+    let additional_labels = config.metrics_config.additional_labels.clone();
+    // Start liveness and readiness probes.
+    let task_handler = handle.spawn(async move {
+        register_probes_and_metrics_handler(health_port, additional_labels).await;
+        anyhow::Ok(())
+    });
+    spawn_health_monitors(&config, processor_name.clone(), &handle);
+    // A backfill runs next to the processor, which already serves the admin API
+    if !is_backfill {
+        spawn_admin_server(&config, &handle)?;
+    }
+    let main_task_handler = handle.spawn(async move {
+        run_processor(
+            processor_name,
+            transaction_stream_config,
+            config.server_config.sqlite_config,
+            checkpoint_store,
+            embedded_migrations,
+            process_function,
+        )
+        .await
+    });
+    tokio::select! {
+        res = task_handler => {
+            res.expect("Probes and metrics handler unexpectedly exited")
+        },
+        res = main_task_handler => {
+            res.expect("Main task handler unexpectedly exited")
+        },
+    }
+}
+
+/// Runs the subcommands that only need the database, or the custom checkpoint store.
+async fn run_db_command(
+    processor_name: &str,
+    sqlite_config: &SqliteConfig,
+    custom_checkpoint_store: Option<Arc<dyn CheckpointStore>>,
+    embedded_migrations: EmbeddedMigrations,
+    command: ServerCommand,
+) -> Result<()> {
+    let db_pool = new_db_pool(sqlite_config)?;
+    let checkpoint_store = SqliteCheckpointStore::new(processor_name, db_pool.clone());
+    match (command, custom_checkpoint_store) {
+        (ServerCommand::Status, Some(custom_checkpoint_store)) => {
+            print_checkpoint_store_status(processor_name, custom_checkpoint_store.as_ref()).await?;
+        },
+        (ServerCommand::ResetCheckpoint { to }, Some(custom_checkpoint_store)) => {
+            reset_checkpoint_store(processor_name, custom_checkpoint_store.as_ref(), to).await?;
+        },
+        (ServerCommand::Migrate { dry_run }, _) => {
+            let report = if dry_run {
+                get_pending_migrations(db_pool, embedded_migrations).await?
+            } else {
+                run_migrations(db_pool, embedded_migrations).await?
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        },
+        (ServerCommand::Status, None) => {
+            let output = serde_json::json!({
+                "processor": processor_name,
+                "chain_id": StatusProvider::get_chain_id(&checkpoint_store).await?,
+                "checkpoint": checkpoint_store.get_checkpoint_status().await?,
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        },
+        (ServerCommand::ResetCheckpoint { to }, None) => {
+            checkpoint_store.reset_processor_status(to).await?;
+            println!(
+                "Reset the checkpoint of {} to version {}",
+                processor_name, to
+            );
+        },
+        (command, _) => anyhow::bail!("`{}` does not need the database", command.name()),
+    }
+    Ok(())
+}
+
+/// The graph of the steps connected in `run_processor`, without building them.
+fn basic_processor_graph<F, Fut>() -> GraphBuilder
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    let mut graph = GraphBuilder::new();
+    let transaction_stream = graph.add_node(
+        "TransactionStreamStep".to_string(),
+        std::any::type_name::<TransactionStreamStep>().to_string(),
+        std::any::type_name::<()>().to_string(),
+        std::any::type_name::<Vec<Transaction>>().to_string(),
+    );
+    let basic_processor_step = graph.add_node(
+        "BasicProcessorStep".to_string(),
+        std::any::type_name::<BasicProcessorStep<F, Fut>>().to_string(),
+        std::any::type_name::<Vec<Transaction>>().to_string(),
+        std::any::type_name::<()>().to_string(),
+    );
+    graph.add_edge_from_to(transaction_stream, basic_processor_step);
+    let version_tracker = graph.add_node(
+        format!("VersionTrackerStep: {}", std::any::type_name::<()>()),
+        std::any::type_name::<VersionTrackerStep<(), Arc<dyn CheckpointStore>>>().to_string(),
+        std::any::type_name::<()>().to_string(),
+        std::any::type_name::<()>().to_string(),
+    );
+    graph.add_edge_from_to(basic_processor_step, version_tracker);
+    graph.set_end_step();
+    graph
+}
+
+async fn run_processor<F, Fut>(
+    processor_name: String,
+    transaction_stream_config: TransactionStreamConfig,
+    sqlite_config: SqliteConfig,
+    checkpoint_store: Option<Arc<dyn CheckpointStore>>,
+    embedded_migrations: EmbeddedMigrations,
+    process_function: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Transaction>, ArcDbPool) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), ProcessorError>> + Send + 'static,
+{
+    let db_pool = new_db_pool(&sqlite_config).expect("Failed to create connection pool");
+    let checkpoint_store: Arc<dyn CheckpointStore> = match checkpoint_store {
+        Some(checkpoint_store) => {
+            register_status_provider(Arc::new(CheckpointStoreStatusProvider::new(
+                checkpoint_store.clone(),
+            )));
+            checkpoint_store
+        },
+        None => {
+            let checkpoint_store = Arc::new(SqliteCheckpointStore::new(
+                processor_name.as_str(),
+                db_pool.clone(),
+            ));
+            register_status_provider(checkpoint_store.clone());
+            checkpoint_store
+        },
+    };
+
+    // Run SDK and user migrations
+    run_migrations(db_pool.clone(), embedded_migrations).await?;
+
+    check_or_update_chain_id(&transaction_stream_config, &checkpoint_store).await?;
+
+    // Merge the starting version from config and the latest processed version from the DB
+    let starting_version =
+        get_starting_version(checkpoint_store.as_ref(), &transaction_stream_config).await?;
+
+    // Define processor steps
+    let transaction_stream = TransactionStreamStep::new(TransactionStreamConfig {
+        starting_version: Some(starting_version),
+        ..transaction_stream_config
+    })
+    .await?;
+    let basic_processor_step = BasicProcessorStep {
+        process_function,
+        conn_pool: db_pool,
+    };
+    let version_tracker =
+        VersionTrackerStep::new(checkpoint_store, DEFAULT_UPDATE_PROCESSOR_STATUS_SECS);
+
+    // Connect processor steps together
+    let (_, buffer_receiver) =
+        ProcessorBuilder::new_with_inputless_first_step(transaction_stream.into_runnable_step())
+            .connect_to(basic_processor_step.into_runnable_step(), 10)
+            .connect_to(version_tracker.into_runnable_step(), 10)
+            .end_and_return_output_receiver(10);
+
+    // (Optional) Parse the results
+    loop {
+        match buffer_receiver.recv().await {
+            Ok(_) => {},
+            Err(_) => {
+                info!("Channel is closed");
+                return Ok(());
+            },
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing_framework"))]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{live_graphs, StepStatus},
+        health::{Watchdog, WatchdogConfig},
+        sqlite::SDK_MIGRATIONS,
+        testing_framework::sdk_test_context::SdkTestContext,
+        utils::checkpoint_store::{Checkpoint, InMemoryCheckpointStore},
+    };
+    use std::{sync::Mutex, time::Duration};
+    use tokio::sync::Notify;
+
+    // Step metrics are global by step name, so processors under test run one at a time
+    static RUN_PROCESSOR_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn test_sqlite_config(directory: &tempfile::TempDir) -> SqliteConfig {
+        SqliteConfig {
+            database_path: directory
+                .path()
+                .join("processor.db")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            db_pool_size: SqliteConfig::default_db_pool_size(),
+            busy_timeout_ms: SqliteConfig::default_busy_timeout_ms(),
+        }
+    }
+
+    async fn test_transaction_stream_config() -> TransactionStreamConfig {
+        let txn = Transaction {
+            version: 1,
+            ..Transaction::default()
+        };
+        let mut test_context = SdkTestContext::new(&[&serde_json::to_vec(&txn).unwrap()]);
+        test_context.init_mock_grpc().await.unwrap();
+        test_context.create_transaction_stream_config()
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_run_processor_shows_its_topology() {
+        let _lock = RUN_PROCESSOR_LOCK.lock().await;
+        let directory = tempfile::tempdir().unwrap();
+
+        // Record the running steps of the live pipelines while a batch is processed
+        let running_steps = Arc::new(Mutex::new(Vec::new()));
+        let process_running_steps = running_steps.clone();
+        run_processor(
+            "topology_test_processor".to_string(),
+            test_transaction_stream_config().await,
+            test_sqlite_config(&directory),
+            None,
+            // The processor has no tables of its own here, so its migrations are the SDK's again
+            SDK_MIGRATIONS,
+            move |_, _| {
+                let steps = live_graphs()
+                    .iter()
+                    .flat_map(|graph| graph.topology().nodes)
+                    .filter(|node| node.status == StepStatus::Running)
+                    .map(|node| node.name)
+                    .collect::<Vec<_>>();
+                process_running_steps.lock().unwrap().extend(steps);
+                async { Ok(()) }
+            },
+        )
+        .await
+        .unwrap();
+
+        let running_steps = running_steps.lock().unwrap();
+        for step_name in ["TransactionStreamStep", "BasicProcessorStep"] {
+            assert!(
+                running_steps.iter().any(|name| name == step_name),
+                "{} is not in {:?}",
+                step_name,
+                running_steps
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_run_processor_with_custom_checkpoint_store() {
+        let _lock = RUN_PROCESSOR_LOCK.lock().await;
+        let directory = tempfile::tempdir().unwrap();
+        let sqlite_config = test_sqlite_config(&directory);
+
+        let checkpoint_store = Arc::new(InMemoryCheckpointStore::new(Checkpoint::default()));
+        run_processor(
+            "checkpoint_store_test_processor".to_string(),
+            test_transaction_stream_config().await,
+            sqlite_config.clone(),
+            Some(checkpoint_store.clone()),
+            SDK_MIGRATIONS,
+            |_, _| async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        // The checkpoint is only in the custom store, not in the SQLite file
+        assert_eq!(checkpoint_store.checkpoint().last_success_version, Some(1));
+        let sqlite_checkpoint_store = SqliteCheckpointStore::new(
+            "checkpoint_store_test_processor",
+            new_db_pool(&sqlite_config).unwrap(),
+        );
+        assert_eq!(
+            sqlite_checkpoint_store.load_checkpoint().await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[allow(clippy::needless_return)]
+    async fn test_watchdog_finds_stalled_step_of_run_processor() {
+        let _lock = RUN_PROCESSOR_LOCK.lock().await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut watchdog = Watchdog::new(WatchdogConfig {
+            stall_timeout_secs: 1,
+            ..Default::default()
+        });
+
+        // The process function hangs until it is released
+        let started = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let process_started = started.clone();
+        let process_release = release.clone();
+        let processor = tokio::spawn(run_processor(
+            "watchdog_test_processor".to_string(),
+            test_transaction_stream_config().await,
+            test_sqlite_config(&directory),
+            None,
+            SDK_MIGRATIONS,
+            move |_, _| {
+                let started = process_started.clone();
+                let release = process_release.clone();
+                async move {
+                    started.notify_one();
+                    release.notified().await;
+                    Ok(())
+                }
+            },
+        ));
+
+        started.notified().await;
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let stalled_steps = watchdog.check();
+        assert!(
+            stalled_steps
+                .iter()
+                .any(|step| step.step_name == "BasicProcessorStep"),
+            "{:?}",
+            stalled_steps
+        );
+
+        release.notify_one();
+        processor.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,4 @@
+pub mod basic_processor_function;
+pub mod basic_processor_step;
+
+pub use basic_processor_function::process;
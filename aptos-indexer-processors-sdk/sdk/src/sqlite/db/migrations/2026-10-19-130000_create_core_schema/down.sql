@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS processor_status;
+DROP TABLE IF EXISTS ledger_infos;
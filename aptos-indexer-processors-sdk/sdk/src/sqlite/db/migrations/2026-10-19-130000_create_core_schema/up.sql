@@ -0,0 +1,10 @@
+-- Tracks latest processed version per processor
+CREATE TABLE IF NOT EXISTS processor_status (
+  processor VARCHAR(100) PRIMARY KEY NOT NULL,
+  last_success_version BIGINT NOT NULL,
+  last_updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_transaction_timestamp TIMESTAMP NULL
+);
+
+-- Tracks chain id
+CREATE TABLE IF NOT EXISTS ledger_infos (chain_id BIGINT PRIMARY KEY NOT NULL);
@@ -0,0 +1,25 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::sqlite::{
+    processor_metadata_schema::processor_metadata::ledger_infos, utils::database::MyDbConnection,
+};
+use diesel::{Identifiable, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+
+#[derive(Debug, Identifiable, Insertable, Queryable)]
+#[diesel(table_name = ledger_infos)]
+#[diesel(primary_key(chain_id))]
+pub struct LedgerInfo {
+    pub chain_id: i64,
+}
+
+impl LedgerInfo {
+    pub fn get(conn: &mut MyDbConnection) -> diesel::QueryResult<Option<Self>> {
+        ledger_infos::table
+            .select(ledger_infos::all_columns)
+            .first::<Self>(conn)
+            .optional()
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::sqlite::{
+    processor_metadata_schema::processor_metadata::processor_status,
+    utils::database::MyDbConnection,
+};
+use diesel::{
+    AsChangeset, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl,
+};
+
+#[derive(AsChangeset, Debug, Insertable)]
+#[diesel(table_name = processor_status)]
+/// Only tracking the latest version successfully processed
+pub struct ProcessorStatus {
+    pub processor: String,
+    pub last_success_version: i64,
+    pub last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(AsChangeset, Debug, Queryable)]
+#[diesel(table_name = processor_status)]
+/// Only tracking the latest version successfully processed
+pub struct ProcessorStatusQuery {
+    pub processor: String,
+    pub last_success_version: i64,
+    pub last_updated: chrono::NaiveDateTime,
+    pub last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+}
+
+impl ProcessorStatusQuery {
+    pub fn get_by_processor(
+        processor_name: &str,
+        conn: &mut MyDbConnection,
+    ) -> diesel::QueryResult<Option<Self>> {
+        processor_status::table
+            .filter(processor_status::processor.eq(processor_name))
+            .first::<Self>(conn)
+            .optional()
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteConfig {
+    // Path of the database file, created if it doesn't exist. `:memory:` is not supported, since
+    // every connection of the pool would get its own database.
+    pub database_path: String,
+    // Size of the connection pool. SQLite runs one write at a time, so this mostly helps reads.
+    #[serde(default = "SqliteConfig::default_db_pool_size")]
+    pub db_pool_size: u32,
+    // How long a connection waits for the write lock of another one before failing
+    #[serde(default = "SqliteConfig::default_busy_timeout_ms")]
+    pub busy_timeout_ms: u64,
+}
+
+impl SqliteConfig {
+    pub const fn default_db_pool_size() -> u32 {
+        4
+    }
+
+    pub const fn default_busy_timeout_ms() -> u64 {
+        5000
+    }
+}
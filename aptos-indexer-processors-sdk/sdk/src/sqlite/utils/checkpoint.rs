@@ -0,0 +1,235 @@
+use super::database::{run_with_connection, ArcDbPool};
+use crate::{
+    aptos_indexer_transaction_stream::utils::time::parse_timestamp,
+    common_steps::ProcessorStatusSaver,
+    health::{CheckpointStatus, StatusProvider},
+    sqlite::{
+        models::{
+            ledger_info::LedgerInfo,
+            processor_status::{ProcessorStatus, ProcessorStatusQuery},
+        },
+        processor_metadata_schema::processor_metadata::{ledger_infos, processor_status},
+    },
+    types::transaction_context::TransactionContext,
+    utils::{
+        chain_id_check::ChainIdChecker, checkpoint_store::CheckpointStore, errors::ProcessorError,
+    },
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use diesel::{query_dsl::methods::FilterDsl, upsert::excluded, ExpressionMethods, RunQueryDsl};
+
+/// A trait implementation of CheckpointStore for SQLite, keeping the checkpoint in
+/// `processor_status` and the chain ID in `ledger_infos`, like the Postgres one. Also serves
+/// `/status`.
+pub struct SqliteCheckpointStore {
+    pub db_pool: ArcDbPool,
+    pub processor_name: String,
+}
+
+impl SqliteCheckpointStore {
+    pub fn new(processor_name: &str, db_pool: ArcDbPool) -> Self {
+        Self {
+            db_pool,
+            processor_name: processor_name.to_string(),
+        }
+    }
+
+    async fn get_processor_status(&self) -> Result<Option<ProcessorStatusQuery>> {
+        let processor_name = self.processor_name.clone();
+        run_with_connection(self.db_pool.clone(), move |conn| {
+            Ok(ProcessorStatusQuery::get_by_processor(
+                &processor_name,
+                conn,
+            )?)
+        })
+        .await
+    }
+
+    async fn write_processor_status(
+        &self,
+        status: ProcessorStatus,
+        allow_backwards: bool,
+    ) -> Result<(), ProcessorError> {
+        run_with_connection(self.db_pool.clone(), move |conn| {
+            let upsert = diesel::insert_into(processor_status::table)
+                .values(&status)
+                .on_conflict(processor_status::processor)
+                .do_update()
+                .set((
+                    processor_status::last_success_version
+                        .eq(excluded(processor_status::last_success_version)),
+                    processor_status::last_updated.eq(excluded(processor_status::last_updated)),
+                    processor_status::last_transaction_timestamp
+                        .eq(excluded(processor_status::last_transaction_timestamp)),
+                ));
+            if allow_backwards {
+                upsert.execute(conn)?;
+            } else {
+                upsert
+                    .filter(
+                        processor_status::last_success_version
+                            .le(excluded(processor_status::last_success_version)),
+                    )
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| ProcessorError::DBStoreError {
+            message: format!("Error saving processor status: {:#}", e),
+            query: None,
+        })
+    }
+}
+
+#[async_trait]
+impl ProcessorStatusSaver for SqliteCheckpointStore {
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        let last_success_version = last_success_batch.metadata.end_version as i64;
+        let last_transaction_timestamp = last_success_batch
+            .metadata
+            .end_transaction_timestamp
+            .as_ref()
+            .map(|t| parse_timestamp(t, last_success_version))
+            .map(|t| t.naive_utc());
+        let status = ProcessorStatus {
+            processor: self.processor_name.clone(),
+            last_success_version,
+            last_transaction_timestamp,
+        };
+        self.write_processor_status(status, false).await
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        let status = ProcessorStatus {
+            processor: self.processor_name.clone(),
+            last_success_version: last_success_version as i64,
+            last_transaction_timestamp: None,
+        };
+        // Unlike saving, resetting may move the status backwards
+        self.write_processor_status(status, true).await
+    }
+}
+
+#[async_trait]
+impl ChainIdChecker for SqliteCheckpointStore {
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        run_with_connection(self.db_pool.clone(), move |conn| {
+            diesel::insert_into(ledger_infos::table)
+                .values(LedgerInfo {
+                    chain_id: chain_id as i64,
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .context("Error updating chain_id!")?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        run_with_connection(self.db_pool.clone(), |conn| {
+            Ok(LedgerInfo::get(conn)?.map(|li| li.chain_id as u64))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for SqliteCheckpointStore {
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        Ok(self
+            .get_processor_status()
+            .await?
+            .map(|status| status.last_success_version as u64))
+    }
+}
+
+#[async_trait]
+impl StatusProvider for SqliteCheckpointStore {
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        ChainIdChecker::get_chain_id(self).await
+    }
+
+    async fn get_checkpoint_status(&self) -> Result<Option<CheckpointStatus>> {
+        Ok(self
+            .get_processor_status()
+            .await?
+            .map(|status| CheckpointStatus {
+                last_success_version: status.last_success_version as u64,
+                last_transaction_timestamp: status.last_transaction_timestamp,
+                last_updated: Some(status.last_updated),
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sqlite::{
+            subconfigs::sqlite_config::SqliteConfig,
+            utils::{database::new_db_pool, migrations::run_migrations},
+            SDK_MIGRATIONS,
+        },
+        types::transaction_context::TransactionMetadata,
+    };
+
+    fn batch(end_version: u64) -> TransactionContext<()> {
+        TransactionContext {
+            data: (),
+            metadata: TransactionMetadata {
+                start_version: end_version,
+                end_version,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_sqlite_checkpoint_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let db_pool = new_db_pool(&SqliteConfig {
+            database_path: directory
+                .path()
+                .join("processor.db")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            db_pool_size: SqliteConfig::default_db_pool_size(),
+            busy_timeout_ms: SqliteConfig::default_busy_timeout_ms(),
+        })
+        .unwrap();
+        // The processor has no tables of its own here, so its migrations are the SDK's again
+        let report = run_migrations(db_pool.clone(), SDK_MIGRATIONS)
+            .await
+            .unwrap();
+        assert_eq!(report.applied.len(), 1);
+        // Running them again is a no-op
+        let report = run_migrations(db_pool.clone(), SDK_MIGRATIONS)
+            .await
+            .unwrap();
+        assert!(report.applied.is_empty());
+
+        let store = SqliteCheckpointStore::new("test_processor", db_pool);
+        assert_eq!(ChainIdChecker::get_chain_id(&store).await.unwrap(), None);
+        store.save_chain_id(2).await.unwrap();
+        store.save_chain_id(3).await.unwrap();
+        assert_eq!(ChainIdChecker::get_chain_id(&store).await.unwrap(), Some(2));
+
+        assert_eq!(store.load_checkpoint().await.unwrap(), None);
+        store.save_processor_status(&batch(10)).await.unwrap();
+        store.save_processor_status(&batch(5)).await.unwrap();
+        assert_eq!(store.load_checkpoint().await.unwrap(), Some(10));
+        store.reset_processor_status(5).await.unwrap();
+        assert_eq!(store.load_checkpoint().await.unwrap(), Some(5));
+    }
+}
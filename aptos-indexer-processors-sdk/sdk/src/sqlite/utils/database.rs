@@ -0,0 +1,180 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Database-related functions for SQLite. Diesel's SQLite connection is synchronous, so queries
+//! run on the blocking thread pool of tokio.
+
+use crate::{sqlite::subconfigs::sqlite_config::SqliteConfig, utils::errors::ProcessorError};
+use diesel::{
+    connection::SimpleConnection,
+    query_builder::{QueryFragment, QueryId},
+    query_dsl::methods::ExecuteDsl,
+    r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection},
+    Connection, SqliteConnection,
+};
+use std::sync::Arc;
+use tracing::warn;
+
+pub type Backend = diesel::sqlite::Sqlite;
+
+pub type MyDbConnection = SqliteConnection;
+pub type DbPool = Pool<ConnectionManager<MyDbConnection>>;
+pub type ArcDbPool = Arc<DbPool>;
+pub type DbPoolConnection = PooledConnection<ConnectionManager<MyDbConnection>>;
+
+// `SQLITE_MAX_VARIABLE_NUMBER` defaults to 32766 since SQLite 3.32
+pub const MAX_DIESEL_PARAM_SIZE: usize = 32766;
+
+#[derive(Debug)]
+struct ConnectionOptions {
+    busy_timeout_ms: u64,
+}
+
+impl CustomizeConnection<MyDbConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut MyDbConnection) -> Result<(), diesel::r2d2::Error> {
+        // WAL lets reads run next to the write, and the busy timeout makes writers queue up
+        // instead of failing with `database is locked`
+        conn.batch_execute(&format!(
+            "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON; PRAGMA busy_timeout = {};",
+            self.busy_timeout_ms
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Opens a pool of connections to the database file, creating it if it doesn't exist.
+pub fn new_db_pool(sqlite_config: &SqliteConfig) -> anyhow::Result<ArcDbPool> {
+    if sqlite_config.database_path == ":memory:" {
+        anyhow::bail!("In-memory SQLite databases are not supported, use a file in a temporary directory instead");
+    }
+    let manager = ConnectionManager::<MyDbConnection>::new(&sqlite_config.database_path);
+    let pool = Pool::builder()
+        .max_size(sqlite_config.db_pool_size)
+        .connection_customizer(Box::new(ConnectionOptions {
+            busy_timeout_ms: sqlite_config.busy_timeout_ms,
+        }))
+        .build(manager)?;
+    Ok(Arc::new(pool))
+}
+
+/// Runs `f` with a connection of the pool, on the blocking thread pool.
+pub async fn run_with_connection<F, R>(pool: ArcDbPool, f: F) -> anyhow::Result<R>
+where
+    F: FnOnce(&mut MyDbConnection) -> anyhow::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        f(&mut conn)
+    })
+    .await?
+}
+
+/// Writes `items_to_insert` in chunks of `chunk_size`. Unlike Postgres, all chunks are written in
+/// one transaction, since SQLite runs one write at a time anyway.
+pub async fn execute_in_chunks<U, T>(
+    pool: ArcDbPool,
+    build_query: fn(Vec<T>) -> U,
+    items_to_insert: &[T],
+    chunk_size: usize,
+) -> Result<(), ProcessorError>
+where
+    U: QueryFragment<Backend> + QueryId + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    if items_to_insert.is_empty() {
+        return Ok(());
+    }
+    let items = items_to_insert.to_vec();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get().map_err(|e| ProcessorError::DBStoreError {
+            message: format!("Error getting connection from pool: {:#}", e),
+            query: None,
+        })?;
+        let conn: &mut MyDbConnection = &mut conn;
+        let chunks = items.chunks(chunk_size.max(1)).collect::<Vec<_>>();
+        let mut failed_chunk = None;
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            for chunk in &chunks {
+                failed_chunk = Some(*chunk);
+                ExecuteDsl::execute(build_query(chunk.to_vec()), conn)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| {
+            let query = failed_chunk.map(|chunk| {
+                diesel::debug_query::<Backend, _>(&build_query(chunk.to_vec())).to_string()
+            });
+            warn!("Error running query: {:?}\n{:?}", e, query);
+            ProcessorError::DBStoreError {
+                message: format!("{:#}", e),
+                query,
+            }
+        })?;
+        Ok(())
+    })
+    .await;
+    result.map_err(|e| ProcessorError::DBStoreError {
+        message: format!("Write task failed: {:#}", e),
+        query: None,
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::{sql_query, RunQueryDsl};
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_execute_in_chunks() {
+        let directory = tempfile::tempdir().unwrap();
+        let pool = new_db_pool(&SqliteConfig {
+            database_path: directory
+                .path()
+                .join("test.db")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            db_pool_size: SqliteConfig::default_db_pool_size(),
+            busy_timeout_ms: SqliteConfig::default_busy_timeout_ms(),
+        })
+        .unwrap();
+        run_with_connection(pool.clone(), |conn| {
+            conn.batch_execute("CREATE TABLE items (id BIGINT PRIMARY KEY NOT NULL)")?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        fn insert_query(ids: Vec<i64>) -> impl QueryFragment<Backend> + QueryId + Send {
+            sql_query(format!(
+                "INSERT INTO items (id) VALUES {}",
+                ids.iter()
+                    .map(|id| format!("({})", id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+
+        let ids = (0..25).collect::<Vec<i64>>();
+        execute_in_chunks(pool.clone(), insert_query, &ids, 10)
+            .await
+            .unwrap();
+        // A failing chunk rolls back the whole write
+        let result = execute_in_chunks(pool.clone(), insert_query, &[100, 101, 0], 2).await;
+        assert!(matches!(result, Err(ProcessorError::DBStoreError { .. })));
+
+        #[derive(diesel::QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            count: i64,
+        }
+        let count = run_with_connection(pool, |conn| {
+            Ok(sql_query("SELECT COUNT(*) AS count FROM items").get_result::<Count>(conn)?)
+        })
+        .await
+        .unwrap();
+        assert_eq!(count.count, 25);
+    }
+}
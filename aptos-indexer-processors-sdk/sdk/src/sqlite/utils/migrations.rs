@@ -0,0 +1,57 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use super::database::{run_with_connection, ArcDbPool};
+use crate::sqlite::SDK_MIGRATIONS;
+use anyhow::Result;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use serde::Serialize;
+use tracing::info;
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+/// Runs the pending SDK migrations, then the processor's. Each migration runs in a transaction,
+/// and SQLite lets one writer in at a time, so replicas sharing the file don't need a lock.
+pub async fn run_migrations(
+    db_pool: ArcDbPool,
+    embedded_migrations: EmbeddedMigrations,
+) -> Result<MigrationReport> {
+    run_with_connection(db_pool, move |conn| {
+        let mut report = MigrationReport::default();
+        for migrations in [SDK_MIGRATIONS, embedded_migrations] {
+            let applied = conn
+                .run_pending_migrations(migrations)
+                .map_err(|e| anyhow::anyhow!("Failed to run migrations: {:#}", e))?;
+            report
+                .applied
+                .extend(applied.into_iter().map(|version| version.to_string()));
+        }
+        info!(applied = ?report.applied, "Ran the SQLite migrations");
+        Ok(report)
+    })
+    .await
+}
+
+/// Lists the pending SDK and processor migrations without running them.
+pub async fn get_pending_migrations(
+    db_pool: ArcDbPool,
+    embedded_migrations: EmbeddedMigrations,
+) -> Result<MigrationReport> {
+    run_with_connection(db_pool, move |conn| {
+        let mut report = MigrationReport::default();
+        for migrations in [SDK_MIGRATIONS, embedded_migrations] {
+            let pending = conn
+                .pending_migrations(migrations)
+                .map_err(|e| anyhow::anyhow!("Failed to list pending migrations: {:#}", e))?;
+            report
+                .pending
+                .extend(pending.iter().map(|migration| migration.name().to_string()));
+        }
+        Ok(report)
+    })
+    .await
+}
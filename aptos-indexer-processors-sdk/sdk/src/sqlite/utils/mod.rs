@@ -0,0 +1,3 @@
+pub mod checkpoint;
+pub mod database;
+pub mod migrations;
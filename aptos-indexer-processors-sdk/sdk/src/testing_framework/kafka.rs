@@ -0,0 +1,34 @@
+use anyhow::{Context, Result};
+use testcontainers::{
+    core::{IntoContainerPort, WaitFor},
+    runners::AsyncRunner,
+    ContainerAsync, GenericImage, ImageExt,
+};
+
+const KAFKA_IMAGE: &str = "apache/kafka";
+const KAFKA_VERSION: &str = "3.8.0";
+// The image advertises `localhost:9092`, so the port is mapped as is
+const KAFKA_PORT: u16 = 9092;
+
+/// A single Kafka broker in a container, e.g. to test a `KafkaSink`.
+pub struct KafkaTestBroker {
+    _container: ContainerAsync<GenericImage>,
+}
+
+impl KafkaTestBroker {
+    pub async fn start() -> Result<Self> {
+        let container = GenericImage::new(KAFKA_IMAGE, KAFKA_VERSION)
+            .with_wait_for(WaitFor::message_on_stdout("Kafka Server started"))
+            .with_mapped_port(KAFKA_PORT, KAFKA_PORT.tcp())
+            .start()
+            .await
+            .context("Failed to start Kafka container")?;
+        Ok(Self {
+            _container: container,
+        })
+    }
+
+    pub fn bootstrap_servers(&self) -> String {
+        format!("localhost:{}", KAFKA_PORT)
+    }
+}
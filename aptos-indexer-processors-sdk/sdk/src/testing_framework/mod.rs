@@ -1,4 +1,6 @@
 pub mod cli_parser;
 pub mod database;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 mod mock_grpc;
 pub mod sdk_test_context;
@@ -25,6 +25,13 @@ impl<T> TransactionContext<T> {
             .map(timestamp_to_unixtime)
     }
 
+    pub fn get_end_transaction_timestamp_unix(&self) -> Option<f64> {
+        self.metadata
+            .end_transaction_timestamp
+            .as_ref()
+            .map(timestamp_to_unixtime)
+    }
+
     pub fn get_transaction_latency(&self) -> Option<f64> {
         self.metadata
             .start_transaction_timestamp
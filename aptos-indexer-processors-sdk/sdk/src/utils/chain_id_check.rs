@@ -15,6 +15,20 @@ pub trait ChainIdChecker {
     async fn get_chain_id(&self) -> Result<Option<u64>>;
 }
 
+#[async_trait]
+impl<T> ChainIdChecker for std::sync::Arc<T>
+where
+    T: ChainIdChecker + Send + Sync + ?Sized,
+{
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        (**self).save_chain_id(chain_id).await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        (**self).get_chain_id().await
+    }
+}
+
 /// Verify the chain id from TransactionStream against the database.
 pub async fn check_or_update_chain_id<T>(
     transaction_stream_config: &TransactionStreamConfig,
@@ -0,0 +1,389 @@
+use super::{chain_id_check::ChainIdChecker, errors::ProcessorError};
+use crate::{
+    aptos_indexer_transaction_stream::{utils::time::parse_timestamp, TransactionStreamConfig},
+    common_steps::ProcessorStatusSaver,
+    health::{CheckpointStatus, StatusProvider},
+    types::transaction_context::TransactionContext,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Where a processor keeps its progress: the chain ID it processes and the last version it
+/// processed successfully. Saving comes from `ProcessorStatusSaver` and the chain ID from
+/// `ChainIdChecker`, so a store can be handed to `VersionTrackerStep` and
+/// `check_or_update_chain_id` as is.
+#[async_trait]
+pub trait CheckpointStore: ProcessorStatusSaver + ChainIdChecker + Send + Sync {
+    /// Returns the last successfully processed version, or `None` if nothing was saved yet.
+    async fn load_checkpoint(&self) -> Result<Option<u64>>;
+}
+
+#[async_trait]
+impl<T> CheckpointStore for Arc<T>
+where
+    T: CheckpointStore + ?Sized,
+{
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        (**self).load_checkpoint().await
+    }
+}
+
+/// Merges the `starting_version` from config and the checkpoint: resumes from the checkpoint if
+/// there is one, and starts from `starting_version`, or 0, otherwise.
+pub async fn get_starting_version<C>(
+    checkpoint_store: &C,
+    transaction_stream_config: &TransactionStreamConfig,
+) -> Result<u64>
+where
+    C: CheckpointStore + ?Sized,
+{
+    let latest_processed_version = checkpoint_store.load_checkpoint().await?;
+    Ok(latest_processed_version.unwrap_or(transaction_stream_config.starting_version.unwrap_or(0)))
+}
+
+/// Prints the chain ID and checkpoint in `checkpoint_store`, for the `status` subcommand.
+pub async fn print_checkpoint_store_status(
+    processor_name: &str,
+    checkpoint_store: &dyn CheckpointStore,
+) -> Result<()> {
+    let output = serde_json::json!({
+        "processor": processor_name,
+        "chain_id": checkpoint_store.get_chain_id().await?,
+        "last_success_version": checkpoint_store.load_checkpoint().await?,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Overwrites the checkpoint in `checkpoint_store`, for the `reset-checkpoint` subcommand.
+pub async fn reset_checkpoint_store(
+    processor_name: &str,
+    checkpoint_store: &dyn CheckpointStore,
+    to: u64,
+) -> Result<()> {
+    checkpoint_store.reset_processor_status(to).await?;
+    println!(
+        "Reset the checkpoint of {} to version {}",
+        processor_name, to
+    );
+    Ok(())
+}
+
+/// Serves the `/status` endpoint from any `CheckpointStore`, for processors run with a custom
+/// store. Stores only tell the last version, not when it was saved.
+pub struct CheckpointStoreStatusProvider {
+    checkpoint_store: Arc<dyn CheckpointStore>,
+}
+
+impl CheckpointStoreStatusProvider {
+    pub fn new(checkpoint_store: Arc<dyn CheckpointStore>) -> Self {
+        Self { checkpoint_store }
+    }
+}
+
+#[async_trait]
+impl StatusProvider for CheckpointStoreStatusProvider {
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        ChainIdChecker::get_chain_id(&self.checkpoint_store).await
+    }
+
+    async fn get_checkpoint_status(&self) -> Result<Option<CheckpointStatus>> {
+        Ok(self
+            .checkpoint_store
+            .load_checkpoint()
+            .await?
+            .map(|last_success_version| CheckpointStatus {
+                last_success_version,
+                last_transaction_timestamp: None,
+                last_updated: None,
+            }))
+    }
+}
+
+/// Progress of a processor, as kept by `FileCheckpointStore` and `InMemoryCheckpointStore`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checkpoint {
+    pub chain_id: Option<u64>,
+    pub last_success_version: Option<u64>,
+    pub last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+}
+
+impl Checkpoint {
+    /// Moves the checkpoint to the end of `last_success_batch`, unless it is already past it.
+    fn advance(&mut self, last_success_batch: &TransactionContext<()>) -> bool {
+        let end_version = last_success_batch.metadata.end_version;
+        if self
+            .last_success_version
+            .is_some_and(|version| version > end_version)
+        {
+            return false;
+        }
+        self.last_success_version = Some(end_version);
+        self.last_transaction_timestamp = last_success_batch
+            .metadata
+            .end_transaction_timestamp
+            .as_ref()
+            .map(|t| parse_timestamp(t, end_version as i64).naive_utc());
+        true
+    }
+
+    fn reset(&mut self, last_success_version: u64) {
+        self.last_success_version = Some(last_success_version);
+        self.last_transaction_timestamp = None;
+    }
+}
+
+/// Keeps the checkpoint in a local JSON file, for processors whose sink has no room for it,
+/// e.g. files or a queue. Each save replaces the file atomically: the new checkpoint is written
+/// to a temporary file, synced, then renamed over the old one, so a crash leaves either the old
+/// or the new checkpoint. The file must not be shared with another processor.
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    // Serializes the writes of the file, and caches its content
+    checkpoint: tokio::sync::Mutex<Checkpoint>,
+}
+
+impl FileCheckpointStore {
+    /// Opens the checkpoint at `path`, which is created on the first save if it doesn't exist.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let checkpoint = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Could not parse the checkpoint at {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Checkpoint::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Could not read the checkpoint at {:?}", path))
+            },
+        };
+        Ok(Self {
+            path,
+            checkpoint: tokio::sync::Mutex::new(checkpoint),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint.lock().await.clone()
+    }
+
+    async fn write(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(checkpoint)?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .with_context(|| format!("Could not create {:?}", tmp_path))?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &bytes).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .with_context(|| format!("Could not replace the checkpoint at {:?}", self.path))?;
+        // Sync the directory too, so that the rename survives a crash
+        #[cfg(unix)]
+        if let Some(directory) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::File::open(directory).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Applies `update` to the checkpoint, and writes it if `update` changed it.
+    async fn update(&self, update: impl FnOnce(&mut Checkpoint) -> bool) -> Result<()> {
+        let mut checkpoint = self.checkpoint.lock().await;
+        let mut updated = checkpoint.clone();
+        if update(&mut updated) && updated != *checkpoint {
+            self.write(&updated).await?;
+            *checkpoint = updated;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProcessorStatusSaver for FileCheckpointStore {
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        self.update(|checkpoint| checkpoint.advance(last_success_batch))
+            .await
+            .map_err(|e| ProcessorError::ProcessError {
+                message: format!("Error saving the checkpoint to {:?}: {:#}", self.path, e),
+            })
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        self.update(|checkpoint| {
+            checkpoint.reset(last_success_version);
+            true
+        })
+        .await
+        .map_err(|e| ProcessorError::ProcessError {
+            message: format!("Error resetting the checkpoint at {:?}: {:#}", self.path, e),
+        })
+    }
+}
+
+#[async_trait]
+impl ChainIdChecker for FileCheckpointStore {
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        self.update(|checkpoint| {
+            checkpoint.chain_id = Some(chain_id);
+            true
+        })
+        .await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        Ok(self.checkpoint.lock().await.chain_id)
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        Ok(self.checkpoint.lock().await.last_success_version)
+    }
+}
+
+/// Keeps the checkpoint in memory, e.g. for tests. Nothing survives a restart.
+#[derive(Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoint: Mutex<Checkpoint>,
+}
+
+impl InMemoryCheckpointStore {
+    pub fn new(checkpoint: Checkpoint) -> Self {
+        Self {
+            checkpoint: Mutex::new(checkpoint),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ProcessorStatusSaver for InMemoryCheckpointStore {
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        self.checkpoint.lock().unwrap().advance(last_success_batch);
+        Ok(())
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        self.checkpoint.lock().unwrap().reset(last_success_version);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChainIdChecker for InMemoryCheckpointStore {
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        self.checkpoint.lock().unwrap().chain_id = Some(chain_id);
+        Ok(())
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        Ok(self.checkpoint.lock().unwrap().chain_id)
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for InMemoryCheckpointStore {
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        Ok(self.checkpoint.lock().unwrap().last_success_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transaction_context::TransactionMetadata;
+
+    fn batch(end_version: u64) -> TransactionContext<()> {
+        TransactionContext {
+            data: (),
+            metadata: TransactionMetadata {
+                start_version: end_version,
+                end_version,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_file_checkpoint_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("checkpoint.json");
+        let store = FileCheckpointStore::new(&path).unwrap();
+        assert_eq!(store.load_checkpoint().await.unwrap(), None);
+        assert_eq!(store.get_chain_id().await.unwrap(), None);
+
+        store.save_chain_id(1).await.unwrap();
+        store.save_processor_status(&batch(10)).await.unwrap();
+        // Saving never moves the checkpoint backwards, resetting does
+        store.save_processor_status(&batch(5)).await.unwrap();
+        assert_eq!(store.load_checkpoint().await.unwrap(), Some(10));
+
+        // The checkpoint survives a restart
+        let store = FileCheckpointStore::new(&path).unwrap();
+        assert_eq!(store.get_chain_id().await.unwrap(), Some(1));
+        assert_eq!(store.load_checkpoint().await.unwrap(), Some(10));
+        store.reset_processor_status(3).await.unwrap();
+        assert_eq!(
+            FileCheckpointStore::new(&path)
+                .unwrap()
+                .load_checkpoint()
+                .await
+                .unwrap(),
+            Some(3)
+        );
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_get_starting_version() {
+        let transaction_stream_config: TransactionStreamConfig =
+            serde_json::from_value(serde_json::json!({
+                "indexer_grpc_data_service_address": "https://grpc.testnet.aptoslabs.com:443",
+                "auth_token": "token",
+                "request_name_header": "test",
+                "starting_version": 100,
+            }))
+            .unwrap();
+        let store = Arc::new(InMemoryCheckpointStore::default());
+        assert_eq!(
+            get_starting_version(&store, &transaction_stream_config)
+                .await
+                .unwrap(),
+            100
+        );
+        store.save_processor_status(&batch(200)).await.unwrap();
+        assert_eq!(
+            get_starting_version(&store, &transaction_stream_config)
+                .await
+                .unwrap(),
+            200
+        );
+    }
+}
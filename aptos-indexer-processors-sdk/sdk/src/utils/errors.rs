@@ -13,6 +13,12 @@ pub enum ProcessorError {
         message: String,
         query: Option<String>,
     },
+    /// A DB write that failed because the database is unreachable, see `is_unavailable_error`.
+    #[error("DB Unavailable Error: {message}, Query: {query:?}")]
+    DBUnavailableError {
+        message: String,
+        query: Option<String>,
+    },
     #[error("Chain ID Check Error: {message}")]
     ChainIdCheckError { message: String },
 }
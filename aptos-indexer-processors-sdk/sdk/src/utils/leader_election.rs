@@ -0,0 +1,422 @@
+use crate::{
+    admin::stream_control,
+    common_steps::ProcessorStatusSaver,
+    types::transaction_context::TransactionContext,
+    utils::{
+        chain_id_check::ChainIdChecker, checkpoint_store::CheckpointStore, errors::ProcessorError,
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use prometheus_client::{metrics::gauge::Gauge, registry::Registry};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderElectionConfig {
+    /// How long the lease is valid after being taken or renewed. A standby takes over at most
+    /// this long after the leader stops renewing.
+    #[serde(default = "LeaderElectionConfig::default_lease_duration_secs")]
+    pub lease_duration_secs: u64,
+    /// How often the lease is renewed by the leader, and tried by standbys. Should be well
+    /// below `lease_duration_secs`.
+    #[serde(default = "LeaderElectionConfig::default_renew_interval_secs")]
+    pub renew_interval_secs: u64,
+    /// Identifies this replica in the lease. Defaults to the hostname and process ID.
+    #[serde(default)]
+    pub holder_id: Option<String>,
+}
+
+impl LeaderElectionConfig {
+    pub const fn default_lease_duration_secs() -> u64 {
+        30
+    }
+
+    pub const fn default_renew_interval_secs() -> u64 {
+        10
+    }
+}
+
+impl Default for LeaderElectionConfig {
+    fn default() -> Self {
+        Self {
+            lease_duration_secs: Self::default_lease_duration_secs(),
+            renew_interval_secs: Self::default_renew_interval_secs(),
+            holder_id: None,
+        }
+    }
+}
+
+/// Storage of the lease that decides which replica of a processor is the leader, e.g. a row in
+/// the database shared by the replicas.
+#[async_trait]
+pub trait LeaseStore: Send + Sync {
+    /// Takes the lease for `holder_id` if it is free or has expired, or renews it if `holder_id`
+    /// already holds it. Returns whether `holder_id` holds the lease.
+    async fn try_acquire_lease(&self, holder_id: &str, lease_duration: Duration) -> Result<bool>;
+
+    /// Gives up the lease if `holder_id` holds it, so that a standby can take over right away.
+    async fn release_lease(&self, holder_id: &str) -> Result<()>;
+}
+
+static IS_LEADER: Lazy<Gauge> = Lazy::new(Gauge::default);
+
+// The leadership reported by `/status`. None if leader election is not enabled.
+static REGISTERED_LEADERSHIP: Lazy<Mutex<Option<Leadership>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init_leader_election_metrics_registry(registry: &mut Registry) {
+    registry.register(
+        "aptos_procsdk_is_leader",
+        "1 if this replica holds the processor lease, 0 if it is a standby",
+        IS_LEADER.clone(),
+    );
+}
+
+/// Whether a replica holds the processor lease, as last seen by its `LeaderElector`.
+#[derive(Clone, Debug, Default)]
+pub struct Leadership(Arc<AtomicBool>);
+
+impl Leadership {
+    pub fn is_leader(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set(&self, is_leader: bool) {
+        self.0.store(is_leader, Ordering::SeqCst);
+    }
+}
+
+/// Registers the leadership reported by `/status` and the `aptos_procsdk_is_leader` metric,
+/// replacing any previous one.
+pub fn register_leadership(leadership: Leadership) {
+    IS_LEADER.set(leadership.is_leader() as i64);
+    *REGISTERED_LEADERSHIP.lock().unwrap() = Some(leadership);
+}
+
+/// Returns whether this replica is the leader, or `None` if leader election is not enabled.
+pub fn is_leader() -> Option<bool> {
+    REGISTERED_LEADERSHIP
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(Leadership::is_leader)
+}
+
+fn default_holder_id() -> String {
+    let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+    format!("{}-{}", hostname, std::process::id())
+}
+
+/// Makes sure that only one replica of a processor streams and writes at a time. Replicas
+/// compete for a lease in a `LeaseStore`; the others stay warm as standbys until it lapses.
+pub struct LeaderElector<S: LeaseStore> {
+    config: LeaderElectionConfig,
+    lease_store: S,
+    holder_id: String,
+    leadership: Leadership,
+}
+
+impl<S: LeaseStore> LeaderElector<S> {
+    pub fn new(config: LeaderElectionConfig, lease_store: S) -> Self {
+        let holder_id = config.holder_id.clone().unwrap_or_else(default_holder_id);
+        Self {
+            config,
+            lease_store,
+            holder_id,
+            leadership: Leadership::default(),
+        }
+    }
+
+    pub fn holder_id(&self) -> &str {
+        &self.holder_id
+    }
+
+    /// The leadership of this replica, e.g. to pass to `register_leadership`.
+    pub fn leadership(&self) -> Leadership {
+        self.leadership.clone()
+    }
+
+    fn set_leader(&self, is_leader: bool) {
+        self.leadership.set(is_leader);
+        let is_registered = REGISTERED_LEADERSHIP
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|leadership| Arc::ptr_eq(&leadership.0, &self.leadership.0));
+        if is_registered {
+            IS_LEADER.set(is_leader as i64);
+        }
+    }
+
+    async fn try_acquire_lease(&self) -> bool {
+        let lease_duration = Duration::from_secs(self.config.lease_duration_secs);
+        match self
+            .lease_store
+            .try_acquire_lease(&self.holder_id, lease_duration)
+            .await
+        {
+            Ok(is_leader) => is_leader,
+            Err(e) => {
+                // Without a confirmed renewal, the lease may lapse, so step down to be safe
+                warn!(holder_id = self.holder_id, error = ?e, "Failed to acquire or renew the lease");
+                false
+            },
+        }
+    }
+
+    /// Renews the lease right before a write that only the leader may do, e.g. saving the
+    /// checkpoint, and returns whether this replica still holds it. A renewed lease can't be
+    /// taken over for `lease_duration_secs`, so the write is fenced if it completes within that
+    /// time, which the `statement_timeout` of `pool_config` can bound.
+    pub async fn confirm_leadership(&self) -> bool {
+        self.try_acquire_lease().await
+    }
+
+    /// Waits until this replica holds the lease.
+    pub async fn wait_for_leadership(&self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.renew_interval_secs.max(1)));
+        self.set_leader(false);
+        info!(
+            holder_id = self.holder_id,
+            "Waiting for the processor lease as a standby"
+        );
+        loop {
+            interval.tick().await;
+            if self.try_acquire_lease().await {
+                info!(holder_id = self.holder_id, "Acquired the processor lease");
+                self.set_leader(true);
+                return;
+            }
+        }
+    }
+
+    /// Keeps renewing the lease while the processor runs. If the lease is lost, the transaction
+    /// stream is paused until this replica is the leader again. The stream is then rewound to
+    /// `get_resume_version`, i.e. the checkpoint saved by the previous leader, and resumed.
+    ///
+    /// Batches already in the pipeline when the lease is lost are still written, so writes have
+    /// to be idempotent. Their checkpoint isn't, see `LeaderFencedCheckpointStore`, so the
+    /// checkpoint of the new leader doesn't move.
+    pub async fn maintain_leadership<F, Fut>(&self, get_resume_version: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<u64>>,
+    {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.renew_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            if self.try_acquire_lease().await {
+                continue;
+            }
+
+            warn!(
+                holder_id = self.holder_id,
+                "Lost the processor lease, pausing the transaction stream"
+            );
+            self.set_leader(false);
+            stream_control().pause();
+            loop {
+                self.wait_for_leadership().await;
+                match get_resume_version().await {
+                    Ok(version) => {
+                        info!(
+                            holder_id = self.holder_id,
+                            version = version,
+                            "Resuming the transaction stream as the leader"
+                        );
+                        stream_control().request_rewind(version);
+                        stream_control().resume();
+                        break;
+                    },
+                    Err(e) => {
+                        warn!(error = ?e, "Failed to get the version to resume from");
+                        self.set_leader(false);
+                    },
+                }
+            }
+        }
+    }
+
+    /// Gives up the lease, e.g. when the processor is done.
+    pub async fn release(&self) {
+        if let Err(e) = self.lease_store.release_lease(&self.holder_id).await {
+            warn!(holder_id = self.holder_id, error = ?e, "Failed to release the processor lease");
+        }
+        self.set_leader(false);
+    }
+}
+
+/// Only saves the checkpoint while this replica holds the lease, confirmed right before each
+/// save. Batches still in the pipeline of a replica that lost the lease then can't move the
+/// checkpoint of the new leader. Skipped saves are retried with the next batch, once this
+/// replica is the leader again.
+pub struct LeaderFencedCheckpointStore<S: LeaseStore> {
+    checkpoint_store: Arc<dyn CheckpointStore>,
+    leader_elector: Arc<LeaderElector<S>>,
+}
+
+impl<S: LeaseStore> LeaderFencedCheckpointStore<S> {
+    pub fn new(
+        checkpoint_store: Arc<dyn CheckpointStore>,
+        leader_elector: Arc<LeaderElector<S>>,
+    ) -> Self {
+        Self {
+            checkpoint_store,
+            leader_elector,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: LeaseStore> ProcessorStatusSaver for LeaderFencedCheckpointStore<S> {
+    async fn save_processor_status(
+        &self,
+        last_success_batch: &TransactionContext<()>,
+    ) -> Result<(), ProcessorError> {
+        if !self.leader_elector.confirm_leadership().await {
+            warn!(
+                holder_id = self.leader_elector.holder_id(),
+                version = last_success_batch.metadata.end_version,
+                "Not the leader, skipping the checkpoint"
+            );
+            return Ok(());
+        }
+        self.checkpoint_store
+            .save_processor_status(last_success_batch)
+            .await
+    }
+
+    async fn reset_processor_status(
+        &self,
+        last_success_version: u64,
+    ) -> Result<(), ProcessorError> {
+        if !self.leader_elector.confirm_leadership().await {
+            return Err(ProcessorError::ProcessError {
+                message: format!(
+                    "{} does not hold the processor lease, not resetting the checkpoint",
+                    self.leader_elector.holder_id()
+                ),
+            });
+        }
+        self.checkpoint_store
+            .reset_processor_status(last_success_version)
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: LeaseStore> ChainIdChecker for LeaderFencedCheckpointStore<S> {
+    async fn save_chain_id(&self, chain_id: u64) -> Result<()> {
+        self.checkpoint_store.save_chain_id(chain_id).await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<u64>> {
+        self.checkpoint_store.get_chain_id().await
+    }
+}
+
+#[async_trait]
+impl<S: LeaseStore> CheckpointStore for LeaderFencedCheckpointStore<S> {
+    async fn load_checkpoint(&self) -> Result<Option<u64>> {
+        self.checkpoint_store.load_checkpoint().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        types::transaction_context::TransactionMetadata,
+        utils::checkpoint_store::{Checkpoint, InMemoryCheckpointStore},
+    };
+    use std::sync::atomic::AtomicUsize;
+
+    /// Grants the lease from the `grant_after`-th attempt on.
+    struct TestLeaseStore {
+        attempts: AtomicUsize,
+        grant_after: usize,
+    }
+
+    #[async_trait]
+    impl LeaseStore for TestLeaseStore {
+        async fn try_acquire_lease(&self, _holder_id: &str, _: Duration) -> Result<bool> {
+            Ok(self.attempts.fetch_add(1, Ordering::SeqCst) + 1 >= self.grant_after)
+        }
+
+        async fn release_lease(&self, _holder_id: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_standby_waits_for_lease() {
+        let elector = LeaderElector::new(
+            LeaderElectionConfig {
+                renew_interval_secs: 1,
+                holder_id: Some("replica-1".to_string()),
+                ..Default::default()
+            },
+            TestLeaseStore {
+                attempts: AtomicUsize::new(0),
+                grant_after: 2,
+            },
+        );
+        assert_eq!(elector.holder_id(), "replica-1");
+
+        let leadership = elector.leadership();
+        elector.wait_for_leadership().await;
+        assert_eq!(elector.lease_store.attempts.load(Ordering::SeqCst), 2);
+        assert!(leadership.is_leader());
+
+        elector.release().await;
+        assert!(!leadership.is_leader());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_checkpoint_is_only_saved_by_the_leader() {
+        let elector = Arc::new(LeaderElector::new(
+            LeaderElectionConfig::default(),
+            TestLeaseStore {
+                attempts: AtomicUsize::new(0),
+                grant_after: 2,
+            },
+        ));
+        let checkpoint_store = Arc::new(InMemoryCheckpointStore::new(Checkpoint::default()));
+        let fenced_store = LeaderFencedCheckpointStore::new(checkpoint_store.clone(), elector);
+        let batch = |end_version| TransactionContext {
+            data: (),
+            metadata: TransactionMetadata {
+                end_version,
+                ..Default::default()
+            },
+        };
+
+        // The lease is held by another replica
+        fenced_store
+            .save_processor_status(&batch(10))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint_store.checkpoint().last_success_version, None);
+        assert_eq!(fenced_store.load_checkpoint().await.unwrap(), None);
+
+        fenced_store
+            .save_processor_status(&batch(20))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint_store.checkpoint().last_success_version, Some(20));
+    }
+}
@@ -1,7 +1,11 @@
 pub mod chain_id_check;
+pub mod checkpoint_store;
 pub mod constants;
 pub mod convert;
 pub mod errors;
 pub mod extract;
+pub mod leader_election;
 pub mod property_map;
 pub mod step_metrics;
+#[cfg(feature = "stream")]
+pub mod stream_hub;
@@ -102,6 +102,12 @@ pub fn init_step_metrics_registry(registry: &mut Registry) {
         POLLING_ERROR_COUNT.clone(),
     );
 
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "last_progress_timestamp_secs"),
+        "Unix timestamp of the last time the step processed or polled anything",
+        LAST_PROGRESS_TIMESTAMP_SECS.clone(),
+    );
+
     // WriteRateLimitStep metrics
     registry.register(
         format!("{}_{}", METRICS_PREFIX, "write_rate_limit_remaining_bytes"),
@@ -114,6 +120,43 @@ pub fn init_step_metrics_registry(registry: &mut Registry) {
         "WriteRateLimitStep bytes written",
         WRITE_RATE_LIMIT_STEP_BYTES_WRITTEN.clone(),
     );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "spool_spooled_batches"),
+        "SpoolStep batches waiting on disk",
+        SPOOL_STEP_SPOOLED_BATCHES.clone(),
+    );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "spool_spooled_bytes"),
+        "SpoolStep bytes waiting on disk",
+        SPOOL_STEP_SPOOLED_BYTES.clone(),
+    );
+
+    // WebhookSinkStep metrics
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "webhook_delivery_lag_secs"),
+        "WebhookSinkStep time from storing a delivery in the outbox to delivering it, per endpoint",
+        WEBHOOK_DELIVERY_LAG_SECS.clone(),
+    );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "webhook_delivered_count"),
+        "WebhookSinkStep deliveries acknowledged by the endpoint",
+        WEBHOOK_DELIVERED_COUNT.clone(),
+    );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "webhook_failed_attempt_count"),
+        "WebhookSinkStep failed delivery attempts",
+        WEBHOOK_FAILED_ATTEMPT_COUNT.clone(),
+    );
+
+    registry.register(
+        format!("{}_{}", METRICS_PREFIX, "webhook_abandoned_delivery_count"),
+        "WebhookSinkStep deliveries given up on after too many failed attempts",
+        WEBHOOK_ABANDONED_DELIVERY_COUNT.clone(),
+    );
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
@@ -121,6 +164,12 @@ pub struct StepMetricLabels {
     pub step_name: String,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct WebhookMetricLabels {
+    pub step_name: String,
+    pub endpoint: String,
+}
+
 // AsyncStep metrics
 pub static LATEST_PROCESSED_VERSION: Lazy<Family<StepMetricLabels, Gauge>> =
     Lazy::new(Family::<StepMetricLabels, Gauge>::default);
@@ -167,6 +216,9 @@ pub static POLLED_SIZE_IN_BYTES: Lazy<Family<StepMetricLabels, Counter>> =
 pub static POLLING_ERROR_COUNT: Lazy<Family<StepMetricLabels, Counter>> =
     Lazy::new(Family::<StepMetricLabels, Counter>::default);
 
+pub static LAST_PROGRESS_TIMESTAMP_SECS: Lazy<Family<StepMetricLabels, Gauge<f64, AtomicU64>>> =
+    Lazy::new(Family::<StepMetricLabels, Gauge<f64, AtomicU64>>::default);
+
 // WriteRateLimitStep metrics
 pub static WRITE_RATE_LIMIT_STEP_REMAINING_BYTES: Lazy<Family<StepMetricLabels, Gauge>> =
     Lazy::new(Family::<StepMetricLabels, Gauge>::default);
@@ -174,6 +226,25 @@ pub static WRITE_RATE_LIMIT_STEP_REMAINING_BYTES: Lazy<Family<StepMetricLabels,
 pub static WRITE_RATE_LIMIT_STEP_BYTES_WRITTEN: Lazy<Family<StepMetricLabels, Counter>> =
     Lazy::new(Family::<StepMetricLabels, Counter>::default);
 
+pub static SPOOL_STEP_SPOOLED_BATCHES: Lazy<Family<StepMetricLabels, Gauge>> =
+    Lazy::new(Family::<StepMetricLabels, Gauge>::default);
+
+pub static SPOOL_STEP_SPOOLED_BYTES: Lazy<Family<StepMetricLabels, Gauge>> =
+    Lazy::new(Family::<StepMetricLabels, Gauge>::default);
+
+// WebhookSinkStep metrics
+pub static WEBHOOK_DELIVERY_LAG_SECS: Lazy<Family<WebhookMetricLabels, Gauge<f64, AtomicU64>>> =
+    Lazy::new(Family::<WebhookMetricLabels, Gauge<f64, AtomicU64>>::default);
+
+pub static WEBHOOK_DELIVERED_COUNT: Lazy<Family<WebhookMetricLabels, Counter>> =
+    Lazy::new(Family::<WebhookMetricLabels, Counter>::default);
+
+pub static WEBHOOK_FAILED_ATTEMPT_COUNT: Lazy<Family<WebhookMetricLabels, Counter>> =
+    Lazy::new(Family::<WebhookMetricLabels, Counter>::default);
+
+pub static WEBHOOK_ABANDONED_DELIVERY_COUNT: Lazy<Family<WebhookMetricLabels, Counter>> =
+    Lazy::new(Family::<WebhookMetricLabels, Counter>::default);
+
 #[derive(Builder)]
 pub struct StepMetrics {
     pub labels: StepMetricLabels,
@@ -208,6 +279,11 @@ pub struct StepMetrics {
 
 impl StepMetrics {
     pub fn log_metrics(&mut self) {
+        // Every call means the step is making progress; the watchdog relies on this
+        LAST_PROGRESS_TIMESTAMP_SECS
+            .get_or_create(&self.labels)
+            .set(unix_timestamp_secs());
+
         // AsyncStep metrics
         if let Some(version) = self.latest_processed_version {
             LATEST_PROCESSED_VERSION
@@ -281,3 +357,20 @@ impl StepMetrics {
         POLLING_ERROR_COUNT.get_or_create(&self.labels).inc();
     }
 }
+
+fn unix_timestamp_secs() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Returns the unix timestamp of the last progress of a step, if it has made any.
+pub fn get_last_progress_timestamp_secs(step_name: &str) -> Option<f64> {
+    let timestamp = LAST_PROGRESS_TIMESTAMP_SECS
+        .get_or_create(&StepMetricLabels {
+            step_name: step_name.to_string(),
+        })
+        .get();
+    (timestamp > 0.0).then_some(timestamp)
+}
@@ -0,0 +1,458 @@
+//! Pushes newly indexed rows to clients of the health server, over WebSocket at `/stream/ws`
+//! or Server-Sent Events at `/stream/sse`. Rows are published by a `BroadcastStep`.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::get,
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamConfig {
+    /// How many recent rows are kept for clients resuming from a version.
+    #[serde(default = "StreamConfig::default_max_buffered_events")]
+    pub max_buffered_events: usize,
+    /// How many rows a client may fall behind before it's disconnected.
+    #[serde(default = "StreamConfig::default_subscriber_buffer_size")]
+    pub subscriber_buffer_size: usize,
+}
+
+impl StreamConfig {
+    pub const fn default_max_buffered_events() -> usize {
+        10_000
+    }
+
+    pub const fn default_subscriber_buffer_size() -> usize {
+        1024
+    }
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            max_buffered_events: Self::default_max_buffered_events(),
+            subscriber_buffer_size: Self::default_subscriber_buffer_size(),
+        }
+    }
+}
+
+/// A row as pushed to clients.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StreamEvent {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub event_type: String,
+    pub module_address: Option<String>,
+    pub data: serde_json::Value,
+}
+
+impl StreamEvent {
+    fn key(&self) -> (i64, i64) {
+        (self.transaction_version, self.event_index)
+    }
+}
+
+/// Where a client starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamCursor {
+    /// Only rows published from now on
+    Live,
+    /// Rows from this version on, inclusive
+    FromVersion(i64),
+    /// Rows after this `(transaction_version, event_index)`, e.g. the last one a client got
+    After(i64, i64),
+}
+
+impl StreamCursor {
+    fn includes(&self, event: &StreamEvent) -> bool {
+        match *self {
+            StreamCursor::Live => true,
+            StreamCursor::FromVersion(version) => event.transaction_version >= version,
+            StreamCursor::After(version, event_index) => event.key() > (version, event_index),
+        }
+    }
+
+    fn first_version(&self) -> Option<i64> {
+        match *self {
+            StreamCursor::Live => None,
+            StreamCursor::FromVersion(version) | StreamCursor::After(version, _) => Some(version),
+        }
+    }
+}
+
+/// The rows a client asked for.
+#[derive(Clone, Debug, Default)]
+pub struct StreamFilter {
+    /// All event types if empty
+    pub event_types: HashSet<String>,
+    /// All module addresses if empty
+    pub module_addresses: HashSet<String>,
+}
+
+impl StreamFilter {
+    pub fn matches(&self, event: &StreamEvent) -> bool {
+        (self.event_types.is_empty() || self.event_types.contains(&event.event_type))
+            && (self.module_addresses.is_empty()
+                || event
+                    .module_address
+                    .as_ref()
+                    .is_some_and(|address| self.module_addresses.contains(&address.to_lowercase())))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error("Rows before version {earliest_version} are no longer buffered, read them from the database")]
+    CursorTooOld { earliest_version: i64 },
+    #[error("The client fell behind by {0} rows, resume from the last row received")]
+    Lagged(u64),
+}
+
+/// Recent rows, and the channel of the new ones.
+pub struct StreamHub {
+    max_buffered_events: usize,
+    state: Mutex<StreamHubState>,
+}
+
+struct StreamHubState {
+    buffer: VecDeque<Arc<StreamEvent>>,
+    // The first version clients can resume from, once a batch was published
+    earliest_version: Option<i64>,
+    sender: broadcast::Sender<Arc<StreamEvent>>,
+}
+
+/// The rows of a client: the buffered ones it asked for, then the new ones.
+pub struct StreamSubscription {
+    backlog: VecDeque<Arc<StreamEvent>>,
+    receiver: broadcast::Receiver<Arc<StreamEvent>>,
+    cursor: StreamCursor,
+}
+
+impl StreamSubscription {
+    /// The next row after the cursor, or `None` once the hub is gone.
+    pub async fn next(&mut self) -> Result<Option<Arc<StreamEvent>>, StreamError> {
+        if let Some(event) = self.backlog.pop_front() {
+            return Ok(Some(event));
+        }
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.cursor.includes(&event) => return Ok(Some(event)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(num_missed)) => return Err(StreamError::Lagged(num_missed)),
+                Err(RecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+}
+
+impl StreamHub {
+    pub fn new(config: &StreamConfig) -> Self {
+        let (sender, _) = broadcast::channel(config.subscriber_buffer_size.max(1));
+        Self {
+            max_buffered_events: config.max_buffered_events,
+            state: Mutex::new(StreamHubState {
+                buffer: VecDeque::new(),
+                earliest_version: None,
+                sender,
+            }),
+        }
+    }
+
+    /// Publishes the rows of a batch starting at `start_version`, after the batches before it.
+    pub fn publish(&self, start_version: i64, events: Vec<StreamEvent>) {
+        let mut state = self.state.lock().unwrap();
+        state.earliest_version.get_or_insert(start_version);
+        for event in events {
+            let event = Arc::new(event);
+            // Fails only if nobody is subscribed
+            let _ = state.sender.send(event.clone());
+            state.buffer.push_back(event);
+        }
+        while state.buffer.len() > self.max_buffered_events {
+            let evicted = state.buffer.pop_front().expect("buffer is not empty");
+            // Rows of the same version are evicted together, so a version is either complete or gone
+            while state
+                .buffer
+                .front()
+                .is_some_and(|event| event.transaction_version == evicted.transaction_version)
+            {
+                state.buffer.pop_front();
+            }
+            state.earliest_version = Some(evicted.transaction_version + 1);
+        }
+    }
+
+    /// Subscribes from `cursor`. Rows published before the hub started are never buffered.
+    pub fn subscribe(&self, cursor: StreamCursor) -> Result<StreamSubscription, StreamError> {
+        let state = self.state.lock().unwrap();
+        if let (Some(version), Some(earliest_version)) =
+            (cursor.first_version(), state.earliest_version)
+        {
+            if version < earliest_version {
+                return Err(StreamError::CursorTooOld { earliest_version });
+            }
+        }
+        // Subscribing with the lock held, so that no row is missed or sent twice
+        let backlog = match cursor {
+            StreamCursor::Live => VecDeque::new(),
+            _ => state
+                .buffer
+                .iter()
+                .filter(|event| cursor.includes(event))
+                .cloned()
+                .collect(),
+        };
+        Ok(StreamSubscription {
+            backlog,
+            receiver: state.sender.subscribe(),
+            cursor,
+        })
+    }
+}
+
+static STREAM_HUB: Lazy<Mutex<Option<Arc<StreamHub>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Turns streaming on, or returns the hub if it's already on.
+pub fn enable_stream_hub(config: &StreamConfig) -> Arc<StreamHub> {
+    STREAM_HUB
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(StreamHub::new(config)))
+        .clone()
+}
+
+pub fn stream_hub() -> Option<Arc<StreamHub>> {
+    STREAM_HUB.lock().unwrap().clone()
+}
+
+/// Query parameters of the stream endpoints. Lists are comma-separated.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamParams {
+    pub event_types: Option<String>,
+    pub module_addresses: Option<String>,
+    pub from_version: Option<i64>,
+}
+
+impl StreamParams {
+    fn filter(&self) -> StreamFilter {
+        let split = |list: &Option<String>| {
+            list.iter()
+                .flat_map(|list| list.split(','))
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect::<HashSet<_>>()
+        };
+        StreamFilter {
+            event_types: split(&self.event_types),
+            module_addresses: split(&self.module_addresses)
+                .into_iter()
+                .map(|address| address.to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// `Last-Event-ID`, sent by SSE clients when they reconnect, wins over `from_version`.
+    fn cursor(&self, last_event_id: Option<&str>) -> StreamCursor {
+        let last_event = last_event_id.and_then(|id| {
+            let (version, event_index) = id.split_once(':')?;
+            Some((version.parse().ok()?, event_index.parse().ok()?))
+        });
+        match (last_event, self.from_version) {
+            (Some((version, event_index)), _) => StreamCursor::After(version, event_index),
+            (None, Some(version)) => StreamCursor::FromVersion(version),
+            (None, None) => StreamCursor::Live,
+        }
+    }
+}
+
+fn subscribe(cursor: StreamCursor) -> Result<StreamSubscription, Response> {
+    let Some(hub) = stream_hub() else {
+        return Err((StatusCode::NOT_FOUND, "Streaming is not enabled").into_response());
+    };
+    hub.subscribe(cursor).map_err(|e| {
+        let earliest_version = match e {
+            StreamError::CursorTooOld { earliest_version } => Some(earliest_version),
+            StreamError::Lagged(_) => None,
+        };
+        (
+            StatusCode::GONE,
+            Json(serde_json::json!({
+                "error": e.to_string(),
+                "earliest_version": earliest_version,
+            })),
+        )
+            .into_response()
+    })
+}
+
+/// Routes of the stream endpoints, served by the health server.
+pub fn stream_router() -> Router {
+    Router::new()
+        .route("/stream/ws", get(websocket_handler))
+        .route("/stream/sse", get(sse_handler))
+}
+
+async fn websocket_handler(
+    websocket: WebSocketUpgrade,
+    Query(params): Query<StreamParams>,
+) -> Response {
+    let subscription = match subscribe(params.cursor(None)) {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    let filter = params.filter();
+    websocket.on_upgrade(move |socket| serve_websocket(socket, subscription, filter))
+}
+
+async fn serve_websocket(
+    mut socket: WebSocket,
+    mut subscription: StreamSubscription,
+    filter: StreamFilter,
+) {
+    loop {
+        tokio::select! {
+            event = subscription.next() => match event {
+                Ok(Some(event)) if filter.matches(&event) => {
+                    let text = serde_json::to_string(&*event).expect("rows serialize to JSON");
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => return,
+                Err(e) => {
+                    let error = serde_json::json!({ "error": e.to_string() }).to_string();
+                    let _ = socket.send(Message::Text(error)).await;
+                    return;
+                },
+            },
+            message = socket.recv() => {
+                if matches!(message, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    return;
+                }
+            },
+        }
+    }
+}
+
+async fn sse_handler(headers: HeaderMap, Query(params): Query<StreamParams>) -> Response {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok());
+    let subscription = match subscribe(params.cursor(last_event_id)) {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    let events =
+        futures::stream::unfold(Some((subscription, params.filter())), |state| async move {
+            let (mut subscription, filter) = state?;
+            loop {
+                match subscription.next().await {
+                    Ok(Some(event)) if filter.matches(&event) => {
+                        let data = serde_json::to_string(&*event).expect("rows serialize to JSON");
+                        let sse_event = Event::default()
+                            .id(format!(
+                                "{}:{}",
+                                event.transaction_version, event.event_index
+                            ))
+                            .event(&event.event_type)
+                            .data(data);
+                        return Some((
+                            Ok::<_, Infallible>(sse_event),
+                            Some((subscription, filter)),
+                        ));
+                    },
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return None,
+                    // The client reconnects with `Last-Event-ID`, and resumes where it stopped
+                    Err(e) => {
+                        let sse_event = Event::default().event("error").data(e.to_string());
+                        return Some((Ok(sse_event), None));
+                    },
+                }
+            }
+        });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(transaction_version: i64, event_index: i64, event_type: &str) -> StreamEvent {
+        StreamEvent {
+            transaction_version,
+            event_index,
+            event_type: event_type.to_string(),
+            module_address: Some("0xCAFE".to_string()),
+            data: serde_json::Value::Null,
+        }
+    }
+
+    fn keys(subscription: &StreamSubscription) -> Vec<(i64, i64)> {
+        subscription
+            .backlog
+            .iter()
+            .map(|event| event.key())
+            .collect()
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_stream_hub() {
+        let hub = StreamHub::new(&StreamConfig {
+            max_buffered_events: 3,
+            subscriber_buffer_size: StreamConfig::default_subscriber_buffer_size(),
+        });
+        let mut live = hub.subscribe(StreamCursor::Live).unwrap();
+        hub.publish(10, vec![event(10, 0, "buy"), event(10, 1, "buy")]);
+        hub.publish(11, vec![event(11, 0, "buy"), event(12, 0, "winner")]);
+        assert_eq!(live.next().await.unwrap().unwrap().key(), (10, 0));
+
+        // Version 10 was evicted as a whole
+        assert!(matches!(
+            hub.subscribe(StreamCursor::FromVersion(10)),
+            Err(StreamError::CursorTooOld {
+                earliest_version: 11
+            })
+        ));
+        let resumed = hub.subscribe(StreamCursor::FromVersion(11)).unwrap();
+        assert_eq!(keys(&resumed), vec![(11, 0), (12, 0)]);
+        let mut resumed = hub.subscribe(StreamCursor::After(11, 0)).unwrap();
+        assert_eq!(keys(&resumed), vec![(12, 0)]);
+        hub.publish(13, vec![event(13, 0, "buy")]);
+        assert_eq!(resumed.next().await.unwrap().unwrap().key(), (12, 0));
+        assert_eq!(resumed.next().await.unwrap().unwrap().key(), (13, 0));
+
+        let params = StreamParams {
+            event_types: Some("winner, refund".to_string()),
+            module_addresses: Some("0xcafe".to_string()),
+            from_version: Some(11),
+        };
+        assert_eq!(params.cursor(Some("12:3")), StreamCursor::After(12, 3));
+        assert_eq!(params.cursor(None), StreamCursor::FromVersion(11));
+        let filter = params.filter();
+        assert!(filter.matches(&event(12, 0, "winner")));
+        assert!(!filter.matches(&event(12, 0, "buy")));
+    }
+}
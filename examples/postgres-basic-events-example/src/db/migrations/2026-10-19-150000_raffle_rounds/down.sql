@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS raffle_events_round_index;
+DROP INDEX IF EXISTS buy_events_round_index;
+DROP TABLE IF EXISTS raffle_rounds;
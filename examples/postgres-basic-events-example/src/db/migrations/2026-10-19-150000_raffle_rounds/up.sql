@@ -0,0 +1,129 @@
+-- Current state of each raffle round, updated from buy_events and raffle_events
+CREATE TABLE raffle_rounds (
+    module_address VARCHAR(255) NOT NULL,
+    coin_type TEXT NOT NULL,
+    sequence BIGINT NOT NULL,
+    tickets_sold BIGINT NOT NULL,
+    apt_collected BIGINT NOT NULL,
+    buyers_count BIGINT NOT NULL,
+    winner TEXT,
+    payout_apt BIGINT,
+    payout_token BIGINT,
+    -- 'open' until the RaffleEvent of the round, then 'closed'
+    status VARCHAR(10) NOT NULL,
+    opened_at BIGINT,
+    closed_at BIGINT,
+    first_transaction_version BIGINT NOT NULL,
+    closed_at_version BIGINT,
+    last_transaction_version BIGINT NOT NULL,
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (module_address, coin_type, sequence)
+);
+CREATE INDEX raffle_rounds_status_index ON raffle_rounds (status);
+-- Rounds are refreshed from the events of the round
+CREATE INDEX buy_events_round_index ON buy_events (coin_type, sequence);
+CREATE INDEX raffle_events_round_index ON raffle_events (coin_type, sequence);
+-- Seeds the rounds of the events stored before this migration, as the processor adds them:
+-- buys add their tickets, priced with raffle_games, until the first RaffleEvent of the round
+-- sets the final count. raffle_games was created by hand until it was indexed; no events were
+-- indexed without it, so there is nothing to seed if it doesn't exist yet.
+DO $$
+BEGIN
+    IF to_regclass('raffle_games') IS NULL THEN
+        RETURN;
+    END IF;
+    WITH buys AS (
+        SELECT
+            '0x' || lpad(lower(regexp_replace(split_part(b.type, '::', 1), '^0x', '')), 64, '0')
+                AS module_address,
+            b.coin_type,
+            b.sequence,
+            b.buyer,
+            b.amount_apt,
+            b.timestamp,
+            b.transaction_version
+        FROM buy_events b
+    ), priced_buys AS (
+        SELECT
+            n.*,
+            COALESCE(
+                (
+                    SELECT FLOOR(n.amount_apt / NULLIF(g.price_per_ticket::numeric, 0))::bigint
+                    FROM raffle_games g
+                    WHERE '0x' || lpad(lower(regexp_replace(g.module_address, '^0x', '')), 64, '0')
+                        = n.module_address
+                        AND g.price_per_ticket ~ '^[0-9]+$'
+                    LIMIT 1
+                ),
+                0
+            ) AS tickets
+        FROM buys n
+    ), buy_rounds AS (
+        SELECT
+            module_address,
+            coin_type,
+            sequence,
+            SUM(tickets)::bigint AS tickets_sold,
+            SUM(amount_apt)::bigint AS apt_collected,
+            COUNT(DISTINCT buyer) AS buyers_count,
+            MIN(timestamp) AS opened_at,
+            MIN(transaction_version) AS first_transaction_version,
+            MAX(transaction_version) AS last_transaction_version
+        FROM priced_buys
+        GROUP BY 1, 2, 3
+    ), draws AS (
+        SELECT DISTINCT ON (module_address, coin_type, sequence) *
+        FROM (
+            SELECT
+                '0x' || lpad(lower(regexp_replace(split_part(r.type, '::', 1), '^0x', '')), 64, '0')
+                    AS module_address,
+                r.coin_type,
+                r.sequence,
+                r.winner,
+                r.total_tickets,
+                r.amount_apt,
+                r.amount_token,
+                r.timestamp,
+                r.transaction_version,
+                r.event_index
+            FROM raffle_events r
+        ) d
+        ORDER BY module_address, coin_type, sequence, transaction_version, event_index
+    )
+    INSERT INTO raffle_rounds (
+        module_address,
+        coin_type,
+        sequence,
+        tickets_sold,
+        apt_collected,
+        buyers_count,
+        winner,
+        payout_apt,
+        payout_token,
+        status,
+        opened_at,
+        closed_at,
+        first_transaction_version,
+        closed_at_version,
+        last_transaction_version
+    )
+    SELECT
+        module_address,
+        coin_type,
+        sequence,
+        COALESCE(d.total_tickets, b.tickets_sold),
+        COALESCE(b.apt_collected, 0),
+        COALESCE(b.buyers_count, 0),
+        d.winner,
+        d.amount_apt,
+        d.amount_token,
+        CASE WHEN d.transaction_version IS NULL THEN 'open' ELSE 'closed' END,
+        b.opened_at,
+        d.timestamp,
+        LEAST(b.first_transaction_version, d.transaction_version),
+        d.transaction_version,
+        GREATEST(b.last_transaction_version, d.transaction_version)
+    FROM buy_rounds b
+    FULL JOIN draws d USING (module_address, coin_type, sequence);
+END $$;
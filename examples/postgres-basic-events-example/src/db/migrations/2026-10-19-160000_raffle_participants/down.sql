@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS buy_events_buyer_index;
+DROP TABLE IF EXISTS raffle_participants;
@@ -0,0 +1,126 @@
+-- Tickets, spending and winnings of each buyer in each raffle round
+CREATE TABLE raffle_participants (
+    module_address VARCHAR(255) NOT NULL,
+    coin_type TEXT NOT NULL,
+    sequence BIGINT NOT NULL,
+    buyer TEXT NOT NULL,
+    tickets BIGINT NOT NULL,
+    apt_spent BIGINT NOT NULL,
+    buy_count BIGINT NOT NULL,
+    is_winner BOOLEAN NOT NULL,
+    payout_apt BIGINT,
+    payout_token BIGINT,
+    first_transaction_version BIGINT NOT NULL,
+    last_transaction_version BIGINT NOT NULL,
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (module_address, coin_type, sequence, buyer)
+);
+-- Lifetime tickets and winnings per address
+CREATE INDEX raffle_participants_buyer_index ON raffle_participants (buyer);
+CREATE INDEX buy_events_buyer_index ON buy_events (coin_type, sequence, buyer);
+-- Seeds the participants of the events stored before this migration, as the processor adds
+-- them: buys add their tickets, priced with raffle_games, unless they came after the RaffleEvent
+-- of the round, and the first RaffleEvent of a round links its winner. Like for raffle_rounds,
+-- there is nothing to seed if raffle_games doesn't exist yet.
+DO $$
+BEGIN
+    IF to_regclass('raffle_games') IS NULL THEN
+        RETURN;
+    END IF;
+    WITH buys AS (
+        SELECT
+            '0x' || lpad(lower(regexp_replace(split_part(b.type, '::', 1), '^0x', '')), 64, '0')
+                AS module_address,
+            b.coin_type,
+            b.sequence,
+            b.buyer,
+            b.amount_apt,
+            b.transaction_version
+        FROM buy_events b
+    ), priced_buys AS (
+        SELECT
+            n.*,
+            CASE
+                WHEN EXISTS (
+                    SELECT 1
+                    FROM raffle_rounds r
+                    WHERE r.module_address = n.module_address
+                        AND r.coin_type = n.coin_type
+                        AND r.sequence = n.sequence
+                        AND r.closed_at_version < n.transaction_version
+                ) THEN 0
+                ELSE COALESCE(
+                    (
+                        SELECT FLOOR(n.amount_apt / NULLIF(g.price_per_ticket::numeric, 0))::bigint
+                        FROM raffle_games g
+                        WHERE '0x' || lpad(
+                            lower(regexp_replace(g.module_address, '^0x', '')), 64, '0'
+                        ) = n.module_address
+                            AND g.price_per_ticket ~ '^[0-9]+$'
+                        LIMIT 1
+                    ),
+                    0
+                )
+            END AS tickets
+        FROM buys n
+    ), buyers AS (
+        SELECT
+            module_address,
+            coin_type,
+            sequence,
+            buyer,
+            SUM(tickets)::bigint AS tickets,
+            SUM(amount_apt)::bigint AS apt_spent,
+            COUNT(*) AS buy_count,
+            MIN(transaction_version) AS first_transaction_version,
+            MAX(transaction_version) AS last_transaction_version
+        FROM priced_buys
+        GROUP BY 1, 2, 3, 4
+    ), wins AS (
+        SELECT DISTINCT ON (module_address, coin_type, sequence) *
+        FROM (
+            SELECT
+                '0x' || lpad(lower(regexp_replace(split_part(r.type, '::', 1), '^0x', '')), 64, '0')
+                    AS module_address,
+                r.coin_type,
+                r.sequence,
+                r.winner AS buyer,
+                r.amount_apt,
+                r.amount_token,
+                r.transaction_version,
+                r.event_index
+            FROM raffle_events r
+        ) w
+        ORDER BY module_address, coin_type, sequence, transaction_version, event_index
+    )
+    INSERT INTO raffle_participants (
+        module_address,
+        coin_type,
+        sequence,
+        buyer,
+        tickets,
+        apt_spent,
+        buy_count,
+        is_winner,
+        payout_apt,
+        payout_token,
+        first_transaction_version,
+        last_transaction_version
+    )
+    SELECT
+        module_address,
+        coin_type,
+        sequence,
+        buyer,
+        COALESCE(b.tickets, 0),
+        COALESCE(b.apt_spent, 0),
+        COALESCE(b.buy_count, 0),
+        w.transaction_version IS NOT NULL,
+        w.amount_apt,
+        w.amount_token,
+        LEAST(b.first_transaction_version, w.transaction_version),
+        GREATEST(b.last_transaction_version, w.transaction_version)
+    FROM buyers b
+    FULL JOIN wins w USING (module_address, coin_type, sequence, buyer);
+END $$;
@@ -0,0 +1,3 @@
+-- The table may predate the indexer, so only the indexes are dropped
+DROP INDEX IF EXISTS raffle_games_fa_metadata_address_index;
+DROP INDEX IF EXISTS raffle_games_module_address_index;
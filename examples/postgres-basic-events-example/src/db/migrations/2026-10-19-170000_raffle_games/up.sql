@@ -0,0 +1,36 @@
+-- raffle_games used to be created and filled outside the indexer. It is now indexed from chain,
+-- one row per fa_raffle module.
+CREATE TABLE IF NOT EXISTS raffle_games (
+    id SERIAL PRIMARY KEY,
+    module_address VARCHAR(255) NOT NULL,
+    fa_metadata_address VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    symbol VARCHAR(50) NOT NULL,
+    description TEXT,
+    logo_uri VARCHAR(500),
+    metadata_uri VARCHAR(500),
+    price_per_ticket VARCHAR(100) NOT NULL,
+    tokens_per_raffle VARCHAR(100) NOT NULL,
+    circulating_supply VARCHAR(100) NOT NULL,
+    raffle_frequency INTEGER NOT NULL,
+    frequency_unit VARCHAR(20) NOT NULL,
+    start_timestamp VARCHAR(100) NOT NULL,
+    end_timestamp VARCHAR(100) NOT NULL,
+    creator_address VARCHAR(255) NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+ALTER TABLE raffle_games ALTER COLUMN created_at SET DEFAULT NOW();
+ALTER TABLE raffle_games ALTER COLUMN updated_at SET DEFAULT NOW();
+-- Rows added by hand may have short or upper case addresses. Store them in the long form like
+-- the indexed rows, then keep one row per module, preferring active and then newer rows.
+UPDATE raffle_games
+SET module_address = '0x' || lpad(lower(regexp_replace(module_address, '^0x', '')), 64, '0');
+DELETE FROM raffle_games g
+USING raffle_games kept
+WHERE kept.module_address = g.module_address
+    AND (COALESCE(kept.is_active, FALSE), kept.id) > (COALESCE(g.is_active, FALSE), g.id);
+-- Deployments are upserted by module
+CREATE UNIQUE INDEX IF NOT EXISTS raffle_games_module_address_index ON raffle_games (module_address);
+CREATE INDEX IF NOT EXISTS raffle_games_fa_metadata_address_index ON raffle_games (fa_metadata_address);
@@ -83,4 +83,54 @@ diesel::table! {
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(buy_events, raffle_events, raffle_games,);
+diesel::table! {
+    raffle_participants (module_address, coin_type, sequence, buyer) {
+        #[max_length = 255]
+        module_address -> Varchar,
+        coin_type -> Text,
+        sequence -> Int8,
+        buyer -> Text,
+        tickets -> Int8,
+        apt_spent -> Int8,
+        buy_count -> Int8,
+        is_winner -> Bool,
+        payout_apt -> Nullable<Int8>,
+        payout_token -> Nullable<Int8>,
+        first_transaction_version -> Int8,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    raffle_rounds (module_address, coin_type, sequence) {
+        #[max_length = 255]
+        module_address -> Varchar,
+        coin_type -> Text,
+        sequence -> Int8,
+        tickets_sold -> Int8,
+        apt_collected -> Int8,
+        buyers_count -> Int8,
+        winner -> Nullable<Text>,
+        payout_apt -> Nullable<Int8>,
+        payout_token -> Nullable<Int8>,
+        #[max_length = 10]
+        status -> Varchar,
+        opened_at -> Nullable<Int8>,
+        closed_at -> Nullable<Int8>,
+        first_transaction_version -> Int8,
+        closed_at_version -> Nullable<Int8>,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    buy_events,
+    raffle_events,
+    raffle_games,
+    raffle_participants,
+    raffle_rounds,
+);
@@ -6,12 +6,14 @@
 use crate::schema::raffle_events;
 use aptos_indexer_processor_sdk::{
     aptos_protos::transaction::v1::Event as EventPB,
+    postgres::utils::database::{MyDbConnection, MAX_DIESEL_PARAM_SIZE},
     utils::convert::{standardize_address, truncate_str},
 };
-use diesel::{Identifiable, Insertable};
+use diesel::{Identifiable, Insertable, QueryResult};
+use diesel_async::RunQueryDsl;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 use parking_lot::RwLock;
 
 
@@ -23,16 +25,27 @@ lazy_static::lazy_static! {
     static ref ACTIVE_MODULES: Arc<RwLock<Vec<String>>> = Arc::new(RwLock::new(Vec::new()));
 }
 
-/// Update the list of active modules
+/// Update the list of active modules, in the long form of their address
 pub fn update_active_modules(modules: Vec<String>) {
     let mut active = ACTIVE_MODULES.write();
-    *active = modules;
+    *active = modules.iter().map(|module| standardize_address(module)).collect();
     println!("✅ [RaffleEvent] Updated active modules: {} modules loaded", active.len());
     for (i, module) in active.iter().enumerate() {
         println!("   {}. {}", i + 1, module);
     }
 }
 
+/// Start or stop monitoring a module, e.g. when its raffle is initialized on chain
+pub fn set_module_active(module_address: &str, is_active: bool) {
+    let module_address = standardize_address(module_address);
+    let mut active = ACTIVE_MODULES.write();
+    active.retain(|module| *module != module_address);
+    if is_active {
+        active.push(module_address.clone());
+    }
+    println!("✅ [RaffleEvent] Module {} is {}", module_address, if is_active { "active" } else { "inactive" });
+}
+
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 /// On-chain representation of a message creation event
@@ -82,11 +95,15 @@ impl RaffleEvent {
     ) -> Option<Self> {
         let t: &str = event.type_str.as_ref();
 
-        // Check against all active modules dynamically
-        let active_modules = ACTIVE_MODULES.read();
-        let is_monitored = active_modules.iter().any(|module_addr| {
-            t.starts_with(&format!("{}::fa_raffle::RaffleEvent", module_addr))
-        });
+        // Check against all active modules dynamically. Event types have the short form of the
+        // address, e.g. `0xcafe::fa_raffle::RaffleEvent`, and modules the long form.
+        let is_monitored = match t.split_once("::") {
+            Some((address, event_type)) if event_type.starts_with("fa_raffle::RaffleEvent") => {
+                let address = standardize_address(address);
+                ACTIVE_MODULES.read().contains(&address)
+            },
+            _ => false,
+        };
 
         if is_monitored {
             let data: RaffleEventOnChain = serde_json::from_str(event.data.as_str()).unwrap();
@@ -140,5 +157,33 @@ impl RaffleEvent {
     }
 }
 
+impl RaffleEvent {
+    /// Inserts the events that aren't stored yet in `conn`, e.g. in the transaction of the batch,
+    /// and returns them. The events of a replayed batch are already stored, so none are returned.
+    pub async fn insert_new(
+        conn: &mut MyDbConnection,
+        events: &[Self],
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::raffle_events::dsl::*;
+        let mut inserted = HashSet::new();
+        for chunk in events.chunks(MAX_DIESEL_PARAM_SIZE / Self::field_count()) {
+            inserted.extend(
+                diesel::insert_into(raffle_events)
+                    .values(chunk)
+                    .on_conflict((transaction_version, event_index))
+                    .do_nothing()
+                    .returning((transaction_version, event_index))
+                    .get_results::<(i64, i64)>(conn)
+                    .await?,
+            );
+        }
+        Ok(events
+            .iter()
+            .filter(|event| inserted.contains(&(event.transaction_version, event.event_index)))
+            .cloned()
+            .collect())
+    }
+}
+
 // Prevent conflicts with other things named `Event`
 pub type RaffleEventModel = RaffleEvent;
@@ -0,0 +1,629 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::schema::raffle_games;
+use aptos_indexer_processor_sdk::{
+    aptos_protos::transaction::v1::{
+        transaction::TxnData, write_set_change::Change, Event as EventPB, Transaction,
+    },
+    postgres::utils::database::{execute_with_better_error_conn, MyDbConnection},
+    utils::convert::standardize_address,
+};
+use diesel::{dsl::now, upsert::excluded, ExpressionMethods, Insertable, QueryResult};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const RAFFLE_MODULE_NAME: &str = "fa_raffle";
+/// Resource published at the module address by the initialization of a raffle. Its fields
+/// mirror the columns of `raffle_games`.
+const RAFFLE_CONFIG_STRUCT: &str = "RaffleConfig";
+const PUBLISH_PACKAGE_EVENT_TYPE: &str = "0x1::code::PublishPackage";
+const FA_METADATA_TYPE: &str = "0x1::fungible_asset::Metadata";
+const FA_SUPPLY_TYPE: &str = "0x1::fungible_asset::Supply";
+const FA_CONCURRENT_SUPPLY_TYPE: &str = "0x1::fungible_asset::ConcurrentSupply";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PublishPackageOnChain {
+    pub code_address: String,
+    pub is_upgrade: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FaMetadata {
+    pub inner: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// On-chain representation of the configuration of a raffle
+pub struct RaffleConfigOnChain {
+    pub fa_metadata: FaMetadata,
+    pub price_per_ticket: String,
+    pub tokens_per_raffle: String,
+    pub raffle_frequency: String,
+    #[serde(default)]
+    pub frequency_unit: Option<String>,
+    pub start_timestamp: String,
+    pub end_timestamp: String,
+    pub creator: String,
+    // Raffles without the flag are active while the resource exists
+    #[serde(default)]
+    pub is_active: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// On-chain representation of the metadata of a fungible asset
+pub struct FaMetadataOnChain {
+    pub name: String,
+    pub symbol: String,
+    pub icon_uri: String,
+    pub project_uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FaSupplyOnChain {
+    pub current: serde_json::Value,
+}
+
+impl FaSupplyOnChain {
+    /// `Supply` holds the supply as a number, `ConcurrentSupply` as an aggregator.
+    fn circulating_supply(&self) -> Option<String> {
+        let current = match &self.current {
+            serde_json::Value::Object(aggregator) => aggregator.get("value")?,
+            current => current,
+        };
+        match current {
+            serde_json::Value::String(supply) => Some(supply.clone()),
+            serde_json::Value::Number(supply) => Some(supply.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A change to `raffle_games`, in the order it happened on chain.
+#[derive(Clone, Debug)]
+pub enum RaffleGameChange {
+    /// An `fa_raffle` module was published. Its raffle is inactive until initialized.
+    Published {
+        module_address: String,
+        creator_address: String,
+    },
+    /// The `RaffleConfig` of the module was written.
+    Configured {
+        module_address: String,
+        config: RaffleConfigOnChain,
+    },
+    /// The `RaffleConfig` of the module was deleted.
+    Removed { module_address: String },
+    /// The metadata of the fungible asset of a raffle was written.
+    FaMetadataWritten {
+        fa_metadata_address: String,
+        metadata: FaMetadataOnChain,
+    },
+    /// The supply of the fungible asset of a raffle was written.
+    SupplyWritten {
+        fa_metadata_address: String,
+        circulating_supply: String,
+    },
+}
+
+/// The module address of a type in a raffle module, e.g. `0xcafe` for
+/// `0xcafe::fa_raffle::RaffleConfig`.
+fn raffle_module_address(type_str: &str) -> Option<&str> {
+    let mut parts = type_str.splitn(3, "::");
+    let address = parts.next()?;
+    (parts.next()? == RAFFLE_MODULE_NAME).then_some(address)
+}
+
+fn is_raffle_config(type_str: &str) -> bool {
+    raffle_module_address(type_str).is_some()
+        && type_str.splitn(3, "::").nth(2) == Some(RAFFLE_CONFIG_STRUCT)
+}
+
+fn from_json<T: for<'de> Deserialize<'de>>(
+    data: &str,
+    type_str: &str,
+    transaction_version: i64,
+) -> Option<T> {
+    serde_json::from_str(data)
+        .map_err(|e| {
+            warn!(
+                transaction_version = transaction_version,
+                type_str = type_str,
+                "Could not parse resource: {:?}",
+                e
+            );
+        })
+        .ok()
+}
+
+impl RaffleGameChange {
+    /// Detects the publication and initialization of raffles in a transaction, from the
+    /// `PublishPackage` events, the modules and the resources written. The fungible assets
+    /// are only read in transactions touching a raffle module, e.g. its initialization.
+    pub fn from_transaction(txn: &Transaction) -> Vec<Self> {
+        let transaction_version = txn.version as i64;
+        let (sender, events) = match txn.txn_data.as_ref() {
+            Some(TxnData::User(tx_inner)) => (
+                tx_inner
+                    .request
+                    .as_ref()
+                    .map(|request| standardize_address(&request.sender)),
+                &tx_inner.events,
+            ),
+            _ => return vec![],
+        };
+        let Some(changes) = txn.info.as_ref().map(|info| &info.changes) else {
+            return vec![];
+        };
+
+        let published_addresses = Self::published_addresses(events);
+        let touches_raffle = events
+            .iter()
+            .any(|event| raffle_module_address(&event.type_str).is_some())
+            || changes.iter().any(|change| match change.change.as_ref() {
+                Some(Change::WriteResource(resource)) => {
+                    raffle_module_address(&resource.type_str).is_some()
+                },
+                Some(Change::DeleteResource(resource)) => {
+                    raffle_module_address(&resource.type_str).is_some()
+                },
+                _ => false,
+            });
+
+        let mut raffle_changes = vec![];
+        let mut fa_changes = vec![];
+        for change in changes.iter().filter_map(|change| change.change.as_ref()) {
+            match change {
+                Change::WriteModule(module) => {
+                    let module_address = standardize_address(&module.address);
+                    let is_raffle_module = module
+                        .data
+                        .as_ref()
+                        .and_then(|data| data.abi.as_ref())
+                        .is_some_and(|abi| abi.name == RAFFLE_MODULE_NAME);
+                    if is_raffle_module && published_addresses.contains(&module_address) {
+                        raffle_changes.push(RaffleGameChange::Published {
+                            module_address,
+                            creator_address: sender.clone().unwrap_or_default(),
+                        });
+                    }
+                },
+                Change::WriteResource(resource) if is_raffle_config(&resource.type_str) => {
+                    if let Some(config) =
+                        from_json(&resource.data, &resource.type_str, transaction_version)
+                    {
+                        raffle_changes.push(RaffleGameChange::Configured {
+                            module_address: standardize_address(
+                                raffle_module_address(&resource.type_str).unwrap(),
+                            ),
+                            config,
+                        });
+                    }
+                },
+                Change::DeleteResource(resource) if is_raffle_config(&resource.type_str) => {
+                    raffle_changes.push(RaffleGameChange::Removed {
+                        module_address: standardize_address(
+                            raffle_module_address(&resource.type_str).unwrap(),
+                        ),
+                    });
+                },
+                Change::WriteResource(resource)
+                    if resource.type_str == FA_METADATA_TYPE && touches_raffle =>
+                {
+                    if let Some(metadata) =
+                        from_json(&resource.data, &resource.type_str, transaction_version)
+                    {
+                        fa_changes.push(RaffleGameChange::FaMetadataWritten {
+                            fa_metadata_address: standardize_address(&resource.address),
+                            metadata,
+                        });
+                    }
+                },
+                Change::WriteResource(resource)
+                    if (resource.type_str == FA_SUPPLY_TYPE
+                        || resource.type_str == FA_CONCURRENT_SUPPLY_TYPE)
+                        && touches_raffle =>
+                {
+                    let supply = from_json::<FaSupplyOnChain>(
+                        &resource.data,
+                        &resource.type_str,
+                        transaction_version,
+                    );
+                    if let Some(circulating_supply) =
+                        supply.and_then(|supply| supply.circulating_supply())
+                    {
+                        fa_changes.push(RaffleGameChange::SupplyWritten {
+                            fa_metadata_address: standardize_address(&resource.address),
+                            circulating_supply,
+                        });
+                    }
+                },
+                _ => {},
+            }
+        }
+        // The raffle has to exist before its fungible asset is updated
+        raffle_changes.extend(fa_changes);
+        raffle_changes
+    }
+
+    /// Addresses of the packages published, not upgraded, by the transaction.
+    fn published_addresses(events: &[EventPB]) -> Vec<String> {
+        events
+            .iter()
+            .filter(|event| event.type_str == PUBLISH_PACKAGE_EVENT_TYPE)
+            .filter_map(|event| serde_json::from_str::<PublishPackageOnChain>(&event.data).ok())
+            .filter(|publish| !publish.is_upgrade)
+            .map(|publish| standardize_address(&publish.code_address))
+            .collect()
+    }
+
+    /// The module and whether its raffle is active after the change, if it changes.
+    pub fn activity(&self) -> Option<(&str, bool)> {
+        match self {
+            RaffleGameChange::Configured {
+                module_address,
+                config,
+            } => Some((module_address, config.is_active.unwrap_or(true))),
+            RaffleGameChange::Removed { module_address } => Some((module_address, false)),
+            _ => None,
+        }
+    }
+
+    pub fn from_transactions(transactions: &[Transaction]) -> Vec<Self> {
+        transactions
+            .iter()
+            .flat_map(Self::from_transaction)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = raffle_games)]
+pub struct NewRaffleGame {
+    pub module_address: String,
+    pub fa_metadata_address: String,
+    pub name: String,
+    pub symbol: String,
+    pub price_per_ticket: String,
+    pub tokens_per_raffle: String,
+    pub circulating_supply: String,
+    pub raffle_frequency: i32,
+    pub frequency_unit: String,
+    pub start_timestamp: String,
+    pub end_timestamp: String,
+    pub creator_address: String,
+    pub is_active: bool,
+}
+
+impl NewRaffleGame {
+    /// A raffle that was published but not initialized yet.
+    fn published(module_address: &str, creator_address: &str) -> Self {
+        Self {
+            module_address: module_address.to_string(),
+            fa_metadata_address: String::new(),
+            name: String::new(),
+            symbol: String::new(),
+            price_per_ticket: "0".to_string(),
+            tokens_per_raffle: "0".to_string(),
+            circulating_supply: "0".to_string(),
+            raffle_frequency: 0,
+            frequency_unit: String::new(),
+            start_timestamp: String::new(),
+            end_timestamp: String::new(),
+            creator_address: creator_address.to_string(),
+            is_active: false,
+        }
+    }
+
+    fn configured(module_address: &str, config: &RaffleConfigOnChain) -> Self {
+        Self {
+            fa_metadata_address: standardize_address(&config.fa_metadata.inner),
+            price_per_ticket: config.price_per_ticket.clone(),
+            tokens_per_raffle: config.tokens_per_raffle.clone(),
+            raffle_frequency: config.raffle_frequency.parse().unwrap_or_default(),
+            frequency_unit: config.frequency_unit.clone().unwrap_or_default(),
+            start_timestamp: config.start_timestamp.clone(),
+            end_timestamp: config.end_timestamp.clone(),
+            is_active: config.is_active.unwrap_or(true),
+            ..Self::published(module_address, &standardize_address(&config.creator))
+        }
+    }
+}
+
+async fn write_raffle_game_change(
+    conn: &mut MyDbConnection,
+    change: &RaffleGameChange,
+) -> QueryResult<usize> {
+    use crate::schema::raffle_games::dsl::*;
+    match change {
+        RaffleGameChange::Published {
+            module_address: published_address,
+            creator_address: creator,
+        } => {
+            execute_with_better_error_conn(
+                conn,
+                diesel::insert_into(raffle_games)
+                    .values(NewRaffleGame::published(published_address, creator))
+                    .on_conflict(module_address)
+                    .do_nothing(),
+            )
+            .await
+        },
+        RaffleGameChange::Configured {
+            module_address: configured_address,
+            config,
+        } => {
+            execute_with_better_error_conn(
+                conn,
+                diesel::insert_into(raffle_games)
+                    .values(NewRaffleGame::configured(configured_address, config))
+                    .on_conflict(module_address)
+                    .do_update()
+                    .set((
+                        fa_metadata_address.eq(excluded(fa_metadata_address)),
+                        price_per_ticket.eq(excluded(price_per_ticket)),
+                        tokens_per_raffle.eq(excluded(tokens_per_raffle)),
+                        raffle_frequency.eq(excluded(raffle_frequency)),
+                        frequency_unit.eq(excluded(frequency_unit)),
+                        start_timestamp.eq(excluded(start_timestamp)),
+                        end_timestamp.eq(excluded(end_timestamp)),
+                        creator_address.eq(excluded(creator_address)),
+                        is_active.eq(excluded(is_active)),
+                        updated_at.eq(now),
+                    )),
+            )
+            .await
+        },
+        RaffleGameChange::Removed {
+            module_address: removed_address,
+        } => {
+            execute_with_better_error_conn(
+                conn,
+                diesel::update(raffle_games)
+                    .filter(module_address.eq(removed_address))
+                    .set((is_active.eq(false), updated_at.eq(now))),
+            )
+            .await
+        },
+        RaffleGameChange::FaMetadataWritten {
+            fa_metadata_address: written_address,
+            metadata,
+        } => {
+            execute_with_better_error_conn(
+                conn,
+                diesel::update(raffle_games)
+                    .filter(fa_metadata_address.eq(written_address))
+                    .set((
+                        name.eq(&metadata.name),
+                        symbol.eq(&metadata.symbol),
+                        logo_uri.eq(&metadata.icon_uri),
+                        metadata_uri.eq(&metadata.project_uri),
+                        updated_at.eq(now),
+                    )),
+            )
+            .await
+        },
+        RaffleGameChange::SupplyWritten {
+            fa_metadata_address: written_address,
+            circulating_supply: supply,
+        } => {
+            execute_with_better_error_conn(
+                conn,
+                diesel::update(raffle_games)
+                    .filter(fa_metadata_address.eq(written_address))
+                    .set((circulating_supply.eq(supply), updated_at.eq(now))),
+            )
+            .await
+        },
+    }
+}
+
+/// Writes the changes to `raffle_games` in order, on the connection of the transaction that
+/// stores the events of the batch. Deployments are rare, so a query per change is fine.
+pub async fn write_raffle_game_changes(
+    conn: &mut MyDbConnection,
+    changes: &[RaffleGameChange],
+) -> QueryResult<()> {
+    for change in changes {
+        write_raffle_game_change(conn, change).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_processor_sdk::aptos_protos::transaction::v1::{
+        DeleteResource, MoveModule, MoveModuleBytecode, TransactionInfo, UserTransaction,
+        UserTransactionRequest, WriteModule, WriteResource, WriteSetChange,
+    };
+
+    const RAFFLE_CONFIG: &str = r#"{
+        "fa_metadata": {"inner": "0xfa"},
+        "price_per_ticket": "100",
+        "tokens_per_raffle": "1000",
+        "raffle_frequency": "1",
+        "frequency_unit": "day",
+        "start_timestamp": "0",
+        "end_timestamp": "0",
+        "creator": "0xbeef"
+    }"#;
+
+    fn user_transaction(events: Vec<EventPB>, changes: Vec<Change>) -> Transaction {
+        Transaction {
+            version: 42,
+            txn_data: Some(TxnData::User(UserTransaction {
+                request: Some(UserTransactionRequest {
+                    sender: "0xbeef".to_string(),
+                    ..Default::default()
+                }),
+                events,
+            })),
+            info: Some(TransactionInfo {
+                changes: changes
+                    .into_iter()
+                    .map(|change| WriteSetChange {
+                        change: Some(change),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn publish_package_event(code_address: &str, is_upgrade: bool) -> EventPB {
+        EventPB {
+            type_str: PUBLISH_PACKAGE_EVENT_TYPE.to_string(),
+            data: format!(
+                r#"{{"code_address": "{}", "is_upgrade": {}}}"#,
+                code_address, is_upgrade
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn write_module(address: &str, name: &str) -> Change {
+        Change::WriteModule(WriteModule {
+            address: address.to_string(),
+            data: Some(MoveModuleBytecode {
+                abi: Some(MoveModule {
+                    name: name.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn write_resource(address: &str, type_str: &str, data: &str) -> Change {
+        Change::WriteResource(WriteResource {
+            address: address.to_string(),
+            type_str: type_str.to_string(),
+            data: data.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_raffle_published_and_configured() {
+        let txn = user_transaction(
+            vec![publish_package_event("0xcafe", false)],
+            vec![
+                // Written before the module, but applied after the raffle is created
+                write_resource(
+                    "0xfa",
+                    FA_CONCURRENT_SUPPLY_TYPE,
+                    r#"{"current": {"max_value": "1000000", "value": "250"}}"#,
+                ),
+                write_module("0xcafe", RAFFLE_MODULE_NAME),
+                write_resource("0xcafe", "0xcafe::fa_raffle::RaffleConfig", RAFFLE_CONFIG),
+                write_resource(
+                    "0xfa",
+                    FA_METADATA_TYPE,
+                    r#"{"name": "Raffle", "symbol": "RFL", "icon_uri": "", "project_uri": ""}"#,
+                ),
+            ],
+        );
+        let changes = RaffleGameChange::from_transaction(&txn);
+        let module_address = standardize_address("0xcafe");
+        let fa_metadata_address = standardize_address("0xfa");
+        assert_eq!(changes.len(), 4);
+        assert!(matches!(
+            &changes[0],
+            RaffleGameChange::Published { module_address: published, creator_address }
+                if *published == module_address && *creator_address == standardize_address("0xbeef")
+        ));
+        assert!(matches!(
+            &changes[1],
+            RaffleGameChange::Configured { module_address: configured, config }
+                if *configured == module_address && config.price_per_ticket == "100"
+        ));
+        assert!(matches!(
+            &changes[2],
+            RaffleGameChange::SupplyWritten { fa_metadata_address: written, circulating_supply }
+                if *written == fa_metadata_address && circulating_supply == "250"
+        ));
+        assert!(matches!(
+            &changes[3],
+            RaffleGameChange::FaMetadataWritten { fa_metadata_address: written, metadata }
+                if *written == fa_metadata_address && metadata.symbol == "RFL"
+        ));
+        // Raffles without `is_active` are active
+        assert_eq!(changes[1].activity(), Some((module_address.as_str(), true)));
+        assert_eq!(changes[0].activity(), None);
+    }
+
+    #[test]
+    fn test_only_new_raffle_modules_are_published() {
+        // Upgrades and other modules of the package aren't new raffles
+        let upgrade = user_transaction(
+            vec![publish_package_event("0xcafe", true)],
+            vec![write_module("0xcafe", RAFFLE_MODULE_NAME)],
+        );
+        assert!(RaffleGameChange::from_transaction(&upgrade).is_empty());
+        let other_module = user_transaction(
+            vec![publish_package_event("0xcafe", false)],
+            vec![write_module("0xcafe", "coin_flip")],
+        );
+        assert!(RaffleGameChange::from_transaction(&other_module).is_empty());
+        // A module published by another package of the transaction
+        let other_package = user_transaction(
+            vec![publish_package_event("0xbeef", false)],
+            vec![write_module("0xcafe", RAFFLE_MODULE_NAME)],
+        );
+        assert!(RaffleGameChange::from_transaction(&other_package).is_empty());
+    }
+
+    #[test]
+    fn test_raffle_removed_and_unrelated_assets() {
+        let txn = user_transaction(
+            vec![],
+            vec![Change::DeleteResource(DeleteResource {
+                address: "0xcafe".to_string(),
+                type_str: "0xcafe::fa_raffle::RaffleConfig".to_string(),
+                ..Default::default()
+            })],
+        );
+        let changes = RaffleGameChange::from_transaction(&txn);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].activity(),
+            Some((standardize_address("0xcafe").as_str(), false))
+        );
+
+        // Fungible assets are only read in transactions touching a raffle
+        let txn = user_transaction(
+            vec![],
+            vec![write_resource(
+                "0xfa",
+                FA_SUPPLY_TYPE,
+                r#"{"current": "250", "maximum": {"vec": []}}"#,
+            )],
+        );
+        assert!(RaffleGameChange::from_transaction(&txn).is_empty());
+
+        // Bad resources are skipped
+        let txn = user_transaction(
+            vec![],
+            vec![write_resource(
+                "0xcafe",
+                "0xcafe::fa_raffle::RaffleConfig",
+                "{}",
+            )],
+        );
+        assert!(RaffleGameChange::from_transaction(&txn).is_empty());
+    }
+
+    #[test]
+    fn test_only_user_transactions_change_raffles() {
+        let mut txn = user_transaction(
+            vec![publish_package_event("0xcafe", false)],
+            vec![write_module("0xcafe", RAFFLE_MODULE_NAME)],
+        );
+        txn.txn_data = None;
+        assert!(RaffleGameChange::from_transaction(&txn).is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    buy_events_model::BuyEventModel,
+    raffle_events_model::RaffleEventModel,
+    raffle_rounds_model::{event_module_address, priced_buys_cte},
+};
+use aptos_indexer_processor_sdk::postgres::utils::database::{
+    execute_with_better_error_conn, MyDbConnection,
+};
+use diesel::{
+    sql_types::{Array, BigInt, Text},
+    QueryResult,
+};
+
+/// Adds the buys of the batch to their buyers. Tickets are counted as in `priced_buys_cte`, like
+/// in `raffle_rounds`, so that the tickets of the buyers of a round add up to its
+/// `tickets_sold`. Buys after the RaffleEvent of a round add no tickets.
+const ADD_BUYS_TO_RAFFLE_PARTICIPANTS_QUERY: &str = concat!(
+    r#"
+WITH new_buys AS (
+    SELECT *
+    FROM unnest($1::text[], $2::text[], $3::bigint[], $4::text[], $5::bigint[], $6::bigint[])
+        AS n (module_address, coin_type, sequence, buyer, amount_apt, transaction_version)
+),"#,
+    priced_buys_cte!(),
+    r#"
+INSERT INTO raffle_participants (
+    module_address,
+    coin_type,
+    sequence,
+    buyer,
+    tickets,
+    apt_spent,
+    buy_count,
+    is_winner,
+    first_transaction_version,
+    last_transaction_version
+)
+SELECT
+    module_address,
+    coin_type,
+    sequence,
+    buyer,
+    SUM(tickets)::bigint,
+    SUM(amount_apt)::bigint,
+    COUNT(*),
+    FALSE,
+    MIN(transaction_version),
+    MAX(transaction_version)
+FROM priced_buys
+GROUP BY 1, 2, 3, 4
+ON CONFLICT (module_address, coin_type, sequence, buyer) DO UPDATE SET
+    tickets = raffle_participants.tickets + EXCLUDED.tickets,
+    apt_spent = raffle_participants.apt_spent + EXCLUDED.apt_spent,
+    buy_count = raffle_participants.buy_count + EXCLUDED.buy_count,
+    first_transaction_version = LEAST(
+        raffle_participants.first_transaction_version,
+        EXCLUDED.first_transaction_version
+    ),
+    last_transaction_version = GREATEST(
+        raffle_participants.last_transaction_version,
+        EXCLUDED.last_transaction_version
+    ),
+    updated_at = NOW()
+"#
+);
+
+/// Links the winner of each RaffleEvent of the batch, with the payout of the round. Like for
+/// `raffle_rounds`, only the first RaffleEvent of a round has a winner.
+const ADD_WINS_TO_RAFFLE_PARTICIPANTS_QUERY: &str = r#"
+WITH wins AS (
+    SELECT DISTINCT ON (module_address, coin_type, sequence) *
+    FROM unnest(
+        $1::text[], $2::text[], $3::bigint[], $4::text[], $5::bigint[], $6::bigint[],
+        $7::bigint[], $8::bigint[]
+    ) AS w (
+        module_address, coin_type, sequence, winner, amount_apt, amount_token,
+        transaction_version, event_index
+    )
+    ORDER BY module_address, coin_type, sequence, transaction_version, event_index
+)
+INSERT INTO raffle_participants (
+    module_address,
+    coin_type,
+    sequence,
+    buyer,
+    tickets,
+    apt_spent,
+    buy_count,
+    is_winner,
+    payout_apt,
+    payout_token,
+    first_transaction_version,
+    last_transaction_version
+)
+SELECT
+    w.module_address,
+    w.coin_type,
+    w.sequence,
+    w.winner,
+    0,
+    0,
+    0,
+    TRUE,
+    w.amount_apt,
+    w.amount_token,
+    w.transaction_version,
+    w.transaction_version
+FROM wins w
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM raffle_participants p
+    WHERE p.module_address = w.module_address
+        AND p.coin_type = w.coin_type
+        AND p.sequence = w.sequence
+        AND p.is_winner
+)
+ON CONFLICT (module_address, coin_type, sequence, buyer) DO UPDATE SET
+    is_winner = TRUE,
+    payout_apt = EXCLUDED.payout_apt,
+    payout_token = EXCLUDED.payout_token,
+    first_transaction_version = LEAST(
+        raffle_participants.first_transaction_version,
+        EXCLUDED.first_transaction_version
+    ),
+    last_transaction_version = GREATEST(
+        raffle_participants.last_transaction_version,
+        EXCLUDED.last_transaction_version
+    ),
+    updated_at = NOW()
+"#;
+
+async fn add_buys_to_raffle_participants(
+    conn: &mut MyDbConnection,
+    new_buy_events: &[BuyEventModel],
+) -> QueryResult<usize> {
+    if new_buy_events.is_empty() {
+        return Ok(0);
+    }
+    execute_with_better_error_conn(
+        conn,
+        diesel::sql_query(ADD_BUYS_TO_RAFFLE_PARTICIPANTS_QUERY)
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event_module_address(&event.type_))
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.coin_type.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.sequence)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.buyer.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.amount_apt)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.transaction_version)
+                    .collect::<Vec<_>>(),
+            ),
+    )
+    .await
+}
+
+async fn add_wins_to_raffle_participants(
+    conn: &mut MyDbConnection,
+    new_raffle_events: &[RaffleEventModel],
+) -> QueryResult<usize> {
+    if new_raffle_events.is_empty() {
+        return Ok(0);
+    }
+    execute_with_better_error_conn(
+        conn,
+        diesel::sql_query(ADD_WINS_TO_RAFFLE_PARTICIPANTS_QUERY)
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event_module_address(&event.type_))
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.coin_type.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.sequence)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.winner.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.amount_apt)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.amount_token)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.transaction_version)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.event_index)
+                    .collect::<Vec<_>>(),
+            ),
+    )
+    .await
+}
+
+/// Updates `raffle_participants` with the events of the batch that weren't stored yet, in the
+/// transaction that stores them, like `update_raffle_rounds`.
+pub async fn update_raffle_participants(
+    conn: &mut MyDbConnection,
+    new_raffle_events: &[RaffleEventModel],
+    new_buy_events: &[BuyEventModel],
+) -> QueryResult<usize> {
+    let buyers = add_buys_to_raffle_participants(conn, new_buy_events).await?;
+    let winners = add_wins_to_raffle_participants(conn, new_raffle_events).await?;
+    Ok(buyers + winners)
+}
+
+#[cfg(all(test, feature = "testing_framework"))]
+mod tests {
+    use crate::{
+        raffle_rounds_model::tests::{buy_event, raffle_event, setup_raffle_db, RAFFLE_ADDRESS},
+        schema::raffle_participants,
+        store_events,
+    };
+    use aptos_indexer_processor_sdk::{
+        postgres::utils::database::ArcDbPool, utils::convert::standardize_address,
+    };
+    use diesel::{ExpressionMethods, QueryDsl};
+    use diesel_async::RunQueryDsl;
+
+    /// Tickets, APT spent, buys, whether they won and the payout of a buyer of round 1.
+    type ParticipantState = (i64, i64, i64, bool, Option<i64>);
+
+    async fn participant(conn_pool: &ArcDbPool, buyer: &str) -> ParticipantState {
+        raffle_participants::table
+            .filter(raffle_participants::module_address.eq(standardize_address(RAFFLE_ADDRESS)))
+            .filter(raffle_participants::sequence.eq(1))
+            .filter(raffle_participants::buyer.eq(buyer))
+            .select((
+                raffle_participants::tickets,
+                raffle_participants::apt_spent,
+                raffle_participants::buy_count,
+                raffle_participants::is_winner,
+                raffle_participants::payout_apt,
+            ))
+            .first::<ParticipantState>(&mut conn_pool.get().await.unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_buys_add_to_participants() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+        let buys = [
+            buy_event(RAFFLE_ADDRESS, "0xa", 250, 1),
+            buy_event(RAFFLE_ADDRESS, "0xa", 100, 2),
+            buy_event(RAFFLE_ADDRESS, "0xb", 300, 3),
+        ];
+
+        store_events(conn_pool.clone(), &[], &[], &buys[..1])
+            .await
+            .unwrap();
+        store_events(conn_pool.clone(), &[], &[], &buys[1..])
+            .await
+            .unwrap();
+        // A replayed batch is already counted
+        store_events(conn_pool.clone(), &[], &[], &buys)
+            .await
+            .unwrap();
+        assert_eq!(
+            participant(&conn_pool, "0xa").await,
+            (3, 350, 2, false, None)
+        );
+        assert_eq!(
+            participant(&conn_pool, "0xb").await,
+            (3, 300, 1, false, None)
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_buys_after_raffle_event_add_no_tickets() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xa", 300, 1)],
+        )
+        .await
+        .unwrap();
+        // Only the buy before the RaffleEvent of the batch counts tickets
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[raffle_event(RAFFLE_ADDRESS, "0xa", 4, 3)],
+            &[
+                buy_event(RAFFLE_ADDRESS, "0xb", 100, 2),
+                buy_event(RAFFLE_ADDRESS, "0xc", 100, 4),
+            ],
+        )
+        .await
+        .unwrap();
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xc", 200, 5)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            participant(&conn_pool, "0xa").await,
+            (3, 300, 1, true, Some(1000))
+        );
+        assert_eq!(
+            participant(&conn_pool, "0xb").await,
+            (1, 100, 1, false, None)
+        );
+        assert_eq!(
+            participant(&conn_pool, "0xc").await,
+            (0, 300, 2, false, None)
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_raffle_event_links_winner() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xa", 100, 1)],
+        )
+        .await
+        .unwrap();
+        // A winner without buys of their own is added, and only the first RaffleEvent of the
+        // round has a winner
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[
+                raffle_event(RAFFLE_ADDRESS, "0xd", 1, 2),
+                raffle_event(RAFFLE_ADDRESS, "0xa", 1, 3),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[raffle_event(RAFFLE_ADDRESS, "0xa", 1, 4)],
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            participant(&conn_pool, "0xd").await,
+            (0, 0, 0, true, Some(1000))
+        );
+        assert_eq!(
+            participant(&conn_pool, "0xa").await,
+            (1, 100, 1, false, None)
+        );
+    }
+}
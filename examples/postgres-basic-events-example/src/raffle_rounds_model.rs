@@ -0,0 +1,685 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{buy_events_model::BuyEventModel, raffle_events_model::RaffleEventModel};
+use aptos_indexer_processor_sdk::{
+    postgres::utils::database::{execute_with_better_error_conn, MyDbConnection},
+    utils::convert::standardize_address,
+};
+use diesel::{
+    sql_types::{Array, BigInt, Text},
+    QueryResult,
+};
+
+/// SQL of the `priced_buys` CTE, the buys of the batch in `new_buys` with the tickets each of
+/// them counts: `FLOOR(amount_apt / price_per_ticket)`, with the `price_per_ticket` of
+/// `raffle_games` in octas, or none if the RaffleEvent of the round came before the buy. Rounds
+/// and participants count tickets with it, so that the tickets of the buyers of a round add up
+/// to its `tickets_sold`. Rows of `raffle_games` added by hand may have short addresses, so
+/// their addresses are compared in the long form.
+macro_rules! priced_buys_cte {
+    () => {
+        r#"
+priced_buys AS (
+    SELECT
+        n.*,
+        CASE
+            WHEN EXISTS (
+                SELECT 1
+                FROM raffle_rounds r
+                WHERE r.module_address = n.module_address
+                    AND r.coin_type = n.coin_type
+                    AND r.sequence = n.sequence
+                    AND r.closed_at_version < n.transaction_version
+            ) THEN 0
+            ELSE COALESCE(
+                (
+                    SELECT FLOOR(n.amount_apt / NULLIF(g.price_per_ticket::numeric, 0))::bigint
+                    FROM raffle_games g
+                    WHERE '0x' || lpad(
+                        lower(regexp_replace(g.module_address, '^0x', '')), 64, '0'
+                    ) = n.module_address
+                        AND g.price_per_ticket ~ '^[0-9]+$'
+                    LIMIT 1
+                ),
+                0
+            )
+        END AS tickets
+    FROM new_buys n
+)"#
+    };
+}
+pub(crate) use priced_buys_cte;
+
+/// Adds the buys of the batch to their rounds. A round is identified by the module emitting its
+/// events, the `coin_type` of the raffle and its `sequence`. Buys count tickets as in
+/// `priced_buys_cte`, until the RaffleEvent of the round sets the final count. A buyer is new
+/// to the round if none of their buys of the round is stored outside the batch. Event types may
+/// have short addresses, so their addresses are compared in the long form.
+const ADD_BUYS_TO_RAFFLE_ROUNDS_QUERY: &str = concat!(
+    r#"
+WITH new_buys AS (
+    SELECT *
+    FROM unnest(
+        $1::text[], $2::text[], $3::bigint[], $4::text[], $5::bigint[], $6::bigint[],
+        $7::bigint[], $8::bigint[]
+    ) AS n (
+        module_address, coin_type, sequence, buyer, amount_apt, timestamp, transaction_version,
+        event_index
+    )
+),"#,
+    priced_buys_cte!(),
+    r#", buyers AS (
+    SELECT
+        p.*,
+        NOT EXISTS (
+            SELECT 1
+            FROM buy_events b
+            WHERE b.coin_type = p.coin_type
+                AND b.sequence = p.sequence
+                AND b.buyer = p.buyer
+                AND '0x' || lpad(
+                    lower(regexp_replace(split_part(b.type, '::', 1), '^0x', '')), 64, '0'
+                ) = p.module_address
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM new_buys o
+                    WHERE o.transaction_version = b.transaction_version
+                        AND o.event_index = b.event_index
+                )
+        ) AS is_new_buyer
+    FROM priced_buys p
+), rounds AS (
+    SELECT
+        module_address,
+        coin_type,
+        sequence,
+        SUM(tickets)::bigint AS tickets_sold,
+        SUM(amount_apt)::bigint AS apt_collected,
+        COUNT(DISTINCT buyer) FILTER (WHERE is_new_buyer) AS new_buyers_count,
+        MIN(timestamp) AS opened_at,
+        MIN(transaction_version) AS first_transaction_version,
+        MAX(transaction_version) AS last_transaction_version
+    FROM buyers
+    GROUP BY 1, 2, 3
+)
+INSERT INTO raffle_rounds (
+    module_address,
+    coin_type,
+    sequence,
+    tickets_sold,
+    apt_collected,
+    buyers_count,
+    status,
+    opened_at,
+    first_transaction_version,
+    last_transaction_version
+)
+SELECT
+    module_address,
+    coin_type,
+    sequence,
+    tickets_sold,
+    apt_collected,
+    new_buyers_count,
+    'open',
+    opened_at,
+    first_transaction_version,
+    last_transaction_version
+FROM rounds
+ON CONFLICT (module_address, coin_type, sequence) DO UPDATE SET
+    tickets_sold = CASE
+        WHEN raffle_rounds.status = 'closed' THEN raffle_rounds.tickets_sold
+        ELSE raffle_rounds.tickets_sold + EXCLUDED.tickets_sold
+    END,
+    apt_collected = raffle_rounds.apt_collected + EXCLUDED.apt_collected,
+    buyers_count = raffle_rounds.buyers_count + EXCLUDED.buyers_count,
+    opened_at = LEAST(raffle_rounds.opened_at, EXCLUDED.opened_at),
+    first_transaction_version = LEAST(
+        raffle_rounds.first_transaction_version,
+        EXCLUDED.first_transaction_version
+    ),
+    last_transaction_version = GREATEST(
+        raffle_rounds.last_transaction_version,
+        EXCLUDED.last_transaction_version
+    ),
+    updated_at = NOW()
+"#
+);
+
+/// Closes the rounds of the RaffleEvents of the batch, with the winner, the payout and the final
+/// ticket count. Only the first RaffleEvent of a round closes it.
+const CLOSE_RAFFLE_ROUNDS_QUERY: &str = r#"
+WITH draws AS (
+    SELECT DISTINCT ON (module_address, coin_type, sequence) *
+    FROM unnest(
+        $1::text[], $2::text[], $3::bigint[], $4::text[], $5::bigint[], $6::bigint[],
+        $7::bigint[], $8::bigint[], $9::bigint[], $10::bigint[]
+    ) AS d (
+        module_address, coin_type, sequence, winner, total_tickets, amount_apt, amount_token,
+        timestamp, transaction_version, event_index
+    )
+    ORDER BY module_address, coin_type, sequence, transaction_version, event_index
+)
+INSERT INTO raffle_rounds (
+    module_address,
+    coin_type,
+    sequence,
+    tickets_sold,
+    apt_collected,
+    buyers_count,
+    winner,
+    payout_apt,
+    payout_token,
+    status,
+    closed_at,
+    first_transaction_version,
+    closed_at_version,
+    last_transaction_version
+)
+SELECT
+    module_address,
+    coin_type,
+    sequence,
+    total_tickets,
+    0,
+    0,
+    winner,
+    amount_apt,
+    amount_token,
+    'closed',
+    timestamp,
+    transaction_version,
+    transaction_version,
+    transaction_version
+FROM draws
+ON CONFLICT (module_address, coin_type, sequence) DO UPDATE SET
+    tickets_sold = EXCLUDED.tickets_sold,
+    winner = EXCLUDED.winner,
+    payout_apt = EXCLUDED.payout_apt,
+    payout_token = EXCLUDED.payout_token,
+    status = EXCLUDED.status,
+    closed_at = EXCLUDED.closed_at,
+    closed_at_version = EXCLUDED.closed_at_version,
+    first_transaction_version = LEAST(
+        raffle_rounds.first_transaction_version,
+        EXCLUDED.first_transaction_version
+    ),
+    last_transaction_version = GREATEST(
+        raffle_rounds.last_transaction_version,
+        EXCLUDED.last_transaction_version
+    ),
+    updated_at = NOW()
+WHERE raffle_rounds.closed_at_version IS NULL
+"#;
+
+/// The address of the module that emitted an event, from its type, in its long form like the
+/// addresses of `raffle_games`.
+pub fn event_module_address(event_type: &str) -> String {
+    standardize_address(event_type.split("::").next().unwrap_or_default())
+}
+
+async fn add_buys_to_raffle_rounds(
+    conn: &mut MyDbConnection,
+    new_buy_events: &[BuyEventModel],
+) -> QueryResult<usize> {
+    if new_buy_events.is_empty() {
+        return Ok(0);
+    }
+    execute_with_better_error_conn(
+        conn,
+        diesel::sql_query(ADD_BUYS_TO_RAFFLE_ROUNDS_QUERY)
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event_module_address(&event.type_))
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.coin_type.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.sequence)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.buyer.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.amount_apt)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.timestamp)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.transaction_version)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_buy_events
+                    .iter()
+                    .map(|event| event.event_index)
+                    .collect::<Vec<_>>(),
+            ),
+    )
+    .await
+}
+
+async fn close_raffle_rounds(
+    conn: &mut MyDbConnection,
+    new_raffle_events: &[RaffleEventModel],
+) -> QueryResult<usize> {
+    if new_raffle_events.is_empty() {
+        return Ok(0);
+    }
+    execute_with_better_error_conn(
+        conn,
+        diesel::sql_query(CLOSE_RAFFLE_ROUNDS_QUERY)
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event_module_address(&event.type_))
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.coin_type.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.sequence)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Text>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.winner.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.total_tickets)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.amount_apt)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.amount_token)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.timestamp)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.transaction_version)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                new_raffle_events
+                    .iter()
+                    .map(|event| event.event_index)
+                    .collect::<Vec<_>>(),
+            ),
+    )
+    .await
+}
+
+/// Updates `raffle_rounds` with the events of the batch that weren't stored yet, in the
+/// transaction that stores them, so that a replayed batch isn't counted twice. Buys are added
+/// before the rounds are closed.
+pub async fn update_raffle_rounds(
+    conn: &mut MyDbConnection,
+    new_raffle_events: &[RaffleEventModel],
+    new_buy_events: &[BuyEventModel],
+) -> QueryResult<usize> {
+    let added = add_buys_to_raffle_rounds(conn, new_buy_events).await?;
+    let closed = close_raffle_rounds(conn, new_raffle_events).await?;
+    Ok(added + closed)
+}
+
+#[cfg(all(test, feature = "testing_framework"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        raffle_games_model::RaffleGameChange, schema::raffle_rounds, store_events, MIGRATIONS,
+    };
+    use aptos_indexer_processor_sdk::{
+        postgres::utils::database::{new_db_pool, run_migrations, ArcDbPool},
+        testing_framework::database::{PostgresTestDatabase, TestDatabase},
+    };
+    use diesel::{ExpressionMethods, QueryDsl};
+    use diesel_async::RunQueryDsl;
+
+    /// Raffle selling tickets for 100 octas, added by hand with its short address.
+    pub(crate) const RAFFLE_ADDRESS: &str = "0xcafe";
+
+    /// Starts Postgres with the migrations of the processor and the raffle at
+    /// `RAFFLE_ADDRESS`. The database lives as long as the returned container.
+    pub(crate) async fn setup_raffle_db() -> (PostgresTestDatabase, ArcDbPool) {
+        let mut db = PostgresTestDatabase::new();
+        db.setup().await.unwrap();
+        let conn_pool = new_db_pool(&db.get_db_url(), None).await.unwrap();
+        run_migrations(db.get_db_url(), conn_pool.clone(), MIGRATIONS).await;
+        diesel::sql_query(
+            "INSERT INTO raffle_games (module_address, fa_metadata_address, name, symbol, \
+             price_per_ticket, tokens_per_raffle, circulating_supply, raffle_frequency, \
+             frequency_unit, start_timestamp, end_timestamp, creator_address, is_active) \
+             VALUES ($1, '0xfa', 'Raffle', 'RFL', '100', '1000', '0', 1, 'day', '0', '0', \
+             '0xbeef', TRUE)",
+        )
+        .bind::<Text, _>(RAFFLE_ADDRESS)
+        .execute(&mut conn_pool.get().await.unwrap())
+        .await
+        .unwrap();
+        (db, conn_pool)
+    }
+
+    /// A buy of round 1 of the raffle at `module_address`, alone in its transaction.
+    pub(crate) fn buy_event(
+        module_address: &str,
+        buyer: &str,
+        amount_apt: i64,
+        transaction_version: i64,
+    ) -> BuyEventModel {
+        let type_ = format!("{}::fa_raffle::BuyEvent", module_address);
+        BuyEventModel {
+            sequence_number: 0,
+            creation_number: 0,
+            account_address: module_address.to_string(),
+            transaction_version,
+            transaction_block_height: transaction_version,
+            type_: type_.clone(),
+            coin_type: "0xfa".to_string(),
+            buyer: buyer.to_string(),
+            sequence: 1,
+            amount_apt,
+            timestamp: transaction_version,
+            event_index: 0,
+            indexed_type: type_,
+        }
+    }
+
+    /// The draw of round 1 of the raffle at `module_address`, alone in its transaction.
+    pub(crate) fn raffle_event(
+        module_address: &str,
+        winner: &str,
+        total_tickets: i64,
+        transaction_version: i64,
+    ) -> RaffleEventModel {
+        let type_ = format!("{}::fa_raffle::RaffleEvent", module_address);
+        RaffleEventModel {
+            sequence_number: 0,
+            creation_number: 0,
+            account_address: module_address.to_string(),
+            transaction_version,
+            transaction_block_height: transaction_version,
+            type_: type_.clone(),
+            coin_type: "0xfa".to_string(),
+            sequence: 1,
+            winner: winner.to_string(),
+            total_tickets,
+            amount_apt: 1000,
+            amount_token: 500,
+            timestamp: transaction_version,
+            event_index: 0,
+            indexed_type: type_,
+        }
+    }
+
+    /// Status, tickets sold, APT collected, buyers, winner and closing version of round 1.
+    type RoundState = (String, i64, i64, i64, Option<String>, Option<i64>);
+
+    async fn round(conn_pool: &ArcDbPool, module_address: &str) -> RoundState {
+        raffle_rounds::table
+            .filter(raffle_rounds::module_address.eq(standardize_address(module_address)))
+            .filter(raffle_rounds::sequence.eq(1))
+            .select((
+                raffle_rounds::status,
+                raffle_rounds::tickets_sold,
+                raffle_rounds::apt_collected,
+                raffle_rounds::buyers_count,
+                raffle_rounds::winner,
+                raffle_rounds::closed_at_version,
+            ))
+            .first::<RoundState>(&mut conn_pool.get().await.unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_first_buy_opens_round() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xa", 250, 1)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            ("open".to_string(), 2, 250, 1, None, None)
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_buys_add_to_open_round() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+        let buys = [
+            buy_event(RAFFLE_ADDRESS, "0xa", 250, 1),
+            buy_event(RAFFLE_ADDRESS, "0xa", 100, 2),
+            buy_event(RAFFLE_ADDRESS, "0xb", 300, 3),
+        ];
+
+        store_events(conn_pool.clone(), &[], &[], &buys[..1])
+            .await
+            .unwrap();
+        store_events(conn_pool.clone(), &[], &[], &buys[1..])
+            .await
+            .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            ("open".to_string(), 6, 650, 2, None, None)
+        );
+
+        // A replayed batch is already counted
+        store_events(conn_pool.clone(), &[], &[], &buys)
+            .await
+            .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            ("open".to_string(), 6, 650, 2, None, None)
+        );
+
+        // The same round of another raffle has buyers of its own
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event("0xdead", "0xa", 100, 4)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, "0xdead").await,
+            ("open".to_string(), 0, 100, 1, None, None)
+        );
+        assert_eq!(round(&conn_pool, RAFFLE_ADDRESS).await.3, 2);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_raffle_event_closes_round() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xa", 250, 1)],
+        )
+        .await
+        .unwrap();
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[raffle_event(RAFFLE_ADDRESS, "0xa", 3, 3)],
+            &[buy_event(RAFFLE_ADDRESS, "0xb", 100, 2)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            (
+                "closed".to_string(),
+                3,
+                350,
+                2,
+                Some("0xa".to_string()),
+                Some(3)
+            )
+        );
+
+        // Buys after the draw don't sell tickets of the closed round
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[],
+            &[buy_event(RAFFLE_ADDRESS, "0xc", 100, 4)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            (
+                "closed".to_string(),
+                3,
+                450,
+                3,
+                Some("0xa".to_string()),
+                Some(3)
+            )
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_duplicate_raffle_event_is_ignored() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+
+        // Only the first RaffleEvent of a batch closes the round
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[
+                raffle_event(RAFFLE_ADDRESS, "0xb", 9, 2),
+                raffle_event(RAFFLE_ADDRESS, "0xa", 5, 1),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            (
+                "closed".to_string(),
+                5,
+                0,
+                0,
+                Some("0xa".to_string()),
+                Some(1)
+            )
+        );
+
+        // Nor does a RaffleEvent of a later batch
+        store_events(
+            conn_pool.clone(),
+            &[],
+            &[raffle_event(RAFFLE_ADDRESS, "0xc", 7, 3)],
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, RAFFLE_ADDRESS).await,
+            (
+                "closed".to_string(),
+                5,
+                0,
+                0,
+                Some("0xa".to_string()),
+                Some(1)
+            )
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_return)]
+    async fn test_buys_priced_by_raffle_configured_in_batch() {
+        let (_db, conn_pool) = setup_raffle_db().await;
+        let config = serde_json::from_str(
+            r#"{
+                "fa_metadata": {"inner": "0xfb"},
+                "price_per_ticket": "50",
+                "tokens_per_raffle": "1000",
+                "raffle_frequency": "1",
+                "start_timestamp": "0",
+                "end_timestamp": "0",
+                "creator": "0xbeef"
+            }"#,
+        )
+        .unwrap();
+
+        // The deployment is stored before the buys of its batch are added to the round
+        store_events(
+            conn_pool.clone(),
+            &[RaffleGameChange::Configured {
+                module_address: standardize_address("0xbabe"),
+                config,
+            }],
+            &[],
+            &[buy_event("0xbabe", "0xa", 250, 1)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            round(&conn_pool, "0xbabe").await,
+            ("open".to_string(), 5, 250, 1, None, None)
+        );
+    }
+}